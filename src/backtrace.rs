@@ -0,0 +1,186 @@
+use cpu::*;
+use mem::Memory;
+
+/* Stops runaway walks through garbage */
+const MAX_FRAMES: usize = 64;
+/* Bytes of stack scanned for a return address once the BP chain is lost */
+const MAX_SCAN: u16 = 0x400;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FrameKind
+{
+	/* Where the CPU is */
+	Current,
+	/* Return addresses of CALL NEAR, CALL FAR and INT */
+	Near,
+	Far,
+	Interrupt
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Frame
+{
+	pub kind: FrameKind,
+	pub cs: u16,
+	pub ip: u16,
+	/* Offset in SS of the return address */
+	pub stack: u16
+}
+
+/* Frames from the innermost one. Real-mode code has no unwinding tables, so
+ * this relies on the usual conventions: candidate return addresses are only
+ * accepted when they follow a CALL or INT instruction, and they are looked
+ * for at the top of the stack (on procedure entry), then after the saved BP
+ * of BP frames (push bp; mov bp, sp), then anywhere further up the stack.
+ * Interrupt frames are recognized by the flags above the return address */
+pub fn backtrace(cpu: &CPU, mem: &Memory) -> Vec<Frame>
+{
+	let mut frames = vec![Frame { kind: FrameKind::Current, cs: cpu.cs, ip: cpu.ip, stack: cpu.sp }];
+	let read = |off: u16| mem.read_u16(cpu.debugger_address(mem, cpu.ss, off));
+
+	let mut cs = cpu.cs;
+	let mut pos = cpu.sp;
+	let mut bp = cpu.bp;
+	while frames.len() < MAX_FRAMES
+	{
+		/* On entry, before or after 'push bp' */
+		let entry = if frames.len() == 1
+		{
+			return_address_at(cpu, mem, pos, cs).or_else(|| return_address_at(cpu, mem, pos.wrapping_add(2), cs))
+		}
+		else
+		{
+			None
+		};
+
+		let has_bp_frame = bp >= pos && bp < 0xfffe;
+		let frame = entry.or_else(||
+			{
+				if !has_bp_frame
+				{
+					return None
+				}
+				/* Interrupts leave BP alone, and their handlers may have
+				 * pushed registers before setting up a frame */
+				(pos .. bp + 2).step_by(2)
+					.filter_map(|off| return_address_at(cpu, mem, off, cs))
+					.find(|frame| frame.kind == FrameKind::Interrupt)
+					.or_else(|| return_address_at(cpu, mem, bp + 2, cs))
+			}).or_else(||
+			{
+				let end = pos.saturating_add(MAX_SCAN);
+				(pos .. end).step_by(2).filter_map(|off| return_address_at(cpu, mem, off, cs)).next()
+			});
+
+		match frame
+		{
+			Some(frame) =>
+			{
+				if has_bp_frame && frame.stack == bp + 2
+				{
+					bp = read(bp);
+				}
+				let size = match frame.kind { FrameKind::Near => 2, FrameKind::Far => 4, _ => 6 };
+				cs = frame.cs;
+				pos = match frame.stack.checked_add(size)
+				{
+					Some(pos) => pos,
+					None => { frames.push(frame); break }
+				};
+				frames.push(frame);
+			}
+			None => break
+		}
+	}
+	frames
+}
+
+/* A return address stored at SS:off, if it follows a call; 'cs' is the code
+ * segment of the callee, for near calls */
+fn return_address_at(cpu: &CPU, mem: &Memory, off: u16, cs: u16) -> Option<Frame>
+{
+	if off > 0xfffa
+	{
+		return None
+	}
+	let read = |off: u16| mem.read_u16(cpu.debugger_address(mem, cpu.ss, off));
+	let (ip, far_cs, flags) = (read(off), read(off + 2), read(off + 4));
+
+	let kind = if flags & FLAG_SET_8086 == FLAG_SET_8086 && follows(cpu, mem, far_cs, ip, is_int)
+	{
+		FrameKind::Interrupt
+	}
+	else if follows(cpu, mem, far_cs, ip, |ins| is_call(ins, true))
+	{
+		FrameKind::Far
+	}
+	else if follows(cpu, mem, cs, ip, |ins| is_call(ins, false))
+	{
+		FrameKind::Near
+	}
+	else
+	{
+		return None
+	};
+	let cs = if kind == FrameKind::Near { cs } else { far_cs };
+	Some(Frame { kind: kind, cs: cs, ip: ip, stack: off })
+}
+
+fn is_int(instruction: &Instruction) -> bool
+{
+	match *instruction
+	{
+		Instruction::SingleBImmOperand(SingleBImmOperandOpCode::INT, _) |
+		Instruction::NoOperand(NoOperandOpCode::INTO) => true,
+		_ => false
+	}
+}
+
+fn is_call(instruction: &Instruction, far: bool) -> bool
+{
+	match *instruction
+	{
+		Instruction::SingleFCOperand(SingleOperandFCOpCode::CALL, ref target) => match *target
+		{
+			FlowControlOperand::DirectSeg(_) | FlowControlOperand::IndirectSeg(_) => !far,
+			FlowControlOperand::DirectInterSeg(..) | FlowControlOperand::IndirectInterSeg(_) => far
+		},
+		_ => false
+	}
+}
+
+/* Whether an instruction matching 'pred' ends right before cs:ip; at most a
+ * segment override prefix and a 4 bytes CALL [BX+disp16] */
+fn follows<F: Fn(&Instruction) -> bool>(cpu: &CPU, mem: &Memory, cs: u16, ip: u16, pred: F) -> bool
+{
+	(1 .. 7).filter(|&len| len <= ip).any(|len|
+		{
+			let start = cpu.debugger_address(mem, cs, ip - len);
+			if start + 6 > mem.size()
+			{
+				return false
+			}
+			let mut bytecode = mem.slice(start, len as u32);
+			let mut prefix = 0;
+			if len > 1 && [0x26, 0x2e, 0x36, 0x3e].contains(&bytecode[0])
+			{
+				bytecode = &bytecode[1 ..];
+				prefix = 1;
+			}
+			/* Only decode what may be a CALL or an INT: parsing arbitrary
+			 * bytes is not always safe */
+			let call_or_int = match bytecode[0]
+			{
+				0xe8 | 0x9a | 0xcd | 0xcc | 0xce => true,
+				/* CALL r/m and CALL FAR m are FF /2 and FF /3 */
+				0xff => bytecode.len() > 1 && (bytecode[1] >> 3) & 0x6 == 0x2,
+				_ => false
+			};
+			if !call_or_int
+			{
+				return false
+			}
+			let sized = parse_instruction(mem.slice_from(start + prefix));
+			sized.size + prefix as u16 == len && pred(&sized.instruction)
+		})
+}
@@ -0,0 +1,92 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::backtrace::*;
+	use super::super::cpu::*;
+	use super::super::mem::Memory;
+
+	fn write(mem: &mut Memory, addr: u32, bytes: &[u8])
+	{
+		for (i, byte) in bytes.iter().enumerate()
+		{
+			mem.write_u8(addr + i as u32, *byte);
+		}
+	}
+
+	fn frame(kind: FrameKind, cs: u16, ip: u16, stack: u16) -> Frame
+	{
+		Frame { kind: kind, cs: cs, ip: ip, stack: stack }
+	}
+
+	/* 1000:0000 CALL 0100 (near), which calls 2000:0000 (far) from 1000:0110,
+	 * which does INT 21 from 2000:0010, handled at 3000:0000 */
+	fn program() -> (CPU, Memory)
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		write(&mut mem, 0x10000, &[0xe8, 0xfd, 0x00]);
+		write(&mut mem, 0x10100, &[0x55, 0x89, 0xe5]);
+		write(&mut mem, 0x10110, &[0x9a, 0x00, 0x00, 0x00, 0x20]);
+		write(&mut mem, 0x20000, &[0x55, 0x89, 0xe5]);
+		write(&mut mem, 0x20010, &[0xcd, 0x21]);
+
+		let mut cpu = CPU::new(0x1000, 0x0000, CpuModel::I8086);
+		cpu.ss = 0;
+		cpu.sp = 0x8000;
+		cpu.bp = 0;
+		(cpu, mem)
+	}
+
+	fn push(cpu: &mut CPU, mem: &mut Memory, val: u16)
+	{
+		cpu.sp -= 2;
+		mem.write_u16(phys_addr(cpu.ss, cpu.sp), val);
+	}
+
+	#[test]
+	fn procedure_entry()
+	{
+		let (mut cpu, mut mem) = program();
+		push(&mut cpu, &mut mem, 0x0003);
+		cpu.ip = 0x0100;
+
+		assert_eq!(backtrace(&cpu, &mem), vec![
+			frame(FrameKind::Current, 0x1000, 0x0100, 0x7ffe),
+			frame(FrameKind::Near, 0x1000, 0x0003, 0x7ffe)]);
+
+		/* After 'push bp' */
+		push(&mut cpu, &mut mem, 0);
+		cpu.ip = 0x0101;
+		assert_eq!(backtrace(&cpu, &mem)[1], frame(FrameKind::Near, 0x1000, 0x0003, 0x7ffe));
+	}
+
+	#[test]
+	fn nested_frames()
+	{
+		let (mut cpu, mut mem) = program();
+		/* CALL 0100; push bp; mov bp, sp */
+		push(&mut cpu, &mut mem, 0x0003);
+		push(&mut cpu, &mut mem, 0);
+		cpu.bp = cpu.sp;
+		/* CALL 2000:0000; push bp; mov bp, sp */
+		push(&mut cpu, &mut mem, 0x1000);
+		push(&mut cpu, &mut mem, 0x0115);
+		let bp = cpu.bp;
+		push(&mut cpu, &mut mem, bp);
+		cpu.bp = cpu.sp;
+		/* INT 21 */
+		push(&mut cpu, &mut mem, FLAG_SET_8086 | FLAG_I);
+		push(&mut cpu, &mut mem, 0x2000);
+		push(&mut cpu, &mut mem, 0x0012);
+		cpu.cs = 0x3000;
+		cpu.ip = 0x0005;
+		/* Some registers saved by the handler */
+		push(&mut cpu, &mut mem, 0x1234);
+		push(&mut cpu, &mut mem, 0x0003);
+
+		assert_eq!(backtrace(&cpu, &mem), vec![
+			frame(FrameKind::Current, 0x3000, 0x0005, 0x7fec),
+			frame(FrameKind::Interrupt, 0x2000, 0x0012, 0x7ff0),
+			frame(FrameKind::Far, 0x1000, 0x0115, 0x7ff8),
+			frame(FrameKind::Near, 0x1000, 0x0003, 0x7ffe)]);
+	}
+}
@@ -3,6 +3,8 @@ use mem::Memory;
 use hw::HW;
 use hw::storage;
 use hw::display;
+use hw::cmos;
+use snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 use std::io::prelude::*;
 
@@ -80,6 +82,35 @@ const BIOS_SEGMENT: u16 = 0xf000;
 const ROM_CONF_TABLE_ADDR: u16 = 0xE6F5;
 const EQUIPMENT_WORD: u16 = 0x21;
 const EQUIPMENT_WORD_ADDR: u32 = 0x410;
+const EQUIPMENT_COPROCESSOR: u16 = 0x2;
+/* ICW1: edge triggered, single controller, ICW4 needed */
+const PIC_ICW1: u8 = 0x13;
+/* ICW2: IRQ0-7 -> INT 08h-0Fh */
+const PIC_ICW2: u8 = 0x08;
+/* ICW4: 8086 mode, buffered */
+const PIC_ICW4: u8 = 0x09;
+const PIC_NONSPECIFIC_EOI: u8 = 0x20;
+/* PIT control word: channel 0, lobyte/hibyte, mode 3, binary */
+const PIT_CH0_SQUARE_WAVE: u8 = 0x36;
+/* PIT control word: channel 1, lobyte only, mode 2, binary */
+const PIT_CH1_RATE_GENERATOR: u8 = 0x54;
+/* DRAM refresh period, in PIT clocks (~15 us) */
+const PIT_CH1_REFRESH_COUNT: u8 = 18;
+const TIMER_TICKS_ADDR: u32 = 0x46c;
+const TIMER_MIDNIGHT_FLAG_ADDR: u32 = 0x470;
+/* 65536 PIT clocks per tick, 1193182 PIT clocks per second */
+const TIMER_TICKS_PER_DAY: u32 = 0x1800B0;
+/* CMOS shutdown codes of programs resetting the 80286 to leave protected
+ * mode: resume at the far pointer stored at 0040:0067, after an EOI to the
+ * PIC or directly */
+const SHUTDOWN_EOI_JUMP: u8 = 0x05;
+const SHUTDOWN_JUMP: u8 = 0x0a;
+const RESUME_POINTER_ADDR: u32 = 0x467;
+/* CMOS extended memory size, in KB */
+const CMOS_EXTENDED_MEMORY: u8 = 0x30;
+/* INT 15h AH=87h: descriptors in the GDT at ES:SI */
+const BLOCK_MOVE_SOURCE: u16 = 0x10;
+const BLOCK_MOVE_DESTINATION: u16 = 0x18;
 
 impl BIOS
 {
@@ -99,6 +130,9 @@ impl BIOS
 
         /* reset vector: ffff:0000 or f000:fff0 */
         if ((0xffff == cs) && (0x0000 == ip)) || ((0xf000 == cs) && (0xfff0 == ip)) {
+            if self.resume_after_reset(cpu, mem, hw) {
+                return;
+            }
             mem.clear_vram();
             hw.display.tty_setcoords(mem, 0, 0, 0);
             self.boot(cpu, mem, hw);
@@ -124,11 +158,30 @@ impl BIOS
                 bios_print!("Non-Maskable Interrupt");
             }
 			0x8 =>
-			{}
+			{
+				/* System timer tick: update the BDA tick count */
+				let mut ticks = mem.read_u16(TIMER_TICKS_ADDR) as u32 | ((mem.read_u16(TIMER_TICKS_ADDR + 2) as u32) << 16);
+				ticks += 1;
+				if ticks >= TIMER_TICKS_PER_DAY
+				{
+					ticks = 0;
+					mem.write_u8(TIMER_MIDNIGHT_FLAG_ADDR, 0x1);
+				}
+				mem.write_u16(TIMER_TICKS_ADDR, (ticks & 0xffff) as u16);
+				mem.write_u16(TIMER_TICKS_ADDR + 2, (ticks >> 16) as u16);
+
+				hw.pic.write_command(PIC_NONSPECIFIC_EOI);
+			}
 			0x9 =>
 			{
 				bios_print!("Keyboard HW interrupt");
 				hw.keyboard.bios_pump_keystrokes(mem);
+				hw.pic.write_command(PIC_NONSPECIFIC_EOI);
+			}
+			0xa ... 0xf =>
+			{
+				bios_print!("Unhandled HW interrupt {:02x}", interrupt_number);
+				hw.pic.write_command(PIC_NONSPECIFIC_EOI);
 			}
 			0x10 =>
 			{
@@ -434,6 +487,20 @@ impl BIOS
 					{
 						bios_print!("BAD IMPLEMENTATION: wait on external event");
 					}
+					0x87 if cpu.timing.model.has_protected_mode() =>
+					{
+						bios_print!("Block move");
+						self.block_move(cpu, mem);
+						cpu.set_reg(BReg::AH, 0);
+						self.clear_carry(cpu, mem);
+					}
+					0x88 if cpu.timing.model.has_protected_mode() =>
+					{
+						bios_print!("Get extended memory size");
+						let size = hw.cmos.read(CMOS_EXTENDED_MEMORY) as u16 | (hw.cmos.read(CMOS_EXTENDED_MEMORY + 1) as u16) << 8;
+						cpu.set_reg(WReg::AX, size);
+						self.clear_carry(cpu, mem);
+					}
 					0x88 => 
 					{
 						bios_print!("Get extended memory size");
@@ -480,7 +547,16 @@ impl BIOS
 								cpu.set_reg(BReg::AL, keystroke.ascii);
 								break;
 							}
-							hw.wait_for_event(cpu);
+							if !hw.has_host_input()
+							{
+								/* Emulated time does not pass while we are
+								 * blocked here, so a scripted keystroke
+								 * would never come: let the guest call us
+								 * again instead, as a real BIOS loops */
+								self.retry_interrupt(cpu, mem, 0x16);
+								break;
+							}
+							hw.wait_for_event();
 						}
 						hw.keyboard.set_irq(true);
 					}
@@ -581,6 +657,8 @@ impl BIOS
 		bios_print!("Boot");
 
 		self.init_ivt(mem);
+		self.init_pic(hw);
+		self.init_pit(hw);
 		
 		{
 			let mut storage_init = 
@@ -598,6 +676,12 @@ impl BIOS
 		}
 
 		self.init_romconf(mem);
+		if cpu.fpu.is_some()
+		{
+			mem.write_u16(EQUIPMENT_WORD_ADDR, EQUIPMENT_WORD | EQUIPMENT_COPROCESSOR);
+		}
+		/* As the POST leaves it */
+		hw.nmi_enabled = true;
 
 		bios_print!("Loading MBR... ");
 		let (boot_storage, bios_drive) = 
@@ -628,6 +712,49 @@ impl BIOS
 		bios_print!("All done; now running the MBR")
 	}
 
+	/* Programs get back to real mode by resetting the 80286, after telling
+	 * the BIOS where to resume; returns false for a regular reset */
+	fn resume_after_reset(&mut self, cpu: &mut CPU, mem: &mut Memory, hw: &mut HW) -> bool
+	{
+		let shutdown = hw.cmos.read(cmos::REG_SHUTDOWN);
+		if shutdown != SHUTDOWN_EOI_JUMP && shutdown != SHUTDOWN_JUMP
+		{
+			return false
+		}
+		bios_print!("Resuming after reset, shutdown code {:02x}", shutdown);
+		hw.cmos.write(cmos::REG_SHUTDOWN, 0);
+		if shutdown == SHUTDOWN_EOI_JUMP
+		{
+			hw.pic.write_command(PIC_NONSPECIFIC_EOI);
+		}
+		cpu.ip = mem.read_u16(RESUME_POINTER_ADDR);
+		cpu.cs = mem.read_u16(RESUME_POINTER_ADDR + 2);
+		true
+	}
+
+	/* Copies CX words between the segments described at ES:SI+10h (source)
+	 * and ES:SI+18h (destination), which may be above 1 MB */
+	fn block_move(&self, cpu: &CPU, mem: &mut Memory)
+	{
+		let (es, si) = (cpu.get_reg(SegReg::ES), cpu.get_reg(WReg::SI));
+		let base = |offset: u16| -> u32
+		{
+			let addr = phys_addr(es, si.wrapping_add(offset + 2));
+			mem.read_u16(addr) as u32 | (mem.read_u8(addr + 2) as u32) << 16
+		};
+		let (source, destination) = (base(BLOCK_MOVE_SOURCE), base(BLOCK_MOVE_DESTINATION));
+		let size = cpu.get_reg(WReg::CX) as u32 * 2;
+
+		let a20 = mem.a20();
+		mem.set_a20(true);
+		for i in 0 .. size
+		{
+			let value = mem.read_u8(source + i);
+			mem.write_u8(destination + i, value);
+		}
+		mem.set_a20(a20);
+	}
+
 	fn write_ivt_entry(&self, mem: &mut Memory, number: u8, seg: u16, addr: u16)
 	{
 		const IVT_OFFSET: u32 = 0;
@@ -656,6 +783,27 @@ impl BIOS
 		}
 	}
 
+	fn init_pic(&self, hw: &mut HW)
+	{
+		hw.pic.write_command(PIC_ICW1);
+		hw.pic.write_data(PIC_ICW2);
+		hw.pic.write_data(PIC_ICW4);
+		/* Unlike a real BIOS, leave all IRQs unmasked: our devices only raise
+		 * their IRQ once the guest enabled them */
+		hw.pic.write_data(0x00);
+	}
+
+	fn init_pit(&self, hw: &mut HW)
+	{
+		/* Channel 0: 18.2 Hz system timer (count 0 = 65536) */
+		hw.pit.write_control(PIT_CH0_SQUARE_WAVE);
+		hw.pit.write_counter(0, 0x00);
+		hw.pit.write_counter(0, 0x00);
+
+		/* Channel 1: DRAM refresh */
+		hw.pit.write_control(PIT_CH1_RATE_GENERATOR);
+		hw.pit.write_counter(1, PIT_CH1_REFRESH_COUNT);
+	}
 	
 	fn init_romconf(&self, mem: &mut Memory)
 	{
@@ -693,6 +841,34 @@ impl BIOS
 		mem.write_u16(flags_addr, flags);
 	}
 
+	/* Makes the IRET of the current BIOS call return onto the INT instruction
+	 * that issued it, so that it runs again. If the call did not come from an
+	 * INT instruction (a far call chaining from a TSR...), we wait in the BIOS
+	 * instead: the IRET runs on a frame returning to this trap with interrupts
+	 * enabled, like the STI/HLT loop of a real BIOS, then the original frame
+	 * is still there when the trap runs again */
+	fn retry_interrupt(&self, cpu: &mut CPU, mem: &mut Memory, interrupt_number: u8)
+	{
+		let ret_ip_addr = phys_addr(cpu.get_reg(SegReg::SS), cpu.get_reg(WReg::SP));
+		let ret_ip = mem.read_u16(ret_ip_addr);
+		let ret_cs = mem.read_u16(ret_ip_addr + 2);
+		let int_addr = phys_addr(ret_cs, ret_ip.wrapping_sub(2));
+
+		if mem.read_u8(int_addr) == 0xcd && mem.read_u8(int_addr + 1) == interrupt_number
+		{
+			mem.write_u16(ret_ip_addr, ret_ip.wrapping_sub(2));
+			return
+		}
+
+		bios_print!("INT {:02x}h not called through an INT instruction ({:04x}:{:04x}), waiting in the BIOS", interrupt_number, ret_cs, ret_ip);
+		let sp = cpu.get_reg(WReg::SP).wrapping_sub(6);
+		let ss = cpu.get_reg(SegReg::SS);
+		mem.write_u16(phys_addr(ss, sp), cpu.get_reg(WReg::IP));
+		mem.write_u16(phys_addr(ss, sp.wrapping_add(2)), cpu.get_reg(SegReg::CS));
+		mem.write_u16(phys_addr(ss, sp.wrapping_add(4)), cpu.flags | FLAG_I);
+		cpu.set_reg(WReg::SP, sp);
+	}
+
 	fn set_carry_value(&self, cpu: &CPU, mem: &mut Memory, set: bool)
 	{
 		self.set_flag_value(cpu, mem, FLAG_C, set);
@@ -708,3 +884,22 @@ impl BIOS
 		self.set_carry_value(cpu, mem, false);
 	}
 }
+
+/* Most of the BIOS state lives in the BDA, hence in the memory snapshot */
+impl Snapshot for BIOS
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"BIOS");
+		writer.write_bool(self.state == BIOSState::Crashed);
+		writer.write_bool(match self.boot_drive { BootDrive::Floppy => false, BootDrive::HardDrive => true });
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"BIOS")?;
+		self.state = if reader.read_bool()? { BIOSState::Crashed } else { BIOSState::Ok };
+		self.boot_drive = if reader.read_bool()? { BootDrive::HardDrive } else { BootDrive::Floppy };
+		Ok(())
+	}
+}
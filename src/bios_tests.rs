@@ -0,0 +1,60 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::bios::*;
+	use super::super::cpu::*;
+	use super::super::frontend::headless::HeadlessFrontend;
+	use super::super::hw::{HW, InputSource};
+	use super::super::hw::input_script::InputScript;
+	use super::super::mem::Memory;
+
+	/* Runs until CS:IP reaches 'ip' in segment 0, stepping the devices too */
+	fn run_until(cpu: &mut CPU, mem: &mut Memory, hw: &mut HW, bios: &mut BIOS, ip: u16)
+	{
+		for clock in 0 .. 100000
+		{
+			if (cpu.cs, cpu.ip) == (0, ip)
+			{
+				return
+			}
+			let cycles = cpu.step(mem, hw, bios);
+			hw.step(mem, clock, 0, cycles);
+		}
+		panic!("{:04x}:{:04x} not reached", 0, ip)
+	}
+
+	#[test]
+	fn wait_for_keystroke_through_far_call()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let script = InputScript::parse("20000 key-down A\n20100 key-up A");
+		let mut hw = HW::new(None, None, None, 0, InputSource::Script(script), Box::new(HeadlessFrontend::new()));
+		let mut bios = BIOS::new(BootDrive::Floppy);
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I8086);
+		cpu.ds = 0x0000;
+		cpu.ss = 0x0000;
+		cpu.sp = 0x2000;
+
+		/* ICW1, ICW2 and ICW4 as the BIOS does */
+		hw.pic.write_command(0x13);
+		hw.pic.write_data(0x08);
+		hw.pic.write_data(0x01);
+		/* INT 09h and INT 16h handled by the BIOS, as after boot */
+		for &vector in [0x09u8, 0x16].iter()
+		{
+			mem.write_u16(vector as u32 * 4, vector as u16);
+			mem.write_u16(vector as u32 * 4 + 2, 0xf000);
+			mem.write_u8(phys_addr(0xf000, vector as u16), 0xcf); // IRET
+		}
+
+		/* XOR AH, AH; PUSHF; CALL FAR [0x0058]: a TSR chaining to the BIOS */
+		for (i, byte) in [0x30, 0xe4, 0x9c, 0xff, 0x1e, 0x58, 0x00].iter().enumerate()
+		{
+			mem.write_u8(0x1000 + i as u32, *byte);
+		}
+
+		run_until(&mut cpu, &mut mem, &mut hw, &mut bios, 0x1007);
+		assert_eq!(cpu.get_reg(WReg::AX), 0x1e61);
+		assert_eq!(cpu.sp, 0x2000);
+	}
+}
@@ -137,4 +137,30 @@ macro_rules! machine_print
 }
 
 #[cfg(not(feature="machine_log"))]
-macro_rules! machine_print {($($x: expr),*) => {()};}
\ No newline at end of file
+macro_rules! machine_print {($($x: expr),*) => {()};}
+
+#[cfg(feature="pic_log")]
+macro_rules! pic_print
+{
+	($y: expr) => {color_print!($crate::ansi_term::Colour::Fixed(200), concat!("[PIC] ", $y))};
+	($y: expr, $($x: expr),*) =>
+	{
+		color_print!($crate::ansi_term::Colour::Fixed(200), concat!("[PIC] ", $y), $($x),*);
+	};	
+}
+
+#[cfg(not(feature="pic_log"))]
+macro_rules! pic_print {($($x: expr),*) => {()};}
+
+#[cfg(feature="pit_log")]
+macro_rules! pit_print
+{
+	($y: expr) => {color_print!($crate::ansi_term::Colour::Fixed(220), concat!("[PIT] ", $y))};
+	($y: expr, $($x: expr),*) =>
+	{
+		color_print!($crate::ansi_term::Colour::Fixed(220), concat!("[PIT] ", $y), $($x),*);
+	};	
+}
+
+#[cfg(not(feature="pit_log"))]
+macro_rules! pit_print {($($x: expr),*) => {()};}
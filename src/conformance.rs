@@ -0,0 +1,548 @@
+use std::fs::File;
+use std::io::Read;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use rustc_serialize::json::Json;
+
+use bios::{BIOS, BootDrive};
+use cpu::*;
+use frontend::headless::HeadlessFrontend;
+use hw::{HW, InputSource};
+use mem::Memory;
+
+/* Enough for REP string instructions with any count */
+const MAX_STEPS: u32 = 0x10000;
+/* Longest 8086/80286 instruction, prefixes included, then some */
+const DECODE_WINDOW: usize = 16;
+
+const CS: usize = 8;
+const IP: usize = 12;
+const FLAGS: usize = 13;
+
+/* One instruction of a conformance corpus: the machine state before and
+ * after it, as a real CPU left it */
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase
+{
+	pub name: String,
+	/* Also written at CS:IP before the initial RAM */
+	pub bytes: Vec<u8>,
+	/* In the order of REGISTER_NAMES */
+	pub initial_regs: [u16; 14],
+	pub initial_ram: Vec<(u32, u8)>,
+	pub final_regs: [u16; 14],
+	pub final_ram: Vec<(u32, u8)>,
+	/* Flags that the instruction leaves undefined are not compared */
+	pub flags_mask: u16
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict
+{
+	Pass,
+	/* The differences, one per register or byte */
+	Fail(Vec<String>),
+	Skip(&'static str)
+}
+
+/* Operands as both decoders see them; memory ones are base, index and
+ * displacement, without the segment */
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DecodedOperand
+{
+	Register(String),
+	Memory(String, String, u16),
+	Immediate(u16),
+	Other(String)
+}
+
+fn register_index(name: &str) -> Result<usize, String>
+{
+	REGISTER_NAMES.iter()
+		.position(|reg| reg.eq_ignore_ascii_case(name))
+		.ok_or_else(|| format!("unknown register {}", name))
+}
+
+fn parse_hex(s: &str) -> Result<u32, String>
+{
+	u32::from_str_radix(s, 16).map_err(|_| format!("invalid hex number {}", s))
+}
+
+/* Corpus files: JSON ones in the format of the single-step test suites, an
+ * array of
+ *   {"name": ..., "bytes": [...],
+ *    "initial": {"regs": {"ax": ..., ...}, "ram": [[address, byte], ...]},
+ *    "final": {"regs": {...}, "ram": [...]}, "flags-mask": ...}
+ * where final registers default to the initial ones and the flags mask to
+ * all flags; or CSV ones, with hex numbers:
+ *   name,bytes,initial regs,initial ram,final regs,final ram[,flags mask]
+ *   add ax bx,01 d8,ax=1234 bx=4321 ...,,ax=5555 ip=0102,
+ * after a header line, where initial registers default to 0; lines
+ * starting with '#' are comments. */
+pub fn load_corpus(filename: &str) -> Result<Vec<TestCase>, String>
+{
+	let mut text = String::new();
+	File::open(filename)
+		.and_then(|mut file| file.read_to_string(&mut text))
+		.map_err(|e| format!("unable to read {}: {}", filename, e))?;
+	parse_corpus(&text)
+}
+
+pub fn parse_corpus(text: &str) -> Result<Vec<TestCase>, String>
+{
+	if text.trim_start().starts_with('[')
+	{
+		parse_json(text)
+	}
+	else
+	{
+		parse_csv(text)
+	}
+}
+
+pub fn parse_json(text: &str) -> Result<Vec<TestCase>, String>
+{
+	let json = Json::from_str(text).map_err(|e| format!("invalid JSON: {}", e))?;
+	let cases = json.as_array().ok_or("a JSON corpus is an array of test cases")?;
+	cases.iter().enumerate()
+		.map(|(i, case)| json_case(case).map_err(|e| format!("test case {}: {}", i, e)))
+		.collect()
+}
+
+fn json_number(json: &Json, what: &str) -> Result<u32, String>
+{
+	json.as_u64()
+		.filter(|n| *n <= 0xffffffff)
+		.map(|n| n as u32)
+		.ok_or_else(|| format!("{} is not a number", what))
+}
+
+fn json_regs(json: Option<&Json>, regs: &mut [u16; 14], complete: bool) -> Result<(), String>
+{
+	let object = json.and_then(|regs| regs.as_object()).ok_or("missing registers")?;
+	for (name, value) in object
+	{
+		regs[register_index(name)?] = json_number(value, name)? as u16;
+	}
+	if complete && object.len() < regs.len()
+	{
+		return Err("missing initial registers".to_string())
+	}
+	Ok(())
+}
+
+fn json_ram(json: Option<&Json>) -> Result<Vec<(u32, u8)>, String>
+{
+	let entries = match json
+	{
+		Some(ram) => ram.as_array().ok_or("RAM is an array of [address, byte]")?,
+		None => return Ok(Vec::new())
+	};
+	entries.iter()
+		.map(|entry| match entry.as_array().map(|pair| &pair[..])
+		{
+			Some([addr, value]) => Ok((json_number(addr, "address")?, json_number(value, "byte")? as u8)),
+			_ => Err("RAM entries are [address, byte]".to_string())
+		})
+		.collect()
+}
+
+fn json_case(json: &Json) -> Result<TestCase, String>
+{
+	let name = json.find("name").and_then(|name| name.as_string()).unwrap_or("").to_string();
+	let bytes = match json.find("bytes").map(|bytes| bytes.as_array())
+	{
+		Some(Some(bytes)) => bytes.iter().map(|byte| json_number(byte, "byte").map(|b| b as u8)).collect::<Result<_, _>>()?,
+		Some(None) => return Err("bytes is an array".to_string()),
+		None => Vec::new()
+	};
+	let initial = json.find("initial").ok_or("missing initial state")?;
+	let expected = json.find("final").ok_or("missing final state")?;
+
+	let mut initial_regs = [0; 14];
+	json_regs(initial.find("regs"), &mut initial_regs, true)?;
+	let mut final_regs = initial_regs;
+	json_regs(expected.find("regs"), &mut final_regs, false)?;
+	let flags_mask = match json.find("flags-mask")
+	{
+		Some(mask) => json_number(mask, "flags-mask")? as u16,
+		None => 0xffff
+	};
+
+	Ok(TestCase
+	{
+		name: name,
+		bytes: bytes,
+		initial_regs: initial_regs,
+		initial_ram: json_ram(initial.find("ram"))?,
+		final_regs: final_regs,
+		final_ram: json_ram(expected.find("ram"))?,
+		flags_mask: flags_mask
+	})
+}
+
+fn csv_regs(field: &str, regs: &mut [u16; 14]) -> Result<(), String>
+{
+	for assignment in field.split_whitespace()
+	{
+		let mut parts = assignment.splitn(2, '=');
+		let (name, value) = (parts.next().unwrap(), parts.next().ok_or_else(|| format!("invalid register value {}", assignment))?);
+		regs[register_index(name)?] = parse_hex(value)? as u16;
+	}
+	Ok(())
+}
+
+fn csv_ram(field: &str) -> Result<Vec<(u32, u8)>, String>
+{
+	field.split_whitespace()
+		.map(|entry|
+		{
+			let mut parts = entry.splitn(2, ':');
+			let addr = parse_hex(parts.next().unwrap())?;
+			let value = parse_hex(parts.next().ok_or_else(|| format!("invalid RAM entry {}", entry))?)?;
+			Ok((addr, value as u8))
+		})
+		.collect()
+}
+
+pub fn parse_csv(text: &str) -> Result<Vec<TestCase>, String>
+{
+	text.lines()
+		.enumerate()
+		.skip(1)
+		.filter(|&(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+		.map(|(i, line)| csv_case(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+		.collect()
+}
+
+fn csv_case(line: &str) -> Result<TestCase, String>
+{
+	let fields: Vec<&str> = line.split(',').collect();
+	if fields.len() < 6 || fields.len() > 7
+	{
+		return Err(format!("expected 6 or 7 fields, got {}", fields.len()))
+	}
+	let bytes = fields[1].split_whitespace()
+		.map(|byte| parse_hex(byte).map(|b| b as u8))
+		.collect::<Result<_, _>>()?;
+	let mut initial_regs = [0; 14];
+	csv_regs(fields[2], &mut initial_regs)?;
+	let mut final_regs = initial_regs;
+	csv_regs(fields[4], &mut final_regs)?;
+	let flags_mask = match fields.get(6).map(|mask| mask.trim())
+	{
+		Some(mask) if !mask.is_empty() => parse_hex(mask)? as u16,
+		_ => 0xffff
+	};
+
+	Ok(TestCase
+	{
+		name: fields[0].trim().to_string(),
+		bytes: bytes,
+		initial_regs: initial_regs,
+		initial_ram: csv_ram(fields[3])?,
+		final_regs: final_regs,
+		final_ram: csv_ram(fields[5])?,
+		flags_mask: flags_mask
+	})
+}
+
+/* Decoders may read past the end of short instructions */
+fn padded(bytes: &[u8]) -> [u8; DECODE_WINDOW]
+{
+	let mut window = [0; DECODE_WINDOW];
+	let len = bytes.len().min(DECODE_WINDOW);
+	window[.. len].copy_from_slice(&bytes[.. len]);
+	window
+}
+
+/* Skips the prefixes, the way CPU::step does */
+fn decode(bytes: &[u8], model: CpuModel) -> (Vec<Prefix>, SizedInstruction)
+{
+	let window = padded(bytes);
+
+	let mut prefixes = Vec::new();
+	let mut offset = 0;
+	loop
+	{
+		let ins = parse_model_instruction(&window[offset ..], model);
+		match ins.instruction
+		{
+			Instruction::Prefix(prefix) if offset < DECODE_WINDOW - 6 =>
+			{
+				prefixes.push(prefix);
+				offset += ins.size as usize;
+			}
+			instruction => return (prefixes, SizedInstruction { size: offset as u16 + ins.size, instruction: instruction })
+		}
+	}
+}
+
+/* Runs the instruction of a test case from a fresh machine */
+pub fn run_case(case: &TestCase, model: CpuModel) -> Verdict
+{
+	let (cs, ip) = (case.initial_regs[CS], case.initial_regs[IP]);
+	if cs == CS_BIOS_TRAP1 || cs == CS_BIOS_TRAP2
+	{
+		return Verdict::Skip("code in a BIOS trap segment")
+	}
+
+	let memory_size = if model.has_protected_mode() { 16 * 1024 * 1024 } else { 1024 * 1024 };
+	let mut mem = Memory::new(memory_size);
+	let mut hw = HW::new(None, None, None, 0, InputSource::Host, Box::new(HeadlessFrontend::new()));
+	let mut bios = BIOS::new(BootDrive::Floppy);
+	let mut cpu = CPU::new(cs, ip, model);
+	cpu.set_registers(&case.initial_regs);
+	for (i, byte) in case.bytes.iter().enumerate()
+	{
+		mem.write_u8(phys_addr(cs, ip.wrapping_add(i as u16)), *byte);
+	}
+	for &(addr, value) in &case.initial_ram
+	{
+		mem.write_u8(addr, value);
+	}
+
+	let repeated = decode(&case.bytes, model).0.iter().any(|prefix| *prefix == Prefix::REP || *prefix == Prefix::REPNE);
+	let ran = panic::catch_unwind(AssertUnwindSafe(||
+	{
+		for _ in 0 .. MAX_STEPS
+		{
+			cpu.step(&mut mem, &mut hw, &mut bios);
+			if !repeated || (cpu.cs, cpu.ip) != (cs, ip) || cpu.state == CPUState::Crashed
+			{
+				break
+			}
+		}
+	}));
+	if ran.is_err()
+	{
+		return Verdict::Fail(vec!["the emulator panicked".to_string()])
+	}
+	if cpu.state == CPUState::Crashed
+	{
+		return Verdict::Fail(vec!["unsupported instruction".to_string()])
+	}
+
+	let mut differences = Vec::new();
+	for (i, (actual, expected)) in cpu.registers().iter().zip(case.final_regs.iter()).enumerate()
+	{
+		let mask = if i == FLAGS { case.flags_mask } else { 0xffff };
+		if (actual ^ expected) & mask != 0
+		{
+			differences.push(format!("{} {:04x}, expected {:04x}", REGISTER_NAMES[i], actual, expected));
+		}
+	}
+	for &(addr, expected) in &case.final_ram
+	{
+		let actual = mem.read_u8(addr);
+		if actual != expected
+		{
+			differences.push(format!("[{:05x}] {:02x}, expected {:02x}", addr, actual, expected));
+		}
+	}
+	if differences.is_empty() { Verdict::Pass } else { Verdict::Fail(differences) }
+}
+
+fn indirect_registers(ir: &IndReg) -> (&'static str, &'static str)
+{
+	match *ir
+	{
+		IndReg::BXSI => ("BX", "SI"),
+		IndReg::BXDI => ("BX", "DI"),
+		IndReg::BPSI => ("BP", "SI"),
+		IndReg::BPDI => ("BP", "DI"),
+		IndReg::SI => ("SI", ""),
+		IndReg::DI => ("DI", ""),
+		IndReg::BP => ("BP", ""),
+		IndReg::BX => ("BX", "")
+	}
+}
+
+fn memory(ir: &IndReg, displacement: u16) -> DecodedOperand
+{
+	let (base, index) = indirect_registers(ir);
+	DecodedOperand::Memory(base.to_string(), index.to_string(), displacement)
+}
+
+fn b_operand(operand: &BOperand) -> DecodedOperand
+{
+	match *operand
+	{
+		BOperand::Reg(r) => DecodedOperand::Register(format!("{:?}", r)),
+		BOperand::Immediate(imm) => DecodedOperand::Immediate(imm as u16),
+		BOperand::Direct(addr) => DecodedOperand::Memory(String::new(), String::new(), addr),
+		BOperand::Indirect(ref ir) => memory(ir, 0),
+		BOperand::Indirect8iDis(ref ir, dis) => memory(ir, dis as i16 as u16),
+		BOperand::Indirect16uDis(ref ir, dis) => memory(ir, dis)
+	}
+}
+
+fn w_operand(operand: &WOperand) -> DecodedOperand
+{
+	match *operand
+	{
+		WOperand::Reg(r) => DecodedOperand::Register(format!("{:?}", r)),
+		WOperand::SegReg(r) => DecodedOperand::Register(format!("{:?}", r)),
+		WOperand::Immediate(imm) => DecodedOperand::Immediate(imm),
+		WOperand::Direct(addr) => DecodedOperand::Memory(String::new(), String::new(), addr),
+		WOperand::Indirect(ref ir) => memory(ir, 0),
+		WOperand::Indirect8iDis(ref ir, dis) => memory(ir, dis as i16 as u16),
+		WOperand::Indirect16uDis(ref ir, dis) => memory(ir, dis)
+	}
+}
+
+fn shift_count(count: &ShiftRotateCount) -> DecodedOperand
+{
+	match *count
+	{
+		ShiftRotateCount::One => DecodedOperand::Immediate(1),
+		ShiftRotateCount::CL => DecodedOperand::Register("CL".to_string()),
+		ShiftRotateCount::Imm(n) => DecodedOperand::Immediate(n as u16)
+	}
+}
+
+/* The explicit operands of an instruction, in no particular order; None
+ * for the forms whose operands the decoders describe differently: string
+ * instructions, branches, XLAT, I/O and INT. ST(0) is left out, as iced-x86
+ * only names it for some of the coprocessor instructions. */
+fn our_operands(instruction: &Instruction) -> Option<Vec<DecodedOperand>>
+{
+	let operands = match *instruction
+	{
+		Instruction::NoOperand(NoOperandOpCode::XLAT) => return None,
+		Instruction::NoOperand(_) | Instruction::FCNoOperandSeg(_) | Instruction::FCNoOperandInterSeg(_) => vec![],
+		Instruction::SingleBOperand(_, ref a) => vec![b_operand(a)],
+		Instruction::SingleWOperand(_, ref a) | Instruction::System(_, ref a) => vec![w_operand(a)],
+		Instruction::TwoBOperands(_, ref a, ref b) => vec![b_operand(a), b_operand(b)],
+		Instruction::TwoWOperands(_, ref a, ref b) => vec![w_operand(a), w_operand(b)],
+		Instruction::ThreeWOperands(_, ref a, ref b, ref c) => vec![w_operand(a), w_operand(b), w_operand(c)],
+		Instruction::ShiftRotateB(_, ref count, ref a) => vec![b_operand(a), shift_count(count)],
+		Instruction::ShiftRotateW(_, ref count, ref a) => vec![w_operand(a), shift_count(count)],
+		Instruction::SingleFCOperand(_, FlowControlOperand::IndirectSeg(ref a)) |
+		Instruction::SingleFCOperand(_, FlowControlOperand::IndirectInterSeg(ref a)) => vec![w_operand(a)],
+		Instruction::SingleWImmFCOperand(_, SingleWImmFCOperand::Seg(imm)) |
+		Instruction::SingleWImmFCOperand(_, SingleWImmFCOperand::InterSeg(imm)) => vec![DecodedOperand::Immediate(imm)],
+		Instruction::Enter(size, level) => vec![DecodedOperand::Immediate(size), DecodedOperand::Immediate(level as u16)],
+		Instruction::Fpu(_, ref operand) => match *operand
+		{
+			FpuOperand::None => vec![],
+			FpuOperand::St(i) | FpuOperand::ToSt(i) | FpuOperand::FromSt(i) => vec![DecodedOperand::Register(format!("ST{}", i))],
+			FpuOperand::Memory(_, ref a) | FpuOperand::Word(ref a) | FpuOperand::Address(ref a) => vec![w_operand(a)]
+		},
+		_ => return None
+	};
+	Some(operands)
+}
+
+fn iced_operands(instr: &iced_x86::Instruction) -> Vec<DecodedOperand>
+{
+	let register = |reg: iced_x86::Register| if reg == iced_x86::Register::None { String::new() } else { format!("{:?}", reg) };
+	(0 .. instr.op_count())
+		.map(|i| match instr.op_kind(i)
+		{
+			iced_x86::OpKind::Register => DecodedOperand::Register(register(instr.op_register(i))),
+			iced_x86::OpKind::Memory => DecodedOperand::Memory(register(instr.memory_base()), register(instr.memory_index()),
+				instr.memory_displacement32() as u16),
+			iced_x86::OpKind::Immediate8 | iced_x86::OpKind::Immediate8_2nd | iced_x86::OpKind::Immediate16 |
+			iced_x86::OpKind::Immediate8to16 => DecodedOperand::Immediate(instr.immediate(i) as u16),
+			kind => DecodedOperand::Other(format!("{:?}", kind))
+		})
+		.collect()
+}
+
+/* Compares the length and operands of an instruction with what iced-x86
+ * decodes, as the given CPU would run it. iced-x86 decodes the instructions
+ * of the 80186 and later, so the forms that the 8086 and 8088 decode
+ * otherwise are skipped, as are those the CPU does not know */
+pub fn cross_check_decoder(bytes: &[u8], model: CpuModel) -> Verdict
+{
+	let (prefixes, ours) = decode(bytes, model);
+	if ours.instruction == Instruction::Invalid
+	{
+		return Verdict::Skip("not an instruction of this CPU")
+	}
+	if !model.has_80186_instructions() && decode(bytes, CpuModel::I80186) != (prefixes, ours)
+	{
+		return Verdict::Skip("an 8086 alias of another instruction")
+	}
+	let window = padded(bytes);
+	/* LOCK is valid with any instruction before the 80386 */
+	let iced = iced_x86::Decoder::new(16, &window, iced_x86::DecoderOptions::NO_INVALID_CHECK).decode();
+	if iced.is_invalid()
+	{
+		return Verdict::Fail(vec![format!("{}: iced-x86 finds no instruction", ours)])
+	}
+	if iced.len() != ours.size as usize
+	{
+		return Verdict::Fail(vec![format!("{}: {} bytes, iced-x86 decodes {} bytes", ours, ours.size, iced.len())])
+	}
+
+	if let Some(mut operands) = our_operands(&ours.instruction)
+	{
+		let mut expected = iced_operands(&iced);
+		if iced.mnemonic() == iced_x86::Mnemonic::Nop || iced.mnemonic() == iced_x86::Mnemonic::Pause
+		{
+			/* 90 is XCHG AX, AX for us, even with REP */
+			expected = vec![DecodedOperand::Register("AX".to_string()); 2];
+		}
+		if let Instruction::Fpu(_, _) = ours.instruction
+		{
+			let st0 = DecodedOperand::Register("ST0".to_string());
+			operands.retain(|operand| *operand != st0);
+			expected.retain(|operand| *operand != st0);
+		}
+		operands.sort();
+		expected.sort();
+		if operands != expected
+		{
+			return Verdict::Fail(vec![format!("{}: operands {:?}, iced-x86 decodes {:?}", ours, operands, expected)])
+		}
+	}
+	Verdict::Pass
+}
+
+/* Runs a corpus file and prints the failures; returns the exit status */
+pub fn run_corpus(filename: &str, model: CpuModel) -> i32
+{
+	let cases = match load_corpus(filename)
+	{
+		Ok(cases) => cases,
+		Err(e) =>
+		{
+			println!("{}", e);
+			return 2
+		}
+	};
+
+	let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+	for case in &cases
+	{
+		let mut differences = match run_case(case, model)
+		{
+			Verdict::Pass => vec![],
+			Verdict::Fail(differences) => differences,
+			Verdict::Skip(reason) =>
+			{
+				println!("SKIP {}: {}", case.name, reason);
+				skipped += 1;
+				continue
+			}
+		};
+		if let Verdict::Fail(errors) = cross_check_decoder(&case.bytes, model)
+		{
+			differences.extend(errors.iter().map(|e| format!("decoder: {}", e)));
+		}
+
+		if differences.is_empty()
+		{
+			passed += 1;
+		}
+		else
+		{
+			println!("FAIL {}: {}", case.name, differences.join("; "));
+			failed += 1;
+		}
+	}
+	println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+	if failed == 0 { 0 } else { 1 }
+}
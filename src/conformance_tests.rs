@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::conformance::*;
+	use super::super::cpu::CpuModel;
+
+	const JSON_CORPUS: &str = include_str!("../cpu_test/single_step.json");
+	const CSV_CORPUS: &str = include_str!("../cpu_test/single_step.csv");
+
+	fn run_all(cases: &[TestCase], model: CpuModel)
+	{
+		for case in cases
+		{
+			assert_eq!(run_case(case, model), Verdict::Pass, "{}", case.name);
+			assert_eq!(cross_check_decoder(&case.bytes, model), Verdict::Pass, "{}", case.name);
+		}
+	}
+
+	#[test]
+	fn json_corpus()
+	{
+		let cases = parse_corpus(JSON_CORPUS).unwrap();
+		assert_eq!(cases.len(), 8);
+		assert_eq!(cases[0].name, "add ax, bx");
+		assert_eq!((cases[0].initial_regs[0], cases[0].final_regs[0], cases[0].final_regs[1]), (0x1234, 0x5555, 0x4321));
+		assert_eq!(cases[3].flags_mask, 0xff2b);
+		run_all(&cases, CpuModel::I8086);
+		run_all(&cases, CpuModel::I80286);
+	}
+
+	#[test]
+	fn csv_corpus()
+	{
+		let cases = parse_corpus(CSV_CORPUS).unwrap();
+		assert_eq!(cases.len(), 2);
+		assert_eq!(cases[1].bytes, vec![0xc4, 0x3e, 0x10, 0x00]);
+		assert_eq!(cases[1].initial_ram[3], (0x20013, 0x12));
+		run_all(&cases, CpuModel::I8088);
+
+		assert!(parse_corpus("name\nnop,90,ip=zz,,,\n").unwrap_err().contains("line 2"));
+		assert!(parse_corpus("[{\"name\": \"nop\"}]").unwrap_err().contains("initial"));
+	}
+
+	#[test]
+	fn differences()
+	{
+		let mut case = parse_corpus(JSON_CORPUS).unwrap().remove(2);
+		case.final_regs[0] = 0xbeee;
+		case.final_ram[1].1 = 0xee;
+		assert_eq!(run_case(&case, CpuModel::I8086), Verdict::Fail(vec![
+			"AX beef, expected beee".to_string(), "[300fe] ef, expected ee".to_string()]));
+
+		/* Only the defined flags are compared */
+		let mut case = parse_corpus(JSON_CORPUS).unwrap().remove(3);
+		case.final_regs[13] ^= 0x00d4;
+		assert_eq!(run_case(&case, CpuModel::I8086), Verdict::Pass);
+		case.final_regs[13] ^= 0x0001;
+		assert_eq!(run_case(&case, CpuModel::I8086), Verdict::Fail(vec!["FLAGS f803, expected f8d6".to_string()]));
+
+		case.initial_regs[8] = 0xf000;
+		assert_eq!(run_case(&case, CpuModel::I8086), Verdict::Skip("code in a BIOS trap segment"));
+	}
+
+	/* Every opcode with every ModRM byte, prefixed or not */
+	#[test]
+	fn decoder_cross_check()
+	{
+		let mut failures = Vec::new();
+		for &model in &[CpuModel::I8086, CpuModel::I80186, CpuModel::I80286]
+		{
+			for prefix in &[None, Some(0x26), Some(0xf3)]
+			{
+				for opcode in 0 .. 0x100
+				{
+					for modrm in 0 .. 0x100
+					{
+						let mut bytes = prefix.iter().cloned().collect::<Vec<u8>>();
+						bytes.extend_from_slice(&[opcode as u8, modrm as u8, 0x12, 0x34, 0x56, 0x78, 0x9a]);
+						if let Verdict::Fail(errors) = cross_check_decoder(&bytes, model)
+						{
+							failures.push(format!("{:?} {:02x?}: {}", model, &bytes[.. 3], errors.join("; ")));
+						}
+					}
+				}
+			}
+		}
+		assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+
+		/* The 8086 aliases are not compared with the 80186 instructions */
+		let alias = Verdict::Skip("an 8086 alias of another instruction");
+		for bytes in &[&[0x60, 0x05][..], &[0xc1], &[0xc8], &[0x82, 0xe3, 0x0f], &[0xd0, 0xf0], &[0xff, 0x3f], &[0xf1, 0x90]]
+		{
+			assert_eq!(cross_check_decoder(bytes, CpuModel::I8086), alias, "{:02x?}", bytes);
+		}
+		assert_eq!(cross_check_decoder(&[0x70, 0x05], CpuModel::I8086), Verdict::Pass);
+		assert_eq!(cross_check_decoder(&[0xd6], CpuModel::I8088), Verdict::Pass);
+		assert_eq!(cross_check_decoder(&[0xc1, 0xe0, 0x04], CpuModel::I80186), Verdict::Pass);
+		assert_eq!(cross_check_decoder(&[0x0f, 0x01, 0xe0], CpuModel::I80186), Verdict::Skip("not an instruction of this CPU"));
+	}
+}
@@ -1,7 +1,14 @@
 use super::instruction::*;
+use super::timing::*;
+use super::traps::{Trap, TrapHit};
+use super::history::History;
+use super::protected::{Fault, ProtectedState};
+use super::fpu::Fpu;
+use super::decode_cache::DecodeCache;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
+use std::cell::Cell;
 use std::fs::File;
-use std::collections::VecDeque;
 
 pub const FLAG_C: u16 = 0b0000000000000001;
 pub const FLAG_P: u16 = 0b0000000000000100;
@@ -75,16 +82,33 @@ pub struct CPU
 
 	pub segment_override_prefix: Option<SegReg>,
 	pub rep_prefix: Option<RepPrefix>,
+	/* Start of the current instruction, prefixes included, where faults
+	 * return to on the 80186 */
+	pub instruction_ip: u16,
 	pub state: CPUState,
-
-	pub pending_interrupts: VecDeque<u8>,
+	pub timing: Timing,
+	/* Descriptor tables and segment caches of the 80286 */
+	pub protected: ProtectedState,
+	/* Exception raised by the current instruction, delivered once it returns;
+	 * set from memory accesses, which only borrow the CPU */
+	pub fault: Cell<Option<Fault>>,
+	/* The numeric coprocessor, if any */
+	pub fpu: Option<Fpu>,
 
 	pub log: Option<File>,
+
+	/* Debugger traps, not part of the machine state */
+	pub traps: Vec<Trap>,
+	pub trap_hits: Vec<TrapHit>,
+	/* Execution history, for the debugger too */
+	pub history: History,
+	/* Instructions decoded so far, by physical address */
+	pub decode_cache: DecodeCache,
 }
 
 impl CPU
 {
-	pub fn new(cs: u16, ip: u16) -> CPU
+	pub fn new(cs: u16, ip: u16, model: CpuModel) -> CPU
 	{
 		let trace_file = 
 			if cfg!(feature="cpu-trace")
@@ -107,13 +131,102 @@ impl CPU
 			ds: 0xbad0,
 			ss: 0xbad0,
 			es: 0xbad0,
-			flags: FLAG_SET_8086,
-            cr0: 0x4,
+			flags: model.fixed_flags(),
+            cr0: 0,
 			segment_override_prefix: None,
 			rep_prefix: None,
+			instruction_ip: ip,
 			state: CPUState::Paused,
-			pending_interrupts: VecDeque::<u8>::new(),
-			log: trace_file
+			timing: Timing::new(model),
+			protected: ProtectedState::new(),
+			fault: Cell::new(None),
+			fpu: None,
+			log: trace_file,
+			traps: Vec::new(),
+			trap_hits: Vec::new(),
+			history: History::new(),
+			decode_cache: DecodeCache::new()
+		}
+	}
+}
+
+/* The execution state (running, paused...) belongs to the debugger session */
+impl Snapshot for CPU
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"CPU ");
+		for reg in &[self.ax, self.bx, self.cx, self.dx, self.sp, self.bp, self.si, self.di,
+			self.cs, self.ds, self.ss, self.es, self.ip, self.flags]
+		{
+			writer.write_u16(*reg);
+		}
+		writer.write_u8(self.cr0);
+		writer.write_u8(match self.segment_override_prefix
+			{
+				None => 0,
+				Some(SegReg::CS) => 1,
+				Some(SegReg::DS) => 2,
+				Some(SegReg::ES) => 3,
+				Some(SegReg::SS) => 4
+			});
+		writer.write_u8(match self.rep_prefix
+			{
+				None => 0,
+				Some(RepPrefix::Rep) => 1,
+				Some(RepPrefix::Repne) => 2
+			});
+		self.timing.save_state(writer);
+		self.protected.save_state(writer);
+		writer.write_bool(self.fpu.is_some());
+		if let Some(ref fpu) = self.fpu
+		{
+			fpu.save_state(writer);
+		}
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"CPU ")?;
+		self.ax = reader.read_u16()?;
+		self.bx = reader.read_u16()?;
+		self.cx = reader.read_u16()?;
+		self.dx = reader.read_u16()?;
+		self.sp = reader.read_u16()?;
+		self.bp = reader.read_u16()?;
+		self.si = reader.read_u16()?;
+		self.di = reader.read_u16()?;
+		self.cs = reader.read_u16()?;
+		self.ds = reader.read_u16()?;
+		self.ss = reader.read_u16()?;
+		self.es = reader.read_u16()?;
+		self.ip = reader.read_u16()?;
+		self.flags = reader.read_u16()?;
+		self.cr0 = reader.read_u8()?;
+		self.segment_override_prefix = match reader.read_u8()?
+		{
+			0 => None,
+			1 => Some(SegReg::CS),
+			2 => Some(SegReg::DS),
+			3 => Some(SegReg::ES),
+			4 => Some(SegReg::SS),
+			x => return Err(format!("invalid segment override prefix {}", x))
+		};
+		self.rep_prefix = match reader.read_u8()?
+		{
+			0 => None,
+			1 => Some(RepPrefix::Rep),
+			2 => Some(RepPrefix::Repne),
+			x => return Err(format!("invalid REP prefix {}", x))
+		};
+		self.timing.load_state(reader)?;
+		self.protected.load_state(reader)?;
+		match (reader.read_bool()?, self.fpu.as_mut())
+		{
+			(false, None) => Ok(()),
+			(true, Some(fpu)) => fpu.load_state(reader),
+			(true, None) => Err("the snapshot was taken with a coprocessor, but there is none".to_string()),
+			(false, Some(_)) => Err("the snapshot was taken without any coprocessor".to_string())
 		}
 	}
 }
@@ -0,0 +1,72 @@
+use super::parser::PrefixedInstruction;
+
+/* Entries are direct-mapped by the low bits of the physical address */
+const CACHE_ENTRIES: usize = 0x10000;
+/* Longer instructions, with a lot of prefixes, are decoded each time */
+pub const MAX_CACHED_SIZE: u16 = 16;
+
+/* Decoded instructions by physical address; Memory tells which bytes got
+ * written since they were decoded */
+pub struct DecodeCache
+{
+	entries: Box<[Option<(u32, PrefixedInstruction)>]>
+}
+
+fn slot(addr: u32) -> usize
+{
+	addr as usize & (CACHE_ENTRIES - 1)
+}
+
+impl DecodeCache
+{
+	pub fn new() -> DecodeCache
+	{
+		DecodeCache
+		{
+			entries: vec![None; CACHE_ENTRIES].into_boxed_slice()
+		}
+	}
+
+	pub fn get(&self, addr: u32) -> Option<PrefixedInstruction>
+	{
+		match self.entries[slot(addr)]
+		{
+			Some((start, prefixed)) if start == addr => Some(prefixed),
+			_ => None
+		}
+	}
+
+	pub fn insert(&mut self, addr: u32, prefixed: PrefixedInstruction)
+	{
+		if prefixed.sized.size <= MAX_CACHED_SIZE
+		{
+			self.entries[slot(addr)] = Some((addr, prefixed));
+		}
+	}
+
+	/* Drops the instructions 'addr' is a byte of */
+	pub fn invalidate(&mut self, addr: u32)
+	{
+		for start in addr.saturating_sub(MAX_CACHED_SIZE as u32 - 1) ..= addr
+		{
+			let entry = &mut self.entries[slot(start)];
+			let stale = match *entry
+				{
+					Some((cached, prefixed)) => cached == start && start + prefixed.sized.size as u32 > addr,
+					None => false
+				};
+			if stale
+			{
+				*entry = None;
+			}
+		}
+	}
+
+	pub fn clear(&mut self)
+	{
+		for entry in self.entries.iter_mut()
+		{
+			*entry = None;
+		}
+	}
+}
@@ -0,0 +1,68 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::decode_cache::*;
+	use super::super::instruction::*;
+	use super::super::parser::*;
+	use super::super::timing::CpuModel;
+	use super::super::super::bios::{BIOS, BootDrive};
+	use super::super::super::frontend::headless::HeadlessFrontend;
+	use super::super::super::hw::{HW, InputSource};
+	use super::super::super::mem::Memory;
+
+	#[test]
+	fn invalidation()
+	{
+		let mut cache = DecodeCache::new();
+		/* MOV WORD [0x1234], 0x5678 */
+		let mov = parse_prefixed_instruction(&[0xc7, 0x06, 0x34, 0x12, 0x78, 0x56], CpuModel::I8086);
+		cache.insert(0x500, mov);
+		cache.insert(0x10506, mov);
+		assert_eq!(cache.get(0x500), Some(mov));
+		assert_eq!(cache.get(0x10500), None);
+
+		/* Bytes around it, then one of its own */
+		cache.invalidate(0x4ff);
+		cache.invalidate(0x506);
+		assert_eq!(cache.get(0x500), Some(mov));
+		cache.invalidate(0x505);
+		assert_eq!(cache.get(0x500), None);
+		assert_eq!(cache.get(0x10506), Some(mov));
+
+		cache.clear();
+		assert_eq!(cache.get(0x10506), None);
+	}
+
+	#[test]
+	fn self_modifying_code()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut hw = HW::new(None, None, None, 0, InputSource::Host, Box::new(HeadlessFrontend::new()));
+		let mut bios = BIOS::new(BootDrive::Floppy);
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I8086);
+		cpu.ds = 0x0000;
+		/* MOV AX, 1; MOV BYTE [0x1001], 5; JMP 0x1000 */
+		for (i, byte) in [0xb8, 0x01, 0x00, 0xc6, 0x06, 0x01, 0x10, 0x05, 0xeb, 0xf6].iter().enumerate()
+		{
+			mem.write_u8(0x1000 + i as u32, *byte);
+		}
+
+		cpu.step(&mut mem, &mut hw, &mut bios);
+		assert_eq!(cpu.ax, 1);
+		assert!(cpu.decode_cache.get(0x1000).is_some());
+		for _ in 0 .. 3
+		{
+			cpu.step(&mut mem, &mut hw, &mut bios);
+		}
+		assert_eq!((cpu.ip, cpu.ax), (0x1003, 5));
+
+		/* Writes from outside the CPU count too */
+		mem.write_u8(0x1002, 0x01);
+		cpu.ip = 0x1000;
+		cpu.step(&mut mem, &mut hw, &mut bios);
+		assert_eq!(cpu.ax, 0x0105);
+		assert_eq!(cpu.decode_cache.get(0x1000).map(|prefixed| prefixed.sized.instruction),
+			Some(Instruction::TwoWOperands(TwoOperandsOpCode::MOV, WOperand::Immediate(0x0105), WOperand::Reg(WReg::AX))));
+	}
+}
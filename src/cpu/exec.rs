@@ -1,53 +1,114 @@
 use super::base::*;
 use super::instruction::*;
-use super::super::mem::Memory;
+use super::super::mem::{CodeWrites, Memory};
 use super::super::bios::BIOS;
 use super::super::hw::HW;
 use super::parser::*;
+use super::decode_cache::MAX_CACHED_SIZE;
+use super::timing::*;
+
+const NMI_VECTOR: u8 = 2;
 
 impl CPU
 {
-	/* Finds the next non-prefix instruction; prefixes are handled during
-	 * the search */
-	fn next_non_prefix_instruction(&mut self, mem: &Memory, ip: u16) -> SizedInstruction
+	/* Decodes the instruction at 'ip' and its prefixes, or finds it in the
+	 * decode cache */
+	fn fetch_instruction(&mut self, mem: &mut Memory, ip: u16) -> PrefixedInstruction
 	{
-		let bytecode = mem.slice_from(phys_addr(self.cs, ip));
-		let instruction_candidate = parse_instruction(bytecode);
-		match instruction_candidate.instruction
+		match mem.take_code_writes()
 		{
-			Instruction::Prefix(p) =>
+			Some(CodeWrites::Bytes(addrs)) =>
 			{
-				self.handle_prefix(p);
-				let ins = self.next_non_prefix_instruction(mem, ip + instruction_candidate.size);
-				/* Includes the prefix in the instruction size */
-				SizedInstruction
+				for addr in addrs
 				{
-					size: instruction_candidate.size + ins.size,
-					instruction: ins.instruction
+					self.decode_cache.invalidate(addr);
 				}
-			}
-			_ =>  instruction_candidate
+			},
+			Some(CodeWrites::All) => self.decode_cache.clear(),
+			None => ()
+		}
+
+		let addr = mem.physical_address(self.code_address(ip));
+		if let Some(prefixed) = self.decode_cache.get(addr)
+		{
+			return prefixed
+		}
+		let prefixed = parse_prefixed_instruction(mem.slice_from(addr), self.timing.model);
+		/* An invalid opcode may depend on more bytes than its size tells */
+		if prefixed.sized.instruction != Instruction::Invalid && prefixed.sized.size <= MAX_CACHED_SIZE
+		{
+			mem.mark_code(addr, prefixed.sized.size as u32);
+			self.decode_cache.insert(addr, prefixed);
 		}
+		prefixed
 	}
 
-	pub fn step(&mut self, mem: &mut Memory, hw: &mut HW, bios: &mut BIOS)
+	/* Runs one instruction; returns the amount of clock cycles spent */
+	pub fn step(&mut self, mem: &mut Memory, hw: &mut HW, bios: &mut BIOS) -> u32
 	{
-		if (self.flags & FLAG_I != 0) && (! self.pending_interrupts.is_empty())
+		if !self.history.is_enabled()
+		{
+			return self.run_step(mem, hw, bios)
+		}
+		self.begin_history_entry(mem);
+		let cycles = self.run_step(mem, hw, bios);
+		self.end_history_entry(mem);
+		cycles
+	}
+
+	fn run_step(&mut self, mem: &mut Memory, hw: &mut HW, bios: &mut BIOS) -> u32
+	{
+		let mut cycles = 0;
+
+		/* Coprocessor exceptions, through the NMI as on the PC */
+		let fpu_interrupt = self.fpu.as_mut().map_or(false, |fpu| fpu.take_interrupt());
+		if fpu_interrupt && hw.nmi_enabled
 		{
-			let irq = self.pending_interrupts.pop_front().unwrap();
+			cpu_print!("Coprocessor NMI");
+			let checkpoint = self.checkpoint();
+			self.request_interrupt(mem, NMI_VECTOR);
+			self.deliver_fault(mem, &checkpoint);
+			self.history_note(Some(NMI_VECTOR));
+			self.timing.flush_queue();
+			cycles += HW_INTERRUPT_CYCLES;
+		}
+
+		if (self.flags & FLAG_I != 0) && hw.pic.has_interrupt()
+		{
+			let irq = hw.pic.acknowledge();
 			cpu_print!("HW IRQ {:02x}", irq);
+			let checkpoint = self.checkpoint();
 			self.request_interrupt(mem, irq);
+			self.deliver_fault(mem, &checkpoint);
+			self.history_note(Some(irq));
+			self.timing.flush_queue();
+			cycles += HW_INTERRUPT_CYCLES;
+			if !self.trap_hits.is_empty()
+			{
+				/* Let the debugger stop on the first instruction of the handler */
+				return cycles
+			}
 		}
 
-		if (self.cs == CS_BIOS_TRAP1) || (self.cs == CS_BIOS_TRAP2)
+		/* Protected mode code may use these selectors too */
+		if !self.protected_mode() && ((self.cs == CS_BIOS_TRAP1) || (self.cs == CS_BIOS_TRAP2))
 		{
 			bios.cpu_trap(self, mem, hw);
 			/* IP should point to an 'IRET' instruction or to a far call, 
 			 * which will run after that. */
+			self.timing.flush_queue();
 		}
 
+		self.history_note(None);
+		/* What a faulting instruction is rolled back to */
+		let checkpoint = self.checkpoint();
+		let cur_cs = self.cs;
 		let cur_ip = self.ip;
-		let instruction = self.next_non_prefix_instruction(mem, cur_ip);
+		self.instruction_ip = cur_ip;
+		let prefixed = self.fetch_instruction(mem, cur_ip);
+		self.handle_prefixes(&prefixed);
+		let prefixes = prefixed.prefixes;
+		let instruction = prefixed.sized;
 
 		let mut delayed_ip_update = false;
 		if self.rep_prefix == None
@@ -60,11 +121,11 @@ impl CPU
 			delayed_ip_update = true;
 		}
 		
-		match instruction.instruction
+		let exec_cycles = match instruction.instruction
 		{
 			Instruction::NoOperand(op) => self.run_noop_ins(mem, op),
-			Instruction::ImplicitBOperand(op) => self.run_imgop_ins(mem, op, &ImplicitBOperand::DSSI, &ImplicitBOperand::ESDI),
-			Instruction::ImplicitWOperand(op) => self.run_imgop_ins(mem, op, &ImplicitWOperand::DSSI, &ImplicitWOperand::ESDI),
+			Instruction::ImplicitBOperand(op) => self.run_imgop_ins(mem, hw, op, &ImplicitBOperand::DSSI, &ImplicitBOperand::ESDI),
+			Instruction::ImplicitWOperand(op) => self.run_imgop_ins(mem, hw, op, &ImplicitWOperand::DSSI, &ImplicitWOperand::ESDI),
 			Instruction::TwoWOperands(op, a, b) => self.run_twop_ins(mem, op, a, b),
 			Instruction::TwoBOperands(op, a, b) => self.run_tbop_ins(mem, op, a, b),
 			Instruction::SingleBOperand(op, a) => self.run_sbop_ins(mem, op, a),
@@ -76,21 +137,50 @@ impl CPU
 			Instruction::ShiftRotateB(op, cnt, a) => self.run_srgop_ins(mem, op, cnt, a),
 			Instruction::ShiftRotateW(op, cnt, a) => self.run_srgop_ins(mem, op, cnt, a),
 			Instruction::SingleWImmFCOperand(op, a) => self.run_swfcop_ins(mem, op, a),
+			Instruction::ThreeWOperands(op, a, b, c) => self.run_thwop_ins(mem, op, a, b, c),
+			Instruction::Enter(size, level) => self.run_enter_ins(mem, size, level),
+			Instruction::System(op, a) => self.run_system_ins(mem, op, a),
+			Instruction::Fpu(op, a) => self.run_fpu_ins(mem, op, a),
 			Instruction::Prefix(_) => unreachable!(),
+			Instruction::Invalid if self.timing.model.has_exceptions() =>
+			{
+				cpu_print!("Invalid opcode at {:04x}:{:04x}", cur_cs, cur_ip);
+				/* Even with a REP prefix: IP is the one of the handler */
+				self.rep_prefix = None;
+				delayed_ip_update = false;
+				self.raise_exception(mem, 6);
+				short_imm_cycles(&SingleBImmOperandOpCode::INT, true)
+			}
 			Instruction::Invalid =>
 			{
-				cpu_print!("Invalid instruction");
-				self.state = CPUState::Crashed
+				/* The 8086 has no invalid opcodes, but not all of them are
+				 * emulated */
+				cpu_print!("Unsupported instruction");
+				self.state = CPUState::Crashed;
+				0
 			}
 		};
 
+		if self.deliver_fault(mem, &checkpoint)
+		{
+			/* Like a jump to the handler */
+			self.rep_prefix = None;
+			delayed_ip_update = false;
+		}
+
+		let repeat = delayed_ip_update && self.rep_prefix != None;
 		if delayed_ip_update && self.rep_prefix == None
 		{
 			/* The current instruction got out of the rep prefix; update IP */
 			self.ip += instruction.size as u16;
 		}
 
+		let jumped = !repeat && (self.cs != cur_cs || self.ip != cur_ip.wrapping_add(instruction.size));
+		cycles += self.instruction_cycles(instruction.size, prefixes, exec_cycles, repeat, jumped);
+
 		self.segment_override_prefix = None;
 		self.rep_prefix = None;
+
+		cycles
 	}
 }
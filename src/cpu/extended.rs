@@ -0,0 +1,1437 @@
+use std::cmp::Ordering;
+
+/* Software implementation of the 80-bit extended precision format of the
+ * 8087: a sign, a 15-bit biased exponent and a 64-bit significand whose
+ * integer bit is explicit. Arithmetic is correctly rounded according to the
+ * precision and rounding control of the coprocessor; the exceptions raised
+ * along the way are accumulated in a Context */
+
+/* Exception flags, in the layout of the status and control words */
+pub const FE_INVALID: u16 = 0x01;
+pub const FE_DENORMAL: u16 = 0x02;
+pub const FE_ZERO_DIVIDE: u16 = 0x04;
+pub const FE_OVERFLOW: u16 = 0x08;
+pub const FE_UNDERFLOW: u16 = 0x10;
+pub const FE_PRECISION: u16 = 0x20;
+pub const FE_ALL: u16 = 0x3f;
+
+const BIAS: i32 = 16383;
+const MAX_EXPONENT: u16 = 0x7fff;
+const INTEGER_BIT: u64 = 1 << 63;
+const QUIET_BIT: u64 = 1 << 62;
+/* Unmasked overflows and underflows deliver their result with the exponent
+ * wrapped around by this amount, for the handler to scale it back */
+const WRAP_BIAS: i32 = 24576;
+/* 10^18: packed BCD values have 18 digits */
+const BCD_LIMIT: u128 = 1_000_000_000_000_000_000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rounding
+{
+	Nearest,
+	Down,
+	Up,
+	Chop
+}
+
+/* Destination format of a rounding; exponents are unbiased */
+struct Format
+{
+	precision: u32,
+	emin: i32,
+	emax: i32
+}
+
+const SINGLE: Format = Format { precision: 24, emin: -126, emax: 127 };
+const DOUBLE: Format = Format { precision: 53, emin: -1022, emax: 1023 };
+const EXTENDED: Format = Format { precision: 64, emin: -16382, emax: 16383 };
+
+pub struct Context
+{
+	pub rounding: Rounding,
+	/* Significand bits of arithmetic results: 24, 53 or 64 */
+	pub precision: u32,
+	/* Exceptions the coprocessor handles itself */
+	pub masks: u16,
+	/* Exceptions raised so far */
+	pub exceptions: u16
+}
+
+impl Context
+{
+	/* Rounding, precision and masks from a control word */
+	pub fn new(control: u16) -> Context
+	{
+		Context
+		{
+			rounding: match (control >> 10) & 3
+			{
+				0 => Rounding::Nearest,
+				1 => Rounding::Down,
+				2 => Rounding::Up,
+				_ => Rounding::Chop
+			},
+			precision: match (control >> 8) & 3
+			{
+				0 => 24,
+				2 => 53,
+				_ => 64
+			},
+			masks: control & FE_ALL,
+			exceptions: 0
+		}
+	}
+
+	pub fn raise(&mut self, exceptions: u16)
+	{
+		self.exceptions |= exceptions
+	}
+
+	fn masked(&self, exception: u16) -> bool
+	{
+		self.masks & exception != 0
+	}
+
+	/* Unmasked invalid operations, denormal operands and divisions by zero
+	 * leave the destination unchanged */
+	pub fn aborts(&self) -> bool
+	{
+		self.exceptions & !self.masks & (FE_INVALID | FE_DENORMAL | FE_ZERO_DIVIDE) != 0
+	}
+
+	/* Unmasked overflows and underflows prevent memory stores too */
+	fn prevents_store(&self) -> bool
+	{
+		self.aborts() || self.exceptions & !self.masks & (FE_OVERFLOW | FE_UNDERFLOW) != 0
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Class
+{
+	Zero,
+	Denormal,
+	Normal,
+	/* Nonzero exponent without integer bit */
+	Unnormal,
+	Infinity,
+	NaN
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Extended
+{
+	pub sign: bool,
+	pub exponent: u16,
+	pub mantissa: u64
+}
+
+/* A finite nonzero value, mantissa * 2^(exponent - 63), normalized */
+#[derive(Clone, Copy)]
+struct Unpacked
+{
+	sign: bool,
+	exponent: i32,
+	mantissa: u64
+}
+
+/* Shifts right, keeping in the lowest bit whether 1 bits were lost */
+fn shift_right_jamming(x: u128, count: u32) -> u128
+{
+	if count == 0
+	{
+		x
+	}
+	else if count >= 128
+	{
+		(x != 0) as u128
+	}
+	else
+	{
+		(x >> count) | ((x & ((1 << count) - 1) != 0) as u128)
+	}
+}
+
+fn rounds_away(rounding: Rounding, sign: bool, odd: bool, half: bool, sticky: bool) -> bool
+{
+	match rounding
+	{
+		Rounding::Nearest => half && (sticky || odd),
+		Rounding::Up => (half || sticky) && !sign,
+		Rounding::Down => (half || sticky) && sign,
+		Rounding::Chop => false
+	}
+}
+
+/* Rounds sig * 2^(exponent - 127) to 'precision' bits within the exponent
+ * range of 'format'. 'wrap' tells whether unmasked overflows and underflows
+ * deliver a result with a wrapped exponent, as register destinations do.
+ * The result is (exponent, mantissa) with the significand bits left aligned:
+ * zero has no mantissa bit set, infinity an exponent above emax, and
+ * denormals no integer bit, at exponent emin */
+fn round(ctx: &mut Context, format: &Format, precision: u32, sign: bool, exponent: i32, sig: u128, wrap: bool) -> (i32, u64)
+{
+	if sig == 0
+	{
+		return (0, 0)
+	}
+	let lz = sig.leading_zeros();
+	let mut sig = sig << lz;
+	let mut exponent = exponent - lz as i32;
+
+	let tiny = exponent < format.emin;
+	let wrap_underflow = tiny && wrap && !ctx.masked(FE_UNDERFLOW) && exponent + WRAP_BIAS >= format.emin;
+	if wrap_underflow
+	{
+		exponent += WRAP_BIAS;
+	}
+	else if tiny
+	{
+		sig = shift_right_jamming(sig, (format.emin - exponent) as u32);
+		exponent = format.emin;
+	}
+
+	let shift = 128 - precision;
+	let rest = sig & ((1 << shift) - 1);
+	let half = 1u128 << (shift - 1);
+	let mut q = sig >> shift;
+	if rounds_away(ctx.rounding, sign, q & 1 != 0, rest >= half, rest & (half - 1) != 0)
+	{
+		q += 1;
+		if q >> precision != 0
+		{
+			q >>= 1;
+			exponent += 1;
+		}
+	}
+
+	let inexact = rest != 0;
+	if tiny && (inexact || !ctx.masked(FE_UNDERFLOW))
+	{
+		ctx.raise(FE_UNDERFLOW);
+	}
+	if inexact
+	{
+		ctx.raise(FE_PRECISION);
+	}
+
+	if exponent > format.emax
+	{
+		ctx.raise(FE_OVERFLOW);
+		if wrap && !ctx.masked(FE_OVERFLOW) && exponent - WRAP_BIAS <= format.emax
+		{
+			exponent -= WRAP_BIAS;
+		}
+		else
+		{
+			ctx.raise(FE_PRECISION);
+			let to_infinity = match ctx.rounding
+			{
+				Rounding::Nearest => true,
+				Rounding::Up => !sign,
+				Rounding::Down => sign,
+				Rounding::Chop => false
+			};
+			return if to_infinity
+			{
+				(format.emax + 1, INTEGER_BIT)
+			}
+			else
+			{
+				(format.emax, !0 << (64 - precision))
+			}
+		}
+	}
+
+	(exponent, (q as u64) << (64 - precision))
+}
+
+/* Integer square root and remainder */
+fn isqrt(n: u128) -> (u128, u128)
+{
+	let mut x = n;
+	let mut root = 0u128;
+	let mut bit = 1u128 << 126;
+	while bit > x
+	{
+		bit >>= 2;
+	}
+	while bit != 0
+	{
+		if x >= root + bit
+		{
+			x -= root + bit;
+			root = (root >> 1) + bit;
+		}
+		else
+		{
+			root >>= 1;
+		}
+		bit >>= 2;
+	}
+	(root, x)
+}
+
+/* Intermediate values of the transcendental functions, sig * 2^(exponent -
+ * 127) with the top bit of sig set, or zero. Operations truncate; twice the
+ * precision of the format absorbs their errors along the series */
+#[derive(Clone, Copy)]
+struct Wide
+{
+	sign: bool,
+	exponent: i32,
+	sig: u128
+}
+
+const WIDE_ZERO: Wide = Wide { sign: false, exponent: 0, sig: 0 };
+const WIDE_ONE: Wide = Wide { sign: false, exponent: 0, sig: 1 << 127 };
+const WIDE_LN_2: Wide = Wide { sign: false, exponent: -1, sig: 0xb17217f7d1cf79abc9e3b39803f2f6af };
+const WIDE_PI: Wide = Wide { sign: false, exponent: 1, sig: 0xc90fdaa22168c234c4c6628b80dc1cd1 };
+const WIDE_SQRT_2: Wide = Wide { sign: false, exponent: 0, sig: 0xb504f333f9de6484597d89b3754abe9f };
+
+/* atan(k / 8) for k from 0 to 8 */
+const ATAN_EIGHTHS: [Wide; 9] =
+[
+	WIDE_ZERO,
+	Wide { sign: false, exponent: -4, sig: 0xfeadd4d5617b6e32c897989f3e888ef7 },
+	Wide { sign: false, exponent: -3, sig: 0xfadbafc96406eb156dc79ef5f7a217e5 },
+	Wide { sign: false, exponent: -2, sig: 0xb7b0ca0f26f784738aa32122dcfe4483 },
+	Wide { sign: false, exponent: -2, sig: 0xed63382b0dda7b456fe445ecbc3a8d03 },
+	Wide { sign: false, exponent: -1, sig: 0x8f005d5ef7f59f9b5c835e1665c43747 },
+	Wide { sign: false, exponent: -1, sig: 0xa4bc7d1934f7092419a87f2a457dac9e },
+	Wide { sign: false, exponent: -1, sig: 0xb8053e2bc2319e73cb2da55210a4443d },
+	Wide { sign: false, exponent: -1, sig: 0xc90fdaa22168c234c4c6628b80dc1cd1 }
+];
+
+/* High half of the 256-bit product */
+fn mul_high(a: u128, b: u128) -> u128
+{
+	let mask = (1u128 << 64) - 1;
+	let (ah, al, bh, bl) = (a >> 64, a & mask, b >> 64, b & mask);
+	let (low, middle1, middle2) = (al * bl, al * bh, ah * bl);
+	let carry = ((low >> 64) + (middle1 & mask) + (middle2 & mask)) >> 64;
+	ah * bh + (middle1 >> 64) + (middle2 >> 64) + carry
+}
+
+impl Wide
+{
+	fn new(sign: bool, exponent: i32, sig: u128) -> Wide
+	{
+		if sig == 0
+		{
+			return Wide { sign: sign, .. WIDE_ZERO }
+		}
+		let lz = sig.leading_zeros();
+		Wide { sign: sign, exponent: exponent - lz as i32, sig: sig << lz }
+	}
+
+	fn from_unpacked(a: &Unpacked) -> Wide
+	{
+		Wide { sign: a.sign, exponent: a.exponent, sig: (a.mantissa as u128) << 64 }
+	}
+
+	fn from_integer(value: u64) -> Wide
+	{
+		Wide::new(false, 127, value as u128)
+	}
+
+	fn is_zero(&self) -> bool
+	{
+		self.sig == 0
+	}
+
+	fn negate(self) -> Wide
+	{
+		Wide { sign: !self.sign, .. self }
+	}
+
+	fn abs(self) -> Wide
+	{
+		Wide { sign: false, .. self }
+	}
+
+	/* Multiplies by 2^count */
+	fn scale(self, count: i32) -> Wide
+	{
+		Wide { exponent: self.exponent + count, .. self }
+	}
+
+	/* Whether the term is too small to change the sum of a series */
+	fn negligible(&self, sum: &Wide) -> bool
+	{
+		self.is_zero() || self.exponent < sum.exponent - 130
+	}
+
+	/* Magnitude rounded to the nearest integer, for magnitudes below 2^63 */
+	fn nearest_integer(&self) -> u64
+	{
+		if self.is_zero() || self.exponent < -1
+		{
+			0
+		}
+		else
+		{
+			(((self.sig >> (126 - self.exponent)) + 1) >> 1) as u64
+		}
+	}
+
+	fn add(self, other: Wide) -> Wide
+	{
+		if self.is_zero()
+		{
+			return other
+		}
+		if other.is_zero()
+		{
+			return self
+		}
+		let (big, small) = if (self.exponent, self.sig) >= (other.exponent, other.sig) { (self, other) } else { (other, self) };
+		let shift = (big.exponent - small.exponent) as u32;
+		let small_sig = if shift >= 128 { 0 } else { small.sig >> shift };
+		if self.sign == other.sign
+		{
+			Wide::new(big.sign, big.exponent + 1, (big.sig >> 1) + (small_sig >> 1))
+		}
+		else
+		{
+			Wide::new(big.sign, big.exponent, big.sig - small_sig)
+		}
+	}
+
+	fn sub(self, other: Wide) -> Wide
+	{
+		self.add(other.negate())
+	}
+
+	fn mul(self, other: Wide) -> Wide
+	{
+		Wide::new(self.sign != other.sign, self.exponent + other.exponent + 1, mul_high(self.sig, other.sig))
+	}
+
+	fn div(self, other: Wide) -> Wide
+	{
+		/* One quotient bit at a time, from the units; 'carry' is the bit
+		 * shifted out of the remainder */
+		let (mut rest, mut quotient, mut carry) = (self.sig, 0u128, false);
+		for _ in 0 .. 128
+		{
+			quotient <<= 1;
+			if carry || rest >= other.sig
+			{
+				rest = rest.wrapping_sub(other.sig);
+				quotient |= 1;
+			}
+			carry = rest >> 127 != 0;
+			rest <<= 1;
+		}
+		Wide::new(self.sign != other.sign, self.exponent - other.exponent, quotient)
+	}
+
+	/* e^x - 1 = x + x^2/2! + x^3/3! + ..., for x between -1 and 1 */
+	fn exp_minus_1(self) -> Wide
+	{
+		let (mut sum, mut term, mut n) = (self, self, 1);
+		loop
+		{
+			n += 1;
+			term = term.mul(self).div(Wide::from_integer(n));
+			if term.negligible(&sum)
+			{
+				return sum
+			}
+			sum = sum.add(term);
+		}
+	}
+
+	/* atanh(x) = x + x^3/3 + x^5/5 + ..., and atan(x) the same series with
+	 * alternating signs, for small x */
+	fn odd_series(self, alternating: bool) -> Wide
+	{
+		let square = if alternating { self.mul(self).negate() } else { self.mul(self) };
+		let (mut sum, mut power, mut n) = (self, self, 1);
+		loop
+		{
+			power = power.mul(square);
+			n += 2;
+			let term = power.div(Wide::from_integer(n));
+			if term.negligible(&sum)
+			{
+				return sum
+			}
+			sum = sum.add(term);
+		}
+	}
+
+	/* Sine and cosine of an angle between -pi/4 and pi/4 */
+	fn sin_cos(self) -> (Wide, Wide)
+	{
+		let square = self.mul(self).negate();
+		let (mut sin, mut cos) = (self, WIDE_ONE);
+		let (mut sin_term, mut cos_term, mut n) = (self, WIDE_ONE, 0);
+		loop
+		{
+			cos_term = cos_term.mul(square).div(Wide::from_integer((n + 1) * (n + 2)));
+			sin_term = sin_term.mul(square).div(Wide::from_integer((n + 2) * (n + 3)));
+			n += 2;
+			if cos_term.negligible(&cos)
+			{
+				return (sin, cos)
+			}
+			sin = sin.add(sin_term);
+			cos = cos.add(cos_term);
+		}
+	}
+
+	/* Arctangent of a value between 0 and 1, from the nearest multiple c of
+	 * 1/8: atan(x) = atan(c) + atan((x - c) / (1 + x c)) */
+	fn atan(self) -> Wide
+	{
+		let k = self.scale(3).nearest_integer();
+		let c = Wide::from_integer(k).scale(-3);
+		let rest = self.sub(c).div(WIDE_ONE.add(self.mul(c)));
+		ATAN_EIGHTHS[k as usize].add(rest.odd_series(true))
+	}
+
+	/* Base 2 logarithm of a positive value m 2^e, with m between sqrt(2)/2
+	 * and sqrt(2): e + 2 atanh((m - 1) / (m + 1)) / ln(2) */
+	fn log2(self) -> Wide
+	{
+		let (e, m) = if self.sig > WIDE_SQRT_2.sig
+		{
+			(self.exponent + 1, Wide { exponent: -1, .. self })
+		}
+		else
+		{
+			(self.exponent, Wide { exponent: 0, .. self })
+		};
+		let log = m.sub(WIDE_ONE).div(m.add(WIDE_ONE)).odd_series(false).scale(1).div(WIDE_LN_2);
+		Wide { sign: e < 0, .. Wide::from_integer(e.unsigned_abs() as u64) }.add(log)
+	}
+
+	/* log2(1 + x) for x above -1, without losing the precision of small x to
+	 * the addition */
+	fn log2_1p(self) -> Wide
+	{
+		if self.exponent < -2
+		{
+			self.div(self.add(WIDE_ONE.scale(1))).odd_series(false).scale(1).div(WIDE_LN_2)
+		}
+		else
+		{
+			self.add(WIDE_ONE).log2()
+		}
+	}
+
+	fn to_extended(self, ctx: &mut Context) -> Extended
+	{
+		if self.is_zero()
+		{
+			Extended::zero(self.sign)
+		}
+		else
+		{
+			Extended::round_pack(ctx, self.sign, self.exponent, self.sig)
+		}
+	}
+}
+
+impl Unpacked
+{
+	/* Magnitude rounded to an integer, and whether it was inexact; huge
+	 * values saturate */
+	fn integer_magnitude(&self, rounding: Rounding) -> (u128, bool)
+	{
+		if self.exponent >= 63
+		{
+			let shift = (self.exponent - 63) as u32;
+			return if shift <= 64 { ((self.mantissa as u128) << shift, false) } else { (!0, false) }
+		}
+		let shift = (63 - self.exponent) as u32;
+		let (q, half, sticky) = if shift > 65
+		{
+			(0, false, true)
+		}
+		else
+		{
+			let m = self.mantissa as u128;
+			(m >> shift, (m >> (shift - 1)) & 1 != 0, m & ((1 << (shift - 1)) - 1) != 0)
+		};
+		let up = rounds_away(rounding, self.sign, q & 1 != 0, half, sticky);
+		(q + up as u128, half || sticky)
+	}
+}
+
+impl Extended
+{
+	pub fn zero(sign: bool) -> Extended
+	{
+		Extended { sign: sign, exponent: 0, mantissa: 0 }
+	}
+
+	pub fn one() -> Extended
+	{
+		Extended { sign: false, exponent: BIAS as u16, mantissa: INTEGER_BIT }
+	}
+
+	pub fn infinity(sign: bool) -> Extended
+	{
+		Extended { sign: sign, exponent: MAX_EXPONENT, mantissa: INTEGER_BIT }
+	}
+
+	/* The masked response to invalid operations */
+	pub fn indefinite() -> Extended
+	{
+		Extended { sign: true, exponent: MAX_EXPONENT, mantissa: INTEGER_BIT | QUIET_BIT }
+	}
+
+	/* Constants of FLDPI, FLDL2T, FLDL2E, FLDLG2 and FLDLN2 */
+	pub fn pi() -> Extended
+	{
+		Extended { sign: false, exponent: 0x4000, mantissa: 0xc90fdaa22168c235 }
+	}
+
+	pub fn log2_10() -> Extended
+	{
+		Extended { sign: false, exponent: 0x4000, mantissa: 0xd49a784bcd1b8afe }
+	}
+
+	pub fn log2_e() -> Extended
+	{
+		Extended { sign: false, exponent: 0x3fff, mantissa: 0xb8aa3b295c17f0bc }
+	}
+
+	pub fn log10_2() -> Extended
+	{
+		Extended { sign: false, exponent: 0x3ffd, mantissa: 0x9a209a84fbcff799 }
+	}
+
+	pub fn ln_2() -> Extended
+	{
+		Extended { sign: false, exponent: 0x3ffe, mantissa: 0xb17217f7d1cf79ac }
+	}
+
+	pub fn class(&self) -> Class
+	{
+		if self.exponent == MAX_EXPONENT
+		{
+			if self.mantissa << 1 == 0 { Class::Infinity } else { Class::NaN }
+		}
+		else if self.mantissa == 0
+		{
+			Class::Zero
+		}
+		else if self.exponent == 0
+		{
+			Class::Denormal
+		}
+		else if self.mantissa & INTEGER_BIT == 0
+		{
+			Class::Unnormal
+		}
+		else
+		{
+			Class::Normal
+		}
+	}
+
+	pub fn is_nan(&self) -> bool
+	{
+		self.class() == Class::NaN
+	}
+
+	pub fn is_zero(&self) -> bool
+	{
+		self.class() == Class::Zero
+	}
+
+	pub fn is_infinity(&self) -> bool
+	{
+		self.class() == Class::Infinity
+	}
+
+	pub fn negate(self) -> Extended
+	{
+		Extended { sign: !self.sign, .. self }
+	}
+
+	pub fn abs(self) -> Extended
+	{
+		Extended { sign: false, .. self }
+	}
+
+	/* Little endian memory layout */
+	pub fn from_bytes(bytes: &[u8]) -> Extended
+	{
+		let mut mantissa = 0;
+		for byte in bytes[.. 8].iter().rev()
+		{
+			mantissa = mantissa << 8 | *byte as u64;
+		}
+		let high = bytes[8] as u16 | (bytes[9] as u16) << 8;
+		Extended { sign: high & 0x8000 != 0, exponent: high & MAX_EXPONENT, mantissa: mantissa }
+	}
+
+	pub fn to_bytes(self) -> [u8; 10]
+	{
+		let mut bytes = [0; 10];
+		for (i, byte) in bytes.iter_mut().take(8).enumerate()
+		{
+			*byte = (self.mantissa >> (8 * i)) as u8;
+		}
+		let high = self.exponent | if self.sign { 0x8000 } else { 0 };
+		bytes[8] = high as u8;
+		bytes[9] = (high >> 8) as u8;
+		bytes
+	}
+
+	fn unpack(&self) -> Unpacked
+	{
+		let exponent = if self.exponent == 0 { 1 } else { self.exponent as i32 } - BIAS;
+		let lz = self.mantissa.leading_zeros();
+		Unpacked { sign: self.sign, exponent: exponent - lz as i32, mantissa: self.mantissa << lz }
+	}
+
+	fn pack(sign: bool, exponent: i32, mantissa: u64) -> Extended
+	{
+		if mantissa == 0
+		{
+			Extended::zero(sign)
+		}
+		else if exponent > EXTENDED.emax
+		{
+			Extended::infinity(sign)
+		}
+		else if mantissa & INTEGER_BIT == 0
+		{
+			Extended { sign: sign, exponent: 0, mantissa: mantissa }
+		}
+		else
+		{
+			Extended { sign: sign, exponent: (exponent + BIAS) as u16, mantissa: mantissa }
+		}
+	}
+
+	/* sig * 2^(exponent - 127), rounded as arithmetic results are */
+	fn round_pack(ctx: &mut Context, sign: bool, exponent: i32, sig: u128) -> Extended
+	{
+		let precision = ctx.precision;
+		let (exponent, mantissa) = round(ctx, &EXTENDED, precision, sign, exponent, sig, true);
+		Extended::pack(sign, exponent, mantissa)
+	}
+
+	/* A finite nonzero value, rounded to the precision control */
+	fn rounded(self, ctx: &mut Context) -> Extended
+	{
+		let a = self.unpack();
+		Extended::round_pack(ctx, a.sign, a.exponent, (a.mantissa as u128) << 64)
+	}
+
+	fn check_denormal(&self, ctx: &mut Context)
+	{
+		if self.class() == Class::Denormal
+		{
+			ctx.raise(FE_DENORMAL);
+		}
+	}
+
+	/* Signaling NaN operands raise an invalid operation; the result is the
+	 * quiet NaN with the larger significand */
+	pub fn nan_result(ctx: &mut Context, a: Extended, b: Extended) -> Option<Extended>
+	{
+		let nans: Vec<Extended> = [a, b].iter().cloned().filter(|x| x.is_nan()).collect();
+		if nans.iter().any(|x| x.mantissa & QUIET_BIT == 0)
+		{
+			ctx.raise(FE_INVALID);
+		}
+		nans.into_iter()
+			.max_by_key(|x| x.mantissa | QUIET_BIT)
+			.map(|x| Extended { mantissa: x.mantissa | INTEGER_BIT | QUIET_BIT, .. x })
+	}
+
+	fn invalid(ctx: &mut Context) -> Extended
+	{
+		ctx.raise(FE_INVALID);
+		Extended::indefinite()
+	}
+
+	pub fn add(self, other: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, other)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		other.check_denormal(ctx);
+
+		match (self.class(), other.class())
+		{
+			(Class::Infinity, Class::Infinity) if self.sign != other.sign => return Extended::invalid(ctx),
+			(Class::Infinity, _) => return self,
+			(_, Class::Infinity) => return other,
+			(Class::Zero, Class::Zero) =>
+			{
+				let sign = if self.sign == other.sign { self.sign } else { ctx.rounding == Rounding::Down };
+				return Extended::zero(sign)
+			}
+			(Class::Zero, _) => return other.rounded(ctx),
+			(_, Class::Zero) => return self.rounded(ctx),
+			_ => {}
+		}
+
+		let (a, b) = (self.unpack(), other.unpack());
+		let (big, small) = if (a.exponent, a.mantissa) >= (b.exponent, b.mantissa) { (a, b) } else { (b, a) };
+		/* 62 guard bits below the significand, one carry bit above */
+		let big_sig = (big.mantissa as u128) << 62;
+		let small_sig = shift_right_jamming((small.mantissa as u128) << 62, (big.exponent - small.exponent) as u32);
+		if a.sign == b.sign
+		{
+			Extended::round_pack(ctx, big.sign, big.exponent + 2, big_sig + small_sig)
+		}
+		else if big_sig == small_sig
+		{
+			Extended::zero(ctx.rounding == Rounding::Down)
+		}
+		else
+		{
+			Extended::round_pack(ctx, big.sign, big.exponent + 2, big_sig - small_sig)
+		}
+	}
+
+	pub fn sub(self, other: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, other)
+		{
+			return nan
+		}
+		self.add(other.negate(), ctx)
+	}
+
+	pub fn mul(self, other: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, other)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		other.check_denormal(ctx);
+
+		let sign = self.sign != other.sign;
+		match (self.class(), other.class())
+		{
+			(Class::Infinity, Class::Zero) | (Class::Zero, Class::Infinity) => Extended::invalid(ctx),
+			(Class::Infinity, _) | (_, Class::Infinity) => Extended::infinity(sign),
+			(Class::Zero, _) | (_, Class::Zero) => Extended::zero(sign),
+			_ =>
+			{
+				let (a, b) = (self.unpack(), other.unpack());
+				let sig = (a.mantissa as u128) * (b.mantissa as u128);
+				Extended::round_pack(ctx, sign, a.exponent + b.exponent + 1, sig)
+			}
+		}
+	}
+
+	pub fn div(self, other: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, other)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		other.check_denormal(ctx);
+
+		let sign = self.sign != other.sign;
+		match (self.class(), other.class())
+		{
+			(Class::Infinity, Class::Infinity) | (Class::Zero, Class::Zero) => Extended::invalid(ctx),
+			(Class::Infinity, _) => Extended::infinity(sign),
+			(_, Class::Infinity) | (Class::Zero, _) => Extended::zero(sign),
+			(_, Class::Zero) =>
+			{
+				ctx.raise(FE_ZERO_DIVIDE);
+				Extended::infinity(sign)
+			}
+			_ =>
+			{
+				let (a, b) = (self.unpack(), other.unpack());
+				let divisor = b.mantissa as u128;
+				/* Keeps the first quotient within 64 bits */
+				let adjust = (a.mantissa >= b.mantissa) as i32;
+				let dividend = (a.mantissa as u128) << (64 - adjust);
+				let (q1, r1) = (dividend / divisor, dividend % divisor);
+				let (q2, r2) = ((r1 << 64) / divisor, (r1 << 64) % divisor);
+				let sig = (q1 << 64) | q2 | (r2 != 0) as u128;
+				Extended::round_pack(ctx, sign, a.exponent - b.exponent - 1 + adjust, sig)
+			}
+		}
+	}
+
+	pub fn sqrt(self, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, self)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+
+		match self.class()
+		{
+			Class::Zero => self,
+			_ if self.sign => Extended::invalid(ctx),
+			Class::Infinity => self,
+			_ =>
+			{
+				let a = self.unpack();
+				/* value = mantissa * 2^e, made an even power of two */
+				let e = a.exponent - 63;
+				let shift = if e & 1 == 0 { 64 } else { 63 };
+				let (root, rest) = isqrt((a.mantissa as u128) << shift);
+				/* The root is never exactly halfway between two integers */
+				let guard = if rest > root { (1 << 63) | 1 } else { (rest != 0) as u128 };
+				Extended::round_pack(ctx, false, (e - shift) / 2 + 63, (root << 64) | guard)
+			}
+		}
+	}
+
+	/* None when unordered, which FCOM and FTST report as invalid */
+	pub fn compare(self, other: Extended, ctx: &mut Context) -> Option<Ordering>
+	{
+		if self.is_nan() || other.is_nan()
+		{
+			ctx.raise(FE_INVALID);
+			return None
+		}
+		self.check_denormal(ctx);
+		other.check_denormal(ctx);
+
+		let magnitude = |x: &Extended| -> (i32, u64)
+		{
+			if x.is_infinity() { (i32::MAX, 0) } else { let u = x.unpack(); (u.exponent, u.mantissa) }
+		};
+		Some(match (self.is_zero(), other.is_zero())
+		{
+			(true, true) => Ordering::Equal,
+			(true, false) => if other.sign { Ordering::Greater } else { Ordering::Less },
+			(false, true) => if self.sign { Ordering::Less } else { Ordering::Greater },
+			_ if self.sign != other.sign => if self.sign { Ordering::Less } else { Ordering::Greater },
+			_ =>
+			{
+				let ordering = magnitude(&self).cmp(&magnitude(&other));
+				if self.sign { ordering.reverse() } else { ordering }
+			}
+		})
+	}
+
+	/* FPREM: remainder of the truncating division by 'other', reduced by at
+	 * most 2^63 at a time. Returns the remainder, the low bits of the
+	 * quotient and whether the reduction is complete */
+	pub fn partial_remainder(self, other: Extended, ctx: &mut Context) -> (Extended, u64, bool)
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, other)
+		{
+			return (nan, 0, true)
+		}
+		self.check_denormal(ctx);
+		other.check_denormal(ctx);
+
+		match (self.class(), other.class())
+		{
+			(Class::Infinity, _) | (_, Class::Zero) => return (Extended::invalid(ctx), 0, true),
+			(_, Class::Infinity) | (Class::Zero, _) => return (self, 0, true),
+			_ => {}
+		}
+
+		let (a, b) = (self.unpack(), other.unpack());
+		let difference = a.exponent - b.exponent;
+		if difference < 0
+		{
+			return (self, 0, true)
+		}
+		let steps = if difference < 64 { difference } else { 63 };
+		let dividend = (a.mantissa as u128) << steps;
+		let (quotient, rest) = (dividend / b.mantissa as u128, dividend % b.mantissa as u128);
+		/* Exact, whatever the precision control */
+		let (exponent, mantissa) = round(ctx, &EXTENDED, 64, a.sign, b.exponent + difference - steps, rest << 64, true);
+		(Extended::pack(a.sign, exponent, mantissa), quotient as u64, difference < 64)
+	}
+
+	/* FSCALE: multiplies by 2 to the power of 'other', truncated */
+	pub fn scale(self, other: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, other)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		other.check_denormal(ctx);
+
+		match (self.class(), other.class())
+		{
+			(Class::Infinity, Class::Infinity) if other.sign => Extended::invalid(ctx),
+			(Class::Zero, Class::Infinity) if !other.sign => Extended::invalid(ctx),
+			(Class::Zero, _) | (Class::Infinity, _) => self,
+			(_, Class::Infinity) => if other.sign { Extended::zero(self.sign) } else { Extended::infinity(self.sign) },
+			(_, Class::Zero) => self.rounded(ctx),
+			_ =>
+			{
+				let a = self.unpack();
+				let b = other.unpack();
+				/* Anything larger overflows or underflows anyway */
+				let (magnitude, _) = b.integer_magnitude(Rounding::Chop);
+				let count = if magnitude > 0x100000 { 0x100000 } else { magnitude as i32 };
+				let exponent = if b.sign { a.exponent - count } else { a.exponent + count };
+				Extended::round_pack(ctx, a.sign, exponent, (a.mantissa as u128) << 64)
+			}
+		}
+	}
+
+	/* FXTRACT: (unbiased exponent, significand between 1 and 2) */
+	pub fn extract(self, ctx: &mut Context) -> (Extended, Extended)
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, self)
+		{
+			return (nan, nan)
+		}
+		self.check_denormal(ctx);
+
+		match self.class()
+		{
+			Class::Infinity => (Extended::infinity(false), self),
+			Class::Zero =>
+			{
+				ctx.raise(FE_ZERO_DIVIDE);
+				(Extended::infinity(true), self)
+			}
+			_ =>
+			{
+				let a = self.unpack();
+				(Extended::from_i64(a.exponent as i64), Extended::pack(a.sign, 0, a.mantissa))
+			}
+		}
+	}
+
+	/* FRNDINT */
+	pub fn round_to_integer(self, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, self)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+
+		match self.class()
+		{
+			Class::Zero | Class::Infinity => self,
+			_ =>
+			{
+				let a = self.unpack();
+				if a.exponent >= 63
+				{
+					return Extended::pack(a.sign, a.exponent, a.mantissa)
+				}
+				let (magnitude, inexact) = a.integer_magnitude(ctx.rounding);
+				if inexact
+				{
+					ctx.raise(FE_PRECISION);
+				}
+				Extended::from_magnitude(a.sign, magnitude as u64)
+			}
+		}
+	}
+
+	fn from_magnitude(sign: bool, magnitude: u64) -> Extended
+	{
+		if magnitude == 0
+		{
+			return Extended::zero(sign)
+		}
+		let lz = magnitude.leading_zeros();
+		Extended::pack(sign, 63 - lz as i32, magnitude << lz)
+	}
+
+	/* Integers of any size are exact */
+	pub fn from_i64(value: i64) -> Extended
+	{
+		Extended::from_magnitude(value < 0, value.unsigned_abs())
+	}
+
+	/* Integer of 'bits' bits, rounded as the control word tells; None when
+	 * an unmasked invalid operation prevents the store */
+	pub fn to_integer(self, bits: u32, ctx: &mut Context) -> Option<i64>
+	{
+		let limit = 1u128 << (bits - 1);
+		let magnitude = match self.class()
+		{
+			Class::NaN | Class::Infinity => None,
+			Class::Zero => Some(0),
+			_ =>
+			{
+				self.check_denormal(ctx);
+				let (magnitude, inexact) = self.unpack().integer_magnitude(ctx.rounding);
+				if inexact
+				{
+					ctx.raise(FE_PRECISION);
+				}
+				if magnitude < limit || (self.sign && magnitude == limit) { Some(magnitude) } else { None }
+			}
+		};
+		match magnitude
+		{
+			Some(magnitude) => Some(if self.sign { -(magnitude as i128) as i64 } else { magnitude as i64 }),
+			None =>
+			{
+				ctx.raise(FE_INVALID);
+				/* The integer indefinite */
+				if ctx.masked(FE_INVALID) { Some(-(limit as i128) as i64) } else { None }
+			}
+		}
+	}
+
+	/* Packed BCD: 18 digits, two per byte from the least significant one,
+	 * and the sign in the top bit of the tenth byte */
+	pub fn from_bcd(bytes: &[u8]) -> Extended
+	{
+		let mut value = 0i64;
+		for byte in bytes[.. 9].iter().rev()
+		{
+			value = value * 100 + (byte >> 4) as i64 * 10 + (byte & 0xf) as i64;
+		}
+		let sign = bytes[9] & 0x80 != 0;
+		Extended::from_magnitude(sign, value as u64)
+	}
+
+	pub fn to_bcd(self, ctx: &mut Context) -> Option<[u8; 10]>
+	{
+		let magnitude = match self.class()
+		{
+			Class::NaN | Class::Infinity => None,
+			Class::Zero => Some(0),
+			_ =>
+			{
+				self.check_denormal(ctx);
+				let (magnitude, inexact) = self.unpack().integer_magnitude(ctx.rounding);
+				if inexact
+				{
+					ctx.raise(FE_PRECISION);
+				}
+				if magnitude < BCD_LIMIT { Some(magnitude) } else { None }
+			}
+		};
+		let mut bytes = [0; 10];
+		match magnitude
+		{
+			Some(mut magnitude) =>
+			{
+				for byte in bytes.iter_mut().take(9)
+				{
+					*byte = (magnitude % 10) as u8 | (((magnitude / 10) % 10) as u8) << 4;
+					magnitude /= 100;
+				}
+				if self.sign
+				{
+					bytes[9] = 0x80;
+				}
+				Some(bytes)
+			}
+			None =>
+			{
+				ctx.raise(FE_INVALID);
+				if !ctx.masked(FE_INVALID)
+				{
+					return None
+				}
+				/* The BCD indefinite */
+				bytes[7] = 0xc0;
+				bytes[8] = 0xff;
+				bytes[9] = 0xff;
+				Some(bytes)
+			}
+		}
+	}
+
+	fn from_ieee(bits: u64, format: &Format) -> Extended
+	{
+		let fraction_bits = format.precision - 1;
+		let exponent_mask = (format.emax * 2 + 1) as u64;
+		let exponent_bits = 64 - exponent_mask.leading_zeros();
+		let sign = (bits >> (fraction_bits + exponent_bits)) & 1 != 0;
+		let exponent = (bits >> fraction_bits) & exponent_mask;
+		let fraction = bits & ((1 << fraction_bits) - 1);
+
+		if exponent == exponent_mask
+		{
+			Extended { sign: sign, exponent: MAX_EXPONENT, mantissa: INTEGER_BIT | fraction << (63 - fraction_bits) }
+		}
+		else if exponent == 0
+		{
+			if fraction == 0
+			{
+				return Extended::zero(sign)
+			}
+			let lz = fraction.leading_zeros();
+			Extended::pack(sign, format.emin - fraction_bits as i32 - lz as i32 + 63, fraction << lz)
+		}
+		else
+		{
+			Extended::pack(sign, exponent as i32 - format.emax, INTEGER_BIT | fraction << (63 - fraction_bits))
+		}
+	}
+
+	/* None when an unmasked exception prevents the store */
+	fn to_ieee(self, format: &Format, ctx: &mut Context) -> Option<u64>
+	{
+		let fraction_bits = format.precision - 1;
+		let exponent_mask = (format.emax * 2 + 1) as u64;
+		let exponent_bits = 64 - exponent_mask.leading_zeros();
+		let sign = (self.sign as u64) << (fraction_bits + exponent_bits);
+		let infinity = sign | exponent_mask << fraction_bits;
+
+		match self.class()
+		{
+			Class::Zero => Some(sign),
+			Class::Infinity => Some(infinity),
+			Class::NaN =>
+			{
+				if self.mantissa & QUIET_BIT == 0
+				{
+					ctx.raise(FE_INVALID);
+					if !ctx.masked(FE_INVALID)
+					{
+						return None
+					}
+				}
+				Some(infinity | ((self.mantissa | QUIET_BIT) << 1) >> (64 - fraction_bits))
+			}
+			_ =>
+			{
+				self.check_denormal(ctx);
+				let a = self.unpack();
+				let (exponent, mantissa) = round(ctx, format, format.precision, a.sign, a.exponent, (a.mantissa as u128) << 64, false);
+				if ctx.prevents_store()
+				{
+					return None
+				}
+				let fraction = (mantissa >> (64 - format.precision)) & ((1 << fraction_bits) - 1);
+				Some(if mantissa == 0
+				{
+					sign
+				}
+				else if exponent > format.emax
+				{
+					infinity
+				}
+				else if mantissa & INTEGER_BIT == 0
+				{
+					sign | fraction
+				}
+				else
+				{
+					sign | ((exponent + format.emax) as u64) << fraction_bits | fraction
+				})
+			}
+		}
+	}
+
+	pub fn from_single(bits: u32) -> Extended
+	{
+		Extended::from_ieee(bits as u64, &SINGLE)
+	}
+
+	pub fn from_double(bits: u64) -> Extended
+	{
+		Extended::from_ieee(bits, &DOUBLE)
+	}
+
+	pub fn to_single(self, ctx: &mut Context) -> Option<u32>
+	{
+		self.to_ieee(&SINGLE, ctx).map(|bits| bits as u32)
+	}
+
+	pub fn to_double(self, ctx: &mut Context) -> Option<u64>
+	{
+		self.to_ieee(&DOUBLE, ctx)
+	}
+
+	/* F2XM1: 2^x - 1, for x between -1 and 1 */
+	pub fn exp2_minus_1(self, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, self)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+
+		match self.class()
+		{
+			Class::Zero => self,
+			Class::Infinity => Extended::invalid(ctx),
+			_ =>
+			{
+				let a = self.unpack();
+				/* The coprocessor gives undefined results out of range */
+				if a.exponent > 0 || (a.exponent == 0 && a.mantissa != INTEGER_BIT)
+				{
+					return Extended::invalid(ctx)
+				}
+				/* 2^-1 - 1 and 2^1 - 1 are exact */
+				if a.exponent == 0
+				{
+					return if a.sign { Extended::pack(true, -1, INTEGER_BIT) } else { Extended::one() }
+				}
+				Wide::from_unpacked(&a).mul(WIDE_LN_2).exp_minus_1().to_extended(ctx)
+			}
+		}
+	}
+
+	/* FYL2X: y * log2(self) */
+	pub fn y_log2_x(self, y: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, y)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		y.check_denormal(ctx);
+
+		if self.sign && !self.is_zero()
+		{
+			return Extended::invalid(ctx)
+		}
+		match (self.class(), y.class())
+		{
+			(Class::Zero, Class::Zero) | (Class::Infinity, Class::Zero) => Extended::invalid(ctx),
+			(Class::Zero, Class::Infinity) => Extended::infinity(!y.sign),
+			(Class::Zero, _) =>
+			{
+				ctx.raise(FE_ZERO_DIVIDE);
+				Extended::infinity(!y.sign)
+			}
+			(Class::Infinity, _) => Extended::infinity(y.sign),
+			_ =>
+			{
+				let log = Wide::from_unpacked(&self.unpack()).log2();
+				match y.class()
+				{
+					Class::Infinity if log.is_zero() => Extended::invalid(ctx),
+					Class::Infinity => Extended::infinity(y.sign != log.sign),
+					Class::Zero => Extended::zero(y.sign != log.sign),
+					_ => Wide::from_unpacked(&y.unpack()).mul(log).to_extended(ctx)
+				}
+			}
+		}
+	}
+
+	/* FYL2XP1: y * log2(self + 1) */
+	pub fn y_log2_x_plus_1(self, y: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, y)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		y.check_denormal(ctx);
+
+		let magnitude = match self.class()
+		{
+			Class::Zero => Ordering::Less,
+			Class::Infinity => Ordering::Greater,
+			_ => { let a = self.unpack(); (a.exponent, a.mantissa).cmp(&(0, INTEGER_BIT)) }
+		};
+		/* At most -1 */
+		if self.sign && magnitude != Ordering::Less
+		{
+			return match y.class()
+			{
+				_ if magnitude == Ordering::Greater => Extended::invalid(ctx),
+				Class::Zero => Extended::invalid(ctx),
+				Class::Infinity => Extended::infinity(!y.sign),
+				_ =>
+				{
+					ctx.raise(FE_ZERO_DIVIDE);
+					Extended::infinity(!y.sign)
+				}
+			}
+		}
+		/* log2(1 + x) has the sign of x */
+		match (self.class(), y.class())
+		{
+			(Class::Infinity, Class::Zero) | (Class::Zero, Class::Infinity) => Extended::invalid(ctx),
+			(Class::Infinity, _) => Extended::infinity(y.sign),
+			(_, Class::Infinity) => Extended::infinity(y.sign != self.sign),
+			(Class::Zero, _) | (_, Class::Zero) => Extended::zero(y.sign != self.sign),
+			_ =>
+			{
+				let log = Wide::from_unpacked(&self.unpack()).log2_1p();
+				Wide::from_unpacked(&y.unpack()).mul(log).to_extended(ctx)
+			}
+		}
+	}
+
+	/* FPATAN: the angle of the point (x, self), between -pi and pi */
+	pub fn atan2(self, x: Extended, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, x)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+		x.check_denormal(ctx);
+
+		let quarter = WIDE_PI.scale(-2);
+		let angle = match (self.class(), x.class())
+		{
+			(Class::Zero, _) if !x.sign => return self,
+			(Class::Zero, _) => WIDE_PI,
+			(Class::Infinity, Class::Infinity) => if x.sign { WIDE_PI.sub(quarter) } else { quarter },
+			(Class::Infinity, _) | (_, Class::Zero) => WIDE_PI.scale(-1),
+			(_, Class::Infinity) if !x.sign => return Extended::zero(self.sign),
+			(_, Class::Infinity) => WIDE_PI,
+			_ =>
+			{
+				let (a, b) = (Wide::from_unpacked(&self.unpack()).abs(), Wide::from_unpacked(&x.unpack()).abs());
+				let angle = if (a.exponent, a.sig) <= (b.exponent, b.sig)
+				{
+					a.div(b).atan()
+				}
+				else
+				{
+					WIDE_PI.scale(-1).sub(b.div(a).atan())
+				};
+				if x.sign { WIDE_PI.sub(angle) } else { angle }
+			}
+		};
+		Wide { sign: self.sign, .. angle }.to_extended(ctx)
+	}
+
+	/* FPTAN, for angles below 2^63 in magnitude as on the 80387; the 8087
+	 * and the 80287 only take them between 0 and pi/4 */
+	pub fn tan(self, ctx: &mut Context) -> Extended
+	{
+		if let Some(nan) = Extended::nan_result(ctx, self, self)
+		{
+			return nan
+		}
+		self.check_denormal(ctx);
+
+		match self.class()
+		{
+			Class::Zero => self,
+			Class::Infinity => Extended::invalid(ctx),
+			_ =>
+			{
+				let a = self.unpack();
+				if a.exponent >= 63
+				{
+					return Extended::invalid(ctx)
+				}
+				/* x = n pi/2 + r, with r between -pi/4 and pi/4 */
+				let x = Wide::from_unpacked(&a);
+				let half_pi = WIDE_PI.scale(-1);
+				let n = x.div(half_pi).nearest_integer();
+				let r = x.sub(Wide { sign: a.sign, .. Wide::from_integer(n).mul(half_pi) });
+				let (sin, cos) = r.sin_cos();
+				let tan = if n & 1 == 0 { sin.div(cos) } else { cos.div(sin).negate() };
+				tan.to_extended(ctx)
+			}
+		}
+	}
+}
@@ -0,0 +1,247 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::extended::*;
+	use std::cmp::Ordering;
+
+	/* All exceptions masked, 64-bit precision, rounding to the nearest */
+	const CONTROL: u16 = 0x037f;
+
+	fn x(value: f64) -> Extended
+	{
+		Extended::from_double(value.to_bits())
+	}
+
+	fn f(value: Extended) -> f64
+	{
+		let mut ctx = Context::new(CONTROL);
+		f64::from_bits(value.to_double(&mut ctx).unwrap())
+	}
+
+	#[test]
+	fn double_conversions()
+	{
+		let mut ctx = Context::new(CONTROL);
+		for value in &[1.0, -2.5, 0.1, 1e300, -1e-310, 5e-324, 0.0, -0.0]
+		{
+			assert_eq!(x(*value).to_double(&mut ctx), Some(value.to_bits()));
+		}
+		assert_eq!(Extended::one(), x(1.0));
+		assert_eq!(x(1e-310).class(), Class::Normal);
+		assert!(x(f64::INFINITY).is_infinity());
+
+		/* Single precision: 0.1 rounds, 2^-149 is the smallest denormal */
+		assert_eq!(x(0.1).to_single(&mut ctx), Some(0.1f32.to_bits()));
+		assert_eq!(ctx.exceptions, FE_PRECISION);
+		assert_eq!(Extended::from_single(1).to_double(&mut ctx), Some(2f64.powi(-149).to_bits()));
+		assert_eq!(x(1e40).to_single(&mut ctx), Some(f32::INFINITY.to_bits()));
+		assert!(ctx.exceptions & FE_OVERFLOW != 0);
+	}
+
+	#[test]
+	fn arithmetic()
+	{
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(x(1.5).add(x(2.25), &mut ctx), x(3.75));
+		assert_eq!(x(1.0).sub(x(3.0), &mut ctx), x(-2.0));
+		assert_eq!(x(1.5).mul(x(-4.0), &mut ctx), x(-6.0));
+		assert_eq!(x(1.0).div(x(8.0), &mut ctx), x(0.125));
+		assert_eq!(x(2.25).sqrt(&mut ctx), x(1.5));
+		assert_eq!(ctx.exceptions, 0);
+
+		/* 1/3 keeps 64 bits, rounded to the nearest */
+		let third = x(1.0).div(x(3.0), &mut ctx);
+		assert_eq!((third.exponent, third.mantissa), (0x3ffd, 0xaaaaaaaaaaaaaaab));
+		assert_eq!(ctx.exceptions, FE_PRECISION);
+
+		/* 1 + 2^-64 is halfway between 1 and its successor: ties to even */
+		let tiny = Extended { sign: false, exponent: 0x3fff - 64, mantissa: 1 << 63 };
+		assert_eq!(Extended::one().add(tiny, &mut ctx), Extended::one());
+		let mut up = Context::new(CONTROL | 0x0800);
+		assert_eq!(Extended::one().add(tiny, &mut up).mantissa, (1 << 63) | 1);
+
+		/* x - x is -0 only when rounding down */
+		assert_eq!(x(2.0).sub(x(2.0), &mut ctx), Extended::zero(false));
+		let mut down = Context::new(CONTROL | 0x0400);
+		assert_eq!(x(2.0).sub(x(2.0), &mut down), Extended::zero(true));
+
+		let sqrt2 = x(2.0).sqrt(&mut ctx);
+		assert_eq!((sqrt2.exponent, sqrt2.mantissa), (0x3fff, 0xb504f333f9de6484));
+	}
+
+	#[test]
+	fn precision_control()
+	{
+		/* 24 and 53-bit significands */
+		let mut single = Context::new(CONTROL & !0x0300);
+		assert_eq!(f(x(1.0).div(x(3.0), &mut single)), (1.0f32 / 3.0) as f64);
+		let mut double = Context::new(CONTROL & !0x0100);
+		assert_eq!(f(x(1.0).div(x(3.0), &mut double)), 1.0 / 3.0);
+	}
+
+	#[test]
+	fn special_values()
+	{
+		let mut ctx = Context::new(CONTROL);
+		let infinity = Extended::infinity(false);
+		assert_eq!(infinity.sub(infinity, &mut ctx), Extended::indefinite());
+		assert_eq!(ctx.exceptions, FE_INVALID);
+
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(x(-1.0).div(Extended::zero(false), &mut ctx), Extended::infinity(true));
+		assert_eq!(ctx.exceptions, FE_ZERO_DIVIDE);
+		assert!(!ctx.aborts());
+		let mut unmasked = Context::new(CONTROL & !0x4);
+		x(-1.0).div(Extended::zero(false), &mut unmasked);
+		assert!(unmasked.aborts());
+
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(x(-4.0).sqrt(&mut ctx), Extended::indefinite());
+		assert_eq!(x(1.0).compare(Extended::indefinite(), &mut ctx), None);
+
+		/* Masked overflow: infinity; unmasked: the exponent wraps around */
+		let huge = Extended { sign: false, exponent: 0x7ffe, mantissa: 1 << 63 };
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(huge.mul(x(2.0), &mut ctx), infinity);
+		assert_eq!(ctx.exceptions, FE_OVERFLOW | FE_PRECISION);
+		let mut unmasked = Context::new(CONTROL & !0x8);
+		assert_eq!(huge.mul(x(2.0), &mut unmasked).exponent, 0x7fff - 24576);
+
+		/* Masked underflow: a denormal */
+		let small = Extended { sign: false, exponent: 1, mantissa: 1 << 63 };
+		let mut ctx = Context::new(CONTROL);
+		let half = small.div(x(2.0), &mut ctx);
+		assert_eq!((half.exponent, half.mantissa, half.class()), (0, 1 << 62, Class::Denormal));
+		assert_eq!(ctx.exceptions, 0);
+		half.add(half, &mut ctx);
+		assert_eq!(ctx.exceptions, FE_DENORMAL);
+	}
+
+	#[test]
+	fn comparisons()
+	{
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(x(1.0).compare(x(2.0), &mut ctx), Some(Ordering::Less));
+		assert_eq!(x(-1.0).compare(x(-2.0), &mut ctx), Some(Ordering::Greater));
+		assert_eq!(Extended::zero(true).compare(Extended::zero(false), &mut ctx), Some(Ordering::Equal));
+		assert_eq!(Extended::infinity(true).compare(x(-1e300), &mut ctx), Some(Ordering::Less));
+		assert_eq!(ctx.exceptions, 0);
+	}
+
+	#[test]
+	fn integers()
+	{
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(Extended::from_i64(-12345), x(-12345.0));
+		assert_eq!(Extended::from_i64(i64::MIN).to_integer(64, &mut ctx), Some(i64::MIN));
+		assert_eq!(x(2.5).to_integer(16, &mut ctx), Some(2));
+		assert_eq!(x(-3.5).to_integer(16, &mut ctx), Some(-4));
+		let mut chop = Context::new(CONTROL | 0x0c00);
+		assert_eq!(x(-3.7).to_integer(16, &mut chop), Some(-3));
+		assert_eq!(x(-3.7).round_to_integer(&mut chop), x(-3.0));
+		assert_eq!(ctx.exceptions, FE_PRECISION);
+
+		/* Out of range: the integer indefinite, or nothing when unmasked */
+		assert_eq!(x(40000.0).to_integer(16, &mut ctx), Some(-32768));
+		assert_eq!(x(-32768.0).to_integer(16, &mut ctx), Some(-32768));
+		let mut unmasked = Context::new(CONTROL & !0x1);
+		assert_eq!(x(40000.0).to_integer(16, &mut unmasked), None);
+	}
+
+	#[test]
+	fn bcd()
+	{
+		let mut ctx = Context::new(CONTROL);
+		let bytes = [0x89, 0x67, 0x45, 0x23, 0x01, 0, 0, 0, 0, 0x80];
+		assert_eq!(Extended::from_bcd(&bytes), x(-123456789.0));
+		assert_eq!(x(-123456789.0).to_bcd(&mut ctx), Some(bytes));
+		assert_eq!(x(1e18).to_bcd(&mut ctx), Some([0, 0, 0, 0, 0, 0, 0, 0xc0, 0xff, 0xff]));
+		assert_eq!(ctx.exceptions, FE_INVALID);
+	}
+
+	#[test]
+	fn remainder_scale_extract()
+	{
+		let mut ctx = Context::new(CONTROL);
+		let (rest, quotient, complete) = x(17.0).partial_remainder(x(5.0), &mut ctx);
+		assert_eq!((rest, quotient & 7, complete), (x(2.0), 3, true));
+		let (rest, _, complete) = x(-7.5).partial_remainder(x(2.0), &mut ctx);
+		assert_eq!((rest, complete), (x(-1.5), true));
+
+		/* 2^100 is reduced in several steps */
+		let (mut rest, _, complete) = x(2f64.powi(100)).partial_remainder(x(3.0), &mut ctx);
+		assert!(!complete);
+		loop
+		{
+			let (next, _, complete) = rest.partial_remainder(x(3.0), &mut ctx);
+			rest = next;
+			if complete
+			{
+				break
+			}
+		}
+		assert_eq!(rest, x(1.0));
+
+		assert_eq!(x(3.0).scale(x(-2.7), &mut ctx), x(0.75));
+		assert_eq!(x(-10.0).extract(&mut ctx), (x(3.0), x(-1.25)));
+		assert_eq!(ctx.exceptions, 0);
+	}
+
+	#[test]
+	fn memory_layout()
+	{
+		let pi = Extended::pi();
+		let bytes = pi.to_bytes();
+		assert_eq!(bytes, [0x35, 0xc2, 0x68, 0x21, 0xa2, 0xda, 0x0f, 0xc9, 0x00, 0x40]);
+		assert_eq!(Extended::from_bytes(&bytes), pi);
+		assert_eq!(f(pi), ::std::f64::consts::PI);
+	}
+
+	#[test]
+	fn transcendental()
+	{
+		let mut ctx = Context::new(CONTROL);
+		let bits = |value: Extended| (value.sign, value.exponent, value.mantissa);
+
+		/* Correctly rounded at 64 bits */
+		assert_eq!(bits(x(0.5).exp2_minus_1(&mut ctx)), (false, 0x3ffd, 0xd413cccfe7799211));
+		assert_eq!(bits(x(-(2f64.powi(-40))).exp2_minus_1(&mut ctx)), (true, 0x3fd6, 0xb17217f7d191fa30));
+		assert_eq!(bits(x(1.0).tan(&mut ctx)), (false, 0x3fff, 0xc75922e5f71d2dc5));
+		assert_eq!(bits(x(1e10).tan(&mut ctx)), (true, 0x3ffe, 0x8ef0007a21fa82f2));
+		assert_eq!(bits(x(1.0).atan2(x(-3.0), &mut ctx)), (false, 0x4000, 0xb4784afefac9e110));
+		assert_eq!(bits(x(-5.0).atan2(x(3.0), &mut ctx)), (true, 0x3fff, 0x83e3634a3d353e7d));
+		assert_eq!(x(10.0).y_log2_x(x(1.0), &mut ctx), Extended::log2_10());
+		assert_eq!(bits(x(0.7).y_log2_x(x(3.0), &mut ctx)), (true, 0x3fff, 0xc59899e70ddce30e));
+		assert_eq!(bits(x(2f64.powi(-30)).y_log2_x_plus_1(x(1.0), &mut ctx)), (false, 0x3fe1, 0xb8aa3b27eac37a6d));
+		assert_eq!(ctx.exceptions, FE_PRECISION);
+
+		/* Exact results */
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(x(-1.0).exp2_minus_1(&mut ctx), x(-0.5));
+		assert_eq!(x(8.0).y_log2_x(x(-2.0), &mut ctx), x(-6.0));
+		assert_eq!(x(1.0).y_log2_x_plus_1(x(3.0), &mut ctx), x(3.0));
+		assert_eq!(x(-0.0).atan2(x(5.0), &mut ctx), x(-0.0));
+		assert_eq!(ctx.exceptions, 0);
+
+		/* Out of range operands */
+		for result in &[x(1.5).exp2_minus_1(&mut ctx), x(2f64.powi(63)).tan(&mut ctx),
+			Extended::infinity(false).tan(&mut ctx), x(-1.0).y_log2_x(x(1.0), &mut ctx),
+			x(-2.0).y_log2_x_plus_1(x(1.0), &mut ctx), x(1.0).y_log2_x(Extended::infinity(false), &mut ctx)]
+		{
+			assert_eq!(*result, Extended::indefinite());
+		}
+		assert_eq!(ctx.exceptions, FE_INVALID);
+		let mut ctx = Context::new(CONTROL);
+		assert_eq!(x(0.0).y_log2_x(x(2.0), &mut ctx), Extended::infinity(true));
+		assert_eq!(x(-1.0).y_log2_x_plus_1(x(-2.0), &mut ctx), Extended::infinity(false));
+		assert_eq!(ctx.exceptions, FE_ZERO_DIVIDE);
+		let mut ctx = Context::new(CONTROL);
+		let huge = Extended { sign: false, exponent: 0x7ffe, mantissa: 1 << 63 };
+		assert_eq!(x(4.0).y_log2_x(huge, &mut ctx), Extended::infinity(false));
+		assert_eq!(ctx.exceptions & FE_OVERFLOW, FE_OVERFLOW);
+
+		/* Quadrants and infinities */
+		assert_eq!(x(0.0).atan2(x(-1.0), &mut ctx), Extended::pi());
+		assert_eq!(f(Extended::infinity(true).atan2(Extended::infinity(true), &mut ctx)), -0.75 * ::std::f64::consts::PI);
+	}
+}
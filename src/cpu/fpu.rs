@@ -0,0 +1,805 @@
+use super::base::*;
+use super::instruction::*;
+use super::reg_access::*;
+use super::operand_access::OperandAccess;
+use super::extended::*;
+use super::protected::{MSW_MP, MSW_EM, MSW_TS};
+use super::timing::esc_cycles;
+use super::super::mem::Memory;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+use std::cmp::Ordering;
+
+/* The 8087 numeric coprocessor, or the 80287 next to an 80286. It runs the
+ * ESC instructions the CPU decodes; its computations are instantaneous */
+
+pub const EXC_NO_COPROCESSOR: u8 = 7;
+
+/* Status word */
+const STATUS_ERROR_SUMMARY: u16 = 0x80;
+const STATUS_C0: u16 = 0x0100;
+const STATUS_C1: u16 = 0x0200;
+const STATUS_C2: u16 = 0x0400;
+const STATUS_TOP_SHIFT: u16 = 11;
+const STATUS_TOP: u16 = 0x3800;
+const STATUS_C3: u16 = 0x4000;
+const STATUS_BUSY: u16 = 0x8000;
+const STATUS_CONDITION: u16 = STATUS_C0 | STATUS_C1 | STATUS_C2 | STATUS_C3;
+
+/* Control word: all exceptions masked, 64-bit precision, rounding to the
+ * nearest */
+const CONTROL_INIT: u16 = 0x037f;
+/* Interrupt enable mask of the 8087: no interrupt at all when set */
+const CONTROL_IEM: u16 = 0x80;
+
+/* Register tags */
+const TAG_VALID: u16 = 0;
+const TAG_ZERO: u16 = 1;
+const TAG_SPECIAL: u16 = 2;
+const TAG_EMPTY: u16 = 3;
+
+/* FSTENV and FSAVE area */
+const ENVIRONMENT_SIZE: u16 = 14;
+const REGISTER_SIZE: u16 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fpu
+{
+	/* Physical registers; ST(i) is register TOP + i */
+	registers: [Extended; 8],
+	top: u8,
+	pub control: u16,
+	/* TOP excluded */
+	status: u16,
+	tags: u16,
+	/* Last instruction other than the control ones, and its memory operand,
+	 * for exception handlers; segments in real mode, selectors in protected
+	 * mode. The opcode leaves out the ESC bits */
+	instruction_selector: u16,
+	instruction_offset: u16,
+	opcode: u16,
+	operand_selector: u16,
+	operand_offset: u16,
+	/* The INT output went up, which the PC routes to the NMI */
+	interrupt: bool
+}
+
+impl Fpu
+{
+	pub fn new() -> Fpu
+	{
+		Fpu
+		{
+			registers: [Extended::zero(false); 8],
+			top: 0,
+			control: CONTROL_INIT,
+			status: 0,
+			tags: 0xffff,
+			instruction_selector: 0,
+			instruction_offset: 0,
+			opcode: 0,
+			operand_selector: 0,
+			operand_offset: 0,
+			interrupt: false
+		}
+	}
+
+	/* FNINIT; FSAVE ends with it too */
+	pub fn init(&mut self)
+	{
+		*self = Fpu { registers: self.registers, .. Fpu::new() };
+	}
+
+	pub fn status_word(&self) -> u16
+	{
+		self.status | (self.top as u16) << STATUS_TOP_SHIFT
+	}
+
+	fn set_status_word(&mut self, value: u16)
+	{
+		self.status = value & !STATUS_TOP;
+		self.top = ((value & STATUS_TOP) >> STATUS_TOP_SHIFT) as u8;
+	}
+
+	pub fn tag_word(&self) -> u16
+	{
+		self.tags
+	}
+
+	fn physical(&self, i: u8) -> usize
+	{
+		((self.top + i) & 7) as usize
+	}
+
+	fn tag(&self, i: u8) -> u16
+	{
+		(self.tags >> (2 * self.physical(i))) & 3
+	}
+
+	fn set_tag(&mut self, i: u8, tag: u16)
+	{
+		let shift = 2 * self.physical(i);
+		self.tags = (self.tags & !(3 << shift)) | tag << shift;
+	}
+
+	pub fn is_empty(&self, i: u8) -> bool
+	{
+		self.tag(i) == TAG_EMPTY
+	}
+
+	/* ST(i), whatever its tag, for debuggers */
+	pub fn st(&self, i: u8) -> Extended
+	{
+		self.registers[self.physical(i)]
+	}
+
+	/* Reading an empty register is a stack underflow */
+	fn read(&self, i: u8, ctx: &mut Context) -> Extended
+	{
+		if self.is_empty(i)
+		{
+			ctx.raise(FE_INVALID);
+			return Extended::indefinite()
+		}
+		self.st(i)
+	}
+
+	fn write(&mut self, i: u8, value: Extended)
+	{
+		let physical = self.physical(i);
+		self.registers[physical] = value;
+		let tag = match value.class()
+		{
+			Class::Zero => TAG_ZERO,
+			Class::Normal => TAG_VALID,
+			_ => TAG_SPECIAL
+		};
+		self.set_tag(i, tag);
+	}
+
+	/* Pushing onto a full stack is a stack overflow */
+	fn push(&mut self, value: Extended, ctx: &mut Context)
+	{
+		let value = if self.is_empty(7) { value } else { ctx.raise(FE_INVALID); Extended::indefinite() };
+		if !ctx.aborts()
+		{
+			self.top = (self.top + 7) & 7;
+			self.write(0, value);
+		}
+	}
+
+	fn pop(&mut self)
+	{
+		self.set_tag(0, TAG_EMPTY);
+		self.top = (self.top + 1) & 7;
+	}
+
+	fn set_condition(&mut self, codes: u16)
+	{
+		self.status = (self.status & !STATUS_CONDITION) | codes;
+	}
+
+	/* Records the exceptions of an instruction */
+	fn raise(&mut self, exceptions: u16)
+	{
+		self.status |= exceptions & FE_ALL;
+		self.update_error_summary();
+	}
+
+	/* Pending unmasked exceptions request an interrupt, once */
+	fn update_error_summary(&mut self)
+	{
+		let pending = self.status & !self.control & FE_ALL != 0;
+		if pending && self.status & STATUS_ERROR_SUMMARY == 0 && self.control & CONTROL_IEM == 0
+		{
+			self.interrupt = true;
+		}
+		if pending
+		{
+			self.status |= STATUS_ERROR_SUMMARY | STATUS_BUSY;
+		}
+		else
+		{
+			self.status &= !(STATUS_ERROR_SUMMARY | STATUS_BUSY);
+		}
+	}
+
+	/* Whether an interrupt was requested since the last call */
+	pub fn take_interrupt(&mut self) -> bool
+	{
+		let interrupt = self.interrupt;
+		self.interrupt = false;
+		interrupt
+	}
+}
+
+fn format_size(format: FpuFormat) -> u16
+{
+	match format
+	{
+		FpuFormat::Word => 2,
+		FpuFormat::Single | FpuFormat::Short => 4,
+		FpuFormat::Double | FpuFormat::Long => 8,
+		FpuFormat::Extended | FpuFormat::Bcd => 10
+	}
+}
+
+fn little_endian(bytes: &[u8]) -> u64
+{
+	bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+fn decode_value(format: FpuFormat, bytes: &[u8], ctx: &mut Context) -> Extended
+{
+	let value = little_endian(&bytes[.. format_size(format) as usize]);
+	match format
+	{
+		FpuFormat::Single =>
+		{
+			if value & 0x7f800000 == 0 && value & 0x7fffff != 0
+			{
+				ctx.raise(FE_DENORMAL);
+			}
+			Extended::from_single(value as u32)
+		}
+		FpuFormat::Double =>
+		{
+			if value & 0x7ff0000000000000 == 0 && value & 0xfffffffffffff != 0
+			{
+				ctx.raise(FE_DENORMAL);
+			}
+			Extended::from_double(value)
+		}
+		FpuFormat::Extended => Extended::from_bytes(bytes),
+		FpuFormat::Word => Extended::from_i64(value as i16 as i64),
+		FpuFormat::Short => Extended::from_i64(value as i32 as i64),
+		FpuFormat::Long => Extended::from_i64(value as i64),
+		FpuFormat::Bcd => Extended::from_bcd(bytes)
+	}
+}
+
+/* None when an unmasked exception prevents the store */
+fn encode_value(format: FpuFormat, value: Extended, ctx: &mut Context) -> Option<[u8; 10]>
+{
+	let to_bytes = |value: u64|
+	{
+		let mut bytes = [0; 10];
+		for (i, byte) in bytes.iter_mut().take(8).enumerate()
+		{
+			*byte = (value >> (8 * i)) as u8;
+		}
+		bytes
+	};
+	match format
+	{
+		FpuFormat::Single => value.to_single(ctx).map(|bits| to_bytes(bits as u64)),
+		FpuFormat::Double => value.to_double(ctx).map(to_bytes),
+		FpuFormat::Extended => Some(value.to_bytes()),
+		FpuFormat::Word => value.to_integer(16, ctx).map(|x| to_bytes(x as u64)),
+		FpuFormat::Short => value.to_integer(32, ctx).map(|x| to_bytes(x as u64)),
+		FpuFormat::Long => value.to_integer(64, ctx).map(|x| to_bytes(x as u64)),
+		FpuFormat::Bcd => value.to_bcd(ctx)
+	}
+}
+
+/* Instructions that leave the instruction and operand pointers alone */
+fn is_control(op: FpuOpCode) -> bool
+{
+	match op
+	{
+		FpuOpCode::FNINIT | FpuOpCode::FNCLEX | FpuOpCode::FNENI | FpuOpCode::FNDISI |
+		FpuOpCode::FLDCW | FpuOpCode::FNSTCW | FpuOpCode::FNSTSW | FpuOpCode::FLDENV |
+		FpuOpCode::FNSTENV | FpuOpCode::FRSTOR | FpuOpCode::FNSAVE | FpuOpCode::FSETPM => true,
+		_ => false
+	}
+}
+
+/* 20-bit address of a real mode pointer */
+fn linear(selector: u16, offset: u16) -> u32
+{
+	phys_addr(selector, offset)
+}
+
+impl CPU
+{
+	fn load_fpu_bytes(&self, mem: &Memory, seg: SegReg, addr: u16, len: u16) -> [u8; 10]
+	{
+		let mut bytes = [0; 10];
+		for i in 0 .. len
+		{
+			bytes[i as usize] = self.load_memory_u8(mem, seg, addr.wrapping_add(i));
+		}
+		bytes
+	}
+
+	fn store_fpu_bytes(&self, mem: &mut Memory, seg: SegReg, addr: u16, bytes: &[u8])
+	{
+		for (i, byte) in bytes.iter().enumerate()
+		{
+			self.store_memory_u8(mem, seg, addr.wrapping_add(i as u16), *byte);
+		}
+	}
+
+	/* Opcode bits of the current ESC instruction, after its prefixes */
+	fn esc_opcode(&self, mem: &Memory) -> u16
+	{
+		let mut address = self.code_address(self.instruction_ip);
+		while let 0x26 | 0x2e | 0x36 | 0x3e | 0xf0 | 0xf2 | 0xf3 = mem.read_u8(address)
+		{
+			address += 1;
+		}
+		((mem.read_u8(address) & 0b111) as u16) << 8 | mem.read_u8(address + 1) as u16
+	}
+
+	fn store_fpu_environment(&self, mem: &mut Memory, fpu: &Fpu, seg: SegReg, addr: u16)
+	{
+		let words = if self.protected_mode()
+		{
+			[fpu.control, fpu.status_word(), fpu.tag_word(), fpu.instruction_offset, fpu.instruction_selector,
+				fpu.operand_offset, fpu.operand_selector]
+		}
+		else
+		{
+			let instruction = linear(fpu.instruction_selector, fpu.instruction_offset);
+			let operand = linear(fpu.operand_selector, fpu.operand_offset);
+			[fpu.control, fpu.status_word(), fpu.tag_word(), instruction as u16,
+				((instruction >> 4) as u16 & 0xf000) | (fpu.opcode & 0x7ff),
+				operand as u16, (operand >> 4) as u16 & 0xf000]
+		};
+		for (i, word) in words.iter().enumerate()
+		{
+			self.store_memory_u16(mem, seg, addr.wrapping_add(2 * i as u16), *word);
+		}
+	}
+
+	fn load_fpu_environment(&self, mem: &Memory, fpu: &mut Fpu, seg: SegReg, addr: u16)
+	{
+		let mut words = [0; 7];
+		for (i, word) in words.iter_mut().enumerate()
+		{
+			*word = self.load_memory_u16(mem, seg, addr.wrapping_add(2 * i as u16));
+		}
+		fpu.control = words[0];
+		fpu.set_status_word(words[1]);
+		fpu.tags = words[2];
+		if self.protected_mode()
+		{
+			fpu.instruction_offset = words[3];
+			fpu.instruction_selector = words[4];
+			fpu.operand_offset = words[5];
+			fpu.operand_selector = words[6];
+		}
+		else
+		{
+			fpu.instruction_offset = words[3];
+			fpu.instruction_selector = words[4] & 0xf000;
+			fpu.opcode = words[4] & 0x7ff;
+			fpu.operand_offset = words[5];
+			fpu.operand_selector = words[6] & 0xf000;
+		}
+		fpu.update_error_summary();
+	}
+
+	/* ESC instructions; without coprocessor, nobody listens to them */
+	pub fn run_fpu_ins(&mut self, mem: &mut Memory, op: FpuOpCode, operand: FpuOperand) -> u32
+	{
+		let cycles = esc_cycles(&operand);
+
+		if self.timing.model.has_protected_mode() && self.cr0 & (MSW_EM | MSW_TS) != 0
+		{
+			self.raise_exception(mem, EXC_NO_COPROCESSOR);
+			return cycles
+		}
+		let mut fpu = match self.fpu.take()
+		{
+			Some(fpu) => fpu,
+			None => return cycles
+		};
+
+		let address = match operand
+		{
+			FpuOperand::Memory(_, ref op) | FpuOperand::Address(ref op) => Some(self.effective_address(op)),
+			FpuOperand::Word(WOperand::Reg(_)) => None,
+			FpuOperand::Word(ref op) => Some(self.effective_address(op)),
+			_ => None
+		};
+		if !is_control(op)
+		{
+			fpu.instruction_selector = self.cs;
+			fpu.instruction_offset = self.instruction_ip;
+			fpu.opcode = self.esc_opcode(mem);
+			if let Some((seg, addr)) = address
+			{
+				fpu.operand_selector = self.get_reg(seg);
+				fpu.operand_offset = addr;
+			}
+		}
+
+		let mut ctx = Context::new(fpu.control);
+		self.execute_fpu(mem, &mut fpu, &mut ctx, op, operand, address);
+		fpu.raise(ctx.exceptions);
+
+		self.fpu = Some(fpu);
+		cycles
+	}
+
+	/* WAIT: with MP set, a switched task has to save the coprocessor state
+	 * first */
+	pub fn check_wait(&mut self, mem: &mut Memory)
+	{
+		if self.timing.model.has_protected_mode() && self.cr0 & (MSW_MP | MSW_TS) == (MSW_MP | MSW_TS)
+		{
+			self.raise_exception(mem, EXC_NO_COPROCESSOR);
+		}
+	}
+
+	fn load_fpu_operand(&self, mem: &Memory, format: FpuFormat, address: Option<(SegReg, u16)>, ctx: &mut Context) -> Extended
+	{
+		let (seg, addr) = address.unwrap();
+		let bytes = self.load_fpu_bytes(mem, seg, addr, format_size(format));
+		decode_value(format, &bytes, ctx)
+	}
+
+	fn execute_fpu(&mut self, mem: &mut Memory, fpu: &mut Fpu, ctx: &mut Context, op: FpuOpCode, operand: FpuOperand, address: Option<(SegReg, u16)>)
+	{
+		match op
+		{
+			FpuOpCode::FADD | FpuOpCode::FADDP | FpuOpCode::FMUL | FpuOpCode::FMULP |
+			FpuOpCode::FSUB | FpuOpCode::FSUBP | FpuOpCode::FSUBR | FpuOpCode::FSUBRP |
+			FpuOpCode::FDIV | FpuOpCode::FDIVP | FpuOpCode::FDIVR | FpuOpCode::FDIVRP =>
+			{
+				let (destination, a, b) = match operand
+				{
+					FpuOperand::Memory(format, _) =>
+					{
+						let a = fpu.read(0, ctx);
+						(0, a, self.load_fpu_operand(mem, format, address, ctx))
+					}
+					FpuOperand::ToSt(i) => (0, fpu.read(0, ctx), fpu.read(i, ctx)),
+					FpuOperand::FromSt(i) => (i, fpu.read(i, ctx), fpu.read(0, ctx)),
+					_ => panic!("Unexpected FPU operand {:?}", operand)
+				};
+				let result = match op
+				{
+					FpuOpCode::FADD | FpuOpCode::FADDP => a.add(b, ctx),
+					FpuOpCode::FMUL | FpuOpCode::FMULP => a.mul(b, ctx),
+					FpuOpCode::FSUB | FpuOpCode::FSUBP => a.sub(b, ctx),
+					FpuOpCode::FSUBR | FpuOpCode::FSUBRP => b.sub(a, ctx),
+					FpuOpCode::FDIV | FpuOpCode::FDIVP => a.div(b, ctx),
+					_ => b.div(a, ctx)
+				};
+				if !ctx.aborts()
+				{
+					fpu.write(destination, result);
+					match op
+					{
+						FpuOpCode::FADDP | FpuOpCode::FMULP | FpuOpCode::FSUBP |
+						FpuOpCode::FSUBRP | FpuOpCode::FDIVP | FpuOpCode::FDIVRP => fpu.pop(),
+						_ => {}
+					}
+				}
+			}
+			FpuOpCode::FCOM | FpuOpCode::FCOMP | FpuOpCode::FCOMPP | FpuOpCode::FTST =>
+			{
+				let a = fpu.read(0, ctx);
+				let b = match operand
+				{
+					FpuOperand::Memory(format, _) => self.load_fpu_operand(mem, format, address, ctx),
+					FpuOperand::St(i) => fpu.read(i, ctx),
+					_ if op == FpuOpCode::FTST => Extended::zero(false),
+					_ => fpu.read(1, ctx)
+				};
+				let codes = match a.compare(b, ctx)
+				{
+					Some(Ordering::Greater) => 0,
+					Some(Ordering::Less) => STATUS_C0,
+					Some(Ordering::Equal) => STATUS_C3,
+					None => STATUS_C3 | STATUS_C2 | STATUS_C0
+				};
+				fpu.set_condition(codes);
+				if !ctx.aborts()
+				{
+					let pops = match op
+					{
+						FpuOpCode::FCOMP => 1,
+						FpuOpCode::FCOMPP => 2,
+						_ => 0
+					};
+					for _ in 0 .. pops
+					{
+						fpu.pop();
+					}
+				}
+			}
+			FpuOpCode::FXAM =>
+			{
+				let value = fpu.st(0);
+				let codes = if fpu.is_empty(0)
+				{
+					STATUS_C3 | STATUS_C0
+				}
+				else
+				{
+					match value.class()
+					{
+						Class::Unnormal => 0,
+						Class::NaN => STATUS_C0,
+						Class::Normal => STATUS_C2,
+						Class::Infinity => STATUS_C2 | STATUS_C0,
+						Class::Zero => STATUS_C3,
+						Class::Denormal => STATUS_C3 | STATUS_C2
+					}
+				};
+				fpu.set_condition(codes | if value.sign { STATUS_C1 } else { 0 });
+			}
+			FpuOpCode::FLD =>
+			{
+				let value = match operand
+				{
+					FpuOperand::Memory(format, _) => self.load_fpu_operand(mem, format, address, ctx),
+					FpuOperand::St(i) => fpu.read(i, ctx),
+					_ => panic!("Unexpected FPU operand {:?}", operand)
+				};
+				fpu.push(value, ctx);
+			}
+			FpuOpCode::FST | FpuOpCode::FSTP =>
+			{
+				let value = fpu.read(0, ctx);
+				let stored = match operand
+				{
+					FpuOperand::St(i) =>
+					{
+						if !ctx.aborts()
+						{
+							fpu.write(i, value);
+						}
+						!ctx.aborts()
+					}
+					FpuOperand::Memory(format, _) => match encode_value(format, value, ctx)
+					{
+						Some(ref bytes) if !ctx.aborts() =>
+						{
+							let (seg, addr) = address.unwrap();
+							self.store_fpu_bytes(mem, seg, addr, &bytes[.. format_size(format) as usize]);
+							true
+						}
+						_ => false
+					},
+					_ => panic!("Unexpected FPU operand {:?}", operand)
+				};
+				if stored && op == FpuOpCode::FSTP
+				{
+					fpu.pop();
+				}
+			}
+			FpuOpCode::FXCH =>
+			{
+				if let FpuOperand::St(i) = operand
+				{
+					let (a, b) = (fpu.read(0, ctx), fpu.read(i, ctx));
+					if !ctx.aborts()
+					{
+						fpu.write(0, b);
+						fpu.write(i, a);
+					}
+				}
+			}
+			FpuOpCode::FCHS | FpuOpCode::FABS | FpuOpCode::FSQRT | FpuOpCode::FRNDINT | FpuOpCode::F2XM1 =>
+			{
+				let a = fpu.read(0, ctx);
+				let result = match op
+				{
+					FpuOpCode::FCHS => a.negate(),
+					FpuOpCode::FABS => a.abs(),
+					FpuOpCode::FSQRT => a.sqrt(ctx),
+					FpuOpCode::FRNDINT => a.round_to_integer(ctx),
+					_ => a.exp2_minus_1(ctx)
+				};
+				if !ctx.aborts()
+				{
+					fpu.write(0, result);
+				}
+			}
+			FpuOpCode::FLDZ => fpu.push(Extended::zero(false), ctx),
+			FpuOpCode::FLD1 => fpu.push(Extended::one(), ctx),
+			FpuOpCode::FLDPI => fpu.push(Extended::pi(), ctx),
+			FpuOpCode::FLDL2T => fpu.push(Extended::log2_10(), ctx),
+			FpuOpCode::FLDL2E => fpu.push(Extended::log2_e(), ctx),
+			FpuOpCode::FLDLG2 => fpu.push(Extended::log10_2(), ctx),
+			FpuOpCode::FLDLN2 => fpu.push(Extended::ln_2(), ctx),
+			FpuOpCode::FSCALE =>
+			{
+				let (a, b) = (fpu.read(0, ctx), fpu.read(1, ctx));
+				let result = a.scale(b, ctx);
+				if !ctx.aborts()
+				{
+					fpu.write(0, result);
+				}
+			}
+			FpuOpCode::FPREM =>
+			{
+				let (a, b) = (fpu.read(0, ctx), fpu.read(1, ctx));
+				let (result, quotient, complete) = a.partial_remainder(b, ctx);
+				if !ctx.aborts()
+				{
+					fpu.write(0, result);
+					let codes = if !complete
+					{
+						STATUS_C2
+					}
+					else
+					{
+						(if quotient & 4 != 0 { STATUS_C0 } else { 0 }) |
+						(if quotient & 2 != 0 { STATUS_C1 } else { 0 }) |
+						(if quotient & 1 != 0 { STATUS_C3 } else { 0 })
+					};
+					fpu.set_condition(codes);
+				}
+			}
+			FpuOpCode::FXTRACT =>
+			{
+				let a = fpu.read(0, ctx);
+				let (exponent, significand) = a.extract(ctx);
+				if !ctx.aborts()
+				{
+					fpu.write(0, exponent);
+					fpu.push(significand, ctx);
+				}
+			}
+			FpuOpCode::FPTAN =>
+			{
+				let a = fpu.read(0, ctx);
+				let result = a.tan(ctx);
+				if !ctx.aborts()
+				{
+					fpu.write(0, result);
+					fpu.push(Extended::one(), ctx);
+				}
+			}
+			/* ST(1) = f(ST, ST(1)), then pop */
+			FpuOpCode::FYL2X | FpuOpCode::FYL2XP1 | FpuOpCode::FPATAN =>
+			{
+				let (x, y) = (fpu.read(0, ctx), fpu.read(1, ctx));
+				let result = match op
+				{
+					FpuOpCode::FYL2X => x.y_log2_x(y, ctx),
+					FpuOpCode::FYL2XP1 => x.y_log2_x_plus_1(y, ctx),
+					_ => y.atan2(x, ctx)
+				};
+				if !ctx.aborts()
+				{
+					fpu.write(1, result);
+					fpu.pop();
+				}
+			}
+			FpuOpCode::FDECSTP | FpuOpCode::FINCSTP =>
+			{
+				fpu.top = if op == FpuOpCode::FDECSTP { (fpu.top + 7) & 7 } else { (fpu.top + 1) & 7 };
+				fpu.set_condition(0);
+			}
+			FpuOpCode::FFREE =>
+			{
+				if let FpuOperand::St(i) = operand
+				{
+					fpu.set_tag(i, TAG_EMPTY);
+				}
+			}
+			FpuOpCode::FNOP | FpuOpCode::FSETPM => {}
+			FpuOpCode::FNINIT => fpu.init(),
+			FpuOpCode::FNCLEX =>
+			{
+				fpu.status &= !(FE_ALL | STATUS_ERROR_SUMMARY | STATUS_BUSY);
+			}
+			/* The 80287 ignores them */
+			FpuOpCode::FNENI | FpuOpCode::FNDISI if !self.timing.model.has_protected_mode() =>
+			{
+				if op == FpuOpCode::FNENI
+				{
+					fpu.control &= !CONTROL_IEM;
+				}
+				else
+				{
+					fpu.control |= CONTROL_IEM;
+				}
+				fpu.update_error_summary();
+			}
+			FpuOpCode::FNENI | FpuOpCode::FNDISI => {}
+			FpuOpCode::FLDCW =>
+			{
+				if let FpuOperand::Word(ref op) = operand
+				{
+					fpu.control = self.load_operand(mem, op);
+					fpu.update_error_summary();
+				}
+			}
+			FpuOpCode::FNSTCW =>
+			{
+				if let FpuOperand::Word(ref op) = operand
+				{
+					self.store_operand(mem, op, fpu.control);
+				}
+			}
+			FpuOpCode::FNSTSW =>
+			{
+				if let FpuOperand::Word(ref op) = operand
+				{
+					self.store_operand(mem, op, fpu.status_word());
+				}
+			}
+			FpuOpCode::FNSTENV | FpuOpCode::FNSAVE =>
+			{
+				let (seg, addr) = address.unwrap();
+				self.store_fpu_environment(mem, fpu, seg, addr);
+				if op == FpuOpCode::FNSAVE
+				{
+					for i in 0 .. 8
+					{
+						let offset = ENVIRONMENT_SIZE + i as u16 * REGISTER_SIZE;
+						self.store_fpu_bytes(mem, seg, addr.wrapping_add(offset), &fpu.st(i).to_bytes());
+					}
+					fpu.init();
+				}
+				else
+				{
+					/* So that the handler runs without being interrupted */
+					fpu.control |= FE_ALL;
+					fpu.update_error_summary();
+				}
+			}
+			FpuOpCode::FLDENV | FpuOpCode::FRSTOR =>
+			{
+				let (seg, addr) = address.unwrap();
+				self.load_fpu_environment(mem, fpu, seg, addr);
+				if op == FpuOpCode::FRSTOR
+				{
+					for i in 0 .. 8
+					{
+						let offset = ENVIRONMENT_SIZE + i as u16 * REGISTER_SIZE;
+						let bytes = self.load_fpu_bytes(mem, seg, addr.wrapping_add(offset), REGISTER_SIZE);
+						let physical = fpu.physical(i);
+						fpu.registers[physical] = Extended::from_bytes(&bytes);
+					}
+				}
+			}
+		}
+	}
+}
+
+impl Snapshot for Fpu
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"FPU ");
+		for register in &self.registers
+		{
+			writer.write_bytes(&register.to_bytes());
+		}
+		writer.write_u8(self.top);
+		for word in &[self.control, self.status, self.tags, self.instruction_selector, self.instruction_offset,
+			self.opcode, self.operand_selector, self.operand_offset]
+		{
+			writer.write_u16(*word);
+		}
+		writer.write_bool(self.interrupt);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"FPU ")?;
+		for register in self.registers.iter_mut()
+		{
+			*register = Extended::from_bytes(&reader.read_bytes(REGISTER_SIZE as usize)?);
+		}
+		self.top = reader.read_u8()? & 7;
+		self.control = reader.read_u16()?;
+		self.status = reader.read_u16()?;
+		self.tags = reader.read_u16()?;
+		self.instruction_selector = reader.read_u16()?;
+		self.instruction_offset = reader.read_u16()?;
+		self.opcode = reader.read_u16()?;
+		self.operand_selector = reader.read_u16()?;
+		self.operand_offset = reader.read_u16()?;
+		self.interrupt = reader.read_bool()?;
+		Ok(())
+	}
+}
@@ -0,0 +1,171 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::extended::Extended;
+	use super::super::fpu::Fpu;
+	use super::super::instruction::*;
+	use super::super::protected::*;
+	use super::super::timing::CpuModel;
+	use super::super::super::mem::Memory;
+
+	fn fpu_cpu(model: CpuModel) -> (Memory, CPU)
+	{
+		let mut cpu = CPU::new(0x0000, 0x1000, model);
+		cpu.fpu = Some(Fpu::new());
+		cpu.ds = 0;
+		cpu.ss = 0;
+		cpu.sp = 0x0800;
+		(Memory::new(0x10000), cpu)
+	}
+
+	fn memory(format: FpuFormat, addr: u16) -> FpuOperand
+	{
+		FpuOperand::Memory(format, WOperand::Direct(addr))
+	}
+
+	fn status(cpu: &CPU) -> u16
+	{
+		cpu.fpu.as_ref().unwrap().status_word()
+	}
+
+	#[test]
+	fn arithmetic_through_memory()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I8086);
+		mem.write_u16(0x100, 3);
+		mem.write_u16(0x102, 4);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD, memory(FpuFormat::Word, 0x100));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FMUL, FpuOperand::ToSt(0));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD, memory(FpuFormat::Word, 0x102));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FMUL, FpuOperand::ToSt(0));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FADDP, FpuOperand::FromSt(1));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FSQRT, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FSTP, memory(FpuFormat::Double, 0x110));
+		assert_eq!(mem.read_u16(0x116), 0x4014);
+		assert_eq!(status(&cpu), 0);
+
+		/* FSUBR and FDIVR, reversed */
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD1, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FSUBR, memory(FpuFormat::Word, 0x102));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FDIVR, memory(FpuFormat::Word, 0x100));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FSTP, memory(FpuFormat::Single, 0x120));
+		assert_eq!((mem.read_u16(0x120), mem.read_u16(0x122)), (0, 0x3f80));
+		assert_eq!(cpu.fpu.as_ref().unwrap().tag_word(), 0xffff);
+	}
+
+	#[test]
+	fn status_and_control_words()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I8086);
+		mem.write_u16(0x100, 0xffff);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNSTCW, FpuOperand::Word(WOperand::Direct(0x100)));
+		assert_eq!(mem.read_u16(0x100), 0x037f);
+
+		cpu.ax = 0xffff;
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNSTSW, FpuOperand::Word(WOperand::Reg(WReg::AX)));
+		assert_eq!(cpu.ax, 0);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNINIT, FpuOperand::None);
+
+		/* Comparisons set C3 and C0, and TOP moves with the stack */
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLDPI, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD1, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FCOM, FpuOperand::St(1));
+		assert_eq!(status(&cpu), 0x3100);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FCOMPP, FpuOperand::None);
+		assert_eq!(status(&cpu), 0x0100);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLDZ, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FTST, FpuOperand::None);
+		assert_eq!(status(&cpu), 0x7800);
+	}
+
+	#[test]
+	fn stack_faults()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I8086);
+		for _ in 0 .. 9
+		{
+			cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD1, FpuOperand::None);
+		}
+		assert_eq!(cpu.fpu.as_ref().unwrap().st(0), Extended::indefinite());
+		assert_eq!(status(&cpu) & 0x3f, 0x01);
+
+		/* Underflow on an empty register */
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNINIT, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FCHS, FpuOperand::None);
+		assert_eq!(status(&cpu), 0x0001);
+		assert!(!cpu.fpu.as_mut().unwrap().take_interrupt());
+	}
+
+	#[test]
+	fn unmasked_exceptions()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I8086);
+		mem.write_u16(0x100, 0x037b);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLDCW, FpuOperand::Word(WOperand::Direct(0x100)));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD1, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLDZ, FpuOperand::None);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FDIVP, FpuOperand::FromSt(1));
+
+		/* The destination is left alone, the error summary asks for the NMI */
+		let fpu = cpu.fpu.as_mut().unwrap();
+		assert_eq!(fpu.st(1), Extended::one());
+		assert_eq!(fpu.status_word() & 0x80ff, 0x8084);
+		assert!(fpu.take_interrupt());
+		assert!(!fpu.take_interrupt());
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNCLEX, FpuOperand::None);
+		assert_eq!(status(&cpu) & 0x80ff, 0);
+	}
+
+	#[test]
+	fn environment()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I8086);
+		cpu.instruction_ip = 0x1234;
+		mem.write_u16(0x100, 0x0007);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD, memory(FpuFormat::Short, 0x100));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNSAVE, FpuOperand::Address(WOperand::Direct(0x200)));
+		assert_eq!(mem.read_u16(0x200), 0x037f);
+		assert_eq!(mem.read_u16(0x202), 0x3800);
+		assert_eq!(mem.read_u16(0x204), 0x3fff);
+		assert_eq!(mem.read_u16(0x206), 0x1234);
+		assert_eq!(mem.read_u16(0x20a), 0x0100);
+		assert_eq!(status(&cpu), 0);
+
+		/* Registers follow in stack order; FRSTOR brings them back */
+		assert_eq!(mem.read_u16(0x200 + 14 + 8), 0x4001);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FRSTOR, FpuOperand::Address(WOperand::Direct(0x200)));
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FSTP, memory(FpuFormat::Long, 0x300));
+		assert_eq!(mem.read_u16(0x300), 7);
+	}
+
+	#[test]
+	fn without_coprocessor()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I8086);
+		cpu.fpu = None;
+		mem.write_u16(0x100, 0x1234);
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FNSTCW, FpuOperand::Word(WOperand::Direct(0x100)));
+		assert_eq!((mem.read_u16(0x100), cpu.ip), (0x1234, 0x1000));
+	}
+
+	#[test]
+	fn emulation_traps()
+	{
+		let (mut mem, mut cpu) = fpu_cpu(CpuModel::I80286);
+		mem.write_u16(0x1c, 0x0500);
+		cpu.instruction_ip = 0x1000;
+		cpu.cr0 = MSW_EM;
+		cpu.run_fpu_ins(&mut mem, FpuOpCode::FLD1, FpuOperand::None);
+		assert_eq!((cpu.cs, cpu.ip, mem.read_u16(0x07fa)), (0, 0x0500, 0x1000));
+
+		/* WAIT only cares about a task switch with MP set */
+		cpu.ip = 0x1000;
+		cpu.cr0 = MSW_MP;
+		cpu.check_wait(&mut mem);
+		assert_eq!(cpu.ip, 0x1000);
+		cpu.cr0 = MSW_MP | MSW_TS;
+		cpu.check_wait(&mut mem);
+		assert_eq!(cpu.ip, 0x0500);
+	}
+}
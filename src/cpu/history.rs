@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use super::base::*;
+use super::fpu::Fpu;
+use super::protected::ProtectedState;
+use super::super::mem::Memory;
+
+/* In the order of CPU::registers() */
+pub const REGISTER_NAMES: [&'static str; 14] =
+	["AX", "BX", "CX", "DX", "SP", "BP", "SI", "DI", "CS", "DS", "SS", "ES", "IP", "FLAGS"];
+
+/* What a CPU step did, enough to undo it */
+#[derive(Clone, Debug, Default)]
+pub struct HistoryEntry
+{
+	/* The instruction that ran; for a step that only dispatched a hardware
+	 * interrupt, the first instruction of the handler */
+	pub cs: u16,
+	pub ip: u16,
+	/* Hardware interrupt dispatched before the instruction */
+	pub irq: Option<u8>,
+	/* Registers that changed: index in REGISTER_NAMES, old and new value */
+	pub registers: Vec<(usize, u16, u16)>,
+	/* MSW and descriptor state of the 80286 before the step, if it changed */
+	pub protected: Option<(u8, ProtectedState)>,
+	/* Coprocessor state before the step, if it changed */
+	pub fpu: Option<Fpu>,
+	/* Bytes written: physical address and old value, in write order */
+	pub writes: Vec<(u32, u8)>
+}
+
+/* Ring buffer of the last steps, most recent last; recording is off while
+ * its capacity is 0, and devices are not part of it */
+pub struct History
+{
+	entries: VecDeque<HistoryEntry>,
+	capacity: usize,
+	/* The step being recorded */
+	current: HistoryEntry,
+	before: [u16; 14],
+	before_protected: Option<(u8, ProtectedState)>,
+	before_fpu: Option<Fpu>
+}
+
+impl History
+{
+	pub fn new() -> History
+	{
+		History
+		{
+			entries: VecDeque::new(),
+			capacity: 0,
+			current: HistoryEntry::default(),
+			before: [0; 14],
+			before_protected: None,
+			before_fpu: None
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool
+	{
+		self.capacity > 0
+	}
+
+	pub fn set_capacity(&mut self, capacity: usize)
+	{
+		self.capacity = capacity;
+		while self.entries.len() > capacity
+		{
+			self.entries.pop_front();
+		}
+	}
+
+	pub fn len(&self) -> usize
+	{
+		self.entries.len()
+	}
+
+	pub fn clear(&mut self)
+	{
+		self.entries.clear();
+	}
+
+	/* The last 'count' steps, oldest first */
+	pub fn last(&self, count: usize) -> impl Iterator<Item = &HistoryEntry>
+	{
+		self.entries.iter().skip(self.entries.len().saturating_sub(count))
+	}
+
+	fn begin(&mut self, registers: [u16; 14], protected: Option<(u8, ProtectedState)>, fpu: Option<Fpu>)
+	{
+		self.before = registers;
+		self.before_protected = protected;
+		self.before_fpu = fpu;
+		self.current.cs = registers[8];
+		self.current.ip = registers[12];
+		self.current.irq = None;
+	}
+
+	fn end(&mut self, registers: [u16; 14], protected: Option<(u8, ProtectedState)>, fpu: Option<&Fpu>, writes: Vec<(u32, u8)>)
+	{
+		/* Reuse the buffers of the oldest entry, the history being full most
+		 * of the time */
+		let mut entry = if self.entries.len() >= self.capacity
+		{
+			self.entries.pop_front().unwrap_or_default()
+		}
+		else
+		{
+			HistoryEntry::default()
+		};
+		entry.cs = self.current.cs;
+		entry.ip = self.current.ip;
+		entry.irq = self.current.irq;
+		entry.registers.clear();
+		entry.registers.extend((0 .. 14).filter(|&i| self.before[i] != registers[i]).map(|i| (i, self.before[i], registers[i])));
+		entry.protected = if self.before_protected != protected { self.before_protected } else { None };
+		let before_fpu = self.before_fpu.take();
+		entry.fpu = if before_fpu.as_ref() != fpu { before_fpu } else { None };
+		entry.writes = writes;
+		self.entries.push_back(entry);
+	}
+}
+
+impl CPU
+{
+	/* AX, BX, CX, DX, SP, BP, SI, DI, CS, DS, SS, ES, IP, FLAGS */
+	pub fn registers(&self) -> [u16; 14]
+	{
+		[self.ax, self.bx, self.cx, self.dx, self.sp, self.bp, self.si, self.di,
+			self.cs, self.ds, self.ss, self.es, self.ip, self.flags]
+	}
+
+	pub fn set_registers(&mut self, registers: &[u16; 14])
+	{
+		self.ax = registers[0];
+		self.bx = registers[1];
+		self.cx = registers[2];
+		self.dx = registers[3];
+		self.sp = registers[4];
+		self.bp = registers[5];
+		self.si = registers[6];
+		self.di = registers[7];
+		self.cs = registers[8];
+		self.ds = registers[9];
+		self.ss = registers[10];
+		self.es = registers[11];
+		self.ip = registers[12];
+		self.flags = registers[13];
+	}
+
+	/* The state beyond the registers that instructions of the 80286 change */
+	fn protected_state(&self) -> Option<(u8, ProtectedState)>
+	{
+		if self.timing.model.has_protected_mode() { Some((self.cr0, self.protected)) } else { None }
+	}
+
+	/* Called by step() around the execution */
+	pub fn begin_history_entry(&mut self, mem: &mut Memory)
+	{
+		let registers = self.registers();
+		let protected = self.protected_state();
+		let fpu = self.fpu.clone();
+		self.history.begin(registers, protected, fpu);
+		mem.start_journal();
+	}
+
+	pub fn end_history_entry(&mut self, mem: &mut Memory)
+	{
+		let registers = self.registers();
+		let protected = self.protected_state();
+		let writes = mem.take_journal();
+		self.history.end(registers, protected, self.fpu.as_ref(), writes);
+	}
+
+	/* Called by step() once the instruction is known */
+	pub fn history_note(&mut self, irq: Option<u8>)
+	{
+		if self.history.is_enabled()
+		{
+			self.history.current.cs = self.cs;
+			self.history.current.ip = self.ip;
+			if irq.is_some()
+			{
+				self.history.current.irq = irq;
+			}
+		}
+	}
+
+	/* Undoes the last recorded step: registers, the 80286 state, the
+	 * coprocessor and memory go back to what they were before it; returns
+	 * it, or None if the history is empty */
+	pub fn step_back(&mut self, mem: &mut Memory) -> Option<HistoryEntry>
+	{
+		let entry = self.history.entries.pop_back()?;
+		for &(addr, old) in entry.writes.iter().rev()
+		{
+			mem.write_u8(addr, old);
+		}
+		let mut registers = self.registers();
+		for &(i, old, _) in &entry.registers
+		{
+			registers[i] = old;
+		}
+		self.set_registers(&registers);
+		if let Some((cr0, protected)) = entry.protected
+		{
+			self.cr0 = cr0;
+			self.protected = protected;
+		}
+		if let Some(ref fpu) = entry.fpu
+		{
+			self.fpu = Some(fpu.clone());
+		}
+		if self.state == CPUState::Crashed
+		{
+			self.state = CPUState::Paused;
+		}
+		Some(entry)
+	}
+}
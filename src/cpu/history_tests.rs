@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::fpu::Fpu;
+	use super::super::instruction::*;
+	use super::super::timing::CpuModel;
+	use super::super::super::mem::Memory;
+
+	/* Runs 'run' as step() would record it */
+	fn recorded<F>(cpu: &mut CPU, mem: &mut Memory, run: F)
+		where F: FnOnce(&mut CPU, &mut Memory)
+	{
+		cpu.begin_history_entry(mem);
+		cpu.history_note(None);
+		run(cpu, mem);
+		cpu.end_history_entry(mem);
+	}
+
+	fn cpu() -> CPU
+	{
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I8086);
+		cpu.ss = 0x0000;
+		cpu.sp = 0x8000;
+		cpu.ax = 0x1234;
+		cpu.history.set_capacity(2);
+		cpu
+	}
+
+	/* CALL 0x20, as step() would record it */
+	fn call(cpu: &mut CPU, mem: &mut Memory)
+	{
+		cpu.begin_history_entry(mem);
+		cpu.history_note(None);
+		cpu.ip += 3;
+		cpu.run_sfcop_ins(mem, SingleOperandFCOpCode::CALL, FlowControlOperand::DirectSeg(0x20));
+		cpu.end_history_entry(mem);
+	}
+
+	#[test]
+	fn record_and_undo()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu();
+		mem.write_u16(0x7ffe, 0xabcd);
+
+		call(&mut cpu, &mut mem);
+		assert_eq!((cpu.ip, cpu.sp, mem.read_u16(0x7ffe)), (0x1023, 0x7ffe, 0x1003));
+		{
+			let entry = cpu.history.last(1).next().unwrap();
+			assert_eq!((entry.cs, entry.ip, entry.irq), (0x0000, 0x1000, None));
+			/* SP and IP */
+			assert_eq!(entry.registers, vec![(4, 0x8000, 0x7ffe), (12, 0x1000, 0x1023)]);
+			assert_eq!(entry.writes, vec![(0x7ffe, 0xcd), (0x7fff, 0xab)]);
+		}
+
+		let entry = cpu.step_back(&mut mem).unwrap();
+		assert_eq!(entry.ip, 0x1000);
+		assert_eq!((cpu.ip, cpu.sp, cpu.ax, mem.read_u16(0x7ffe)), (0x1000, 0x8000, 0x1234, 0xabcd));
+		assert!(cpu.step_back(&mut mem).is_none());
+	}
+
+	#[test]
+	fn capacity()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu();
+		for _ in 0 .. 3
+		{
+			call(&mut cpu, &mut mem);
+		}
+		assert_eq!(cpu.history.len(), 2);
+		let ips: Vec<u16> = cpu.history.last(5).map(|entry| entry.ip).collect();
+		assert_eq!(ips, vec![0x1023, 0x1046]);
+
+		cpu.history.set_capacity(0);
+		assert!(!cpu.history.is_enabled());
+		assert_eq!(cpu.history.len(), 0);
+	}
+
+	#[test]
+	fn protected_state()
+	{
+		let mut mem = Memory::new(16 * 1024 * 1024);
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I80286);
+		cpu.ds = 0x0000;
+		cpu.history.set_capacity(2);
+		/* GDT at 0x2000, with a code segment at 0x10000 */
+		mem.write_u16(0x500, 0x0f);
+		mem.write_u16(0x502, 0x2000);
+		mem.write_u16(0x2008, 0xffff);
+		mem.write_u16(0x200a, 0x0000);
+		mem.write_u8(0x200c, 0x01);
+		mem.write_u8(0x200d, 0x9a);
+		cpu.run_system_ins(&mut mem, SystemOpCode::LGDT, WOperand::Direct(0x500));
+		let real_mode = cpu.protected;
+
+		cpu.ax = 0x0001;
+		recorded(&mut cpu, &mut mem, |cpu, mem| { cpu.run_system_ins(mem, SystemOpCode::LMSW, WOperand::Reg(WReg::AX)); });
+		recorded(&mut cpu, &mut mem, |cpu, mem| { cpu.run_sfcop_ins(mem, SingleOperandFCOpCode::JMP, FlowControlOperand::DirectInterSeg(0x08, 0x0100)); });
+		assert_eq!((cpu.cs, cpu.ip, cpu.code_address(cpu.ip)), (0x08, 0x0100, 0x10100));
+
+		/* Back to the old selector and its cache */
+		cpu.step_back(&mut mem).unwrap();
+		assert_eq!((cpu.cs, cpu.ip, cpu.code_address(cpu.ip)), (0x0000, 0x1000, 0x1000));
+		assert!(cpu.protected_mode());
+		cpu.step_back(&mut mem).unwrap();
+		assert!(!cpu.protected_mode());
+		assert_eq!(cpu.protected, real_mode);
+	}
+
+	#[test]
+	fn fpu_state()
+	{
+		let mut mem = Memory::new(0x10000);
+		let mut cpu = cpu();
+		cpu.fpu = Some(Fpu::new());
+		recorded(&mut cpu, &mut mem, |cpu, mem| { cpu.run_fpu_ins(mem, FpuOpCode::FLD1, FpuOperand::None); });
+		recorded(&mut cpu, &mut mem, |cpu, _| { cpu.ax = 0x5678; });
+		assert_ne!(cpu.fpu, Some(Fpu::new()));
+
+		/* Only the steps that changed it record it */
+		assert!(cpu.step_back(&mut mem).unwrap().fpu.is_none());
+		assert!(cpu.step_back(&mut mem).unwrap().fpu.is_some());
+		assert_eq!(cpu.fpu, Some(Fpu::new()));
+		assert_eq!(cpu.fpu.as_ref().unwrap().tag_word(), 0xffff);
+	}
+}
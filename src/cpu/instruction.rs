@@ -42,7 +42,7 @@ pub enum IndReg
 	BX
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum BOperand
 {
 	Reg(BReg),
@@ -53,7 +53,7 @@ pub enum BOperand
 	Indirect16uDis(IndReg, u16)
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum WOperand
 {
 	Reg(WReg),
@@ -66,7 +66,7 @@ pub enum WOperand
 }
 
 /* TODO: opcode -> mnemonic? */
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum NoOperandOpCode
 {
 	HLT,
@@ -91,20 +91,30 @@ pub enum NoOperandOpCode
 	STC,
 	CLD,
 	WAIT,
-    FNINIT
+	/* Undocumented: AL = CF ? 0xff : 0 */
+	SALC,
+	/* 80186 */
+	PUSHA,
+	POPA,
+	LEAVE,
+	/* 80286 */
+	CLTS
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum ImplicitOperandOpCode
 {
     MOVS,
     CMPS,
     SCAS,
     LODS,
-    STOS
+    STOS,
+    /* 80186 */
+    INS,
+    OUTS
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum SingleOperandOpCode
 {
 	PUSH,
@@ -121,7 +131,7 @@ pub enum SingleOperandOpCode
     AAD
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum TwoOperandsOpCode
 {
 	ADD,
@@ -137,24 +147,151 @@ pub enum TwoOperandsOpCode
 	XCHG,
 	LEA,
 	LDS,
-	LES
+	LES,
+	/* 80186: 'from' is the memory operand holding the bounds */
+	BOUND,
+	/* 80286: 'from' is the selector */
+	LAR,
+	LSL,
+	/* 80286: 'from' is the register holding the requested privilege level */
+	ARPL
+}
+
+/* 80186 */
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ThreeOperandsOpCode
+{
+	IMUL
+}
+
+/* 80286 system instructions; the descriptor table ones (LGDT, SGDT...)
+ * take a 6-byte memory operand, the others a word */
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum SystemOpCode
+{
+	SLDT,
+	STR,
+	LLDT,
+	LTR,
+	VERR,
+	VERW,
+	SGDT,
+	SIDT,
+	LGDT,
+	LIDT,
+	SMSW,
+	LMSW
+}
+
+/* 8087 instructions (ESC opcodes). The arithmetic ones stand for their
+ * integer forms too (FIADD...), told apart by the memory format, and FLD and
+ * FSTP for FBLD and FBSTP; the 'N' ones do not wait for the coprocessor */
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FpuOpCode
+{
+	FLD,
+	FST,
+	FSTP,
+	FXCH,
+	FADD,
+	FADDP,
+	FMUL,
+	FMULP,
+	FSUB,
+	FSUBP,
+	FSUBR,
+	FSUBRP,
+	FDIV,
+	FDIVP,
+	FDIVR,
+	FDIVRP,
+	FCOM,
+	FCOMP,
+	FCOMPP,
+	FTST,
+	FXAM,
+	FCHS,
+	FABS,
+	FLDZ,
+	FLD1,
+	FLDPI,
+	FLDL2T,
+	FLDL2E,
+	FLDLG2,
+	FLDLN2,
+	F2XM1,
+	FYL2X,
+	FPTAN,
+	FPATAN,
+	FXTRACT,
+	FDECSTP,
+	FINCSTP,
+	FPREM,
+	FYL2XP1,
+	FSQRT,
+	FRNDINT,
+	FSCALE,
+	FFREE,
+	FNOP,
+	FNINIT,
+	FNCLEX,
+	FNENI,
+	FNDISI,
+	FLDCW,
+	FNSTCW,
+	FNSTSW,
+	FLDENV,
+	FNSTENV,
+	FRSTOR,
+	FNSAVE,
+	/* 80287: a no-op */
+	FSETPM
+}
+
+/* Memory operand formats of the 8087 */
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FpuFormat
+{
+	Single,
+	Double,
+	Extended,
+	/* 16, 32 and 64-bit integers */
+	Word,
+	Short,
+	Long,
+	Bcd
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FpuOperand
+{
+	None,
+	St(u8),
+	/* ST = ST op ST(i) */
+	ToSt(u8),
+	/* ST(i) = ST(i) op ST */
+	FromSt(u8),
+	Memory(FpuFormat, WOperand),
+	/* Control or status word, in memory or AX */
+	Word(WOperand),
+	/* Environment or state of the coprocessor */
+	Address(WOperand)
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum SingleOperandFCOpCode
 {
 	CALL,
-	JMP,
-    FNSTCW
+	JMP
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum NoOpFCOpCode
 {
 	RET
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum SingleBImmOperandOpCode
 {
 	JMPS,
@@ -189,7 +326,7 @@ pub enum SingleBImmOperandOpCode
 	OUTVW
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum ShiftRotateOpCode
 {
 	SHL,
@@ -198,31 +335,33 @@ pub enum ShiftRotateOpCode
 	ROL,
 	ROR,
 	RCL,
-	RCR
+	RCR,
+	/* 8086 only: sets all the bits of the operand */
+	SETMO
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum ShiftRotateCount
 {
 	One,
 	CL,
-	Imm(u8) // REMOVEME: unsupported on a 8086
+	Imm(u8) // 80186
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum SingleWImmFCOpCode
 {
 	RETANDADDTOSP
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum SingleWImmFCOperand
 {
 	Seg(u16),
 	InterSeg(u16)
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum FlowControlOperand
 {
 	DirectSeg(u16), // TODO: should be DirectIntraSeg?
@@ -231,7 +370,7 @@ pub enum FlowControlOperand
 	IndirectInterSeg(WOperand)
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum Prefix
 {
 	LOCK,
@@ -240,7 +379,7 @@ pub enum Prefix
 	REPNE
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum Instruction
 {
 	NoOperand(NoOperandOpCode),
@@ -258,11 +397,17 @@ pub enum Instruction
 	SingleWImmFCOperand(SingleWImmFCOpCode, SingleWImmFCOperand),
 	ShiftRotateB(ShiftRotateOpCode, ShiftRotateCount, BOperand),
 	ShiftRotateW(ShiftRotateOpCode, ShiftRotateCount, WOperand),
+	/* Source, immediate, destination */
+	ThreeWOperands(ThreeOperandsOpCode, WOperand, WOperand, WOperand),
+	/* ENTER frame size, nesting level */
+	Enter(u16, u8),
+	System(SystemOpCode, WOperand),
+	Fpu(FpuOpCode, FpuOperand),
 	Prefix(Prefix),
 	Invalid
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub struct SizedInstruction
 {
 	pub instruction: Instruction,
@@ -297,6 +442,11 @@ impl Display for Instruction
 			Instruction::SingleWImmFCOperand(ref op, ref a) => write!(f, "{:?} {:?}", op, a),
 			Instruction::ShiftRotateB(ref op, ref rot, ref a) => write!(f, "{:?} {:?}, {:?}", op, rot, a),
 			Instruction::ShiftRotateW(ref op, ref rot, ref a) => write!(f, "{:?} {:?}, {:?}", op, rot, a),
+			Instruction::ThreeWOperands(ref op, ref a, ref b, ref c) => write!(f, "{:?} WORD {}, {}, {}", op, a, b, c),
+			Instruction::Enter(size, level) => write!(f, "ENTER #{:x}, #{:x}", size, level),
+			Instruction::System(ref op, ref a) => write!(f, "{:?} {}", op, a),
+			Instruction::Fpu(ref op, FpuOperand::None) => write!(f, "{:?}", op),
+			Instruction::Fpu(ref op, ref a) => write!(f, "{:?} {}", op, a),
 			Instruction::Prefix(ref op) => write!(f, "{:?}", op),
 			Instruction::Invalid => write!(f, "BAD")
 		}
@@ -320,6 +470,32 @@ impl Display for WOperand
 	}
 }
 
+impl Display for FpuOperand
+{
+	fn fmt(&self, f: &mut Formatter) -> Result
+	{
+		match *self
+		{
+			FpuOperand::None => Ok(()),
+			FpuOperand::St(i) => write!(f, "ST({})", i),
+			FpuOperand::ToSt(i) => write!(f, "ST, ST({})", i),
+			FpuOperand::FromSt(i) => write!(f, "ST({}), ST", i),
+			FpuOperand::Memory(format, ref a) => write!(f, "{} {}", match format
+				{
+					FpuFormat::Single => "DWORD",
+					FpuFormat::Double => "QWORD",
+					FpuFormat::Extended => "TBYTE",
+					FpuFormat::Word => "WORD INT",
+					FpuFormat::Short => "DWORD INT",
+					FpuFormat::Long => "QWORD INT",
+					FpuFormat::Bcd => "TBYTE BCD"
+				}, a),
+			FpuOperand::Word(ref a) => write!(f, "WORD {}", a),
+			FpuOperand::Address(ref a) => write!(f, "{}", a)
+		}
+	}
+}
+
 impl Display for BOperand
 {
 	fn fmt(&self, f: &mut Formatter) -> Result
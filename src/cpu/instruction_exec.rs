@@ -2,6 +2,11 @@ use super::instruction::*;
 use super::base::*;
 use super::reg_access::*;
 use super::operand_access::*;
+use super::timing::*;
+use super::traps::TrapEvent;
+use super::io_dispatch::PortAccess;
+use super::protected::*;
+use super::parser::PrefixedInstruction;
 use super::super::mem::Memory;
 use super::super::hw::HW;
 
@@ -9,21 +14,26 @@ use std::io::Write;
 
 impl CPU
 {
-	fn stack_push(&mut self, mem: &mut Memory, val: u16)
+	pub fn stack_push(&mut self, mem: &mut Memory, val: u16)
 	{
 		self.sp = self.sp.wrapping_sub(2);
-		self.store_memory_u16_noov(mem, self.ss, self.sp, val);
+		self.store_memory_u16_noov(mem, SegReg::SS, self.sp, val);
 	}
 
-	fn stack_pop(&mut self, mem: &Memory) -> u16
+	pub fn stack_pop(&mut self, mem: &Memory) -> u16
 	{
-		let value = self.load_memory_u16_noov(mem, self.ss, self.sp);
+		let value = self.load_memory_u16_noov(mem, SegReg::SS, self.sp);
 		self.sp = self.sp.wrapping_add(2);
 		value
 	}
 
-	fn return_from_interrupt(&mut self, mem: &Memory)
+	fn return_from_interrupt(&mut self, mem: &mut Memory)
 	{
+		if self.protected_mode()
+		{
+			return self.protected_interrupt_return(mem)
+		}
+
 		let ip = self.stack_pop(mem);
 		let cs = self.stack_pop(mem);
 		
@@ -37,17 +47,26 @@ impl CPU
 
 		self.ip = ip;
 		self.cs = cs;
-		self.flags = self.stack_pop(mem);
+		let flags = self.stack_pop(mem);
+		self.load_flags(flags);
 	}
 
-	// returns true if the CPU should run the next instruction; TODO: use a CPU attribute for this?
-	pub fn run_noop_ins(&mut self, mem: &mut Memory, op: NoOperandOpCode)
+	/* Instruction executors return the amount of clock cycles spent */
+	pub fn run_noop_ins(&mut self, mem: &mut Memory, op: NoOperandOpCode) -> u32
 	{
+		let cycles = no_operand_cycles(&op);
+
 		match op
 		{
-			NoOperandOpCode::CLI => self.flags = self.flags & not(FLAG_I),
+			NoOperandOpCode::CLI => if self.check_io_privilege()
+			{
+				self.flags = self.flags & not(FLAG_I)
+			},
 			NoOperandOpCode::CLD => self.flags = self.flags & not(FLAG_D),
-			NoOperandOpCode::STI => self.flags = self.flags | FLAG_I,
+			NoOperandOpCode::STI => if self.check_io_privilege()
+			{
+				self.flags = self.flags | FLAG_I
+			},
 			NoOperandOpCode::STD => self.flags = self.flags | FLAG_D,
 			NoOperandOpCode::STC => self.set_flag(FLAG_C),
 			NoOperandOpCode::CLC => self.clear_flag(FLAG_C),
@@ -76,17 +95,21 @@ impl CPU
 			},
 			NoOperandOpCode::POPF =>
 			{
-				// TODO: restrict popped flags?
 				let f = self.stack_pop(mem);
-				self.flags = f | FLAG_SET_8086;
+				self.load_flags(f);
 			}
 			NoOperandOpCode::IRET => self.return_from_interrupt(mem),
 			NoOperandOpCode::XLAT =>
 			{
 				let old_al = self.get_reg(BReg::AL);
-				let new_al = self.load_memory_u8(mem, self.ds, self.bx + (old_al as u16));
+				let new_al = self.load_memory_u8(mem, SegReg::DS, self.bx + (old_al as u16));
 				self.set_reg(BReg::AL, new_al);
 			}
+			NoOperandOpCode::SALC =>
+			{
+				let al = if self.flags & FLAG_C != 0 { 0xff } else { 0 };
+				self.set_reg(BReg::AL, al);
+			}
 			NoOperandOpCode::LAHF =>
 			{
 				let flags = self.flags as u8; // Drop hi bits of flags register
@@ -124,22 +147,78 @@ impl CPU
 				self.set_flag_value(FLAG_C, carry);
 				self.clear_flag(FLAG_A);
 			}
-			NoOperandOpCode::HLT => cpu_print!("HLT"),
-			//NoOperandOpCode::WAIT => cpu_print!("WAIT?"),
-            NoOperandOpCode::FNINIT => {
-                if 0 < (self.get_cr0() & 0x4) {
-                    self.request_interrupt(mem, 0x02);
-                } else {
-                    panic!("FPU not implemented");
-                }
-            }
+			NoOperandOpCode::PUSHA =>
+			{
+				/* SP as it was before the first push */
+				let sp = self.sp;
+				for &value in &[self.ax, self.cx, self.dx, self.bx, sp, self.bp, self.si, self.di]
+				{
+					self.stack_push(mem, value);
+				}
+			}
+			NoOperandOpCode::POPA =>
+			{
+				self.di = self.stack_pop(mem);
+				self.si = self.stack_pop(mem);
+				self.bp = self.stack_pop(mem);
+				/* SP is skipped */
+				self.stack_pop(mem);
+				self.bx = self.stack_pop(mem);
+				self.dx = self.stack_pop(mem);
+				self.cx = self.stack_pop(mem);
+				self.ax = self.stack_pop(mem);
+			}
+			NoOperandOpCode::LEAVE =>
+			{
+				self.sp = self.bp;
+				self.bp = self.stack_pop(mem);
+			}
+			NoOperandOpCode::HLT => if self.check_cpl0()
+			{
+				cpu_print!("HLT")
+			},
+			NoOperandOpCode::CLTS => if self.check_cpl0()
+			{
+				self.cr0 &= !MSW_TS
+			},
+			/* The coprocessor is done as soon as it starts */
+			NoOperandOpCode::WAIT => self.check_wait(mem),
 			_ => panic!("Unhandled no-operand opcode: {:?} ({:04x}:{:04x})", op, self.cs, self.ip)
 		}
+
+		cycles
 	}
 
-	pub fn queue_hw_interrupt(&mut self, interrupt_number: u8)
+	/* Divide errors and invalid opcodes: the 8086 returns after the faulting
+	 * instruction, later models on it so that handlers can restart it. In
+	 * protected mode, they are delivered as faults once the instruction
+	 * returns */
+	pub fn raise_exception(&mut self, mem: &mut Memory, interrupt_number: u8)
 	{
-		self.pending_interrupts.push_back(interrupt_number);
+		if self.protected_mode()
+		{
+			if self.fault.get().is_none()
+			{
+				self.fault.set(Some(Fault { vector: interrupt_number, error_code: None }));
+			}
+			return
+		}
+		if self.timing.model.has_exceptions()
+		{
+			self.ip = self.instruction_ip;
+		}
+		self.request_interrupt(mem, interrupt_number);
+	}
+
+	/* INT n: in protected mode, gates may not be accessible from the current
+	 * privilege level */
+	pub fn software_interrupt(&mut self, mem: &mut Memory, interrupt_number: u8)
+	{
+		if self.protected_mode()
+		{
+			return self.protected_interrupt(mem, interrupt_number, None, true)
+		}
+		self.request_interrupt(mem, interrupt_number)
 	}
 
 	pub fn request_interrupt(&mut self, mem: &mut Memory, interrupt_number: u8)
@@ -149,15 +228,24 @@ impl CPU
 			println!("DOS CALL; irq={:02x}, AX={:04x}", interrupt_number, self.ax);
 		}*/
 
+		if self.protected_mode()
+		{
+			return self.protected_interrupt(mem, interrupt_number, None, false)
+		}
 		let flags = self.flags;
 		let ip = self.ip;
 		let cs = self.cs;
+		if !self.traps.is_empty()
+		{
+			self.check_traps(TrapEvent::Interrupt { number: interrupt_number, return_cs: cs, return_ip: ip });
+		}
 		self.stack_push(mem, flags); // WARNING: user code may rely on flag register format
 		self.flags = self.flags & not(FLAG_I) & not(FLAG_T);
 		self.stack_push(mem, cs);
 		self.stack_push(mem, ip);
 
-		let ivt_addr = (interrupt_number as u32) * 4;
+		/* The 80286 can move the real mode interrupt table with LIDT */
+		let ivt_addr = self.protected.idtr.base + (interrupt_number as u32) * 4;
 		let handler_ip = mem.read_u16(ivt_addr);
 		let handler_cs = mem.read_u16(ivt_addr + 2);
 
@@ -173,9 +261,10 @@ impl CPU
 		self.ip = handler_ip;
 	}
 
-	pub fn run_sbiop_ins(&mut self, mem: &mut Memory, hw: &mut HW, op: SingleBImmOperandOpCode, operand: u8)
+	pub fn run_sbiop_ins(&mut self, mem: &mut Memory, hw: &mut HW, op: SingleBImmOperandOpCode, operand: u8) -> u32
 	{
 		let signed = operand as i8;
+		let old_ip = self.ip;
 		/* TODO: this kind of branching instructions actually uses signed operands
 		 * should we update the Instruction structure? */
 
@@ -293,54 +382,58 @@ impl CPU
 			},
 			SingleBImmOperandOpCode::INB =>
 			{
-				let ret = self.io_inb(operand as u16, hw);
+				let ret = self.io_inb(mem, operand as u16, hw);
 				self.set_reg(BReg::AL, ret)
 			}
 			SingleBImmOperandOpCode::OUTB =>
 			{
 				let val = self.get_reg(BReg::AL);
-				self.io_outb(operand as u16, val, hw)
+				self.io_outb(mem, operand as u16, val, hw)
 			}
 			SingleBImmOperandOpCode::OUTW =>
 			{
 				let val = self.get_reg(WReg::AX);
-				self.io_outw(operand as u16, val, hw)
+				self.io_outw(mem, operand as u16, val, hw)
 			}
 			SingleBImmOperandOpCode::INW =>
 			{
-				let ret = self.io_inw(operand as u16, hw);
+				let ret = self.io_inw(mem, operand as u16, hw);
 				self.set_reg(WReg::AX, ret)
 			}
 			SingleBImmOperandOpCode::OUTVB =>
 			{
 				let val = self.get_reg(BReg::AL);
 				let dest = self.dx;
-				self.io_outb(dest, val, hw)
+				self.io_outb(mem, dest, val, hw)
 			}
 			SingleBImmOperandOpCode::OUTVW =>
 			{
 				let val = self.get_reg(WReg::AX);
 				let dest = self.dx;
-				self.io_outw(dest, val, hw)
+				self.io_outw(mem, dest, val, hw)
 			}
 			SingleBImmOperandOpCode::INVB =>
 			{
 				let src = self.dx;
-				let ret = self.io_inb(src, hw);
+				let ret = self.io_inb(mem, src, hw);
 				self.set_reg(BReg::AL, ret)
 			}
 			SingleBImmOperandOpCode::INVW =>
 			{
 				let src = self.dx;
-				let ret = self.io_inw(src, hw);
+				let ret = self.io_inw(mem, src, hw);
 				self.set_reg(WReg::AX, ret)
 			}
-			SingleBImmOperandOpCode::INT => self.request_interrupt(mem, operand),
+			SingleBImmOperandOpCode::INT => self.software_interrupt(mem, operand),
 		}
+
+		short_imm_cycles(&op, self.ip != old_ip)
 	}
 
-	pub fn run_twop_ins(&mut self, mem: &mut Memory, op: TwoOperandsOpCode, from: WOperand, to: WOperand)
+	pub fn run_twop_ins(&mut self, mem: &mut Memory, op: TwoOperandsOpCode, from: WOperand, to: WOperand) -> u32
 	{
+		let cycles = two_operands_cycles(&op, &from, &to);
+
 		/* TODO: use interleaved match blocks and only create variables when needed, 
 		 * solving the ownership fight */
 		let store = |cpu: &mut CPU, mem: &mut Memory, result: u16|
@@ -354,13 +447,13 @@ impl CPU
 			{
 				let (w1, w2) = self.load_woperand_32(mem, &from);
 				store(self, mem, w1); // First word is the address
-				self.ds = w2; // DS receives the second word
+				self.load_segment(mem, SegReg::DS, w2); // DS receives the second word
 			},
 			TwoOperandsOpCode::LES =>
 			{
 				let (w1, w2) = self.load_woperand_32(mem, &from);
 				store(self, mem, w1); // First word is the address
-				self.es = w2; // DS receives the second word
+				self.load_segment(mem, SegReg::ES, w2); // ES receives the second word
 			},
 			TwoOperandsOpCode::LEA =>
 			{
@@ -376,8 +469,50 @@ impl CPU
 
 				store(self, mem, offset);
 			},
+			TwoOperandsOpCode::BOUND =>
+			{
+				let (lower, upper) = self.load_woperand_32(mem, &from);
+				let index = self.load_operand(mem, &to) as i16;
+				if index < lower as i16 || index > upper as i16
+				{
+					self.raise_exception(mem, 5);
+				}
+			},
+			TwoOperandsOpCode::LAR | TwoOperandsOpCode::LSL | TwoOperandsOpCode::ARPL if !self.protected_mode() =>
+				self.raise_exception(mem, EXC_INVALID_OPCODE),
+			TwoOperandsOpCode::LAR | TwoOperandsOpCode::LSL =>
+			{
+				let selector = self.load_operand(mem, &from);
+				/* ZF tells whether the descriptor was visible */
+				match self.descriptor_info(mem, selector, op == TwoOperandsOpCode::LAR)
+				{
+					Some(value) =>
+					{
+						store(self, mem, value);
+						self.set_flag(FLAG_Z);
+					}
+					None => self.clear_flag(FLAG_Z)
+				}
+			},
+			TwoOperandsOpCode::ARPL =>
+			{
+				/* Raises the RPL of the selector to the one of 'from' */
+				let selector = self.load_operand(mem, &to);
+				let rpl = self.load_operand(mem, &from) & 0x3;
+				if selector & 0x3 < rpl
+				{
+					store(self, mem, (selector & 0xfffc) | rpl);
+					self.set_flag(FLAG_Z);
+				}
+				else
+				{
+					self.clear_flag(FLAG_Z);
+				}
+			},
 			_ => self.run_tgop_ins(mem, op, &from, &to)
 		}
+
+		cycles
 	}
 
 	pub fn set_szp_flags<ValueType>(&mut self, val: &ValueType)
@@ -513,8 +648,10 @@ impl CPU
 		}
 	}
 
-	pub fn run_tbop_ins(&mut self, mem: &mut Memory, op: TwoOperandsOpCode, from: BOperand, to: BOperand)
+	pub fn run_tbop_ins(&mut self, mem: &mut Memory, op: TwoOperandsOpCode, from: BOperand, to: BOperand) -> u32
 	{
+		let cycles = two_operands_cycles(&op, &from, &to);
+
 		/* TODO: use interleaved match blocks and only create variables when needed, 
 		 * solving the ownership fight */
 
@@ -523,29 +660,36 @@ impl CPU
 			TwoOperandsOpCode::LDS => panic!("LDS cannot be used with byte operands"),
 			_ => self.run_tgop_ins(mem, op, &from, &to)
 		}
+
+		cycles
 	}
 
 	fn get_srcount(&self, shift_count: ShiftRotateCount) -> u8
 	{
-		match shift_count
+		let cnt = match shift_count
 		{
 			ShiftRotateCount::CL => self.get_reg(BReg::CL),
 			ShiftRotateCount::One => 1,
 			ShiftRotateCount::Imm(x) => x
-		}
+		};
+
+		/* The 80186 and later mask the count with 0x1f, but not the 8086,
+		 * which can spend a long time shifting by 255 */
+		if self.timing.model.has_80186_instructions() { cnt & 0x1f } else { cnt }
 	}
 
-	pub fn run_srgop_ins<OperandType>(&mut self, mem: &mut Memory, op: ShiftRotateOpCode, shift_count: ShiftRotateCount, operand: OperandType)
-		where CPU: OperandAccess<OperandType>
+	pub fn run_srgop_ins<OperandType>(&mut self, mem: &mut Memory, op: ShiftRotateOpCode, shift_count: ShiftRotateCount, operand: OperandType) -> u32
+		where CPU: OperandAccess<OperandType>, OperandType: TimedOperand
 	{
 		let from_value = self.load_operand(mem, &operand);
-		/* Processors >= 286 mask the count with 0x1f, but not the 8086... */
+		let by_one = shift_count == ShiftRotateCount::One;
 		let cnt = self.get_srcount(shift_count);
+		let cycles = shift_rotate_cycles(by_one, &operand, cnt);
 
 		/* We should not do anything if cnt == 0; rather than handling this case
 		 * in many places, just discard it here */
 		if cnt == 0
-		{ return }
+		{ return cycles }
 
 		/* 8 for u8, 16 for u16 */
 		let bit_count = <CPU as OperandAccess<OperandType>>::ValueType::bit_count();
@@ -627,7 +771,18 @@ impl CPU
 				{ self.set_flag_value(FLAG_C, from_value.rcrcl_c_flag_fo(has_carry, cnt)); }
 				self.store_operand(mem, &operand, to_value)
 			}
+			ShiftRotateOpCode::SETMO =>
+			{
+				let mut to_value = zero;
+				to_value.not();
+				self.set_szp_flags(&to_value);
+				self.set_oc_flags((false, false));
+				self.set_flag_value(FLAG_A, false);
+				self.store_operand(mem, &operand, to_value)
+			}
 		}
+
+		cycles
 	}
 
 	pub fn run_sgop_ins<OperandType>(&mut self, mem: &mut Memory, op: SingleOperandOpCode, operand: &OperandType)
@@ -692,7 +847,7 @@ impl CPU
 			{
 				match value.div(self.get_aux(), self.get_acc())
 				{
-					None => self.request_interrupt(mem, 0),
+					None => self.raise_exception(mem, 0),
 					Some((quot, remain)) =>
 					{
 						self.set_acc(quot);
@@ -704,7 +859,7 @@ impl CPU
 			{
 				match value.idiv(self.get_aux(), self.get_acc())
 				{
-					None => self.request_interrupt(mem, 0),
+					None => self.raise_exception(mem, 0),
 					Some((quot, remain)) =>
 					{
 						self.set_acc(quot);
@@ -716,8 +871,10 @@ impl CPU
 		}
 	}
 
-	pub fn run_sbop_ins(&mut self, mem: &mut Memory, op: SingleOperandOpCode, oper: BOperand)
+	pub fn run_sbop_ins(&mut self, mem: &mut Memory, op: SingleOperandOpCode, oper: BOperand) -> u32
 	{
+        let cycles = single_operand_cycles(&op, &oper, false);
+
         match op {
             SingleOperandOpCode::AAM => {
                 let tmp = self.get_reg(BReg::AL);
@@ -753,61 +910,118 @@ impl CPU
 
             _ => self.run_sgop_ins(mem, op, &oper)
         }
+
+        cycles
 	}
 
-	pub fn run_swop_ins(&mut self, mem: &mut Memory, op: SingleOperandOpCode, oper: WOperand)
+	pub fn run_swop_ins(&mut self, mem: &mut Memory, op: SingleOperandOpCode, oper: WOperand) -> u32
 	{
+		let cycles = single_operand_cycles(&op, &oper, true);
 		let op_value = self.load_operand(mem, &oper);
 
 		match op
 		{
 			SingleOperandOpCode::PUSH => 
 			{
-				// TODO: PUSH SP behaves differently between 8086 and 286+ processors
-				self.stack_push(mem, op_value)
+				/* The 8086 and the 80186 push SP as decremented by the push,
+				 * the 80286 as it was before */
+				let value = match oper
+				{
+					WOperand::Reg(WReg::SP) if !self.timing.model.has_protected_mode() => op_value.wrapping_sub(2),
+					_ => op_value
+				};
+				self.stack_push(mem, value)
 			},
 			SingleOperandOpCode::POP =>
 			{
-				if oper == WOperand::SegReg(SegReg::CS)
-				{
-					println!("Error: POP CS instruction encountered.");
-					println!("Either the code is 8086-specific and really intends to this horrible thing, either you are running 186+ code.");
-					println!("Aborting before this gets ugly...");
-					self.state = CPUState::Crashed;
-				}
-				else
-				{
-					let val = self.stack_pop(mem);
-					self.store_operand(mem, &oper, val)
-				}
+				/* Including POP CS, which only the 8086 has (0x0f is an
+				 * invalid opcode on the 80186) */
+				let val = self.stack_pop(mem);
+				self.store_operand(mem, &oper, val)
 			},
 			_ => self.run_sgop_ins(mem, op, &oper)
 		}
+
+		cycles
+	}
+
+	/* 80186 IMUL reg, rm, imm: the product is truncated to 16 bits */
+	pub fn run_thwop_ins(&mut self, mem: &mut Memory, op: ThreeOperandsOpCode, from: WOperand, imm: WOperand, to: WOperand) -> u32
+	{
+		let cycles = three_operands_cycles(&op, &from);
+
+		match op
+		{
+			ThreeOperandsOpCode::IMUL =>
+			{
+				let a = self.load_operand(mem, &from) as i16 as i32;
+				let b = self.load_operand(mem, &imm) as i16 as i32;
+				let product = a * b;
+				let overflow = product != product as i16 as i32;
+				self.set_oc_flags((overflow, overflow));
+				self.store_operand(mem, &to, product as u16);
+			}
+		}
+
+		cycles
 	}
 
-	pub fn handle_prefix(&mut self, prefix: Prefix)
+	/* 80186 ENTER: pushes BP, copies 'level' - 1 frame pointers of the
+	 * enclosing procedures, then allocates 'size' bytes */
+	pub fn run_enter_ins(&mut self, mem: &mut Memory, size: u16, level: u8) -> u32
 	{
-		match prefix
+		let level = level & 0x1f;
+		let bp = self.bp;
+		self.stack_push(mem, bp);
+		let frame = self.sp;
+
+		if level > 0
 		{
-			Prefix::LOCK => cpu_print!("Warning: unhandled prefix: LOCK{}",""),
-			Prefix::SEGMENT(sr) => self.segment_override_prefix = Some(sr),
-			Prefix::REP => self.rep_prefix = Some(RepPrefix::Rep),
-			Prefix::REPNE => self.rep_prefix = Some(RepPrefix::Repne)
+			for _ in 1 .. level
+			{
+				self.bp = self.bp.wrapping_sub(2);
+				let pointer = self.load_memory_u16_noov(mem, SegReg::SS, self.bp);
+				self.stack_push(mem, pointer);
+			}
+			self.stack_push(mem, frame);
 		}
+
+		self.bp = frame;
+		self.sp = self.sp.wrapping_sub(size);
+
+		enter_cycles(level)
 	}
 
-	pub fn run_imgop_ins<OperandType>(&mut self, mem: &mut Memory, op: ImplicitOperandOpCode, src: &OperandType, dst: &OperandType)
-		where CPU: OperandAccess<OperandType>
+	pub fn handle_prefixes(&mut self, prefixed: &PrefixedInstruction)
+	{
+		if prefixed.lock
+		{
+			cpu_print!("Warning: unhandled prefix: LOCK{}","");
+		}
+		if let Some(sreg) = prefixed.segment_override
+		{
+			self.segment_override_prefix = Some(sreg);
+		}
+		if let Some(rep) = prefixed.rep_prefix
+		{
+			self.rep_prefix = Some(rep);
+		}
+	}
+
+	pub fn run_imgop_ins<OperandType>(&mut self, mem: &mut Memory, hw: &mut HW, op: ImplicitOperandOpCode, src: &OperandType, dst: &OperandType) -> u32
+		where CPU: PortAccess<OperandType>
 	{
 		let operand_size = (<CPU as OperandAccess<OperandType>>::ValueType::bit_count() / 8) as i16;
 		let increment: u16 = if self.flags & FLAG_D != 0 {(-1 * operand_size) as u16} else {operand_size as u16};
+		let (single_cycles, repeated_cycles) = string_cycles(&op);
+		let repeated = self.rep_prefix != None;
 		
-		if self.rep_prefix != None && self.cx == 0
+		if repeated && self.cx == 0
 		{
 			/* We can reach this case if somebody
 			 * attempts to run REP XXX with CX=0 */
 			self.rep_prefix = None;
-			return;
+			return rep_string_cycles(0, true);
 		}
 
 		match op
@@ -851,6 +1065,20 @@ impl CPU
 				self.set_szp_flags(&result);
 
 				self.di = self.di.wrapping_add(increment);
+			},
+			ImplicitOperandOpCode::INS =>
+			{
+				let port = self.dx;
+				let value = self.port_in(mem, port, hw);
+				self.store_operand(mem, dst, value);
+				self.di = self.di.wrapping_add(increment);
+			},
+			ImplicitOperandOpCode::OUTS =>
+			{
+				let value = self.load_operand(mem, src);
+				let port = self.dx;
+				self.port_out(mem, port, value, hw);
+				self.si = self.si.wrapping_add(increment);
 			}
 		}
 
@@ -863,6 +1091,7 @@ impl CPU
 				match op
 				{
 					ImplicitOperandOpCode::MOVS | ImplicitOperandOpCode::STOS | ImplicitOperandOpCode::LODS => false, // LODS can actually be used with REP... TODO: Why?
+					ImplicitOperandOpCode::INS | ImplicitOperandOpCode::OUTS => false,
 					ImplicitOperandOpCode::CMPS | ImplicitOperandOpCode::SCAS =>
 					match rep_mode
 					{
@@ -884,10 +1113,20 @@ impl CPU
 				}
 			}
 		}
+
+		if repeated
+		{
+			rep_string_cycles(repeated_cycles, self.rep_prefix == None)
+		}
+		else
+		{
+			single_cycles
+		}
 	}
 
-	pub fn run_sfcop_ins(&mut self, mem: &mut Memory, op: SingleOperandFCOpCode, to: FlowControlOperand)
+	pub fn run_sfcop_ins(&mut self, mem: &mut Memory, op: SingleOperandFCOpCode, to: FlowControlOperand) -> u32
 	{
+		let cycles = flow_control_cycles(&op, &to);
 		let (is_far, cs, ip) = self.load_fcoperand(mem, &to);
 
 		match op
@@ -902,13 +1141,25 @@ impl CPU
 					}
 				}
 
-                self.cs = cs;
-                self.ip = ip;
+				if is_far && self.protected_mode()
+				{
+					self.protected_far_transfer(mem, cs, ip, false);
+				}
+				else
+				{
+					self.cs = cs;
+					self.ip = ip;
+				}
 			}
 			SingleOperandFCOpCode::CALL =>
 			{
 				let (old_cs, old_ip) = (self.cs, self.ip);
 
+				if is_far && self.protected_mode()
+				{
+					self.protected_far_transfer(mem, cs, ip, true);
+					return cycles
+				}
 				if is_far
 				{
 					if cfg!(feature="cpu-trace")
@@ -926,28 +1177,26 @@ impl CPU
         		self.cs = cs;
         		self.ip = ip;
 			}
-            SingleOperandFCOpCode::FNSTCW => {
-                if 0 < (self.get_cr0() & 0x4) {
-                    self.request_interrupt(mem, 0x02);
-                } else {
-                    panic!("FPU not implemented");
-                }
-            }
 		}
+
+		cycles
 	}
 
-	pub fn run_fcnoop_seg_ins(&mut self, mem: &Memory, op: NoOpFCOpCode)
+	pub fn run_fcnoop_seg_ins(&mut self, mem: &Memory, op: NoOpFCOpCode) -> u32
 	{
 		match op
 		{
 			NoOpFCOpCode::RET => self.ip = self.stack_pop(mem)
 		}
+
+		return_cycles(false, false)
 	}
 
-	pub fn run_fcnoop_iseg_ins(&mut self, mem: &Memory, op: NoOpFCOpCode)
+	pub fn run_fcnoop_iseg_ins(&mut self, mem: &mut Memory, op: NoOpFCOpCode) -> u32
 	{
 		match op
 		{
+			NoOpFCOpCode::RET if self.protected_mode() => self.protected_far_return(mem, 0),
 			NoOpFCOpCode::RET =>
 			{
 				let ip = self.stack_pop(mem);
@@ -965,9 +1214,11 @@ impl CPU
 				self.cs = cs;
 			}
 		}
+
+		return_cycles(true, false)
 	}
 
-	pub fn run_swfcop_ins(&mut self, mem: &Memory, op: SingleWImmFCOpCode, x: SingleWImmFCOperand)
+	pub fn run_swfcop_ins(&mut self, mem: &mut Memory, op: SingleWImmFCOpCode, x: SingleWImmFCOperand) -> u32
 	{
 		match op
 		{
@@ -979,6 +1230,12 @@ impl CPU
 					{
 						self.ip = self.stack_pop(mem);
 						self.sp += to_add;
+						return_cycles(false, true)
+					}
+					SingleWImmFCOperand::InterSeg(to_add) if self.protected_mode() =>
+					{
+						self.protected_far_return(mem, to_add);
+						return_cycles(true, true)
 					}
 					SingleWImmFCOperand::InterSeg(to_add) =>
 					{
@@ -996,6 +1253,7 @@ impl CPU
 						self.ip = ip;
 						self.cs = cs;
 						self.sp += to_add;
+						return_cycles(true, true)
 					}
 				}
 			}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::instruction::*;
+	use super::super::timing::CpuModel;
+	use super::super::super::mem::Memory;
+
+	fn cpu(model: CpuModel) -> CPU
+	{
+		let mut cpu = CPU::new(0x0000, 0x1000, model);
+		cpu.ss = 0x0000;
+		cpu.sp = 0x8000;
+		cpu.bp = 0x9000;
+		cpu
+	}
+
+	#[test]
+	fn pusha_popa()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I80186);
+		cpu.set_registers(&[1, 2, 3, 4, 0x8000, 6, 7, 8, 0, 0, 0, 0, 0x1000, FLAG_SET_8086]);
+
+		cpu.run_noop_ins(&mut mem, NoOperandOpCode::PUSHA);
+		assert_eq!(cpu.sp, 0x7ff0);
+		/* DI first, AX last */
+		let pushed: Vec<u16> = (0 .. 8).map(|i| mem.read_u16(0x7ff0 + i * 2)).collect();
+		assert_eq!(pushed, vec![8, 7, 6, 0x8000, 2, 4, 3, 1]);
+
+		mem.write_u16(0x7ff6, 0x1234);
+		cpu.set_registers(&[0; 14]);
+		cpu.sp = 0x7ff0;
+		cpu.run_noop_ins(&mut mem, NoOperandOpCode::POPA);
+		assert_eq!(&cpu.registers()[.. 8], &[1, 2, 3, 4, 0x8000, 6, 7, 8]);
+	}
+
+	#[test]
+	fn enter_leave()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I80186);
+
+		/* Outer procedure, then a nested one copying its frame pointer */
+		cpu.run_enter_ins(&mut mem, 4, 0);
+		assert_eq!((cpu.bp, cpu.sp, mem.read_u16(0x7ffe)), (0x7ffe, 0x7ffa, 0x9000));
+		mem.write_u16(0x7ffc, 0xaaaa);
+		cpu.run_enter_ins(&mut mem, 2, 2);
+		assert_eq!((cpu.bp, cpu.sp), (0x7ff8, 0x7ff2));
+		let frame: Vec<u16> = (0 .. 3).map(|i| mem.read_u16(0x7ff4 + i * 2)).collect();
+		assert_eq!(frame, vec![0x7ff8, 0xaaaa, 0x7ffe]);
+
+		cpu.run_noop_ins(&mut mem, NoOperandOpCode::LEAVE);
+		assert_eq!((cpu.bp, cpu.sp), (0x7ffe, 0x7ffa));
+		cpu.run_noop_ins(&mut mem, NoOperandOpCode::LEAVE);
+		assert_eq!((cpu.bp, cpu.sp), (0x9000, 0x8000));
+	}
+
+	#[test]
+	fn imul_immediate()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I80186);
+		cpu.cx = 0xfffd;
+
+		cpu.run_thwop_ins(&mut mem, ThreeOperandsOpCode::IMUL,
+			WOperand::Reg(WReg::CX), WOperand::Immediate(0x0100), WOperand::Reg(WReg::BX));
+		assert_eq!((cpu.bx, cpu.flags & (FLAG_C | FLAG_O)), (0xfd00, 0));
+
+		cpu.run_thwop_ins(&mut mem, ThreeOperandsOpCode::IMUL,
+			WOperand::Reg(WReg::CX), WOperand::Immediate(0x4000), WOperand::Reg(WReg::BX));
+		assert_eq!((cpu.bx, cpu.flags & (FLAG_C | FLAG_O)), (0x4000, FLAG_C | FLAG_O));
+	}
+
+	#[test]
+	fn bound()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I80186);
+		cpu.ds = 0x0000;
+		cpu.ax = 0xffff;
+		cpu.instruction_ip = 0x0ffc;
+		mem.write_u16(0x500, 0xfffe);
+		mem.write_u16(0x502, 0x0010);
+		mem.write_u16(5 * 4, 0x0100);
+		mem.write_u16(5 * 4 + 2, 0x0200);
+
+		/* -1 is within [-2, 16] */
+		cpu.run_twop_ins(&mut mem, TwoOperandsOpCode::BOUND, WOperand::Direct(0x500), WOperand::Reg(WReg::AX));
+		assert_eq!((cpu.cs, cpu.ip), (0x0000, 0x1000));
+
+		cpu.ax = 0x0011;
+		cpu.run_twop_ins(&mut mem, TwoOperandsOpCode::BOUND, WOperand::Direct(0x500), WOperand::Reg(WReg::AX));
+		assert_eq!((cpu.cs, cpu.ip), (0x0200, 0x0100));
+		/* The handler returns to BOUND */
+		assert_eq!(mem.read_u16(0x7ffa), 0x0ffc);
+	}
+
+	#[test]
+	fn shift_count()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		for &(model, expected) in &[(CpuModel::I8086, 0x0000), (CpuModel::I80188, 0x0002)]
+		{
+			let mut cpu = cpu(model);
+			cpu.ax = 0x0001;
+			cpu.cx = 0x0021;
+			cpu.run_srgop_ins(&mut mem, ShiftRotateOpCode::SHL, ShiftRotateCount::CL, WOperand::Reg(WReg::AX));
+			assert_eq!(cpu.ax, expected);
+		}
+	}
+
+	#[test]
+	fn setmo()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I8086);
+		cpu.ax = 0x1234;
+		cpu.flags = FLAG_C | FLAG_O | FLAG_Z;
+		cpu.run_srgop_ins(&mut mem, ShiftRotateOpCode::SETMO, ShiftRotateCount::One, BOperand::Reg(BReg::AL));
+		assert_eq!(cpu.ax, 0x12ff);
+		assert_eq!(cpu.flags & (FLAG_C | FLAG_O | FLAG_Z | FLAG_S | FLAG_P), FLAG_S | FLAG_P);
+
+		/* Nothing happens with a zero count */
+		cpu.cx = 0;
+		cpu.run_srgop_ins(&mut mem, ShiftRotateOpCode::SETMO, ShiftRotateCount::CL, WOperand::Reg(WReg::AX));
+		assert_eq!(cpu.ax, 0x12ff);
+		cpu.cx = 1;
+		cpu.run_srgop_ins(&mut mem, ShiftRotateOpCode::SETMO, ShiftRotateCount::CL, WOperand::Reg(WReg::AX));
+		assert_eq!(cpu.ax, 0xffff);
+	}
+}
@@ -1,12 +1,30 @@
 use super::base::*;
+use super::operand_access::OperandAccess;
+use super::traps::TrapEvent;
 use super::super::hw::HW;
+use super::super::mem::Memory;
+
+/* PS/2 "fast A20" gate: bit 1 enables A20, bit 0 resets the CPU */
+const SYSTEM_CONTROL_A20: u8 = 0x2;
+const SYSTEM_CONTROL_RESET: u8 = 0x1;
+/* NMI mask register of the PC */
+const NMI_MASK_ENABLE: u8 = 0x80;
+/* Bit 7 of the CMOS index on the PC/AT */
+const CMOS_NMI_DISABLE: u8 = 0x80;
 
 impl CPU
 {
-	pub fn io_inb(&mut self, port: u16, hw: &mut HW) -> u8
+	pub fn io_inb(&mut self, mem: &Memory, port: u16, hw: &mut HW) -> u8
 	{
-		match port
+		if !self.check_io_privilege()
 		{
+			return 0
+		}
+		let value = match port
+		{
+			0x20 => hw.pic.read_command(),
+			0x21 => hw.pic.read_data(),
+			0x40 ... 0x42 => hw.pit.read_counter((port - 0x40) as usize),
 			0x60 =>
 			{
 				match hw.keyboard.io_get_scancode()
@@ -15,7 +33,15 @@ impl CPU
 					None => 0x0
 				}
 			}
-			0x61 => hw.keyboard.get_ppi_a(),
+			0x61 =>
+			{
+				/* Bit 5 reflects the output of the PIT channel 2 */
+				let timer2_out = if hw.pit.output(2) { 0x20 } else { 0x0 };
+				(hw.keyboard.get_ppi_a() & !0x20) | timer2_out
+			}
+			0x64 => hw.keyboard.get_controller_status(),
+			0x71 => hw.cmos.read_data(),
+			0x92 => if mem.a20() { SYSTEM_CONTROL_A20 } else { 0x0 },
 			0x3da =>
 			{
 				hw.display.get_status_reg()
@@ -31,26 +57,97 @@ impl CPU
 				cpu_print!("Warning: byte input from unknown IO port {:04x}", port);
 				0x0
 			}
+		};
+		if !self.traps.is_empty()
+		{
+			self.check_traps(TrapEvent::PortIn { port: port, size: 1, value: value as u16 });
 		}
+		value
 	}
 
-	pub fn io_inw(&mut self, port: u16, _hw: &mut HW) -> u16
+	pub fn io_inw(&mut self, _mem: &Memory, port: u16, _hw: &mut HW) -> u16
 	{
-		match port
+		if !self.check_io_privilege()
+		{
+			return 0
+		}
+		let value = match port
 		{
 			_ =>
 			{
 				cpu_print!("Warning: word input from unknown IO port {:04x}", port);
 				0x0
 			}
+		};
+		if !self.traps.is_empty()
+		{
+			self.check_traps(TrapEvent::PortIn { port: port, size: 2, value: value });
 		}
+		value
 	}
 
-	pub fn io_outb(&mut self, port: u16, value: u8, hw: &mut HW)
+	pub fn io_outb(&mut self, mem: &mut Memory, port: u16, value: u8, hw: &mut HW)
 	{
+		if !self.check_io_privilege()
+		{
+			return
+		}
+		if !self.traps.is_empty()
+		{
+			self.check_traps(TrapEvent::PortOut { port: port, size: 1, value: value as u16 });
+		}
 		match port
 		{
-			0x61 => hw.keyboard.set_ppi_a(value),
+			0x20 => hw.pic.write_command(value),
+			0x21 => hw.pic.write_data(value),
+			0x40 ... 0x42 => hw.pit.write_counter((port - 0x40) as usize, value),
+			0x43 => hw.pit.write_control(value),
+			0x60 => match hw.keyboard.take_controller_command()
+			{
+				/* Output port of the keyboard controller: bit 1 gates A20,
+				 * clearing bit 0 resets the CPU */
+				Some(0xd1) =>
+				{
+					mem.set_a20(value & 0x2 != 0);
+					if value & 0x1 == 0
+					{
+						self.reset();
+					}
+				}
+				_ => cpu_print!("Warning: byte output {:04x} to the keyboard", value)
+			},
+			0x61 =>
+			{
+				/* Bit 0 gates the PIT channel 2, bit 1 enables the speaker */
+				hw.pit.set_gate(2, value & 0x1 != 0);
+				hw.speaker.set_ppi_bits(value);
+				hw.keyboard.set_ppi_a(value);
+			}
+			0x64 => match value
+			{
+				0xd1 => hw.keyboard.set_controller_command(value),
+				0xdd => mem.set_a20(false),
+				0xdf => mem.set_a20(true),
+				/* Pulses the reset line: how the 80286 gets back to real mode */
+				0xfe => self.reset(),
+				_ => cpu_print!("Warning: unknown keyboard controller command {:02x}", value)
+			},
+			0x70 =>
+			{
+				hw.nmi_enabled = value & CMOS_NMI_DISABLE == 0;
+				hw.cmos.write_index(value);
+			}
+			0x71 => hw.cmos.write_data(value),
+			0x92 =>
+			{
+				mem.set_a20(value & SYSTEM_CONTROL_A20 != 0);
+				if value & SYSTEM_CONTROL_RESET != 0
+				{
+					self.reset();
+				}
+			}
+			/* The second PIC of the PC/AT is not emulated */
+			0xa0 if !self.timing.model.has_protected_mode() => hw.nmi_enabled = value & NMI_MASK_ENABLE != 0,
 			0x3f8 => hw.com1.write_rtd(value),
 			0x3f9 => hw.com1.write_ier(value),
 			0x3fb => hw.com1.write_lc(value),
@@ -62,14 +159,41 @@ impl CPU
 		}
 	}
 
-	pub fn io_outw(&mut self, port: u16, _value: u16, _hw: &mut HW)
+	pub fn io_outw(&mut self, _mem: &mut Memory, port: u16, value: u16, _hw: &mut HW)
 	{
+		if !self.check_io_privilege()
+		{
+			return
+		}
+		if !self.traps.is_empty()
+		{
+			self.check_traps(TrapEvent::PortOut { port: port, size: 2, value: value });
+		}
 		match port
 		{
 			_ =>
 			{
-				cpu_print!("Warning: word output {:04x} to unknown IO port {:04x}", _value, port);
+				cpu_print!("Warning: word output {:04x} to unknown IO port {:04x}", value, port);
 			}
 		}
 	}
-}
\ No newline at end of file
+}
+
+/* Port I/O of the size of a string instruction operand (INS/OUTS) */
+pub trait PortAccess<OperandType>: OperandAccess<OperandType>
+{
+	fn port_in(&mut self, mem: &Memory, port: u16, hw: &mut HW) -> <Self as OperandAccess<OperandType>>::ValueType;
+	fn port_out(&mut self, mem: &mut Memory, port: u16, value: <Self as OperandAccess<OperandType>>::ValueType, hw: &mut HW);
+}
+
+impl PortAccess<ImplicitBOperand> for CPU
+{
+	fn port_in(&mut self, mem: &Memory, port: u16, hw: &mut HW) -> u8 { self.io_inb(mem, port, hw) }
+	fn port_out(&mut self, mem: &mut Memory, port: u16, value: u8, hw: &mut HW) { self.io_outb(mem, port, value, hw) }
+}
+
+impl PortAccess<ImplicitWOperand> for CPU
+{
+	fn port_in(&mut self, mem: &Memory, port: u16, hw: &mut HW) -> u16 { self.io_inw(mem, port, hw) }
+	fn port_out(&mut self, mem: &mut Memory, port: u16, value: u16, hw: &mut HW) { self.io_outw(mem, port, value, hw) }
+}
@@ -4,18 +4,18 @@ use super::super::mem::Memory;
 
 impl CPU
 {
-	pub fn get_ireg_addr(&self, ir: &IndReg) -> (u16, u16) // Seg, Addr
+	pub fn get_ireg_addr(&self, ir: &IndReg) -> (SegReg, u16) // Seg, Addr
 	{
 		match *ir
 		{
-			IndReg::BX => (self.ds, self.bx),
-			IndReg::BP => (self.ss, self.bp),
-			IndReg::SI => (self.ds, self.si),
-			IndReg::DI => (self.ds, self.di),
-			IndReg::BXSI => (self.ds, self.bx.wrapping_add(self.si)),
-			IndReg::BXDI => (self.ds, self.bx.wrapping_add(self.di)),
-			IndReg::BPSI => (self.ss, self.bp.wrapping_add(self.si)),
-			IndReg::BPDI => (self.ss, self.bp.wrapping_add(self.di))
+			IndReg::BX => (SegReg::DS, self.bx),
+			IndReg::BP => (SegReg::SS, self.bp),
+			IndReg::SI => (SegReg::DS, self.si),
+			IndReg::DI => (SegReg::DS, self.di),
+			IndReg::BXSI => (SegReg::DS, self.bx.wrapping_add(self.si)),
+			IndReg::BXDI => (SegReg::DS, self.bx.wrapping_add(self.di)),
+			IndReg::BPSI => (SegReg::SS, self.bp.wrapping_add(self.si)),
+			IndReg::BPDI => (SegReg::SS, self.bp.wrapping_add(self.di))
 		}
 	}
 
@@ -1,10 +1,10 @@
 use mem::Memory;
 use super::base::*;
-use super::reg_access::*;
+use super::instruction::SegReg;
 
 impl CPU
 {
-	pub fn apply_segment_override(&self, seg: u16) -> u16
+	pub fn apply_segment_override(&self, seg: SegReg) -> SegReg
 	{
 		// TODO: WARNING: if BP was used, then seg should be overriden with BP (see page 28 of user manual)
 		// TODO2: no override if we are actually using ES (destination of string operations)
@@ -12,49 +12,75 @@ impl CPU
 		match self.segment_override_prefix
 		{
 			None => seg,
-			Some(sr) => self.get_reg(sr)
+			Some(sr) => sr
 		}
 	}
 
 	// TODO/ read/write or load/store, but avoid both
-	pub fn store_memory_u16(&self, mem: &mut Memory, seg: u16, addr: u16, val: u16)
+	pub fn store_memory_u16(&self, mem: &mut Memory, seg: SegReg, addr: u16, val: u16)
 	{
-		mem.write_u16((self.apply_segment_override(seg) as u32) * 0x10 + addr as u32, val)
+		self.store_memory_u16_noov(mem, self.apply_segment_override(seg), addr, val)
 	}
 
-	pub fn load_memory_u16(&self, mem: &Memory, seg: u16, addr: u16) -> u16
+	pub fn load_memory_u16(&self, mem: &Memory, seg: SegReg, addr: u16) -> u16
 	{
-		mem.read_u16((self.apply_segment_override(seg) as u32) * 0x10 + addr as u32)
+		self.load_memory_u16_noov(mem, self.apply_segment_override(seg), addr)
 	}
 
-	pub fn store_memory_u8(&self, mem: &mut Memory, seg: u16, addr: u16, val: u8)
+	pub fn store_memory_u8(&self, mem: &mut Memory, seg: SegReg, addr: u16, val: u8)
 	{
-		mem.write_u8((self.apply_segment_override(seg) as u32) * 0x10 + addr as u32, val)
+		self.store_memory_u8_noov(mem, self.apply_segment_override(seg), addr, val)
 	}
 
-	pub fn load_memory_u8(&self, mem: &Memory, seg: u16, addr: u16) -> u8
+	pub fn load_memory_u8(&self, mem: &Memory, seg: SegReg, addr: u16) -> u8
 	{
-		mem.read_u8((self.apply_segment_override(seg) as u32) * 0x10 + addr as u32)
+		self.load_memory_u8_noov(mem, self.apply_segment_override(seg), addr)
 	}
 
-	/* No override variants (for DI with string instructions with invariably use ES) */
-	pub fn store_memory_u16_noov(&self, mem: &mut Memory, seg: u16, addr: u16, val: u16)
+	/* No override variants (for DI with string instructions with invariably use ES).
+	 * Accesses that fault in protected mode read as 0 and write nothing, the
+	 * instruction being abandoned anyway */
+	pub fn store_memory_u16_noov(&self, mem: &mut Memory, seg: SegReg, addr: u16, val: u16)
 	{
-		mem.write_u16((seg as u32) * 0x10 + addr as u32, val)
+		if let Some(phys) = self.linear_address(seg, addr, 2, true)
+		{
+			self.timing.on_word_access(phys);
+			mem.write_u16(phys, val)
+		}
 	}
 
-	pub fn load_memory_u16_noov(&self, mem: &Memory, seg: u16, addr: u16) -> u16
+	pub fn load_memory_u16_noov(&self, mem: &Memory, seg: SegReg, addr: u16) -> u16
 	{
-		mem.read_u16((seg as u32) * 0x10 + addr as u32)
+		match self.linear_address(seg, addr, 2, false)
+		{
+			Some(phys) =>
+			{
+				self.timing.on_word_access(phys);
+				mem.read_u16(phys)
+			}
+			None => 0
+		}
 	}
 
-	pub fn store_memory_u8_noov(&self, mem: &mut Memory, seg: u16, addr: u16, val: u8)
+	pub fn store_memory_u8_noov(&self, mem: &mut Memory, seg: SegReg, addr: u16, val: u8)
 	{
-		mem.write_u8((seg as u32) * 0x10 + addr as u32, val)
+		if let Some(phys) = self.linear_address(seg, addr, 1, true)
+		{
+			self.timing.on_byte_access();
+			mem.write_u8(phys, val)
+		}
 	}
 
-	pub fn load_memory_u8_noov(&self, mem: &Memory, seg: u16, addr: u16) -> u8
+	pub fn load_memory_u8_noov(&self, mem: &Memory, seg: SegReg, addr: u16) -> u8
 	{
-		mem.read_u8((seg as u32) * 0x10 + addr as u32)
+		match self.linear_address(seg, addr, 1, false)
+		{
+			Some(phys) =>
+			{
+				self.timing.on_byte_access();
+				mem.read_u8(phys)
+			}
+			None => 0
+		}
 	}
-}
\ No newline at end of file
+}
@@ -2,16 +2,35 @@ pub mod base;
 pub mod instruction;
 pub mod parser;
 mod parser_tests;
+pub mod decode_cache;
+mod decode_cache_tests;
 pub mod mem_access;
 pub mod reg_access;
 pub mod ireg_access;
 pub mod operand_access;
 pub mod instruction_exec;
+mod instruction_exec_tests;
 pub mod exec;
+pub mod timing;
+mod timing_tests;
 pub mod debug;
 mod io_dispatch;
+pub mod traps;
+mod traps_tests;
+pub mod history;
+mod history_tests;
+pub mod protected;
+mod protected_tests;
+pub mod extended;
+mod extended_tests;
+pub mod fpu;
+mod fpu_tests;
 
 pub use self::base::*;
 pub use self::instruction::*;
 pub use self::reg_access::*;
-pub use self::parser::parse_instruction;
\ No newline at end of file
+pub use self::timing::{CpuModel, CPU_FREQUENCY_HZ};
+pub use self::parser::{parse_instruction, parse_model_instruction};
+pub use self::traps::{Trap, TrapEvent, TrapKind};
+pub use self::history::REGISTER_NAMES;
+pub use self::fpu::Fpu;
\ No newline at end of file
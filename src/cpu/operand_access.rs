@@ -727,7 +727,7 @@ impl OperandAccess<BOperand> for CPU
 			BOperand::Indirect(ref ir) => self.get_ireg_u8_value(mem, ir),
 			BOperand::Indirect8iDis(ref ir, dis) => self.get_ireg_u8d_u8_value(mem, ir, dis),
 			BOperand::Indirect16uDis(ref ir, dis) => self.get_ireg_u16d_u8_value(mem, ir, dis),
-			BOperand::Direct(addr) => self.load_memory_u8(mem, self.ds, addr),
+			BOperand::Direct(addr) => self.load_memory_u8(mem, SegReg::DS, addr),
 		}
 	}
 
@@ -739,7 +739,7 @@ impl OperandAccess<BOperand> for CPU
 			BOperand::Indirect(ref ir) => self.set_ireg_u8_value(mem, ir, val),
 			BOperand::Indirect8iDis(ref ir, dis) => self.set_ireg_u8d_u8_value(mem, ir, dis, val),
 			BOperand::Indirect16uDis(ref ir, dis) => self.set_ireg_u16d_u8_value(mem, ir, dis, val),
-			BOperand::Direct(addr) => self.store_memory_u8(mem, self.ds, addr, val),
+			BOperand::Direct(addr) => self.store_memory_u8(mem, SegReg::DS, addr, val),
 			_ => panic!("Unhandled byte operand write: {:?}", dst)
 		}
 	}
@@ -761,7 +761,7 @@ impl OperandAccess<WOperand> for CPU
 			WOperand::Reg(wr) => self.get_reg(wr),
 			WOperand::SegReg(sr) => self.get_reg(sr),
 			WOperand::Immediate(ref imm) => *imm,
-			WOperand::Direct(addr) => self.load_memory_u16(mem, self.ds, addr),
+			WOperand::Direct(addr) => self.load_memory_u16(mem, SegReg::DS, addr),
 			WOperand::Indirect(ref ir) => self.get_ireg_u16_value(mem, ir),
 			WOperand::Indirect8iDis(ref ir, dis) => self.get_ireg_u8d_u16_value(mem, ir, dis),
 			WOperand::Indirect16uDis(ref ir, dis) => self.get_ireg_u16d_u16_value(mem, ir, dis),
@@ -773,8 +773,8 @@ impl OperandAccess<WOperand> for CPU
 		match *dst
 		{
 			WOperand::Reg(wr) => self.set_reg(wr, val),
-			WOperand::SegReg(sr) => self.set_reg(sr, val),
-			WOperand::Direct(addr) => self.store_memory_u16(mem, self.ds, addr, val),
+			WOperand::SegReg(sr) => self.load_segment(mem, sr, val),
+			WOperand::Direct(addr) => self.store_memory_u16(mem, SegReg::DS, addr, val),
 			WOperand::Indirect(ref ir) => self.set_ireg_u16_value(mem, ir, val),
 			WOperand::Indirect8iDis(ref ir, dis) => self.set_ireg_u8d_u16_value(mem, ir, dis, val),
 			WOperand::Indirect16uDis(ref ir, dis) => self.set_ireg_u16d_u16_value(mem, ir, dis, val),
@@ -796,8 +796,8 @@ impl OperandAccess<ImplicitBOperand> for CPU
 	{
 		match *src
 		{
-			ImplicitBOperand::DSSI => self.load_memory_u8(mem, self.ds, self.si),
-			ImplicitBOperand::ESDI => self.load_memory_u8_noov(mem, self.es, self.di),
+			ImplicitBOperand::DSSI => self.load_memory_u8(mem, SegReg::DS, self.si),
+			ImplicitBOperand::ESDI => self.load_memory_u8_noov(mem, SegReg::ES, self.di),
 		}
 	}
 
@@ -805,8 +805,8 @@ impl OperandAccess<ImplicitBOperand> for CPU
 	{
 		match *dst
 		{
-			ImplicitBOperand::DSSI => self.store_memory_u8(mem, self.ds, self.si, val),
-			ImplicitBOperand::ESDI => self.store_memory_u8_noov(mem, self.es, self.di, val),
+			ImplicitBOperand::DSSI => self.store_memory_u8(mem, SegReg::DS, self.si, val),
+			ImplicitBOperand::ESDI => self.store_memory_u8_noov(mem, SegReg::ES, self.di, val),
 		}
 	}
 
@@ -824,16 +824,16 @@ impl OperandAccess<ImplicitWOperand> for CPU
 	{
 		match *src
 		{
-			ImplicitWOperand::DSSI => self.load_memory_u16(mem, self.ds, self.si),
-			ImplicitWOperand::ESDI => self.load_memory_u16_noov(mem, self.es, self.di),
+			ImplicitWOperand::DSSI => self.load_memory_u16(mem, SegReg::DS, self.si),
+			ImplicitWOperand::ESDI => self.load_memory_u16_noov(mem, SegReg::ES, self.di),
 		}	}
 
 	fn store_operand(&mut self, mem: &mut Memory, dst: &ImplicitWOperand, val: u16)
 	{
 		match *dst
 		{
-			ImplicitWOperand::DSSI => self.store_memory_u16(mem, self.ds, self.si, val),
-			ImplicitWOperand::ESDI => self.store_memory_u16_noov(mem, self.es, self.di, val),
+			ImplicitWOperand::DSSI => self.store_memory_u16(mem, SegReg::DS, self.si, val),
+			ImplicitWOperand::ESDI => self.store_memory_u16_noov(mem, SegReg::ES, self.di, val),
 		}
 	}
 
@@ -845,31 +845,36 @@ impl OperandAccess<ImplicitWOperand> for CPU
 
 impl CPU
 {
-	pub fn load_woperand_32(&self, mem: &Memory, src: &WOperand) -> (u16, u16)
+	/* Segment and offset of a memory operand, the segment override applied */
+	pub fn effective_address(&self, src: &WOperand) -> (SegReg, u16)
 	{
-		let (seg, addr) =
-			match *src
+		match *src
+		{
+			WOperand::Indirect(ref reg) => self.get_ireg_addr(&reg),
+			WOperand::Direct(ref addr) => (self.apply_segment_override(SegReg::DS), *addr),
+			WOperand::Indirect8iDis(ref reg, dis) =>
 			{
-				WOperand::Indirect(ref reg) => self.get_ireg_addr(&reg),
-				WOperand::Direct(ref addr) => (self.apply_segment_override(self.ds), *addr),
-				WOperand::Indirect8iDis(ref reg, dis) =>
-				{
-					let (seg, addr) = self.get_ireg_addr(&reg);
-					(seg, addr.wrapping_add((dis as i16) as u16))
-				}
-				WOperand::Indirect16uDis(ref reg, dis) =>
-				{
-					let (seg, addr) = self.get_ireg_addr(&reg);
-					(seg, addr.wrapping_add(dis))
-				}
-				WOperand::Immediate(_) => 
-					panic!("Attempting to load 32-bit value from an immediate 16-bit operand (cs:ip={:04x}:{:04x})", self.cs, self.ip),
-				_ => panic!("Unsupported 32-bit load from {:?}", src)
-			};
+				let (seg, addr) = self.get_ireg_addr(&reg);
+				(seg, addr.wrapping_add((dis as i16) as u16))
+			}
+			WOperand::Indirect16uDis(ref reg, dis) =>
+			{
+				let (seg, addr) = self.get_ireg_addr(&reg);
+				(seg, addr.wrapping_add(dis))
+			}
+			WOperand::Immediate(_) => 
+				panic!("Attempting to address an immediate 16-bit operand (cs:ip={:04x}:{:04x})", self.cs, self.ip),
+			_ => panic!("Unsupported memory operand {:?}", src)
+		}
+	}
+
+	pub fn load_woperand_32(&self, mem: &Memory, src: &WOperand) -> (u16, u16)
+	{
+		let (seg, addr) = self.effective_address(src);
 
 		(
 			self.load_memory_u16(mem, seg, addr), 
-			self.load_memory_u16(mem, seg, addr + 2)
+			self.load_memory_u16(mem, seg, addr.wrapping_add(2))
 		)
 	}
 
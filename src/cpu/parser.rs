@@ -1,4 +1,6 @@
+use super::base::RepPrefix;
 use super::instruction::*;
+use super::timing::CpuModel;
 
 fn get_breg(reg: u8) -> BReg
 {
@@ -144,781 +146,787 @@ fn get_rm_woperand(data: &[u8]) -> (WOperand, u16)
 	)
 }
 
-fn dispatch_w <OpCodeType, ByteFunc, WordFunc> (opcode: OpCodeType, w: u8, byte: ByteFunc, word: WordFunc) -> SizedInstruction
-	where ByteFunc: Fn(OpCodeType) -> SizedInstruction, WordFunc: Fn(OpCodeType) -> SizedInstruction
+/* How an opcode decodes; the operand size (w), direction (d) and register
+ * fields come from the opcode byte, the others from the ModRM byte */
+#[derive(Clone, Copy)]
+enum Form
 {
-	match w
-	{
-		0 => byte(opcode),
-		1 => word(opcode),
-		_ => panic!("Unexpected w")
-	}
+	Invalid,
+	Prefix(Prefix),
+	NoOperand(NoOperandOpCode),
+	String(ImplicitOperandOpCode),
+	/* reg and rm, in the order d gives */
+	RegRm(TwoOperandsOpCode),
+	/* reg, rm: XCHG, TEST and ARPL */
+	RmReg(TwoOperandsOpCode),
+	/* rm into a word register: LEA, LDS, LES, LAR... */
+	Load(TwoOperandsOpCode),
+	AccImm(TwoOperandsOpCode),
+	/* The register and w are in the low 4 bits of the opcode */
+	RegImm(TwoOperandsOpCode),
+	/* MOV between the accumulator and a direct address */
+	AccDirect,
+	Reg(SingleOperandOpCode),
+	XchgAx,
+	SegReg(SingleOperandOpCode),
+	/* MOV from or to a segment register; true for the 8086, which only
+	 * looks at the low 2 bits of the register and accepts MOV CS, rm */
+	MovSegReg(bool),
+	ShortImm(SingleBImmOperandOpCode),
+	/* IN and OUT through DX */
+	PortDx(SingleBImmOperandOpCode),
+	Int3,
+	Near(SingleOperandFCOpCode),
+	Far(SingleOperandFCOpCode),
+	Ret,
+	RetImm,
+	RetFar,
+	RetFarImm,
+	/* AAM and AAD */
+	ByteImm(SingleOperandOpCode),
+	Esc,
+	/* Decoded by the reg field of the ModRM byte */
+	Group(&'static [Form; 8]),
+	/* 0x0f, then the opcode byte */
+	TwoByte(&'static [Form; 8]),
+	/* Raises INT 6 with a register operand */
+	MemoryOnly(&'static Form),
+	ImmRm(TwoOperandsOpCode),
+	/* Word operand, sign-extended 8-bit immediate */
+	SignedImmRm(TwoOperandsOpCode),
+	Rm(SingleOperandOpCode),
+	/* By 1, CL or an immediate count depending on the opcode */
+	Shift(ShiftRotateOpCode),
+	Indirect(SingleOperandFCOpCode),
+	FarIndirect(SingleOperandFCOpCode),
+	PushImm,
+	PushSignedImm,
+	ImulImm,
+	ImulSignedImm,
+	Enter,
+	System(SystemOpCode)
 }
 
-pub fn decode_with_iced(bytecode: &[u8]) -> SizedInstruction
+/* 0x80, 0x81 */
+const IMMEDIATE: [Form; 8] = [
+	Form::ImmRm(TwoOperandsOpCode::ADD), Form::ImmRm(TwoOperandsOpCode::OR),
+	Form::ImmRm(TwoOperandsOpCode::ADC), Form::ImmRm(TwoOperandsOpCode::SBB),
+	Form::ImmRm(TwoOperandsOpCode::AND), Form::ImmRm(TwoOperandsOpCode::SUB),
+	Form::ImmRm(TwoOperandsOpCode::XOR), Form::ImmRm(TwoOperandsOpCode::CMP)];
+
+/* 0x82 on the 80186 and later; the 8086 runs it as 0x80 */
+const BYTE_IMMEDIATE: [Form; 8] = [
+	Form::ImmRm(TwoOperandsOpCode::ADD), Form::Invalid,
+	Form::ImmRm(TwoOperandsOpCode::ADC), Form::ImmRm(TwoOperandsOpCode::SBB),
+	Form::Invalid, Form::ImmRm(TwoOperandsOpCode::SUB),
+	Form::Invalid, Form::ImmRm(TwoOperandsOpCode::CMP)];
+
+/* 0x83 */
+const SIGNED_IMMEDIATE: [Form; 8] = [
+	Form::SignedImmRm(TwoOperandsOpCode::ADD), Form::SignedImmRm(TwoOperandsOpCode::OR),
+	Form::SignedImmRm(TwoOperandsOpCode::ADC), Form::SignedImmRm(TwoOperandsOpCode::SBB),
+	Form::SignedImmRm(TwoOperandsOpCode::AND), Form::SignedImmRm(TwoOperandsOpCode::SUB),
+	Form::SignedImmRm(TwoOperandsOpCode::XOR), Form::SignedImmRm(TwoOperandsOpCode::CMP)];
+
+/* 0x8f */
+const POP_RM: [Form; 8] = [
+	Form::Rm(SingleOperandOpCode::POP), Form::Invalid, Form::Invalid, Form::Invalid,
+	Form::Invalid, Form::Invalid, Form::Invalid, Form::Invalid];
+
+/* The 8086 ignores the reg field */
+const POP_RM_8086: [Form; 8] = [Form::Rm(SingleOperandOpCode::POP); 8];
+
+/* 0xc6, 0xc7 */
+const MOV_IMMEDIATE: [Form; 8] = [
+	Form::ImmRm(TwoOperandsOpCode::MOV), Form::Invalid, Form::Invalid, Form::Invalid,
+	Form::Invalid, Form::Invalid, Form::Invalid, Form::Invalid];
+
+const MOV_IMMEDIATE_8086: [Form; 8] = [Form::ImmRm(TwoOperandsOpCode::MOV); 8];
+
+/* 0xc0, 0xc1 and 0xd0 to 0xd3 */
+const SHIFTS: [Form; 8] = [
+	Form::Shift(ShiftRotateOpCode::ROL), Form::Shift(ShiftRotateOpCode::ROR),
+	Form::Shift(ShiftRotateOpCode::RCL), Form::Shift(ShiftRotateOpCode::RCR),
+	Form::Shift(ShiftRotateOpCode::SHL), Form::Shift(ShiftRotateOpCode::SHR),
+	Form::Invalid, Form::Shift(ShiftRotateOpCode::SAR)];
+
+const SHIFTS_8086: [Form; 8] = [
+	Form::Shift(ShiftRotateOpCode::ROL), Form::Shift(ShiftRotateOpCode::ROR),
+	Form::Shift(ShiftRotateOpCode::RCL), Form::Shift(ShiftRotateOpCode::RCR),
+	Form::Shift(ShiftRotateOpCode::SHL), Form::Shift(ShiftRotateOpCode::SHR),
+	Form::Shift(ShiftRotateOpCode::SETMO), Form::Shift(ShiftRotateOpCode::SAR)];
+
+/* 0xf6, 0xf7 */
+const UNARY: [Form; 8] = [
+	Form::ImmRm(TwoOperandsOpCode::TEST), Form::Invalid,
+	Form::Rm(SingleOperandOpCode::NOT), Form::Rm(SingleOperandOpCode::NEG),
+	Form::Rm(SingleOperandOpCode::MUL), Form::Rm(SingleOperandOpCode::IMUL),
+	Form::Rm(SingleOperandOpCode::DIV), Form::Rm(SingleOperandOpCode::IDIV)];
+
+const UNARY_8086: [Form; 8] = [
+	Form::ImmRm(TwoOperandsOpCode::TEST), Form::ImmRm(TwoOperandsOpCode::TEST),
+	Form::Rm(SingleOperandOpCode::NOT), Form::Rm(SingleOperandOpCode::NEG),
+	Form::Rm(SingleOperandOpCode::MUL), Form::Rm(SingleOperandOpCode::IMUL),
+	Form::Rm(SingleOperandOpCode::DIV), Form::Rm(SingleOperandOpCode::IDIV)];
+
+/* 0xfe */
+const INC_DEC: [Form; 8] = [
+	Form::Rm(SingleOperandOpCode::INC), Form::Rm(SingleOperandOpCode::DEC), Form::Invalid, Form::Invalid,
+	Form::Invalid, Form::Invalid, Form::Invalid, Form::Invalid];
+
+/* 0xff */
+const INDIRECT_8086: [Form; 8] = [
+	Form::Rm(SingleOperandOpCode::INC), Form::Rm(SingleOperandOpCode::DEC),
+	Form::Indirect(SingleOperandFCOpCode::CALL), Form::FarIndirect(SingleOperandFCOpCode::CALL),
+	Form::Indirect(SingleOperandFCOpCode::JMP), Form::FarIndirect(SingleOperandFCOpCode::JMP),
+	Form::Rm(SingleOperandOpCode::PUSH), Form::Rm(SingleOperandOpCode::PUSH)];
+
+const INDIRECT_80186: [Form; 8] = [
+	Form::Rm(SingleOperandOpCode::INC), Form::Rm(SingleOperandOpCode::DEC),
+	Form::Indirect(SingleOperandFCOpCode::CALL), Form::MemoryOnly(&Form::FarIndirect(SingleOperandFCOpCode::CALL)),
+	Form::Indirect(SingleOperandFCOpCode::JMP), Form::MemoryOnly(&Form::FarIndirect(SingleOperandFCOpCode::JMP)),
+	Form::Rm(SingleOperandOpCode::PUSH), Form::Invalid];
+
+/* 0x0f 0x00 to 0x07 */
+const SYSTEM: [Form; 8] = [
+	Form::Group(&SYSTEM_00), Form::Group(&SYSTEM_01),
+	Form::Load(TwoOperandsOpCode::LAR), Form::Load(TwoOperandsOpCode::LSL),
+	Form::Invalid, Form::Invalid, Form::NoOperand(NoOperandOpCode::CLTS), Form::Invalid];
+
+const SYSTEM_00: [Form; 8] = [
+	Form::System(SystemOpCode::SLDT), Form::System(SystemOpCode::STR),
+	Form::System(SystemOpCode::LLDT), Form::System(SystemOpCode::LTR),
+	Form::System(SystemOpCode::VERR), Form::System(SystemOpCode::VERW),
+	Form::Invalid, Form::Invalid];
+
+const SYSTEM_01: [Form; 8] = [
+	Form::MemoryOnly(&Form::System(SystemOpCode::SGDT)), Form::MemoryOnly(&Form::System(SystemOpCode::SIDT)),
+	Form::MemoryOnly(&Form::System(SystemOpCode::LGDT)), Form::MemoryOnly(&Form::System(SystemOpCode::LIDT)),
+	Form::System(SystemOpCode::SMSW), Form::Invalid,
+	Form::System(SystemOpCode::LMSW), Form::Invalid];
+
+/* The 8086 does not check all the bits of some opcodes: 0x60 to 0x6f run as
+ * 0x70 to 0x7f, 0x82 as 0x80, 0xc0, 0xc1, 0xc8 and 0xc9 as 0xc2, 0xc3, 0xca
+ * and 0xcb, and 0xf1 as 0xf0; nor of some reg fields, see the _8086 groups */
+const I8086_OPCODES: [Form; 256] = [
+	/* 0x00 */
+	Form::RegRm(TwoOperandsOpCode::ADD), Form::RegRm(TwoOperandsOpCode::ADD),
+	Form::RegRm(TwoOperandsOpCode::ADD), Form::RegRm(TwoOperandsOpCode::ADD),
+	Form::AccImm(TwoOperandsOpCode::ADD), Form::AccImm(TwoOperandsOpCode::ADD),
+	Form::SegReg(SingleOperandOpCode::PUSH), Form::SegReg(SingleOperandOpCode::POP),
+	/* 0x08 */
+	Form::RegRm(TwoOperandsOpCode::OR), Form::RegRm(TwoOperandsOpCode::OR),
+	Form::RegRm(TwoOperandsOpCode::OR), Form::RegRm(TwoOperandsOpCode::OR),
+	Form::AccImm(TwoOperandsOpCode::OR), Form::AccImm(TwoOperandsOpCode::OR),
+	Form::SegReg(SingleOperandOpCode::PUSH), Form::SegReg(SingleOperandOpCode::POP),
+	/* 0x10 */
+	Form::RegRm(TwoOperandsOpCode::ADC), Form::RegRm(TwoOperandsOpCode::ADC),
+	Form::RegRm(TwoOperandsOpCode::ADC), Form::RegRm(TwoOperandsOpCode::ADC),
+	Form::AccImm(TwoOperandsOpCode::ADC), Form::AccImm(TwoOperandsOpCode::ADC),
+	Form::SegReg(SingleOperandOpCode::PUSH), Form::SegReg(SingleOperandOpCode::POP),
+	/* 0x18 */
+	Form::RegRm(TwoOperandsOpCode::SBB), Form::RegRm(TwoOperandsOpCode::SBB),
+	Form::RegRm(TwoOperandsOpCode::SBB), Form::RegRm(TwoOperandsOpCode::SBB),
+	Form::AccImm(TwoOperandsOpCode::SBB), Form::AccImm(TwoOperandsOpCode::SBB),
+	Form::SegReg(SingleOperandOpCode::PUSH), Form::SegReg(SingleOperandOpCode::POP),
+	/* 0x20 */
+	Form::RegRm(TwoOperandsOpCode::AND), Form::RegRm(TwoOperandsOpCode::AND),
+	Form::RegRm(TwoOperandsOpCode::AND), Form::RegRm(TwoOperandsOpCode::AND),
+	Form::AccImm(TwoOperandsOpCode::AND), Form::AccImm(TwoOperandsOpCode::AND),
+	Form::Prefix(Prefix::SEGMENT(SegReg::ES)), Form::NoOperand(NoOperandOpCode::DAA),
+	/* 0x28 */
+	Form::RegRm(TwoOperandsOpCode::SUB), Form::RegRm(TwoOperandsOpCode::SUB),
+	Form::RegRm(TwoOperandsOpCode::SUB), Form::RegRm(TwoOperandsOpCode::SUB),
+	Form::AccImm(TwoOperandsOpCode::SUB), Form::AccImm(TwoOperandsOpCode::SUB),
+	Form::Prefix(Prefix::SEGMENT(SegReg::CS)), Form::NoOperand(NoOperandOpCode::DAS),
+	/* 0x30 */
+	Form::RegRm(TwoOperandsOpCode::XOR), Form::RegRm(TwoOperandsOpCode::XOR),
+	Form::RegRm(TwoOperandsOpCode::XOR), Form::RegRm(TwoOperandsOpCode::XOR),
+	Form::AccImm(TwoOperandsOpCode::XOR), Form::AccImm(TwoOperandsOpCode::XOR),
+	Form::Prefix(Prefix::SEGMENT(SegReg::SS)), Form::NoOperand(NoOperandOpCode::AAA),
+	/* 0x38 */
+	Form::RegRm(TwoOperandsOpCode::CMP), Form::RegRm(TwoOperandsOpCode::CMP),
+	Form::RegRm(TwoOperandsOpCode::CMP), Form::RegRm(TwoOperandsOpCode::CMP),
+	Form::AccImm(TwoOperandsOpCode::CMP), Form::AccImm(TwoOperandsOpCode::CMP),
+	Form::Prefix(Prefix::SEGMENT(SegReg::DS)), Form::NoOperand(NoOperandOpCode::AAS),
+	/* 0x40 */
+	Form::Reg(SingleOperandOpCode::INC), Form::Reg(SingleOperandOpCode::INC),
+	Form::Reg(SingleOperandOpCode::INC), Form::Reg(SingleOperandOpCode::INC),
+	Form::Reg(SingleOperandOpCode::INC), Form::Reg(SingleOperandOpCode::INC),
+	Form::Reg(SingleOperandOpCode::INC), Form::Reg(SingleOperandOpCode::INC),
+	/* 0x48 */
+	Form::Reg(SingleOperandOpCode::DEC), Form::Reg(SingleOperandOpCode::DEC),
+	Form::Reg(SingleOperandOpCode::DEC), Form::Reg(SingleOperandOpCode::DEC),
+	Form::Reg(SingleOperandOpCode::DEC), Form::Reg(SingleOperandOpCode::DEC),
+	Form::Reg(SingleOperandOpCode::DEC), Form::Reg(SingleOperandOpCode::DEC),
+	/* 0x50 */
+	Form::Reg(SingleOperandOpCode::PUSH), Form::Reg(SingleOperandOpCode::PUSH),
+	Form::Reg(SingleOperandOpCode::PUSH), Form::Reg(SingleOperandOpCode::PUSH),
+	Form::Reg(SingleOperandOpCode::PUSH), Form::Reg(SingleOperandOpCode::PUSH),
+	Form::Reg(SingleOperandOpCode::PUSH), Form::Reg(SingleOperandOpCode::PUSH),
+	/* 0x58 */
+	Form::Reg(SingleOperandOpCode::POP), Form::Reg(SingleOperandOpCode::POP),
+	Form::Reg(SingleOperandOpCode::POP), Form::Reg(SingleOperandOpCode::POP),
+	Form::Reg(SingleOperandOpCode::POP), Form::Reg(SingleOperandOpCode::POP),
+	Form::Reg(SingleOperandOpCode::POP), Form::Reg(SingleOperandOpCode::POP),
+	/* 0x60 */
+	Form::ShortImm(SingleBImmOperandOpCode::JO), Form::ShortImm(SingleBImmOperandOpCode::JNO),
+	Form::ShortImm(SingleBImmOperandOpCode::JB), Form::ShortImm(SingleBImmOperandOpCode::JNB),
+	Form::ShortImm(SingleBImmOperandOpCode::JE), Form::ShortImm(SingleBImmOperandOpCode::JNE),
+	Form::ShortImm(SingleBImmOperandOpCode::JBE), Form::ShortImm(SingleBImmOperandOpCode::JNBE),
+	/* 0x68 */
+	Form::ShortImm(SingleBImmOperandOpCode::JS), Form::ShortImm(SingleBImmOperandOpCode::JNS),
+	Form::ShortImm(SingleBImmOperandOpCode::JP), Form::ShortImm(SingleBImmOperandOpCode::JNP),
+	Form::ShortImm(SingleBImmOperandOpCode::JL), Form::ShortImm(SingleBImmOperandOpCode::JNL),
+	Form::ShortImm(SingleBImmOperandOpCode::JLE), Form::ShortImm(SingleBImmOperandOpCode::JNLE),
+	/* 0x70 */
+	Form::ShortImm(SingleBImmOperandOpCode::JO), Form::ShortImm(SingleBImmOperandOpCode::JNO),
+	Form::ShortImm(SingleBImmOperandOpCode::JB), Form::ShortImm(SingleBImmOperandOpCode::JNB),
+	Form::ShortImm(SingleBImmOperandOpCode::JE), Form::ShortImm(SingleBImmOperandOpCode::JNE),
+	Form::ShortImm(SingleBImmOperandOpCode::JBE), Form::ShortImm(SingleBImmOperandOpCode::JNBE),
+	/* 0x78 */
+	Form::ShortImm(SingleBImmOperandOpCode::JS), Form::ShortImm(SingleBImmOperandOpCode::JNS),
+	Form::ShortImm(SingleBImmOperandOpCode::JP), Form::ShortImm(SingleBImmOperandOpCode::JNP),
+	Form::ShortImm(SingleBImmOperandOpCode::JL), Form::ShortImm(SingleBImmOperandOpCode::JNL),
+	Form::ShortImm(SingleBImmOperandOpCode::JLE), Form::ShortImm(SingleBImmOperandOpCode::JNLE),
+	/* 0x80 */
+	Form::Group(&IMMEDIATE), Form::Group(&IMMEDIATE),
+	Form::Group(&IMMEDIATE), Form::Group(&SIGNED_IMMEDIATE),
+	Form::RmReg(TwoOperandsOpCode::TEST), Form::RmReg(TwoOperandsOpCode::TEST),
+	Form::RmReg(TwoOperandsOpCode::XCHG), Form::RmReg(TwoOperandsOpCode::XCHG),
+	/* 0x88 */
+	Form::RegRm(TwoOperandsOpCode::MOV), Form::RegRm(TwoOperandsOpCode::MOV),
+	Form::RegRm(TwoOperandsOpCode::MOV), Form::RegRm(TwoOperandsOpCode::MOV),
+	Form::MovSegReg(true), Form::Load(TwoOperandsOpCode::LEA),
+	Form::MovSegReg(true), Form::Group(&POP_RM_8086),
+	/* 0x90 */
+	Form::XchgAx, Form::XchgAx, Form::XchgAx, Form::XchgAx,
+	Form::XchgAx, Form::XchgAx, Form::XchgAx, Form::XchgAx,
+	/* 0x98 */
+	Form::NoOperand(NoOperandOpCode::CBW), Form::NoOperand(NoOperandOpCode::CWD),
+	Form::Far(SingleOperandFCOpCode::CALL), Form::NoOperand(NoOperandOpCode::WAIT),
+	Form::NoOperand(NoOperandOpCode::PUSHF), Form::NoOperand(NoOperandOpCode::POPF),
+	Form::NoOperand(NoOperandOpCode::SAHF), Form::NoOperand(NoOperandOpCode::LAHF),
+	/* 0xa0 */
+	Form::AccDirect, Form::AccDirect, Form::AccDirect, Form::AccDirect,
+	Form::String(ImplicitOperandOpCode::MOVS), Form::String(ImplicitOperandOpCode::MOVS),
+	Form::String(ImplicitOperandOpCode::CMPS), Form::String(ImplicitOperandOpCode::CMPS),
+	/* 0xa8 */
+	Form::AccImm(TwoOperandsOpCode::TEST), Form::AccImm(TwoOperandsOpCode::TEST),
+	Form::String(ImplicitOperandOpCode::STOS), Form::String(ImplicitOperandOpCode::STOS),
+	Form::String(ImplicitOperandOpCode::LODS), Form::String(ImplicitOperandOpCode::LODS),
+	Form::String(ImplicitOperandOpCode::SCAS), Form::String(ImplicitOperandOpCode::SCAS),
+	/* 0xb0 */
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	/* 0xb8 */
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	Form::RegImm(TwoOperandsOpCode::MOV), Form::RegImm(TwoOperandsOpCode::MOV),
+	/* 0xc0 */
+	Form::RetImm, Form::Ret, Form::RetImm, Form::Ret,
+	Form::Load(TwoOperandsOpCode::LES), Form::Load(TwoOperandsOpCode::LDS),
+	Form::Group(&MOV_IMMEDIATE_8086), Form::Group(&MOV_IMMEDIATE_8086),
+	/* 0xc8 */
+	Form::RetFarImm, Form::RetFar, Form::RetFarImm, Form::RetFar,
+	Form::Int3, Form::ShortImm(SingleBImmOperandOpCode::INT),
+	Form::NoOperand(NoOperandOpCode::INTO), Form::NoOperand(NoOperandOpCode::IRET),
+	/* 0xd0 */
+	Form::Group(&SHIFTS_8086), Form::Group(&SHIFTS_8086), Form::Group(&SHIFTS_8086), Form::Group(&SHIFTS_8086),
+	Form::ByteImm(SingleOperandOpCode::AAM), Form::ByteImm(SingleOperandOpCode::AAD),
+	Form::NoOperand(NoOperandOpCode::SALC), Form::NoOperand(NoOperandOpCode::XLAT),
+	/* 0xd8 */
+	Form::Esc, Form::Esc, Form::Esc, Form::Esc,
+	Form::Esc, Form::Esc, Form::Esc, Form::Esc,
+	/* 0xe0 */
+	Form::ShortImm(SingleBImmOperandOpCode::LOOPNZ), Form::ShortImm(SingleBImmOperandOpCode::LOOPZ),
+	Form::ShortImm(SingleBImmOperandOpCode::LOOP), Form::ShortImm(SingleBImmOperandOpCode::JCXZ),
+	Form::ShortImm(SingleBImmOperandOpCode::INB), Form::ShortImm(SingleBImmOperandOpCode::INW),
+	Form::ShortImm(SingleBImmOperandOpCode::OUTB), Form::ShortImm(SingleBImmOperandOpCode::OUTW),
+	/* 0xe8 */
+	Form::Near(SingleOperandFCOpCode::CALL), Form::Near(SingleOperandFCOpCode::JMP),
+	Form::Far(SingleOperandFCOpCode::JMP), Form::ShortImm(SingleBImmOperandOpCode::JMPS),
+	Form::PortDx(SingleBImmOperandOpCode::INVB), Form::PortDx(SingleBImmOperandOpCode::INVW),
+	Form::PortDx(SingleBImmOperandOpCode::OUTVB), Form::PortDx(SingleBImmOperandOpCode::OUTVW),
+	/* 0xf0 */
+	Form::Prefix(Prefix::LOCK), Form::Prefix(Prefix::LOCK),
+	Form::Prefix(Prefix::REPNE), Form::Prefix(Prefix::REP),
+	Form::NoOperand(NoOperandOpCode::HLT), Form::NoOperand(NoOperandOpCode::CMC),
+	Form::Group(&UNARY_8086), Form::Group(&UNARY_8086),
+	/* 0xf8 */
+	Form::NoOperand(NoOperandOpCode::CLC), Form::NoOperand(NoOperandOpCode::STC),
+	Form::NoOperand(NoOperandOpCode::CLI), Form::NoOperand(NoOperandOpCode::STI),
+	Form::NoOperand(NoOperandOpCode::CLD), Form::NoOperand(NoOperandOpCode::STD),
+	Form::Group(&INC_DEC), Form::Group(&INDIRECT_8086)];
+
+/* The 80186 raises INT 6 for the undefined opcodes, and for register operands
+ * where only memory ones make sense */
+const I80186_OPCODES: [Form; 256] = with_opcodes(I8086_OPCODES, &[
+	/* POP CS */
+	(0x0f, Form::Invalid),
+	(0x60, Form::NoOperand(NoOperandOpCode::PUSHA)),
+	(0x61, Form::NoOperand(NoOperandOpCode::POPA)),
+	(0x62, Form::MemoryOnly(&Form::Load(TwoOperandsOpCode::BOUND))),
+	(0x63, Form::Invalid),
+	(0x64, Form::Invalid),
+	(0x65, Form::Invalid),
+	(0x66, Form::Invalid),
+	(0x67, Form::Invalid),
+	(0x68, Form::PushImm),
+	(0x69, Form::ImulImm),
+	(0x6a, Form::PushSignedImm),
+	(0x6b, Form::ImulSignedImm),
+	(0x6c, Form::String(ImplicitOperandOpCode::INS)),
+	(0x6d, Form::String(ImplicitOperandOpCode::INS)),
+	(0x6e, Form::String(ImplicitOperandOpCode::OUTS)),
+	(0x6f, Form::String(ImplicitOperandOpCode::OUTS)),
+	(0x82, Form::Group(&BYTE_IMMEDIATE)),
+	(0x8c, Form::MovSegReg(false)),
+	(0x8d, Form::MemoryOnly(&Form::Load(TwoOperandsOpCode::LEA))),
+	(0x8e, Form::MovSegReg(false)),
+	(0x8f, Form::Group(&POP_RM)),
+	(0xc0, Form::Group(&SHIFTS)),
+	(0xc1, Form::Group(&SHIFTS)),
+	(0xc4, Form::MemoryOnly(&Form::Load(TwoOperandsOpCode::LES))),
+	(0xc5, Form::MemoryOnly(&Form::Load(TwoOperandsOpCode::LDS))),
+	(0xc6, Form::Group(&MOV_IMMEDIATE)),
+	(0xc7, Form::Group(&MOV_IMMEDIATE)),
+	(0xc8, Form::Enter),
+	(0xc9, Form::NoOperand(NoOperandOpCode::LEAVE)),
+	(0xd0, Form::Group(&SHIFTS)),
+	(0xd1, Form::Group(&SHIFTS)),
+	(0xd2, Form::Group(&SHIFTS)),
+	(0xd3, Form::Group(&SHIFTS)),
+	(0xf1, Form::Invalid),
+	(0xf6, Form::Group(&UNARY)),
+	(0xf7, Form::Group(&UNARY)),
+	(0xff, Form::Group(&INDIRECT_80186))]);
+
+/* The system instructions, mostly behind 0x0f */
+const I80286_OPCODES: [Form; 256] = with_opcodes(I80186_OPCODES, &[
+	(0x0f, Form::TwoByte(&SYSTEM)),
+	(0x63, Form::RmReg(TwoOperandsOpCode::ARPL))]);
+
+static OPCODES: [[Form; 256]; 3] = [I8086_OPCODES, I80186_OPCODES, I80286_OPCODES];
+
+const fn with_opcodes(table: [Form; 256], changes: &[(u8, Form)]) -> [Form; 256]
 {
-	let mut decoder = iced_x86::Decoder::new(16, bytecode, iced_x86::DecoderOptions::NONE);
-	let instr = decoder.decode();
-
-	let opcode = instr.code();
-
-	match opcode {
-		iced_x86::Code::Aad_imm8 => {
-			let op_kind = instr.op0_kind();
-			match op_kind {
-				iced_x86::OpKind::Immediate8 => {
-					let imm = instr.immediate8();
-
-					SizedInstruction {
-						instruction: Instruction::SingleBOperand(SingleOperandOpCode::AAD, BOperand::Immediate(imm)),
-						size: instr.len() as u16
-					}
-				},
-				_ => panic!("Unexpected operand type.")
-			}
-		}
-
-		iced_x86::Code::Aam_imm8 => {
-			let op_kind = instr.op0_kind();
-			match op_kind {
-				iced_x86::OpKind::Immediate8 => {
-					let imm = instr.immediate8();
-
-					SizedInstruction {
-						instruction: Instruction::SingleBOperand(SingleOperandOpCode::AAM, BOperand::Immediate(imm)),
-						size: instr.len() as u16
-					}
-				},
-				_ => panic!("Unexpected operand type.")
-			}
-		},
-
-        iced_x86::Code::Fninit => {
-            SizedInstruction {
-                instruction: Instruction::NoOperand(NoOperandOpCode::FNINIT),
-                size: instr.len() as u16
-            }
-        },
-        iced_x86::Code::Fnstcw_m2byte => {
-            let op_kind = instr.op0_kind();
-
-            match op_kind {
-                iced_x86::OpKind::Memory => {
-                    SizedInstruction {
-                        instruction: Instruction::SingleFCOperand(SingleOperandFCOpCode::FNSTCW, FlowControlOperand::DirectSeg(instr.memory_displacement64() as u16)),
-                        //instruction: Instruction::SingleWOperand(SingleOperandOpCode::FNSTCW, WOperand::Indirect8iDis(instr.memory_displacement64() as i8)),
-                        size: instr.len() as u16
-                    }
-                }
-                _ => panic!("Unexpected operand type: {:?}", op_kind)
-            }
-        }
-
-		_ => invalid_instruction()
-	}
-}
-
-pub fn parse_instruction(bytecode: &[u8]) -> SizedInstruction
-{
-	/* TODO: ** prefixes ** */
-	let sized_ins = prefix4bit(bytecode);
-
-	match sized_ins.instruction {
-		Instruction::Invalid => decode_with_iced(bytecode),
-		_ => sized_ins
-	}
-}
-
-// Catch immediate mov to reg
-fn prefix4bit(bytecode: &[u8]) -> SizedInstruction
-{
-	let tentative_opcode = bytecode[0] >> 4;
-	let opcode_byte = bytecode[0];
-	let make_imm_reg_instruction = |opcode: TwoOperandsOpCode|
+	let mut table = table;
+	let mut i = 0;
+	while i < changes.len()
 	{
-		let w = (opcode_byte & 0b00001000) >> 3;
-		match w
-		{
-			0 =>
-			{
-				let register = get_breg(opcode_byte & 0b111);
-				let imm = bytecode[1];
-				SizedInstruction
-				{
-					instruction: Instruction::TwoBOperands(opcode, BOperand::Immediate(imm), BOperand::Reg(register)),
-					size: 2
-				}
-			},
-			1 =>
-			{
-				let register = get_wreg(opcode_byte & 0b111);
-				let imm = (bytecode[1] as u16) + ((bytecode[2] as u16) << 8);
-				SizedInstruction
-				{
-					instruction: Instruction::TwoWOperands(opcode, WOperand::Immediate(imm), WOperand::Reg(register)),
-					size: 3
-				}
-			},
-			_ => panic!("Unexpected w")
-		}
-	};
-
-	match tentative_opcode
-	{
-		0b1011 => make_imm_reg_instruction(TwoOperandsOpCode::MOV),
-		_ => prefix5bit(bytecode)
+		table[changes[i].0 as usize] = changes[i].1;
+		i += 1;
 	}
+	table
 }
 
-// Catch register instructions
-fn prefix5bit(bytecode: &[u8]) -> SizedInstruction
+fn opcodes(model: CpuModel) -> &'static [Form; 256]
 {
-	let tentative_opcode = bytecode[0] >> 3;
-	let reg_id = bytecode[0] & 0b00000111;
-	let register = get_wreg(reg_id);
-	let make_reg_instruction = |op: SingleOperandOpCode| -> SizedInstruction
+	if model.has_protected_mode()
 	{
-		SizedInstruction
-		{
-			instruction: Instruction::SingleWOperand(op, WOperand::Reg(register)),
-			size: 1
-		}
-	};
-
-	match tentative_opcode
+		&OPCODES[2]
+	}
+	else if model.has_80186_instructions()
 	{
-		0b01010 => make_reg_instruction(SingleOperandOpCode::PUSH),
-		0b01011 => make_reg_instruction(SingleOperandOpCode::POP),
-		0b01000 => make_reg_instruction(SingleOperandOpCode::INC),
-		0b01001 => make_reg_instruction(SingleOperandOpCode::DEC),
-		0b10010 => 
-		{
-			SizedInstruction
-			{
-				instruction: Instruction::TwoWOperands(
-					TwoOperandsOpCode::XCHG,
-					WOperand::Reg(WReg::AX),
-					WOperand::Reg(register)),
-				size: 1
-			}
-		}
-		// 0b11011 => esc, <-- TODO
-		_ => prefix6bit(bytecode)
+		&OPCODES[1]
 	}
-}
-
-fn apply_swap<Operand> (a: Operand, b: Operand, swap: u8) -> (Operand, Operand)
-{
-	match swap
+	else
 	{
-		0 => (a,b),
-		1 => (b,a),
-		_ => panic!("Bad swap value")
+		&OPCODES[0]
 	}
 }
 
-fn get_u16(off: usize, data: &[u8]) -> u16
+fn sized(instruction: Instruction, size: u16) -> SizedInstruction
 {
-	data[off] as u16 | ((data[off+1] as u16) << 8)
-}
-
-fn two_operands_instruction_reg_rm(opcode: TwoOperandsOpCode, d: u8, w: u8, bytecode: &[u8]) -> SizedInstruction
-{
-	let addressing_byte = bytecode[1];
-	let reg = (addressing_byte & 0b00111000) >> 3;
-
-	let (instruction, rm_operand_size) = 
-		match w
-		{
-			0 =>
-			{
-				let (rm_operand, rm_operand_size) = get_rm_boperand(bytecode);
-				let (op1, op2) = apply_swap(BOperand::Reg(get_breg(reg)), rm_operand, d);
-				(Instruction::TwoBOperands(opcode, op1, op2), rm_operand_size)
-			},
-			1 =>
-			{
-				let (rm_operand, rm_operand_size) = get_rm_woperand(bytecode);
-				let (op1, op2) = apply_swap(WOperand::Reg(get_wreg(reg)), rm_operand, d);
-				(Instruction::TwoWOperands(opcode, op1, op2), rm_operand_size)
-			},
-			_ => panic!("Invalid w")
-		};
-	
-
 	SizedInstruction
 	{
 		instruction: instruction,
-		size: 2 + rm_operand_size
+		size: size
 	}
 }
 
-fn prefix6bit(bytecode: &[u8]) -> SizedInstruction
+/* 'bytecode' starts with the opcode 'form' was found for */
+fn decode(form: Form, bytecode: &[u8]) -> SizedInstruction
 {
-	let opcode_byte = bytecode[0];
-	let tentative_opcode = bytecode[0] >> 2;
-	let d = (opcode_byte & 0b00000010) >> 1;
-	let w = (opcode_byte & 0b00000001) >> 0;
-
-	let usual_instruction = |opcode: TwoOperandsOpCode|
-		two_operands_instruction_reg_rm(opcode, d, w, bytecode);
-
-	match tentative_opcode
-	{
-		0b101000 => // Mov immediate addressing <-> acc
-		{
-			let addr = (bytecode[1] as u16) + ((bytecode[2] as u16) << 8);
-
-			let instruction =
-				match w
+	let opcode = bytecode[0];
+	let w = opcode & 0b1;
+	let d = (opcode & 0b10) >> 1;
+	let reg = || (bytecode[1] & 0b00111000) >> 3;
+
+	match form
+	{
+		Form::Invalid => invalid_instruction(),
+		Form::Prefix(prefix) => sized(Instruction::Prefix(prefix), 1),
+		Form::NoOperand(op) => sized(Instruction::NoOperand(op), 1),
+		Form::String(op) if w == 0 => sized(Instruction::ImplicitBOperand(op), 1),
+		Form::String(op) => sized(Instruction::ImplicitWOperand(op), 1),
+		Form::RegRm(op) => two_operands_instruction_reg_rm(op, d, w, bytecode),
+		Form::RmReg(op) => two_operands_instruction_reg_rm(op, 0, w, bytecode),
+		Form::Load(op) => two_operands_instruction_reg_rm(op, 1, 1, bytecode),
+		Form::AccImm(op) if w == 0 =>
+			sized(Instruction::TwoBOperands(op, BOperand::Immediate(bytecode[1]), BOperand::Reg(BReg::AL)), 2),
+		Form::AccImm(op) =>
+			sized(Instruction::TwoWOperands(op, WOperand::Immediate(get_u16(1, bytecode)), WOperand::Reg(WReg::AX)), 3),
+		Form::RegImm(op) if opcode & 0b1000 == 0 =>
+			sized(Instruction::TwoBOperands(op, BOperand::Immediate(bytecode[1]), BOperand::Reg(get_breg(opcode & 0b111))), 2),
+		Form::RegImm(op) =>
+			sized(Instruction::TwoWOperands(op, WOperand::Immediate(get_u16(1, bytecode)), WOperand::Reg(get_wreg(opcode & 0b111))), 3),
+		Form::AccDirect =>
+		{
+			let addr = get_u16(1, bytecode);
+			let instruction = if w == 0
 				{
-					0 =>
-					{
-						let (op1, op2) = apply_swap(BOperand::Direct(addr), BOperand::Reg(BReg::AL), d);
-						Instruction::TwoBOperands(TwoOperandsOpCode::MOV, op1, op2)
-					},
-					1 =>
-					{
-						let (op1, op2) = apply_swap(WOperand::Direct(addr), WOperand::Reg(WReg::AX), d);
-						Instruction::TwoWOperands(TwoOperandsOpCode::MOV, op1, op2)
-					},
-					_ => panic!("Invalid w")
+					let (op1, op2) = apply_swap(BOperand::Direct(addr), BOperand::Reg(BReg::AL), d);
+					Instruction::TwoBOperands(TwoOperandsOpCode::MOV, op1, op2)
+				}
+				else
+				{
+					let (op1, op2) = apply_swap(WOperand::Direct(addr), WOperand::Reg(WReg::AX), d);
+					Instruction::TwoWOperands(TwoOperandsOpCode::MOV, op1, op2)
 				};
-
-			SizedInstruction
+			sized(instruction, 3)
+		},
+		Form::Reg(op) => sized(Instruction::SingleWOperand(op, WOperand::Reg(get_wreg(opcode & 0b111))), 1),
+		Form::XchgAx =>
+			sized(Instruction::TwoWOperands(TwoOperandsOpCode::XCHG, WOperand::Reg(WReg::AX), WOperand::Reg(get_wreg(opcode & 0b111))), 1),
+		Form::SegReg(op) => sized(Instruction::SingleWOperand(op, WOperand::SegReg(get_segreg((opcode >> 3) & 0b11))), 1),
+		Form::MovSegReg(alias) =>
+		{
+			let sreg = if alias { reg() & 0b11 } else { reg() };
+			if sreg > 3 || (!alias && d == 1 && sreg == 1)
 			{
-				instruction: instruction,
-				size: 3
+				return invalid_instruction()
 			}
-		}
-
-		/* No-mindfuck 6-bit prefix instructions, yay */
-		0b100010 => usual_instruction(TwoOperandsOpCode::MOV),
-		0b000000 => usual_instruction(TwoOperandsOpCode::ADD), /* go back here once we know what conflicts with this */
-		0b000100 => usual_instruction(TwoOperandsOpCode::ADC),
-		0b001010 => usual_instruction(TwoOperandsOpCode::SUB),
-		0b000110 => usual_instruction(TwoOperandsOpCode::SBB),
-		0b001110 => usual_instruction(TwoOperandsOpCode::CMP),
-		0b001000 => usual_instruction(TwoOperandsOpCode::AND),
-		0b000010 => usual_instruction(TwoOperandsOpCode::OR),
-		0b001100 => usual_instruction(TwoOperandsOpCode::XOR),
-		_ => prefix3_3_bit(bytecode)
-	}
-}
-
-// Catch segment register push/pop
-fn prefix3_3_bit(bytecode: &[u8]) -> SizedInstruction
-{
-	let opcode_byte = bytecode[0];
-	let tentative_opcode = opcode_byte & 0b11100111;
-	let sreg = (opcode_byte & 0b00011000) >> 3;
-
-	let instruction = |opcode: SingleOperandOpCode|
-	{
-		SizedInstruction
-		{
-			instruction: Instruction::SingleWOperand(opcode, WOperand::SegReg(get_segreg(sreg))),
-			size: 1
-		}
-	};
-
-	match tentative_opcode
-	{
-		0b00000111 => instruction(SingleOperandOpCode::POP),
-		0b00000110 => instruction(SingleOperandOpCode::PUSH),
-		0b00100110 => 
-		SizedInstruction
-		{
-			instruction: Instruction::Prefix(Prefix::SEGMENT(get_segreg(sreg))),
-			size: 1
+			let (rm_op, rm_sz) = get_rm_woperand(bytecode);
+			let (op1, op2) = apply_swap(WOperand::SegReg(get_segreg(sreg)), rm_op, d);
+			sized(Instruction::TwoWOperands(TwoOperandsOpCode::MOV, op1, op2), 2 + rm_sz)
 		},
-		_ => prefix7bit(bytecode)
-	}
-}
-
-// Catch 7-bits opcodes?
-fn prefix7bit(bytecode: &[u8]) -> SizedInstruction
-{
-	let tentative_opcode = bytecode[0] >> 1;
-	let w = bytecode[0] & 0b1;
-
-	let two_op_ins_with_acc = |opcode: TwoOperandsOpCode|
-	{
-		match w
-		{
-			0 => SizedInstruction
-			{
-				instruction: Instruction::TwoBOperands(opcode, BOperand::Immediate(bytecode[1]), BOperand::Reg(BReg::AL)),
-				size: 2
-			},
-			1 => 
-			{
-				let imm = (bytecode[1] as u16) + ((bytecode[2] as u16) << 8);
-				SizedInstruction
-				{
-					instruction: Instruction::TwoWOperands(opcode, WOperand::Immediate(imm), WOperand::Reg(WReg::AX)),
-					size: 3
-				}
-			},
-			_ => panic!("Invalid w")
-		}
-	};
-
-	let string_ins = |opcode: ImplicitOperandOpCode|
-	{
-		SizedInstruction
-		{
-			instruction: match w
-				{
-					0 => Instruction::ImplicitBOperand(opcode),
-					1 => Instruction::ImplicitWOperand(opcode),
-					_ => panic!("invalid w")
-				},
-			size: 1
-		}
-	};
-
-	match tentative_opcode
-	{
-		0b1000011 => two_operands_instruction_reg_rm(TwoOperandsOpCode::XCHG, 0, w, bytecode),
-		0b0000010 => two_op_ins_with_acc(TwoOperandsOpCode::ADD),
-		0b0001010 => two_op_ins_with_acc(TwoOperandsOpCode::ADC),
-		0b0010110 => two_op_ins_with_acc(TwoOperandsOpCode::SUB),
-		0b0001110 => two_op_ins_with_acc(TwoOperandsOpCode::SBB),
-		0b0010010 => two_op_ins_with_acc(TwoOperandsOpCode::AND),
-		0b1010100 => two_op_ins_with_acc(TwoOperandsOpCode::TEST),
-		0b1000010 => two_operands_instruction_reg_rm(TwoOperandsOpCode::TEST, 0, w, bytecode), // NOTE: the 8086 user manual is mistaken here
-		0b0000110 => two_op_ins_with_acc(TwoOperandsOpCode::OR),
-		0b0011010 => two_op_ins_with_acc(TwoOperandsOpCode::XOR),
-		0b0011110 => two_op_ins_with_acc(TwoOperandsOpCode::CMP),
-		0b1010010 => string_ins(ImplicitOperandOpCode::MOVS),
-		0b1010011 => string_ins(ImplicitOperandOpCode::CMPS),
-		0b1010111 => string_ins(ImplicitOperandOpCode::SCAS),
-		0b1010110 => string_ins(ImplicitOperandOpCode::LODS),
-		0b1010101 => string_ins(ImplicitOperandOpCode::STOS),
-		_ => prefix_8bit(bytecode)
-	}
-}
-
-fn prefix_8bit(bytecode: &[u8]) -> SizedInstruction
-{
-	let tentative_opcode = bytecode[0];
-	let no_op_ins = |opcode: NoOperandOpCode|
-	{
-		SizedInstruction
-		{
-			instruction: Instruction::NoOperand(opcode),
-			size: 1
-		}
-	};
-
-	let jump_ins = |opcode: SingleBImmOperandOpCode|
-	{
-		SizedInstruction
+		Form::ShortImm(op) => sized(Instruction::SingleBImmOperand(op, bytecode[1]), 2),
+		Form::PortDx(op) => sized(Instruction::SingleBImmOperand(op, 0x42), 1),
+		Form::Int3 => sized(Instruction::SingleBImmOperand(SingleBImmOperandOpCode::INT, 0x3), 1),
+		Form::Near(op) => sized(Instruction::SingleFCOperand(op, FlowControlOperand::DirectSeg(get_u16(1, bytecode))), 3),
+		Form::Far(op) =>
+			sized(Instruction::SingleFCOperand(op, FlowControlOperand::DirectInterSeg(get_u16(3, bytecode), get_u16(1, bytecode))), 5),
+		Form::Ret => sized(Instruction::FCNoOperandSeg(NoOpFCOpCode::RET), 1),
+		Form::RetFar => sized(Instruction::FCNoOperandInterSeg(NoOpFCOpCode::RET), 1),
+		Form::RetImm =>
+			sized(Instruction::SingleWImmFCOperand(SingleWImmFCOpCode::RETANDADDTOSP, SingleWImmFCOperand::Seg(get_u16(1, bytecode))), 3),
+		Form::RetFarImm =>
+			sized(Instruction::SingleWImmFCOperand(SingleWImmFCOpCode::RETANDADDTOSP, SingleWImmFCOperand::InterSeg(get_u16(1, bytecode))), 3),
+		Form::ByteImm(op) => sized(Instruction::SingleBOperand(op, BOperand::Immediate(bytecode[1])), 2),
+		Form::Esc => esc_instruction(bytecode),
+		Form::Group(forms) => decode(forms[reg() as usize], bytecode),
+		Form::TwoByte(forms) => match forms.get(bytecode[1] as usize).map(|form| decode(*form, &bytecode[1 ..]))
+		{
+			Some(SizedInstruction { instruction: Instruction::Invalid, .. }) | None => invalid_instruction(),
+			Some(ins) => sized(ins.instruction, ins.size + 1)
+		},
+		Form::MemoryOnly(_) if bytecode[1] >> 6 == 0b11 => invalid_instruction(),
+		Form::MemoryOnly(form) => decode(*form, bytecode),
+		Form::ImmRm(op) if w == 0 =>
 		{
-			instruction: Instruction::SingleBImmOperand(opcode, bytecode[1]),
-			size: 2
-		}
-	};
-
-	let iov_ins = |opcode: SingleBImmOperandOpCode|
-	{
-		SizedInstruction
+			let (rm_op, sz) = get_rm_boperand(bytecode);
+			sized(Instruction::TwoBOperands(op, BOperand::Immediate(bytecode[2 + sz as usize]), rm_op), 3 + sz)
+		},
+		Form::ImmRm(op) =>
 		{
-			instruction: Instruction::SingleBImmOperand(opcode, 0x42),
-			size: 1
-		}
-	};
-
-	let direct_intraseg_fc_ins = |opcode: SingleOperandFCOpCode|
-	{
-		let op = FlowControlOperand::DirectSeg((bytecode[1] as u16) + ((bytecode[2] as u16) << 8));
-		SizedInstruction
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			sized(Instruction::TwoWOperands(op, WOperand::Immediate(get_u16(2 + sz as usize, bytecode)), rm_op), 4 + sz)
+		},
+		Form::SignedImmRm(op) =>
 		{
-			instruction: Instruction::SingleFCOperand(opcode, op),
-			size: 3
-		}
-	};
-
-	let direct_interseg_fc_ins = |opcode: SingleOperandFCOpCode|
-	{
-		let op = FlowControlOperand::DirectInterSeg(
-			(bytecode[3] as u16) + ((bytecode[4] as u16) << 8), // CS
-			(bytecode[1] as u16) + ((bytecode[2] as u16) << 8)); // IP
-		SizedInstruction
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			let imm = (bytecode[2 + sz as usize] as i8) as u16;
+			sized(Instruction::TwoWOperands(op, WOperand::Immediate(imm), rm_op), 3 + sz)
+		},
+		Form::Rm(op) if w == 0 =>
 		{
-			instruction: Instruction::SingleFCOperand(opcode, op),
-			size: 5
-		}
-	};
-
-	let prefix_ins = |prefix: Prefix|
-	{
-		SizedInstruction
+			let (rm_op, sz) = get_rm_boperand(bytecode);
+			sized(Instruction::SingleBOperand(op, rm_op), 2 + sz)
+		},
+		Form::Rm(op) =>
 		{
-			instruction: Instruction::Prefix(prefix),
-			size: 1
-		}
-	};
-
-	match tentative_opcode
-	{
-		0b11010111 => no_op_ins(NoOperandOpCode::XLAT),
-		0b10001101 => two_operands_instruction_reg_rm(TwoOperandsOpCode::LEA, 1, 1, bytecode),
-		0b11000101 => two_operands_instruction_reg_rm(TwoOperandsOpCode::LDS, 1, 1, bytecode),
-		0b11000100 => two_operands_instruction_reg_rm(TwoOperandsOpCode::LES, 1, 1, bytecode),
-		0b10011111 => no_op_ins(NoOperandOpCode::LAHF),
-		0b10011110 => no_op_ins(NoOperandOpCode::SAHF),
-		0b10011100 => no_op_ins(NoOperandOpCode::PUSHF),
-		0b10011101 => no_op_ins(NoOperandOpCode::POPF),
-		0b00110111 => no_op_ins(NoOperandOpCode::AAA),
-		0b00100111 => no_op_ins(NoOperandOpCode::DAA),
-		0b00111111 => no_op_ins(NoOperandOpCode::AAS),
-		0b00101111 => no_op_ins(NoOperandOpCode::DAS),
-		0b10011000 => no_op_ins(NoOperandOpCode::CBW),
-		0b10011001 => no_op_ins(NoOperandOpCode::CWD),
-		0b11101000 => direct_intraseg_fc_ins(SingleOperandFCOpCode::CALL),
-		0b10011010 => direct_interseg_fc_ins(SingleOperandFCOpCode::CALL),
-		0b11101001 => direct_intraseg_fc_ins(SingleOperandFCOpCode::JMP),
-		0b11101011 => jump_ins(SingleBImmOperandOpCode::JMPS),
-		0b11101010 => direct_interseg_fc_ins(SingleOperandFCOpCode::JMP),
-		0b11000011 => /* ret intraseg */
-			SizedInstruction
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			sized(Instruction::SingleWOperand(op, rm_op), 2 + sz)
+		},
+		Form::Shift(op) =>
+		{
+			/* 0xc0 and 0xc1 take an immediate count, 0xd0 to 0xd3 shift by 1 or CL */
+			let count = |sz: u16|
+				if opcode & 0b00010000 == 0 { (ShiftRotateCount::Imm(bytecode[2 + sz as usize]), 1) }
+				else if d == 0 { (ShiftRotateCount::One, 0) }
+				else { (ShiftRotateCount::CL, 0) };
+			if w == 0
 			{
-				instruction: Instruction::FCNoOperandSeg(NoOpFCOpCode::RET),
-				size: 1
-			},
-		0b11000010 => /* ret intraseg; sp += data */
-		{
-			let op = SingleWImmFCOperand::Seg((bytecode[1] as u16) + ((bytecode[2] as u16) << 8));
-			SizedInstruction
+				let (rm_op, sz) = get_rm_boperand(bytecode);
+				let (count, count_sz) = count(sz);
+				sized(Instruction::ShiftRotateB(op, count, rm_op), 2 + sz + count_sz)
+			}
+			else
 			{
-				instruction: Instruction::SingleWImmFCOperand(SingleWImmFCOpCode::RETANDADDTOSP, op),
-				size: 3
+				let (rm_op, sz) = get_rm_woperand(bytecode);
+				let (count, count_sz) = count(sz);
+				sized(Instruction::ShiftRotateW(op, count, rm_op), 2 + sz + count_sz)
 			}
 		},
-		0b11001011 => /* ret interseg */
-			SizedInstruction
-			{
-				instruction: Instruction::FCNoOperandInterSeg(NoOpFCOpCode::RET),
-				size: 1
-			},
-		0b11001010 => /* ret interseg; sp += data */
+		Form::Indirect(op) =>
 		{
-			let op = SingleWImmFCOperand::InterSeg((bytecode[1] as u16) + ((bytecode[2] as u16) << 8));
-			SizedInstruction
-			{
-				instruction: Instruction::SingleWImmFCOperand(SingleWImmFCOpCode::RETANDADDTOSP, op),
-				size: 3
-			}
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			sized(Instruction::SingleFCOperand(op, FlowControlOperand::IndirectSeg(rm_op)), 2 + sz)
 		},
-		0b01110100 => jump_ins(SingleBImmOperandOpCode::JE),
-		0b01111100 => jump_ins(SingleBImmOperandOpCode::JL),
-		0b01111110 => jump_ins(SingleBImmOperandOpCode::JLE),
-		0b01110010 => jump_ins(SingleBImmOperandOpCode::JB),
-		0b01110110 => jump_ins(SingleBImmOperandOpCode::JBE),
-		0b01111010 => jump_ins(SingleBImmOperandOpCode::JP),
-		0b01110000 => jump_ins(SingleBImmOperandOpCode::JO),
-		0b01111000 => jump_ins(SingleBImmOperandOpCode::JS),
-		0b01110101 => jump_ins(SingleBImmOperandOpCode::JNE),
-		0b01111101 => jump_ins(SingleBImmOperandOpCode::JNL),
-		0b01111111 => jump_ins(SingleBImmOperandOpCode::JNLE),
-		0b01110011 => jump_ins(SingleBImmOperandOpCode::JNB),
-		0b01110111 => jump_ins(SingleBImmOperandOpCode::JNBE),
-		0b01111011 => jump_ins(SingleBImmOperandOpCode::JNP),
-		0b01110001 => jump_ins(SingleBImmOperandOpCode::JNO),
-		0b01111001 => jump_ins(SingleBImmOperandOpCode::JNS),
-		0b11100010 => jump_ins(SingleBImmOperandOpCode::LOOP),
-		0b11100001 => jump_ins(SingleBImmOperandOpCode::LOOPZ),
-		0b11100000 => jump_ins(SingleBImmOperandOpCode::LOOPNZ),
-		0b11100011 => jump_ins(SingleBImmOperandOpCode::JCXZ),
-		/* end control transfer */
-		0b11001101 => jump_ins(SingleBImmOperandOpCode::INT), // actually same kind as jumps instruction...
-		0b11100100 => jump_ins(SingleBImmOperandOpCode::INB),
-		0b11100101 => jump_ins(SingleBImmOperandOpCode::INW),
-		0b11100110 => jump_ins(SingleBImmOperandOpCode::OUTB),
-		0b11100111 => jump_ins(SingleBImmOperandOpCode::OUTW),
-		0b11101100 => iov_ins(SingleBImmOperandOpCode::INVB),
-		0b11101101 => iov_ins(SingleBImmOperandOpCode::INVW),
-		0b11101110 => iov_ins(SingleBImmOperandOpCode::OUTVB),
-		0b11101111 => iov_ins(SingleBImmOperandOpCode::OUTVW),
-		/*0b11001100 => int 3 ?*/
-		0b11001110 => no_op_ins(NoOperandOpCode::INTO),
-		0b11001111 => no_op_ins(NoOperandOpCode::IRET),
-		0b11111000 => no_op_ins(NoOperandOpCode::CLC),
-		0b11110101 => no_op_ins(NoOperandOpCode::CMC),
-		0b11111001 => no_op_ins(NoOperandOpCode::STC),
-		0b11111100 => no_op_ins(NoOperandOpCode::CLD),
-		0b11111101 => no_op_ins(NoOperandOpCode::STD),
-		0b11111010 => no_op_ins(NoOperandOpCode::CLI),
-		0b11111011 => no_op_ins(NoOperandOpCode::STI),
-		0b11110100 => no_op_ins(NoOperandOpCode::HLT),
-		0b10011011 => no_op_ins(NoOperandOpCode::WAIT),
-		0b11110010 => prefix_ins(Prefix::REPNE),
-		0b11110011 => prefix_ins(Prefix::REP),
-		0b11110000 => prefix_ins(Prefix::LOCK),
-		0b11001100 => SizedInstruction
+		Form::FarIndirect(op) =>
 		{
-			size: 1,
-			instruction: Instruction::SingleBImmOperand(SingleBImmOperandOpCode::INT, 0x3)
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			sized(Instruction::SingleFCOperand(op, FlowControlOperand::IndirectInterSeg(rm_op)), 2 + sz)
 		},
-		_ => prefix7_1_1bit(bytecode)
-	}
-}
-
-fn prefix7_1_1bit(bytecode: &[u8]) -> SizedInstruction
-{
-	let opcode_bytes = ((bytecode[0] as u16) << 8) + bytecode[1] as u16;
-	let masked = opcode_bytes & 0b1111110100100000;
-
-	match masked
-	{
-		0b1000110000000000 => 
+		Form::PushImm => sized(Instruction::SingleWOperand(SingleOperandOpCode::PUSH, WOperand::Immediate(get_u16(1, bytecode))), 3),
+		Form::PushSignedImm =>
+			sized(Instruction::SingleWOperand(SingleOperandOpCode::PUSH, WOperand::Immediate((bytecode[1] as i8) as u16)), 2),
+		Form::ImulImm | Form::ImulSignedImm =>
+		{
+			/* IMUL reg, rm, imm16 or sign-extended imm8 */
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			let (imm, immsz) = match form
+				{
+					Form::ImulSignedImm => ((bytecode[2 + sz as usize] as i8) as u16, 1),
+					_ => (get_u16(2 + sz as usize, bytecode), 2)
+				};
+			sized(Instruction::ThreeWOperands(ThreeOperandsOpCode::IMUL,
+				rm_op, WOperand::Immediate(imm), WOperand::Reg(get_wreg(reg()))), 2 + sz + immsz)
+		},
+		Form::Enter => sized(Instruction::Enter(get_u16(1, bytecode), bytecode[3]), 4),
+		Form::System(op) =>
 		{
-			/* Mov from/to SegReg */
-			let sr = (bytecode[1] & 0b00011000) >> 3;
-			let d = (bytecode[0] & 0b10) >> 1;
-			let (rm_op, rm_sz) = get_rm_woperand(bytecode);
-			let (op1, op2) = apply_swap(WOperand::SegReg(get_segreg(sr)), rm_op, d);
-			SizedInstruction
-			{
-				instruction: Instruction::TwoWOperands(TwoOperandsOpCode::MOV, op1, op2),
-				size: rm_sz + 2
-			}
+			let (rm_op, sz) = get_rm_woperand(bytecode);
+			sized(Instruction::System(op, rm_op), 2 + sz)
 		}
-		_ => prefix6_3bit(bytecode)
 	}
 }
 
-fn prefix6_3bit(bytecode: &[u8]) -> SizedInstruction
+/* Decodes an instruction as the 8086 would */
+pub fn parse_instruction(bytecode: &[u8]) -> SizedInstruction
 {
-	let masked = 0b1111110000111000 & (((bytecode[0] as u16) << 8) | bytecode[1] as u16);
-	let w = bytecode[0] & 0b1;
-	let sv = (bytecode[0] & 0b10) >> 1;
+	parse_model_instruction(bytecode, CpuModel::I8086)
+}
 
-	let tp_imm_rm_w = |opcode: TwoOperandsOpCode|
-	{
-		let (op, sz) = get_rm_woperand(bytecode);
-		let (imm, immsz) = 
-			match sv
-			{
-				0 => (get_u16(2+sz as usize, bytecode), 2),
-				1 => ((bytecode[2+sz as usize] as i8) as u16, 1),
-				_ => panic!("Invalid s bit")
-			};
+/* Decodes an instruction as 'model' would */
+pub fn parse_model_instruction(bytecode: &[u8], model: CpuModel) -> SizedInstruction
+{
+	decode(opcodes(model)[bytecode[0] as usize], bytecode)
+}
 
-		SizedInstruction
-		{
-			instruction: Instruction::TwoWOperands(opcode, WOperand::Immediate(imm), op),
-			size: 2 + sz + immsz
-		}
-	};
+/* An instruction along with what the prefixes before it change */
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct PrefixedInstruction
+{
+	pub segment_override: Option<SegReg>,
+	pub rep_prefix: Option<RepPrefix>,
+	pub lock: bool,
+	pub prefixes: u32,
+	/* Its size includes the prefixes */
+	pub sized: SizedInstruction
+}
 
-	let tp_imm_rm_b = |opcode: TwoOperandsOpCode|
+/* Decodes an instruction and its prefixes as 'model' would; the last
+ * segment override and repeat prefixes count */
+pub fn parse_prefixed_instruction(bytecode: &[u8], model: CpuModel) -> PrefixedInstruction
+{
+	let mut prefixed = PrefixedInstruction
 	{
-		let (op, sz) = get_rm_boperand(bytecode);
-
-		SizedInstruction
-		{
-			instruction: Instruction::TwoBOperands(opcode, BOperand::Immediate(bytecode[2 + sz as usize]), op),
-			size: 2 + sz + 1
-		}
+		segment_override: None,
+		rep_prefix: None,
+		lock: false,
+		prefixes: 0,
+		sized: invalid_instruction()
 	};
-
-	let tp_imm_rm = |opcode: TwoOperandsOpCode| 
-		dispatch_w(opcode, w, tp_imm_rm_b, tp_imm_rm_w);
-
-	let sr_rm = |opcode: ShiftRotateOpCode|
+	let mut offset = 0;
+	loop
 	{
-		let count =
-			match sv
-			{
-				0 => ShiftRotateCount::One,
-				1 => ShiftRotateCount::CL,
-				_ => panic!("invalid sv")
-			};
-
-		match w
+		let sized = parse_model_instruction(&bytecode[offset ..], model);
+		match sized.instruction
 		{
-			0 =>
+			Instruction::Prefix(Prefix::SEGMENT(sreg)) => prefixed.segment_override = Some(sreg),
+			Instruction::Prefix(Prefix::REP) => prefixed.rep_prefix = Some(RepPrefix::Rep),
+			Instruction::Prefix(Prefix::REPNE) => prefixed.rep_prefix = Some(RepPrefix::Repne),
+			Instruction::Prefix(Prefix::LOCK) => prefixed.lock = true,
+			_ =>
 			{
-				let (op, sz) = get_rm_boperand(bytecode);
-				SizedInstruction
+				prefixed.sized = SizedInstruction
 				{
-					instruction: Instruction::ShiftRotateB(opcode, count, op),
-					size: 2+sz
-				}
-			},
-			1 =>
-			{
-				let (op, sz) = get_rm_woperand(bytecode);
-				SizedInstruction
-				{
-					instruction: Instruction::ShiftRotateW(opcode, count, op),
-					size: 2+sz
-				}
+					size: offset as u16 + sized.size,
+					instruction: sized.instruction
+				};
+				return prefixed
 			}
-			_ => panic!("invalid w")
 		}
-	};
-
-	match masked
-	{
-		0b1000000000000000 => tp_imm_rm(TwoOperandsOpCode::ADD),
-		0b1000000000010000 => tp_imm_rm(TwoOperandsOpCode::ADC),
-		0b1000000000101000 => tp_imm_rm(TwoOperandsOpCode::SUB),
-		0b1000000000011000 => tp_imm_rm(TwoOperandsOpCode::SBB),
-		0b1000000000111000 => tp_imm_rm(TwoOperandsOpCode::CMP),
-		0b1101000000100000 => sr_rm(ShiftRotateOpCode::SHL),
-		0b1101000000101000 => sr_rm(ShiftRotateOpCode::SHR),
-		0b1101000000111000 => sr_rm(ShiftRotateOpCode::SAR),
-		0b1101000000000000 => sr_rm(ShiftRotateOpCode::ROL),
-		0b1101000000001000 => sr_rm(ShiftRotateOpCode::ROR),
-		0b1101000000010000 => sr_rm(ShiftRotateOpCode::RCL),
-		0b1101000000011000 => sr_rm(ShiftRotateOpCode::RCR),
-		_ => prefix7_3bit(bytecode)
+		prefixed.prefixes += 1;
+		offset += 1;
 	}
 }
 
-fn prefix7_3bit(bytecode: &[u8]) -> SizedInstruction
+/* Coprocessor instructions; the 8086 only computes the address of their
+ * memory operand */
+fn esc_instruction(bytecode: &[u8]) -> SizedInstruction
 {
-	let masked = 0b1111111000111000 & (((bytecode[0] as u16) << 8) | bytecode[1] as u16);
-	let w = bytecode[0] & 0b1;
-
-	let tp_ins_imm_rm_b = |opcode: TwoOperandsOpCode|
-	{
-		let (op, sz) = get_rm_boperand(bytecode);
-		let imm = bytecode[2+sz as usize];
-		SizedInstruction
-		{
-			instruction: Instruction::TwoBOperands(opcode, BOperand::Immediate(imm), op),
-			size: 3 + sz
-		}
-	};
-
-	let tp_ins_imm_rm_w = |opcode: TwoOperandsOpCode|
-	{
-		let (op, sz) = get_rm_woperand(bytecode);
-		let imm = ((bytecode[(2 + sz + 1) as usize] as u16) << 8) | (bytecode[(2 + sz) as usize] as u16);
-		SizedInstruction
-		{
-			instruction: Instruction::TwoWOperands(opcode, WOperand::Immediate(imm), op),
-			size: 4 + sz
-		}
-	};
-
-	/* TODO: this VS a single function that matches w() */
-	let tp_ins_imm_rm = |opcode: TwoOperandsOpCode| 
-		dispatch_w(opcode, w, tp_ins_imm_rm_b, tp_ins_imm_rm_w);
-
-	let sp_ins_rm_b = |opcode: SingleOperandOpCode|
-	{
-		let (op, sz) = get_rm_boperand(bytecode);
-		SizedInstruction
+	let esc = bytecode[0] & 0b111;
+	let addressing_byte = bytecode[1];
+	let reg = (addressing_byte & 0b00111000) >> 3;
+	let i = addressing_byte & 0b111;
+	let arithmetic = [FpuOpCode::FADD, FpuOpCode::FMUL, FpuOpCode::FCOM, FpuOpCode::FCOMP,
+		FpuOpCode::FSUB, FpuOpCode::FSUBR, FpuOpCode::FDIV, FpuOpCode::FDIVR];
+
+	if addressing_byte >> 6 != 0b11
+	{
+		let (operand, sz) = get_rm_woperand(bytecode);
+		let (op, operand) = match (esc, reg)
+		{
+			(0b000, _) => (arithmetic[reg as usize], FpuOperand::Memory(FpuFormat::Single, operand)),
+			(0b001, 0) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Single, operand)),
+			(0b001, 2) => (FpuOpCode::FST, FpuOperand::Memory(FpuFormat::Single, operand)),
+			(0b001, 3) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Single, operand)),
+			(0b001, 4) => (FpuOpCode::FLDENV, FpuOperand::Address(operand)),
+			(0b001, 5) => (FpuOpCode::FLDCW, FpuOperand::Word(operand)),
+			(0b001, 6) => (FpuOpCode::FNSTENV, FpuOperand::Address(operand)),
+			(0b001, 7) => (FpuOpCode::FNSTCW, FpuOperand::Word(operand)),
+			(0b010, _) => (arithmetic[reg as usize], FpuOperand::Memory(FpuFormat::Short, operand)),
+			(0b011, 0) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Short, operand)),
+			(0b011, 2) => (FpuOpCode::FST, FpuOperand::Memory(FpuFormat::Short, operand)),
+			(0b011, 3) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Short, operand)),
+			(0b011, 5) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Extended, operand)),
+			(0b011, 7) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Extended, operand)),
+			(0b100, _) => (arithmetic[reg as usize], FpuOperand::Memory(FpuFormat::Double, operand)),
+			(0b101, 0) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Double, operand)),
+			(0b101, 2) => (FpuOpCode::FST, FpuOperand::Memory(FpuFormat::Double, operand)),
+			(0b101, 3) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Double, operand)),
+			(0b101, 4) => (FpuOpCode::FRSTOR, FpuOperand::Address(operand)),
+			(0b101, 6) => (FpuOpCode::FNSAVE, FpuOperand::Address(operand)),
+			(0b101, 7) => (FpuOpCode::FNSTSW, FpuOperand::Word(operand)),
+			(0b110, _) => (arithmetic[reg as usize], FpuOperand::Memory(FpuFormat::Word, operand)),
+			(0b111, 0) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Word, operand)),
+			(0b111, 2) => (FpuOpCode::FST, FpuOperand::Memory(FpuFormat::Word, operand)),
+			(0b111, 3) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Word, operand)),
+			(0b111, 4) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Bcd, operand)),
+			(0b111, 5) => (FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Long, operand)),
+			(0b111, 6) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Bcd, operand)),
+			(0b111, 7) => (FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Long, operand)),
+			_ => return invalid_instruction()
+		};
+		return SizedInstruction
 		{
-			instruction: Instruction::SingleBOperand(opcode, op),
+			instruction: Instruction::Fpu(op, operand),
 			size: 2 + sz
 		}
-	};
+	}
 
-	let sp_ins_rm_w = |opcode: SingleOperandOpCode|
-	{
-		let (op, sz) = get_rm_woperand(bytecode);
-		SizedInstruction
-		{
-			instruction: Instruction::SingleWOperand(opcode, op),
-			size: 2 + sz
-		}
+	/* The register forms of ESC 4 and 6 swap the plain and reversed
+	 * subtractions and divisions */
+	let reversed = [FpuOpCode::FADD, FpuOpCode::FMUL, FpuOpCode::FCOM, FpuOpCode::FCOMP,
+		FpuOpCode::FSUBR, FpuOpCode::FSUB, FpuOpCode::FDIVR, FpuOpCode::FDIV];
+	let popping = [FpuOpCode::FADDP, FpuOpCode::FMULP, FpuOpCode::FCOM, FpuOpCode::FCOMP,
+		FpuOpCode::FSUBRP, FpuOpCode::FSUBP, FpuOpCode::FDIVRP, FpuOpCode::FDIVP];
+	let no_operand = |op: FpuOpCode| (op, FpuOperand::None);
+	let (op, operand) = match (esc, reg, i)
+	{
+		/* FCOM and FCOMP, and their undocumented aliases in ESC 4 and 6 */
+		(0b000, 2, _) | (0b000, 3, _) | (0b100, 2, _) | (0b100, 3, _) | (0b110, 2, _) => (arithmetic[reg as usize], FpuOperand::St(i)),
+		(0b000, _, _) => (arithmetic[reg as usize], FpuOperand::ToSt(i)),
+		(0b001, 0, _) => (FpuOpCode::FLD, FpuOperand::St(i)),
+		(0b001, 1, _) | (0b101, 1, _) => (FpuOpCode::FXCH, FpuOperand::St(i)),
+		(0b001, 2, 0) => no_operand(FpuOpCode::FNOP),
+		(0b001, 3, _) | (0b101, 3, _) => (FpuOpCode::FSTP, FpuOperand::St(i)),
+		(0b001, 4, 0) => no_operand(FpuOpCode::FCHS),
+		(0b001, 4, 1) => no_operand(FpuOpCode::FABS),
+		(0b001, 4, 4) => no_operand(FpuOpCode::FTST),
+		(0b001, 4, 5) => no_operand(FpuOpCode::FXAM),
+		(0b001, 5, 0) => no_operand(FpuOpCode::FLD1),
+		(0b001, 5, 1) => no_operand(FpuOpCode::FLDL2T),
+		(0b001, 5, 2) => no_operand(FpuOpCode::FLDL2E),
+		(0b001, 5, 3) => no_operand(FpuOpCode::FLDPI),
+		(0b001, 5, 4) => no_operand(FpuOpCode::FLDLG2),
+		(0b001, 5, 5) => no_operand(FpuOpCode::FLDLN2),
+		(0b001, 5, 6) => no_operand(FpuOpCode::FLDZ),
+		(0b001, 6, 0) => no_operand(FpuOpCode::F2XM1),
+		(0b001, 6, 1) => no_operand(FpuOpCode::FYL2X),
+		(0b001, 6, 2) => no_operand(FpuOpCode::FPTAN),
+		(0b001, 6, 3) => no_operand(FpuOpCode::FPATAN),
+		(0b001, 6, 4) => no_operand(FpuOpCode::FXTRACT),
+		(0b001, 6, 6) => no_operand(FpuOpCode::FDECSTP),
+		(0b001, 6, 7) => no_operand(FpuOpCode::FINCSTP),
+		(0b001, 7, 0) => no_operand(FpuOpCode::FPREM),
+		(0b001, 7, 1) => no_operand(FpuOpCode::FYL2XP1),
+		(0b001, 7, 2) => no_operand(FpuOpCode::FSQRT),
+		(0b001, 7, 4) => no_operand(FpuOpCode::FRNDINT),
+		(0b001, 7, 5) => no_operand(FpuOpCode::FSCALE),
+		(0b011, 4, 0) => no_operand(FpuOpCode::FNENI),
+		(0b011, 4, 1) => no_operand(FpuOpCode::FNDISI),
+		(0b011, 4, 2) => no_operand(FpuOpCode::FNCLEX),
+		(0b011, 4, 3) => no_operand(FpuOpCode::FNINIT),
+		(0b011, 4, 4) => no_operand(FpuOpCode::FSETPM),
+		(0b100, _, _) => (reversed[reg as usize], FpuOperand::FromSt(i)),
+		(0b101, 0, _) => (FpuOpCode::FFREE, FpuOperand::St(i)),
+		(0b101, 2, _) => (FpuOpCode::FST, FpuOperand::St(i)),
+		(0b110, 3, 1) => no_operand(FpuOpCode::FCOMPP),
+		(0b110, 3, _) => return invalid_instruction(),
+		(0b110, _, _) => (popping[reg as usize], FpuOperand::FromSt(i)),
+		/* 80287 */
+		(0b111, 4, 0) => (FpuOpCode::FNSTSW, FpuOperand::Word(WOperand::Reg(WReg::AX))),
+		_ => return invalid_instruction()
 	};
 
-	let sp_ins_rm = |opcode: SingleOperandOpCode|
-		{ dispatch_w(opcode, w, sp_ins_rm_b, sp_ins_rm_w) };
-
-	match masked
+	SizedInstruction
 	{
-		0b1100011000000000 => tp_ins_imm_rm(TwoOperandsOpCode::MOV),
-		0b1111111000000000 => sp_ins_rm(SingleOperandOpCode::INC),
-		0b1111111000001000 => sp_ins_rm(SingleOperandOpCode::DEC),
-		0b1111011000011000 => sp_ins_rm(SingleOperandOpCode::NEG),
-		0b1111011000100000 => sp_ins_rm(SingleOperandOpCode::MUL),
-		0b1111011000101000 => sp_ins_rm(SingleOperandOpCode::IMUL),
-		0b1111011000110000 => sp_ins_rm(SingleOperandOpCode::DIV),
-		0b1111011000111000 => sp_ins_rm(SingleOperandOpCode::IDIV),
-		0b1111011000010000 => sp_ins_rm(SingleOperandOpCode::NOT),
-		0b1000000000100000 => tp_ins_imm_rm(TwoOperandsOpCode::AND),
-		0b1111011000000000 => tp_ins_imm_rm(TwoOperandsOpCode::TEST),
-		0b1000000000001000 => tp_ins_imm_rm(TwoOperandsOpCode::OR),
-		0b1000000000110000 => tp_ins_imm_rm(TwoOperandsOpCode::XOR),
-		_ => prefix8_3bit(bytecode)
+		instruction: Instruction::Fpu(op, operand),
+		size: 2
 	}
 }
 
-// Catch opcodes that can only be distinguished by using two bytes...
-fn prefix8_3bit(bytecode: &[u8]) -> SizedInstruction
+fn apply_swap<Operand> (a: Operand, b: Operand, swap: u8) -> (Operand, Operand)
 {
-	let opcode_bytes = ((bytecode[0] as u16) << 8) + bytecode[1] as u16;
-	let masked = opcode_bytes & 0b1111111100111000;
-
-	let get_op_sz = || get_rm_woperand(bytecode);
-
-	let stack_ins = |opcode: SingleOperandOpCode|
-	{
-		let (op, sz) = get_op_sz();
-
-		SizedInstruction
-		{
-			instruction: Instruction::SingleWOperand(opcode, op),
-			size: 2 + sz
-		}
-	};
-
-	let fc_ins = |opcode: SingleOperandFCOpCode, op_wrap: fn(WOperand) -> FlowControlOperand|
+	match swap
 	{
-		let (op, sz) = get_op_sz();
-
-		SizedInstruction
-		{
-			instruction: Instruction::SingleFCOperand(opcode, op_wrap(op)),
-			size: 2 + sz
-		}
-	};
+		0 => (a,b),
+		1 => (b,a),
+		_ => panic!("Bad swap value")
+	}
+}
 
+fn get_u16(off: usize, data: &[u8]) -> u16
+{
+	data[off] as u16 | ((data[off+1] as u16) << 8)
+}
 
-	let tp_ins_imm8ext_rm = |opcode: TwoOperandsOpCode|
-	{
-		let (op, sz) = get_rm_woperand(bytecode);
-		let imm = bytecode[2+sz as usize];
-		SizedInstruction
-		{
-			instruction: Instruction::TwoWOperands(opcode, WOperand::Immediate((imm as i8) as u16), op),
-			size: 3 + sz
-		}
-	};
+fn two_operands_instruction_reg_rm(opcode: TwoOperandsOpCode, d: u8, w: u8, bytecode: &[u8]) -> SizedInstruction
+{
+	let addressing_byte = bytecode[1];
+	let reg = (addressing_byte & 0b00111000) >> 3;
 
-	let sr_ins_imm8_rm = |opcode: ShiftRotateOpCode|
-	{
-		let (op, sz) = get_rm_woperand(bytecode);
-		let imm = bytecode[2+sz as usize];
-		SizedInstruction
+	let (instruction, rm_operand_size) = 
+		match w
 		{
-			instruction: Instruction::ShiftRotateW(opcode, ShiftRotateCount::Imm(imm), op),
-			size: 3 + sz
-		}
-	};
+			0 =>
+			{
+				let (rm_operand, rm_operand_size) = get_rm_boperand(bytecode);
+				let (op1, op2) = apply_swap(BOperand::Reg(get_breg(reg)), rm_operand, d);
+				(Instruction::TwoBOperands(opcode, op1, op2), rm_operand_size)
+			},
+			1 =>
+			{
+				let (rm_operand, rm_operand_size) = get_rm_woperand(bytecode);
+				let (op1, op2) = apply_swap(WOperand::Reg(get_wreg(reg)), rm_operand, d);
+				(Instruction::TwoWOperands(opcode, op1, op2), rm_operand_size)
+			},
+			_ => panic!("Invalid w")
+		};
+	
 
-	match masked
+	SizedInstruction
 	{
-		0b1111111100110000 => stack_ins(SingleOperandOpCode::PUSH),
-		0b1000111100000000 => stack_ins(SingleOperandOpCode::POP),
-		0b1100000100100000 => sr_ins_imm8_rm(ShiftRotateOpCode::SHL), // REMOVE ME: unsupported by 8086
-		0b1000001100100000 => tp_ins_imm8ext_rm(TwoOperandsOpCode::AND), // REMOVE ME: unsupported by a 8086
-		0b1000001100001000 => tp_ins_imm8ext_rm(TwoOperandsOpCode::OR), // REMOVE ME: unsupported by a 8086
-		0b1000001100110000 => tp_ins_imm8ext_rm(TwoOperandsOpCode::XOR), // REMOVE ME: unsupported by a 8086
-		0b1111111100010000 => fc_ins(SingleOperandFCOpCode::CALL, FlowControlOperand::IndirectSeg),
-		0b1111111100011000 => fc_ins(SingleOperandFCOpCode::CALL, FlowControlOperand::IndirectInterSeg),
-		0b1111111100100000 => fc_ins(SingleOperandFCOpCode::JMP, FlowControlOperand::IndirectSeg),
-		0b1111111100101000 => fc_ins(SingleOperandFCOpCode::JMP, FlowControlOperand::IndirectInterSeg),
-		_ => invalid_instruction()
+		instruction: instruction,
+		size: 2 + rm_operand_size
 	}
 }
 
@@ -3,6 +3,8 @@ mod tests
 {
 	use super::super::instruction::*;
 	use super::super::parser::*;
+	use super::super::timing::CpuModel;
+	use super::super::base::RepPrefix;
 
 	fn ins(size: u16, instruction: Instruction) -> SizedInstruction
 	{
@@ -111,4 +113,158 @@ mod tests
 			assert_eq!(expected_instruction, instruction);
 		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn undefined_opcodes()
+	{
+		let i8086 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I8086);
+		let i80186 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I80186);
+
+		/* 0x64 is JE, as 0x74 */
+		assert_eq!(i8086(&[0x64, 0x10]), ins(2, Instruction::SingleBImmOperand(SingleBImmOperandOpCode::JE, 0x10)));
+		assert_eq!(i8086(&[0xc1]), ins(1, Instruction::FCNoOperandSeg(NoOpFCOpCode::RET)));
+		assert_eq!(i8086(&[0xc8, 0x04, 0x00]), ins(3, Instruction::SingleWImmFCOperand(
+			SingleWImmFCOpCode::RETANDADDTOSP, SingleWImmFCOperand::InterSeg(4))));
+		assert_eq!(i8086(&[0xf1]), ins(1, Instruction::Prefix(Prefix::LOCK)));
+		assert_eq!(i8086(&[0x0f]), ins(1, Instruction::SingleWOperand(SingleOperandOpCode::POP, WOperand::SegReg(SegReg::CS))));
+		assert_eq!(i8086(&[0xd6]), ins(1, Instruction::NoOperand(NoOperandOpCode::SALC)));
+
+		assert_eq!(i80186(&[0x0f, 0x00]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0x64, 0x10]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0xd6]), ins(1, Instruction::NoOperand(NoOperandOpCode::SALC)));
+
+		/* Register operands instead of memory ones, MOV CS */
+		assert_eq!(i8086(&[0x8d, 0xc3]), ins(2, Instruction::TwoWOperands(
+			TwoOperandsOpCode::LEA, WOperand::Reg(WReg::BX), WOperand::Reg(WReg::AX))));
+		assert_eq!(i80186(&[0x8d, 0xc3]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0xc4, 0xc3]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0x8e, 0xc8]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0xff, 0xdb]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0xff, 0x1f]).instruction, Instruction::SingleFCOperand(
+			SingleOperandFCOpCode::CALL, FlowControlOperand::IndirectInterSeg(WOperand::Indirect(IndReg::BX))));
+
+		/* Reg fields the 8086 ignores, or partly */
+		let aliases: &[(&[u8], Instruction)] = &[
+			(&[0xf7, 0xcb, 0x34, 0x12], Instruction::TwoWOperands(TwoOperandsOpCode::TEST, WOperand::Immediate(0x1234), WOperand::Reg(WReg::BX))),
+			(&[0xd0, 0xf0], Instruction::ShiftRotateB(ShiftRotateOpCode::SETMO, ShiftRotateCount::One, BOperand::Reg(BReg::AL))),
+			(&[0xd3, 0x37], Instruction::ShiftRotateW(ShiftRotateOpCode::SETMO, ShiftRotateCount::CL, WOperand::Indirect(IndReg::BX))),
+			(&[0x82, 0xe3, 0x0f], Instruction::TwoBOperands(TwoOperandsOpCode::AND, BOperand::Immediate(0x0f), BOperand::Reg(BReg::BL))),
+			(&[0x8f, 0x3f], Instruction::SingleWOperand(SingleOperandOpCode::POP, WOperand::Indirect(IndReg::BX))),
+			(&[0xc6, 0xd8, 0x12], Instruction::TwoBOperands(TwoOperandsOpCode::MOV, BOperand::Immediate(0x12), BOperand::Reg(BReg::AL))),
+			(&[0x8e, 0xe0], Instruction::TwoWOperands(TwoOperandsOpCode::MOV, WOperand::Reg(WReg::AX), WOperand::SegReg(SegReg::ES))),
+			(&[0x8c, 0xf8], Instruction::TwoWOperands(TwoOperandsOpCode::MOV, WOperand::SegReg(SegReg::DS), WOperand::Reg(WReg::AX))),
+			(&[0xff, 0x3f], Instruction::SingleWOperand(SingleOperandOpCode::PUSH, WOperand::Indirect(IndReg::BX)))];
+		for &(bytecode, ref instruction) in aliases
+		{
+			assert_eq!(i8086(bytecode), ins(bytecode.len() as u16, *instruction));
+			assert_eq!(i80186(bytecode).instruction, Instruction::Invalid);
+		}
+		assert_eq!(i80186(&[0x8c, 0xc8]), ins(2, Instruction::TwoWOperands(TwoOperandsOpCode::MOV, WOperand::SegReg(SegReg::CS), WOperand::Reg(WReg::AX))));
+	}
+
+	#[test]
+	fn instructions_80186()
+	{
+		let i8086 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I8086);
+		let i80188 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I80188);
+
+		assert_eq!(i80188(&[0x60]), ins(1, Instruction::NoOperand(NoOperandOpCode::PUSHA)));
+		assert_eq!(i80188(&[0x61]), ins(1, Instruction::NoOperand(NoOperandOpCode::POPA)));
+		assert_eq!(i80188(&[0x62, 0x47, 0x04]), ins(3, Instruction::TwoWOperands(
+			TwoOperandsOpCode::BOUND, WOperand::Indirect8iDis(IndReg::BX, 4), WOperand::Reg(WReg::AX))));
+		assert_eq!(i80188(&[0x62, 0xc0]).instruction, Instruction::Invalid);
+		assert_eq!(i80188(&[0x68, 0x34, 0x12]), ins(3, Instruction::SingleWOperand(
+			SingleOperandOpCode::PUSH, WOperand::Immediate(0x1234))));
+		assert_eq!(i80188(&[0x6a, 0xfe]), ins(2, Instruction::SingleWOperand(
+			SingleOperandOpCode::PUSH, WOperand::Immediate(0xfffe))));
+		assert_eq!(i80188(&[0x69, 0xd9, 0x00, 0x01]), ins(4, Instruction::ThreeWOperands(
+			ThreeOperandsOpCode::IMUL, WOperand::Reg(WReg::CX), WOperand::Immediate(0x100), WOperand::Reg(WReg::BX))));
+		assert_eq!(i80188(&[0x6b, 0x06, 0x00, 0x02, 0x80]), ins(5, Instruction::ThreeWOperands(
+			ThreeOperandsOpCode::IMUL, WOperand::Direct(0x200), WOperand::Immediate(0xff80), WOperand::Reg(WReg::AX))));
+		assert_eq!(i80188(&[0x6c]), ins(1, Instruction::ImplicitBOperand(ImplicitOperandOpCode::INS)));
+		assert_eq!(i80188(&[0x6f]), ins(1, Instruction::ImplicitWOperand(ImplicitOperandOpCode::OUTS)));
+		assert_eq!(i80188(&[0xc1, 0xe8, 0x04]), ins(3, Instruction::ShiftRotateW(
+			ShiftRotateOpCode::SHR, ShiftRotateCount::Imm(4), WOperand::Reg(WReg::AX))));
+		assert_eq!(i80188(&[0xc0, 0x07, 0x02]), ins(3, Instruction::ShiftRotateB(
+			ShiftRotateOpCode::ROL, ShiftRotateCount::Imm(2), BOperand::Indirect(IndReg::BX))));
+		assert_eq!(i80188(&[0xc8, 0x10, 0x00, 0x01]), ins(4, Instruction::Enter(0x10, 1)));
+		assert_eq!(i80188(&[0xc9]), ins(1, Instruction::NoOperand(NoOperandOpCode::LEAVE)));
+
+		/* The 8086 decodes these as other instructions */
+		assert_eq!(i8086(&[0x60, 0x02]), ins(2, Instruction::SingleBImmOperand(SingleBImmOperandOpCode::JO, 0x02)));
+		assert_eq!(i8086(&[0xc9]), ins(1, Instruction::FCNoOperandInterSeg(NoOpFCOpCode::RET)));
+		/* AND with a sign-extended immediate works on all models */
+		assert_eq!(i8086(&[0x83, 0xe0, 0xf0]), ins(3, Instruction::TwoWOperands(
+			TwoOperandsOpCode::AND, WOperand::Immediate(0xfff0), WOperand::Reg(WReg::AX))));
+	}
+
+	#[test]
+	fn instructions_80286()
+	{
+		let i80186 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I80186);
+		let i80286 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I80286);
+
+		assert_eq!(i80286(&[0x0f, 0x01, 0x16, 0x00, 0x05]), ins(5, Instruction::System(
+			SystemOpCode::LGDT, WOperand::Direct(0x500))));
+		assert_eq!(i80286(&[0x0f, 0x01, 0xe0]), ins(3, Instruction::System(
+			SystemOpCode::SMSW, WOperand::Reg(WReg::AX))));
+		assert_eq!(i80286(&[0x0f, 0x01, 0xf0]), ins(3, Instruction::System(
+			SystemOpCode::LMSW, WOperand::Reg(WReg::AX))));
+		/* The descriptor table registers only have memory operands */
+		assert_eq!(i80286(&[0x0f, 0x01, 0xd8]).instruction, Instruction::Invalid);
+		assert_eq!(i80286(&[0x0f, 0x00, 0xd8]), ins(3, Instruction::System(
+			SystemOpCode::LTR, WOperand::Reg(WReg::AX))));
+		assert_eq!(i80286(&[0x0f, 0x02, 0xc3]), ins(3, Instruction::TwoWOperands(
+			TwoOperandsOpCode::LAR, WOperand::Reg(WReg::BX), WOperand::Reg(WReg::AX))));
+		assert_eq!(i80286(&[0x0f, 0x06]), ins(2, Instruction::NoOperand(NoOperandOpCode::CLTS)));
+		assert_eq!(i80286(&[0x63, 0xd8]), ins(2, Instruction::TwoWOperands(
+			TwoOperandsOpCode::ARPL, WOperand::Reg(WReg::BX), WOperand::Reg(WReg::AX))));
+		/* The 80186 instructions are still there */
+		assert_eq!(i80286(&[0x60]), ins(1, Instruction::NoOperand(NoOperandOpCode::PUSHA)));
+
+		assert_eq!(i80186(&[0x0f, 0x01, 0xe0]).instruction, Instruction::Invalid);
+		assert_eq!(i80186(&[0x63, 0xd8]).instruction, Instruction::Invalid);
+	}
+	#[test]
+	fn esc_instructions()
+	{
+		let i8086 = |bytecode: &[u8]| parse_model_instruction(bytecode, CpuModel::I8086);
+
+		assert_eq!(i8086(&[0xd9, 0x07]), ins(2, Instruction::Fpu(
+			FpuOpCode::FLD, FpuOperand::Memory(FpuFormat::Single, WOperand::Indirect(IndReg::BX)))));
+		assert_eq!(i8086(&[0xdd, 0x5e, 0xf8]), ins(3, Instruction::Fpu(
+			FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Double, WOperand::Indirect8iDis(IndReg::BP, -8)))));
+		assert_eq!(i8086(&[0xdf, 0x3e, 0x00, 0x05]), ins(4, Instruction::Fpu(
+			FpuOpCode::FSTP, FpuOperand::Memory(FpuFormat::Long, WOperand::Direct(0x500)))));
+		assert_eq!(i8086(&[0xd9, 0x3e, 0x00, 0x05]), ins(4, Instruction::Fpu(
+			FpuOpCode::FNSTCW, FpuOperand::Word(WOperand::Direct(0x500)))));
+		assert_eq!(i8086(&[0xd8, 0xc1]), ins(2, Instruction::Fpu(FpuOpCode::FADD, FpuOperand::ToSt(1))));
+		/* With ST(i) as the destination, the reversed forms swap */
+		assert_eq!(i8086(&[0xdc, 0xe9]), ins(2, Instruction::Fpu(FpuOpCode::FSUB, FpuOperand::FromSt(1))));
+		assert_eq!(i8086(&[0xde, 0xf9]), ins(2, Instruction::Fpu(FpuOpCode::FDIVP, FpuOperand::FromSt(1))));
+		assert_eq!(i8086(&[0xde, 0xd9]), ins(2, Instruction::Fpu(FpuOpCode::FCOMPP, FpuOperand::None)));
+		assert_eq!(i8086(&[0xdf, 0xe0]), ins(2, Instruction::Fpu(
+			FpuOpCode::FNSTSW, FpuOperand::Word(WOperand::Reg(WReg::AX)))));
+		assert_eq!(i8086(&[0xdb, 0xe3]), ins(2, Instruction::Fpu(FpuOpCode::FNINIT, FpuOperand::None)));
+		assert_eq!(i8086(&[0xd9, 0xfa]), ins(2, Instruction::Fpu(FpuOpCode::FSQRT, FpuOperand::None)));
+		assert_eq!(i8086(&[0xd9, 0xd8]).instruction, Instruction::Fpu(FpuOpCode::FSTP, FpuOperand::St(0)));
+		assert_eq!(i8086(&[0xd9, 0xd1]).instruction, Instruction::Invalid);
+	}
+
+	#[test]
+	fn prefixed_instructions()
+	{
+		/* ES: REP CS: MOVSW; the last segment override counts */
+		let movs = parse_prefixed_instruction(&[0x26, 0xf3, 0x2e, 0xa5, 0x90], CpuModel::I8086);
+		assert_eq!((movs.segment_override, movs.rep_prefix, movs.lock, movs.prefixes), (Some(SegReg::CS), Some(RepPrefix::Rep), false, 3));
+		assert_eq!(movs.sized, ins(4, Instruction::ImplicitWOperand(ImplicitOperandOpCode::MOVS)));
+
+		let nop = parse_prefixed_instruction(&[0x90], CpuModel::I8086);
+		assert_eq!((nop.segment_override, nop.rep_prefix, nop.prefixes, nop.sized.size), (None, None, 0, 1));
+
+		/* 0xf1 is LOCK on the 8086 only; the prefixes still count in the size */
+		let lock = parse_prefixed_instruction(&[0xf1, 0x90], CpuModel::I8086);
+		assert_eq!((lock.lock, lock.sized.size), (true, 2));
+		assert_eq!(parse_prefixed_instruction(&[0xf0, 0xf1, 0x90], CpuModel::I80186).sized, ins(1, Instruction::Invalid));
+	}
+}
@@ -0,0 +1,1340 @@
+use super::base::*;
+use super::instruction::*;
+use super::reg_access::*;
+use super::operand_access::OperandAccess;
+use super::timing::system_cycles;
+use super::traps::TrapEvent;
+use super::super::mem::Memory;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+/* Machine status word: the 80286 has no CR0, its MSW lives in the cr0 field */
+pub const MSW_PE: u8 = 0x1;
+pub const MSW_MP: u8 = 0x2;
+pub const MSW_EM: u8 = 0x4;
+pub const MSW_TS: u8 = 0x8;
+
+pub const FLAG_IOPL: u16 = 0b0011000000000000;
+pub const FLAG_NT: u16 = 0b0100000000000000;
+
+pub const EXC_INVALID_OPCODE: u8 = 6;
+pub const EXC_DOUBLE_FAULT: u8 = 8;
+pub const EXC_INVALID_TSS: u8 = 10;
+pub const EXC_NOT_PRESENT: u8 = 11;
+pub const EXC_STACK: u8 = 12;
+pub const EXC_GENERAL_PROTECTION: u8 = 13;
+
+/* Descriptor access byte; some bits mean different things for code and
+ * data segments */
+const ACCESS_PRESENT: u8 = 0x80;
+const ACCESS_SEGMENT: u8 = 0x10;
+const ACCESS_CODE: u8 = 0x08;
+const ACCESS_CONFORMING: u8 = 0x04;
+const ACCESS_EXPAND_DOWN: u8 = 0x04;
+const ACCESS_READABLE: u8 = 0x02;
+const ACCESS_WRITABLE: u8 = 0x02;
+const ACCESS_ACCESSED: u8 = 0x01;
+/* Tells an available TSS from a busy one */
+const ACCESS_BUSY: u8 = 0x02;
+
+/* System descriptor types */
+const TYPE_AVAILABLE_TSS: u8 = 1;
+const TYPE_LDT: u8 = 2;
+const TYPE_BUSY_TSS: u8 = 3;
+const TYPE_CALL_GATE: u8 = 4;
+const TYPE_TASK_GATE: u8 = 5;
+const TYPE_INTERRUPT_GATE: u8 = 6;
+const TYPE_TRAP_GATE: u8 = 7;
+
+/* 80286 task state segment */
+const TSS_MIN_LIMIT: u16 = 43;
+const TSS_BACK_LINK: u32 = 0;
+/* SP0, SS0, SP1, SS1, SP2, SS2 */
+const TSS_STACKS: u32 = 2;
+const TSS_IP: u32 = 14;
+const TSS_FLAGS: u32 = 16;
+/* AX, CX, DX, BX, SP, BP, SI, DI */
+const TSS_REGISTERS: u32 = 18;
+/* ES, CS, SS, DS */
+const TSS_SEGMENTS: u32 = 34;
+const TSS_LDT: u32 = 42;
+
+/* A segment or system descriptor, or the hidden part of a segment register
+ * caching the descriptor it was loaded from */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Descriptor
+{
+	pub base: u32,
+	pub limit: u16,
+	pub access: u8
+}
+
+impl Descriptor
+{
+	/* What a segment register holds in real mode */
+	pub fn real_mode(selector: u16) -> Descriptor
+	{
+		Descriptor
+		{
+			base: (selector as u32) << 4,
+			limit: 0xffff,
+			access: ACCESS_PRESENT | ACCESS_SEGMENT | ACCESS_WRITABLE | ACCESS_ACCESSED
+		}
+	}
+
+	fn read(mem: &Memory, addr: u32) -> Descriptor
+	{
+		Descriptor
+		{
+			base: mem.read_u16(addr + 2) as u32 | (mem.read_u8(addr + 4) as u32) << 16,
+			limit: mem.read_u16(addr),
+			access: mem.read_u8(addr + 5)
+		}
+	}
+
+	pub fn present(&self) -> bool
+	{
+		self.access & ACCESS_PRESENT != 0
+	}
+
+	pub fn dpl(&self) -> u16
+	{
+		((self.access >> 5) & 0x3) as u16
+	}
+
+	fn is_segment(&self) -> bool
+	{
+		self.access & ACCESS_SEGMENT != 0
+	}
+
+	fn is_code(&self) -> bool
+	{
+		self.is_segment() && self.access & ACCESS_CODE != 0
+	}
+
+	fn is_data(&self) -> bool
+	{
+		self.is_segment() && self.access & ACCESS_CODE == 0
+	}
+
+	fn conforming(&self) -> bool
+	{
+		self.is_code() && self.access & ACCESS_CONFORMING != 0
+	}
+
+	fn readable(&self) -> bool
+	{
+		self.is_data() || (self.is_code() && self.access & ACCESS_READABLE != 0)
+	}
+
+	fn writable(&self) -> bool
+	{
+		self.is_data() && self.access & ACCESS_WRITABLE != 0
+	}
+
+	fn system_type(&self) -> Option<u8>
+	{
+		if self.is_segment() { None } else { Some(self.access & 0x0f) }
+	}
+
+	/* Gates hold a selector and an offset instead of a base and a limit */
+	fn gate_offset(&self) -> u16
+	{
+		self.limit
+	}
+
+	fn gate_selector(&self) -> u16
+	{
+		self.base as u16
+	}
+
+	/* Parameters copied by call gates */
+	fn gate_word_count(&self) -> u16
+	{
+		((self.base >> 16) & 0x1f) as u16
+	}
+
+	/* Whether 'size' bytes at 'offset' are within the segment; expand-down
+	 * segments hold the offsets above their limit */
+	fn contains(&self, offset: u16, size: u16) -> bool
+	{
+		let last = offset as u32 + size as u32 - 1;
+		if self.is_data() && self.access & ACCESS_EXPAND_DOWN != 0
+		{
+			offset > self.limit && last <= 0xffff
+		}
+		else
+		{
+			last <= self.limit as u32
+		}
+	}
+}
+
+/* GDTR and IDTR */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TableRegister
+{
+	pub base: u32,
+	pub limit: u16
+}
+
+/* The 80286 state beyond the 8086 registers; the other models keep the
+ * real mode interrupt table at 0 */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ProtectedState
+{
+	/* Hidden parts of ES, CS, SS and DS, in this order; only used in
+	 * protected mode */
+	pub segments: [Descriptor; 4],
+	pub gdtr: TableRegister,
+	pub idtr: TableRegister,
+	pub ldtr: u16,
+	pub ldt: Descriptor,
+	pub tr: u16,
+	pub tss: Descriptor
+}
+
+impl ProtectedState
+{
+	pub fn new() -> ProtectedState
+	{
+		ProtectedState
+		{
+			segments: [Descriptor::default(); 4],
+			gdtr: TableRegister { base: 0, limit: 0xffff },
+			idtr: TableRegister { base: 0, limit: 0x3ff },
+			ldtr: 0,
+			ldt: Descriptor::default(),
+			tr: 0,
+			tss: Descriptor::default()
+		}
+	}
+}
+
+fn segment_index(sr: SegReg) -> usize
+{
+	match sr
+	{
+		SegReg::ES => 0,
+		SegReg::CS => 1,
+		SegReg::SS => 2,
+		SegReg::DS => 3
+	}
+}
+
+/* An exception raised by the current instruction */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fault
+{
+	pub vector: u8,
+	pub error_code: Option<u16>
+}
+
+/* What an instruction that faults is rolled back to */
+pub struct Checkpoint
+{
+	registers: [u16; 14],
+	segments: [Descriptor; 4],
+	tr: u16
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum TaskSwitch
+{
+	Jump,
+	/* CALL, INT and exceptions through task gates: the new task links back
+	 * to the current one */
+	Call,
+	Iret
+}
+
+impl CPU
+{
+	pub fn protected_mode(&self) -> bool
+	{
+		self.cr0 & MSW_PE != 0
+	}
+
+	/* Current privilege level */
+	pub fn cpl(&self) -> u16
+	{
+		if self.protected_mode() { self.cs & 0x3 } else { 0 }
+	}
+
+	fn iopl(&self) -> u16
+	{
+		(self.flags & FLAG_IOPL) >> 12
+	}
+
+	/* Records a fault; the instruction goes on until it returns, but memory
+	 * accesses are ignored from then on. Only the first fault counts */
+	pub fn raise_fault(&self, vector: u8, error_code: u16)
+	{
+		if self.fault.get().is_none()
+		{
+			self.fault.set(Some(Fault { vector: vector, error_code: Some(error_code) }));
+		}
+	}
+
+	fn faulted(&self) -> bool
+	{
+		self.fault.get().is_some()
+	}
+
+	fn set_segment(&mut self, sr: SegReg, selector: u16, cache: Descriptor)
+	{
+		self.set_reg(sr, selector);
+		self.protected.segments[segment_index(sr)] = cache;
+	}
+
+	pub fn segment_cache(&self, sr: SegReg) -> Descriptor
+	{
+		self.protected.segments[segment_index(sr)]
+	}
+
+	/* Address of 'size' bytes at seg:offset, checked against the segment in
+	 * protected mode; None after a fault */
+	pub fn linear_address(&self, seg: SegReg, offset: u16, size: u16, write: bool) -> Option<u32>
+	{
+		if !self.protected_mode()
+		{
+			return Some(((self.get_reg(seg) as u32) << 4) + offset as u32)
+		}
+		if self.faulted()
+		{
+			return None
+		}
+
+		let cache = &self.protected.segments[segment_index(seg)];
+		let vector = if seg == SegReg::SS { EXC_STACK } else { EXC_GENERAL_PROTECTION };
+		if !cache.present()
+		{
+			/* Null selector */
+			self.raise_fault(vector, 0);
+			None
+		}
+		else if (write && !cache.writable()) || (!write && !cache.readable())
+		{
+			self.raise_fault(EXC_GENERAL_PROTECTION, 0);
+			None
+		}
+		else if !cache.contains(offset, size)
+		{
+			self.raise_fault(vector, 0);
+			None
+		}
+		else
+		{
+			Some(cache.base + offset as u32)
+		}
+	}
+
+	/* Where the instruction at CS:ip is */
+	pub fn code_address(&self, ip: u16) -> u32
+	{
+		if self.protected_mode()
+		{
+			self.segment_cache(SegReg::CS).base + ip as u32
+		}
+		else
+		{
+			((self.cs as u32) << 4) + ip as u32
+		}
+	}
+
+	/* Where seg:offset is for the debugger, which names segments by their
+	 * selector in protected mode: the descriptor cache of a segment register
+	 * holding it, else its descriptor; unknown selectors have a null base.
+	 * Never faults */
+	pub fn debugger_address(&self, mem: &Memory, seg: u16, offset: u16) -> u32
+	{
+		if !self.protected_mode()
+		{
+			return phys_addr(seg, offset)
+		}
+		let loaded = [SegReg::CS, SegReg::SS, SegReg::DS, SegReg::ES].iter()
+			.find(|&&sr| self.get_reg(sr) == seg)
+			.map(|&sr| self.segment_cache(sr));
+		let base = loaded.or_else(|| self.read_descriptor(mem, seg)).map_or(0, |desc| desc.base);
+		base + offset as u32
+	}
+
+	/* Address of the descriptor 'selector' refers to, or None if it is
+	 * beyond its table */
+	fn descriptor_address(&self, selector: u16) -> Option<u32>
+	{
+		let (base, limit) = if selector & 0x4 != 0
+			{
+				if !self.protected.ldt.present()
+				{
+					return None
+				}
+				(self.protected.ldt.base, self.protected.ldt.limit)
+			}
+			else
+			{
+				(self.protected.gdtr.base, self.protected.gdtr.limit)
+			};
+		let index = (selector & 0xfff8) as u32;
+		if index + 7 > limit as u32 { None } else { Some(base + index) }
+	}
+
+	fn read_descriptor(&self, mem: &Memory, selector: u16) -> Option<Descriptor>
+	{
+		self.descriptor_address(selector).map(|addr| Descriptor::read(mem, addr))
+	}
+
+	fn set_descriptor_bits(&self, mem: &mut Memory, selector: u16, mask: u8, set: bool)
+	{
+		if let Some(addr) = self.descriptor_address(selector)
+		{
+			let access = mem.read_u8(addr + 5);
+			let new_access = if set { access | mask } else { access & !mask };
+			if new_access != access
+			{
+				mem.write_u8(addr + 5, new_access);
+			}
+		}
+	}
+
+	/* Descriptor of the segment 'selector' loads into DS, ES or SS at
+	 * privilege level 'cpl'; raises a fault, #GP or 'vector' (#TS during
+	 * task switches), and returns None if it cannot. A null selector gives
+	 * an unusable segment */
+	fn data_segment(&self, mem: &mut Memory, sr: SegReg, selector: u16, cpl: u16, vector: u8) -> Option<Descriptor>
+	{
+		let error_code = selector & 0xfffc;
+		if error_code == 0
+		{
+			if sr == SegReg::SS
+			{
+				self.raise_fault(vector, 0);
+				return None
+			}
+			return Some(Descriptor::default())
+		}
+
+		let mut desc = match self.read_descriptor(mem, selector)
+		{
+			Some(desc) => desc,
+			None =>
+			{
+				self.raise_fault(vector, error_code);
+				return None
+			}
+		};
+		let allowed = if sr == SegReg::SS
+			{
+				desc.writable() && selector & 0x3 == cpl && desc.dpl() == cpl
+			}
+			else
+			{
+				desc.readable() && (desc.conforming() || desc.dpl() >= cpl.max(selector & 0x3))
+			};
+		if !allowed
+		{
+			self.raise_fault(vector, error_code);
+			return None
+		}
+		if !desc.present()
+		{
+			self.raise_fault(if sr == SegReg::SS { EXC_STACK } else { EXC_NOT_PRESENT }, error_code);
+			return None
+		}
+
+		self.set_descriptor_bits(mem, selector, ACCESS_ACCESSED, true);
+		desc.access |= ACCESS_ACCESSED;
+		Some(desc)
+	}
+
+	/* MOV, POP, LDS and LES to a segment register */
+	pub fn load_segment(&mut self, mem: &mut Memory, sr: SegReg, selector: u16)
+	{
+		if !self.protected_mode()
+		{
+			self.set_reg(sr, selector);
+			return
+		}
+		if sr == SegReg::CS
+		{
+			/* Only far transfers can load CS */
+			self.fault.set(Some(Fault { vector: EXC_INVALID_OPCODE, error_code: None }));
+			return
+		}
+		let cpl = self.cpl();
+		if let Some(cache) = self.data_segment(mem, sr, selector, cpl, EXC_GENERAL_PROTECTION)
+		{
+			self.set_segment(sr, selector, cache);
+		}
+	}
+
+	/* POPF and IRET: the 80286 keeps FLAGS bits 12-15 clear in real mode;
+	 * in protected mode, only CPL 0 changes IOPL, and IF needs CPL <= IOPL */
+	pub fn load_flags(&mut self, value: u16)
+	{
+		if !self.protected_mode()
+		{
+			self.flags = (value & 0x0fff) | self.timing.model.fixed_flags();
+			return
+		}
+
+		let cpl = self.cpl();
+		let mut mask = FLAG_NT | 0x0fff;
+		if cpl == 0
+		{
+			mask |= FLAG_IOPL;
+		}
+		if cpl > self.iopl()
+		{
+			mask &= !FLAG_I;
+		}
+		self.flags = (self.flags & !mask) | (value & mask);
+	}
+
+	/* CLI, STI and port I/O need CPL <= IOPL in protected mode; raises #GP
+	 * and returns false otherwise */
+	pub fn check_io_privilege(&self) -> bool
+	{
+		let allowed = !self.protected_mode() || self.cpl() <= self.iopl();
+		if !allowed
+		{
+			self.raise_fault(EXC_GENERAL_PROTECTION, 0);
+		}
+		allowed
+	}
+
+	/* HLT and the instructions loading system registers need CPL 0 */
+	pub fn check_cpl0(&self) -> bool
+	{
+		let allowed = self.cpl() == 0;
+		if !allowed
+		{
+			self.raise_fault(EXC_GENERAL_PROTECTION, 0);
+		}
+		allowed
+	}
+
+	/* LMSW: PE can be set but not cleared. The segment registers keep
+	 * addressing what they did in real mode until they are loaded again */
+	fn load_msw(&mut self, value: u16)
+	{
+		let entering = !self.protected_mode() && value & MSW_PE as u16 != 0;
+		self.cr0 = (self.cr0 & MSW_PE) | (value & 0x0f) as u8;
+		if entering
+		{
+			for &sr in &[SegReg::ES, SegReg::CS, SegReg::SS, SegReg::DS]
+			{
+				let selector = self.get_reg(sr);
+				self.protected.segments[segment_index(sr)] = Descriptor::real_mode(selector);
+			}
+		}
+	}
+
+	fn load_ldt(&mut self, mem: &Memory, selector: u16, vector: u8)
+	{
+		let error_code = selector & 0xfffc;
+		if error_code == 0
+		{
+			self.protected.ldtr = selector;
+			self.protected.ldt = Descriptor::default();
+			return
+		}
+
+		/* The LDT descriptor is in the GDT */
+		match if selector & 0x4 != 0 { None } else { self.read_descriptor(mem, selector) }
+		{
+			Some(ref desc) if desc.system_type() != Some(TYPE_LDT) => self.raise_fault(vector, error_code),
+			Some(ref desc) if !desc.present() => self.raise_fault(EXC_NOT_PRESENT, error_code),
+			Some(desc) =>
+			{
+				self.protected.ldtr = selector;
+				self.protected.ldt = desc;
+			}
+			None => self.raise_fault(vector, error_code)
+		}
+	}
+
+	fn load_task_register(&mut self, mem: &mut Memory, selector: u16)
+	{
+		let error_code = selector & 0xfffc;
+		match if error_code == 0 || selector & 0x4 != 0 { None } else { self.read_descriptor(mem, selector) }
+		{
+			Some(ref desc) if desc.system_type() != Some(TYPE_AVAILABLE_TSS) =>
+				self.raise_fault(EXC_GENERAL_PROTECTION, error_code),
+			Some(ref desc) if !desc.present() => self.raise_fault(EXC_NOT_PRESENT, error_code),
+			Some(mut desc) =>
+			{
+				self.set_descriptor_bits(mem, selector, ACCESS_BUSY, true);
+				desc.access |= ACCESS_BUSY;
+				self.protected.tr = selector;
+				self.protected.tss = desc;
+			}
+			None => self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+		}
+	}
+
+	/* VERR and VERW */
+	fn verify_segment(&self, mem: &Memory, selector: u16, write: bool) -> bool
+	{
+		if selector & 0xfffc == 0
+		{
+			return false
+		}
+		match self.read_descriptor(mem, selector)
+		{
+			Some(ref desc) if desc.is_segment() =>
+			{
+				let visible = desc.conforming() || desc.dpl() >= self.cpl().max(selector & 0x3);
+				visible && if write { desc.writable() } else { desc.readable() }
+			}
+			_ => false
+		}
+	}
+
+	/* LAR and LSL: the access byte (as the high byte) or the limit of the
+	 * descriptor, if visible at the current privilege level */
+	pub fn descriptor_info(&self, mem: &Memory, selector: u16, access: bool) -> Option<u16>
+	{
+		if selector & 0xfffc == 0
+		{
+			return None
+		}
+		let desc = self.read_descriptor(mem, selector)?;
+		let valid_type = match desc.system_type()
+		{
+			None => true,
+			Some(TYPE_AVAILABLE_TSS) | Some(TYPE_LDT) | Some(TYPE_BUSY_TSS) => true,
+			/* Gates have no limit */
+			Some(TYPE_CALL_GATE) | Some(TYPE_TASK_GATE) => access,
+			Some(_) => false
+		};
+		let visible = desc.conforming() || desc.dpl() >= self.cpl().max(selector & 0x3);
+		if !valid_type || !visible
+		{
+			return None
+		}
+		Some(if access { (desc.access as u16) << 8 } else { desc.limit })
+	}
+
+	/* 6-byte operand of LGDT and LIDT: limit, then 24-bit base */
+	fn load_table_register(&self, mem: &Memory, operand: &WOperand) -> TableRegister
+	{
+		let (seg, addr) = self.effective_address(operand);
+		let limit = self.load_memory_u16(mem, seg, addr);
+		let base_low = self.load_memory_u16(mem, seg, addr.wrapping_add(2));
+		let base_high = self.load_memory_u8(mem, seg, addr.wrapping_add(4));
+		TableRegister { base: base_low as u32 | (base_high as u32) << 16, limit: limit }
+	}
+
+	/* SGDT and SIDT store 0xff as the unused sixth byte */
+	fn store_table_register(&self, mem: &mut Memory, operand: &WOperand, table: TableRegister)
+	{
+		let (seg, addr) = self.effective_address(operand);
+		self.store_memory_u16(mem, seg, addr, table.limit);
+		self.store_memory_u16(mem, seg, addr.wrapping_add(2), table.base as u16);
+		self.store_memory_u8(mem, seg, addr.wrapping_add(4), (table.base >> 16) as u8);
+		self.store_memory_u8(mem, seg, addr.wrapping_add(5), 0xff);
+	}
+
+	pub fn run_system_ins(&mut self, mem: &mut Memory, op: SystemOpCode, operand: WOperand) -> u32
+	{
+		let cycles = system_cycles(&op, &operand);
+
+		match op
+		{
+			SystemOpCode::SLDT | SystemOpCode::STR | SystemOpCode::LLDT | SystemOpCode::LTR |
+			SystemOpCode::VERR | SystemOpCode::VERW if !self.protected_mode() =>
+			{
+				/* Protected mode only */
+				self.raise_exception(mem, EXC_INVALID_OPCODE);
+				return cycles
+			}
+			_ => {}
+		}
+
+		match op
+		{
+			SystemOpCode::SLDT =>
+			{
+				let ldtr = self.protected.ldtr;
+				self.store_operand(mem, &operand, ldtr);
+			}
+			SystemOpCode::STR =>
+			{
+				let tr = self.protected.tr;
+				self.store_operand(mem, &operand, tr);
+			}
+			SystemOpCode::LLDT => if self.check_cpl0()
+			{
+				let selector = self.load_operand(mem, &operand);
+				self.load_ldt(mem, selector, EXC_GENERAL_PROTECTION);
+			},
+			SystemOpCode::LTR => if self.check_cpl0()
+			{
+				let selector = self.load_operand(mem, &operand);
+				self.load_task_register(mem, selector);
+			},
+			SystemOpCode::VERR | SystemOpCode::VERW =>
+			{
+				let selector = self.load_operand(mem, &operand);
+				let valid = self.verify_segment(mem, selector, op == SystemOpCode::VERW);
+				self.set_flag_value(FLAG_Z, valid);
+			}
+			SystemOpCode::SGDT =>
+			{
+				let gdtr = self.protected.gdtr;
+				self.store_table_register(mem, &operand, gdtr);
+			}
+			SystemOpCode::SIDT =>
+			{
+				let idtr = self.protected.idtr;
+				self.store_table_register(mem, &operand, idtr);
+			}
+			SystemOpCode::LGDT => if self.check_cpl0()
+			{
+				self.protected.gdtr = self.load_table_register(mem, &operand);
+			},
+			SystemOpCode::LIDT => if self.check_cpl0()
+			{
+				self.protected.idtr = self.load_table_register(mem, &operand);
+			},
+			SystemOpCode::SMSW =>
+			{
+				/* The reserved bits read as 1 */
+				let msw = 0xfff0 | self.cr0 as u16;
+				self.store_operand(mem, &operand, msw);
+			}
+			SystemOpCode::LMSW => if self.check_cpl0()
+			{
+				let msw = self.load_operand(mem, &operand);
+				if !self.faulted()
+				{
+					self.load_msw(msw);
+				}
+			}
+		}
+
+		cycles
+	}
+
+	/* SS:SP of privilege level 'dpl', from the current TSS */
+	fn inner_stack(&self, mem: &mut Memory, dpl: u16) -> Option<(u16, u16, Descriptor)>
+	{
+		let tss = self.protected.tss;
+		let offset = TSS_STACKS + dpl as u32 * 4;
+		if offset + 3 > tss.limit as u32
+		{
+			self.raise_fault(EXC_INVALID_TSS, self.protected.tr & 0xfffc);
+			return None
+		}
+		let sp = mem.read_u16(tss.base + offset);
+		let ss = mem.read_u16(tss.base + offset + 2);
+		self.data_segment(mem, SegReg::SS, ss, dpl, EXC_INVALID_TSS).map(|desc| (ss, sp, desc))
+	}
+
+	/* Loads CS:IP with a code segment at privilege level 'cpl', pushing the
+	 * return address for calls */
+	fn enter_code_segment(&mut self, mem: &mut Memory, selector: u16, mut desc: Descriptor, offset: u16, cpl: u16, call: bool)
+	{
+		if offset > desc.limit
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, 0)
+		}
+		if call
+		{
+			let (cs, ip) = (self.cs, self.ip);
+			self.stack_push(mem, cs);
+			self.stack_push(mem, ip);
+			if self.faulted()
+			{
+				return
+			}
+		}
+		self.set_descriptor_bits(mem, selector, ACCESS_ACCESSED, true);
+		desc.access |= ACCESS_ACCESSED;
+		self.set_segment(SegReg::CS, (selector & 0xfffc) | cpl, desc);
+		self.ip = offset;
+	}
+
+	/* Code segment descriptor for a far transfer to 'selector', or None
+	 * after a fault */
+	fn code_segment(&self, mem: &Memory, selector: u16, ext: u16) -> Option<Descriptor>
+	{
+		let error_code = selector & 0xfffc;
+		if error_code == 0
+		{
+			self.raise_fault(EXC_GENERAL_PROTECTION, ext);
+			return None
+		}
+		match self.read_descriptor(mem, selector)
+		{
+			Some(desc) if desc.is_code() => Some(desc),
+			_ =>
+			{
+				self.raise_fault(EXC_GENERAL_PROTECTION, error_code | ext);
+				None
+			}
+		}
+	}
+
+	/* Far JMP and CALL in protected mode: to a code segment, or through a
+	 * call gate, a task gate or a TSS */
+	pub fn protected_far_transfer(&mut self, mem: &mut Memory, selector: u16, offset: u16, call: bool)
+	{
+		let cpl = self.cpl();
+		let rpl = selector & 0x3;
+		let error_code = selector & 0xfffc;
+		if error_code == 0
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, 0)
+		}
+		let desc = match self.read_descriptor(mem, selector)
+		{
+			Some(desc) => desc,
+			None => return self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+		};
+
+		if desc.is_segment()
+		{
+			let allowed = desc.is_code() &&
+				if desc.conforming() { desc.dpl() <= cpl } else { rpl <= cpl && desc.dpl() == cpl };
+			if !allowed
+			{
+				return self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+			}
+			if !desc.present()
+			{
+				return self.raise_fault(EXC_NOT_PRESENT, error_code)
+			}
+			return self.enter_code_segment(mem, selector, desc, offset, cpl, call)
+		}
+
+		let kind = match desc.system_type()
+		{
+			Some(TYPE_CALL_GATE) | Some(TYPE_TASK_GATE) | Some(TYPE_AVAILABLE_TSS) => desc.system_type(),
+			_ => return self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+		};
+		if desc.dpl() < cpl || desc.dpl() < rpl
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+		}
+		if !desc.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, error_code)
+		}
+		let switch = if call { TaskSwitch::Call } else { TaskSwitch::Jump };
+		match kind
+		{
+			Some(TYPE_CALL_GATE) => self.call_gate(mem, desc, call),
+			Some(TYPE_TASK_GATE) => self.switch_task(mem, desc.gate_selector(), switch),
+			_ => self.switch_task(mem, selector, switch)
+		}
+	}
+
+	/* Calls to a more privileged, non-conforming code segment switch to its
+	 * stack and copy the parameters there */
+	fn call_gate(&mut self, mem: &mut Memory, gate: Descriptor, call: bool)
+	{
+		let cpl = self.cpl();
+		let selector = gate.gate_selector();
+		let error_code = selector & 0xfffc;
+		let mut desc = match self.code_segment(mem, selector, 0)
+		{
+			Some(desc) => desc,
+			None => return
+		};
+		if desc.dpl() > cpl || (!call && !desc.conforming() && desc.dpl() != cpl)
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+		}
+		if !desc.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, error_code)
+		}
+		let offset = gate.gate_offset();
+
+		if !call || desc.conforming() || desc.dpl() == cpl
+		{
+			return self.enter_code_segment(mem, selector, desc, offset, cpl, call)
+		}
+
+		let new_cpl = desc.dpl();
+		let (ss, sp, ss_desc) = match self.inner_stack(mem, new_cpl)
+		{
+			Some(stack) => stack,
+			None => return
+		};
+		if offset > desc.limit
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, 0)
+		}
+		let (old_cs, old_ip, old_ss, old_sp) = (self.cs, self.ip, self.ss, self.sp);
+		let parameters: Vec<u16> = (0 .. gate.gate_word_count())
+			.map(|i| self.load_memory_u16_noov(mem, SegReg::SS, old_sp.wrapping_add(i * 2)))
+			.collect();
+
+		self.set_segment(SegReg::SS, ss, ss_desc);
+		self.sp = sp;
+		self.stack_push(mem, old_ss);
+		self.stack_push(mem, old_sp);
+		for &parameter in parameters.iter().rev()
+		{
+			self.stack_push(mem, parameter);
+		}
+		self.stack_push(mem, old_cs);
+		self.stack_push(mem, old_ip);
+		if self.faulted()
+		{
+			return
+		}
+
+		self.set_descriptor_bits(mem, selector, ACCESS_ACCESSED, true);
+		desc.access |= ACCESS_ACCESSED;
+		self.set_segment(SegReg::CS, error_code | new_cpl, desc);
+		self.ip = offset;
+	}
+
+	/* RETF in protected mode; 'release' bytes of parameters are released
+	 * from both stacks when returning to an outer privilege level */
+	pub fn protected_far_return(&mut self, mem: &mut Memory, release: u16)
+	{
+		let ip = self.stack_pop(mem);
+		let cs = self.stack_pop(mem);
+		if !self.faulted()
+		{
+			self.return_to(mem, cs, ip, release, None);
+		}
+	}
+
+	/* IRET in protected mode: back to the interrupted task if NT is set */
+	pub fn protected_interrupt_return(&mut self, mem: &mut Memory)
+	{
+		if self.flags & FLAG_NT != 0
+		{
+			let back_link = mem.read_u16(self.protected.tss.base + TSS_BACK_LINK);
+			return self.switch_task(mem, back_link, TaskSwitch::Iret)
+		}
+
+		let ip = self.stack_pop(mem);
+		let cs = self.stack_pop(mem);
+		let flags = self.stack_pop(mem);
+		if !self.faulted()
+		{
+			self.return_to(mem, cs, ip, 0, Some(flags));
+		}
+	}
+
+	/* Common part of RETF and IRET, once CS:IP (and FLAGS) are popped */
+	fn return_to(&mut self, mem: &mut Memory, selector: u16, offset: u16, release: u16, flags: Option<u16>)
+	{
+		let cpl = self.cpl();
+		let rpl = selector & 0x3;
+		let error_code = selector & 0xfffc;
+		let mut desc = match self.code_segment(mem, selector, 0)
+		{
+			Some(desc) => desc,
+			None => return
+		};
+		let allowed = rpl >= cpl &&
+			if desc.conforming() { desc.dpl() <= rpl } else { desc.dpl() == rpl };
+		if !allowed
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, error_code)
+		}
+		if !desc.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, error_code)
+		}
+		if offset > desc.limit
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, 0)
+		}
+
+		/* With the privileges of the current level */
+		if let Some(flags) = flags
+		{
+			self.load_flags(flags);
+		}
+		self.sp = self.sp.wrapping_add(release);
+		let outer_stack = if rpl > cpl
+			{
+				let sp = self.stack_pop(mem);
+				let ss = self.stack_pop(mem);
+				match self.data_segment(mem, SegReg::SS, ss, rpl, EXC_GENERAL_PROTECTION)
+				{
+					Some(ss_desc) if !self.faulted() => Some((ss, sp, ss_desc)),
+					_ => return
+				}
+			}
+			else
+			{
+				None
+			};
+
+		self.set_descriptor_bits(mem, selector, ACCESS_ACCESSED, true);
+		desc.access |= ACCESS_ACCESSED;
+		self.set_segment(SegReg::CS, selector, desc);
+		self.ip = offset;
+
+		if let Some((ss, sp, ss_desc)) = outer_stack
+		{
+			self.set_segment(SegReg::SS, ss, ss_desc);
+			self.sp = sp.wrapping_add(release);
+			/* The outer level may not keep more privileged data segments */
+			for &sr in &[SegReg::DS, SegReg::ES]
+			{
+				let cache = self.segment_cache(sr);
+				if cache.present() && !cache.conforming() && cache.dpl() < rpl
+				{
+					self.set_segment(sr, 0, Descriptor::default());
+				}
+			}
+		}
+	}
+
+	/* INT, exceptions and hardware interrupts in protected mode, through an
+	 * interrupt, trap or task gate of the IDT */
+	pub fn protected_interrupt(&mut self, mem: &mut Memory, vector: u8, error_code: Option<u16>, software: bool)
+	{
+		if !self.traps.is_empty()
+		{
+			let (cs, ip) = (self.cs, self.ip);
+			self.check_traps(TrapEvent::Interrupt { number: vector, return_cs: cs, return_ip: ip });
+		}
+		let cpl = self.cpl();
+		/* Error codes tell external events from the instruction */
+		let ext = if software { 0 } else { 1 };
+		let gate_error = vector as u16 * 8 + 2 + ext;
+		let idt = self.protected.idtr;
+		if vector as u32 * 8 + 7 > idt.limit as u32
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, gate_error)
+		}
+		let gate = Descriptor::read(mem, idt.base + vector as u32 * 8);
+		let kind = gate.system_type();
+		match kind
+		{
+			Some(TYPE_TASK_GATE) | Some(TYPE_INTERRUPT_GATE) | Some(TYPE_TRAP_GATE) => {},
+			_ => return self.raise_fault(EXC_GENERAL_PROTECTION, gate_error)
+		}
+		if software && gate.dpl() < cpl
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, gate_error)
+		}
+		if !gate.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, gate_error)
+		}
+
+		if kind == Some(TYPE_TASK_GATE)
+		{
+			self.switch_task(mem, gate.gate_selector(), TaskSwitch::Call);
+			if let Some(code) = error_code
+			{
+				self.stack_push(mem, code);
+			}
+			return
+		}
+
+		let selector = gate.gate_selector();
+		let mut desc = match self.code_segment(mem, selector, ext)
+		{
+			Some(desc) => desc,
+			None => return
+		};
+		if desc.dpl() > cpl
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, (selector & 0xfffc) | ext)
+		}
+		if !desc.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, (selector & 0xfffc) | ext)
+		}
+		let offset = gate.gate_offset();
+		if offset > desc.limit
+		{
+			return self.raise_fault(EXC_GENERAL_PROTECTION, ext)
+		}
+
+		let (flags, cs, ip) = (self.flags, self.cs, self.ip);
+		let new_cpl = if !desc.conforming() && desc.dpl() < cpl
+			{
+				let (ss, sp, ss_desc) = match self.inner_stack(mem, desc.dpl())
+				{
+					Some(stack) => stack,
+					None => return
+				};
+				let (old_ss, old_sp) = (self.ss, self.sp);
+				self.set_segment(SegReg::SS, ss, ss_desc);
+				self.sp = sp;
+				self.stack_push(mem, old_ss);
+				self.stack_push(mem, old_sp);
+				desc.dpl()
+			}
+			else
+			{
+				cpl
+			};
+		self.stack_push(mem, flags);
+		self.stack_push(mem, cs);
+		self.stack_push(mem, ip);
+		if let Some(code) = error_code
+		{
+			self.stack_push(mem, code);
+		}
+		if self.faulted()
+		{
+			return
+		}
+
+		self.set_descriptor_bits(mem, selector, ACCESS_ACCESSED, true);
+		desc.access |= ACCESS_ACCESSED;
+		self.set_segment(SegReg::CS, (selector & 0xfffc) | new_cpl, desc);
+		self.ip = offset;
+		self.flags &= !(FLAG_T | FLAG_NT);
+		if kind == Some(TYPE_INTERRUPT_GATE)
+		{
+			self.flags &= !FLAG_I;
+		}
+	}
+
+	/* Saves the registers into the current TSS, then loads the ones of the
+	 * task 'selector' refers to */
+	fn switch_task(&mut self, mem: &mut Memory, selector: u16, kind: TaskSwitch)
+	{
+		let error_code = selector & 0xfffc;
+		/* Returning to a task checks its TSS as the incoming task does */
+		let vector = if kind == TaskSwitch::Iret { EXC_INVALID_TSS } else { EXC_GENERAL_PROTECTION };
+		let expected = if kind == TaskSwitch::Iret { TYPE_BUSY_TSS } else { TYPE_AVAILABLE_TSS };
+		let mut desc = match if selector & 0x4 != 0 { None } else { self.read_descriptor(mem, selector) }
+		{
+			Some(desc) => desc,
+			None => return self.raise_fault(vector, error_code)
+		};
+		if desc.system_type() != Some(expected)
+		{
+			return self.raise_fault(vector, error_code)
+		}
+		if !desc.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, error_code)
+		}
+		if desc.limit < TSS_MIN_LIMIT
+		{
+			return self.raise_fault(EXC_INVALID_TSS, error_code)
+		}
+
+		/* Outgoing task */
+		let old_tss = self.protected.tss.base;
+		let old_tr = self.protected.tr;
+		let flags = if kind == TaskSwitch::Iret { self.flags & !FLAG_NT } else { self.flags };
+		mem.write_u16(old_tss + TSS_IP, self.ip);
+		mem.write_u16(old_tss + TSS_FLAGS, flags);
+		for (i, &value) in [self.ax, self.cx, self.dx, self.bx, self.sp, self.bp, self.si, self.di].iter().enumerate()
+		{
+			mem.write_u16(old_tss + TSS_REGISTERS + i as u32 * 2, value);
+		}
+		for (i, &value) in [self.es, self.cs, self.ss, self.ds].iter().enumerate()
+		{
+			mem.write_u16(old_tss + TSS_SEGMENTS + i as u32 * 2, value);
+		}
+		if kind != TaskSwitch::Call
+		{
+			self.set_descriptor_bits(mem, old_tr, ACCESS_BUSY, false);
+		}
+
+		/* Incoming task */
+		if kind == TaskSwitch::Call
+		{
+			mem.write_u16(desc.base + TSS_BACK_LINK, old_tr);
+		}
+		if kind != TaskSwitch::Iret
+		{
+			self.set_descriptor_bits(mem, selector, ACCESS_BUSY, true);
+		}
+		desc.access |= ACCESS_BUSY;
+		self.protected.tr = selector;
+		self.protected.tss = desc;
+		self.cr0 |= MSW_TS;
+
+		let tss = desc.base;
+		self.ip = mem.read_u16(tss + TSS_IP);
+		self.flags = mem.read_u16(tss + TSS_FLAGS) & 0x7fff;
+		if kind == TaskSwitch::Call
+		{
+			self.flags |= FLAG_NT;
+		}
+		let registers: Vec<u16> = (0 .. 8).map(|i| mem.read_u16(tss + TSS_REGISTERS + i * 2)).collect();
+		self.ax = registers[0];
+		self.cx = registers[1];
+		self.dx = registers[2];
+		self.bx = registers[3];
+		self.sp = registers[4];
+		self.bp = registers[5];
+		self.si = registers[6];
+		self.di = registers[7];
+		let segments: Vec<u16> = (0 .. 4).map(|i| mem.read_u16(tss + TSS_SEGMENTS + i * 2)).collect();
+		let ldt = mem.read_u16(tss + TSS_LDT);
+
+		/* The selectors are loaded first, so that faults happen in the new
+		 * task */
+		for (i, &sr) in [SegReg::ES, SegReg::CS, SegReg::SS, SegReg::DS].iter().enumerate()
+		{
+			self.set_segment(sr, segments[i], Descriptor::default());
+		}
+		self.load_ldt(mem, ldt, EXC_INVALID_TSS);
+		if self.faulted()
+		{
+			return
+		}
+
+		let cs = segments[1];
+		let cpl = cs & 0x3;
+		let mut code = match self.code_segment(mem, cs, 0)
+		{
+			Some(code) => code,
+			None => return
+		};
+		if (code.conforming() && code.dpl() > cpl) || (!code.conforming() && code.dpl() != cpl)
+		{
+			return self.raise_fault(EXC_INVALID_TSS, cs & 0xfffc)
+		}
+		if !code.present()
+		{
+			return self.raise_fault(EXC_NOT_PRESENT, cs & 0xfffc)
+		}
+		self.set_descriptor_bits(mem, cs, ACCESS_ACCESSED, true);
+		code.access |= ACCESS_ACCESSED;
+		self.set_segment(SegReg::CS, cs, code);
+
+		for &(i, sr) in &[(2, SegReg::SS), (3, SegReg::DS), (0, SegReg::ES)]
+		{
+			match self.data_segment(mem, sr, segments[i], cpl, EXC_INVALID_TSS)
+			{
+				Some(cache) => self.set_segment(sr, segments[i], cache),
+				None => return
+			}
+		}
+	}
+
+	/* Taken before each instruction in protected mode */
+	pub fn checkpoint(&self) -> Checkpoint
+	{
+		Checkpoint
+		{
+			registers: self.registers(),
+			segments: self.protected.segments,
+			tr: self.protected.tr
+		}
+	}
+
+	/* Delivers the fault the instruction raised, if any: it is abandoned,
+	 * and the exception handler returns to it. Faulting again while doing
+	 * so is a double fault, and a third one shuts the CPU down. Returns
+	 * whether there was a fault */
+	pub fn deliver_fault(&mut self, mem: &mut Memory, checkpoint: &Checkpoint) -> bool
+	{
+		let fault = match self.fault.take()
+		{
+			Some(fault) => fault,
+			None => return false
+		};
+		/* A task switch that faults leaves the CPU in the new task */
+		if self.protected.tr == checkpoint.tr
+		{
+			self.set_registers(&checkpoint.registers);
+			self.protected.segments = checkpoint.segments;
+		}
+		cpu_print!("Fault {:?} at {:04x}:{:04x}", fault, self.cs, self.ip);
+
+		let before_delivery = self.checkpoint();
+		self.protected_interrupt(mem, fault.vector, fault.error_code, false);
+		if self.fault.take().is_some()
+		{
+			self.set_registers(&before_delivery.registers);
+			self.protected.segments = before_delivery.segments;
+			self.protected_interrupt(mem, EXC_DOUBLE_FAULT, Some(0), false);
+			if self.fault.take().is_some()
+			{
+				cpu_print!("Triple fault: shutdown");
+				self.reset();
+			}
+		}
+		true
+	}
+
+	/* Processor reset, as on power-on; on the PC/AT, the keyboard controller
+	 * and triple faults reset the 80286 to get back to real mode. The
+	 * general registers are left as they are */
+	pub fn reset(&mut self)
+	{
+		self.cr0 = 0;
+		self.flags = self.timing.model.fixed_flags();
+		self.cs = 0xf000;
+		self.ip = 0xfff0;
+		self.ds = 0;
+		self.ss = 0;
+		self.es = 0;
+		self.protected = ProtectedState::new();
+		self.fault.set(None);
+		self.segment_override_prefix = None;
+		self.rep_prefix = None;
+		self.timing.flush_queue();
+	}
+}
+
+fn save_descriptor(writer: &mut SnapshotWriter, desc: &Descriptor)
+{
+	writer.write_u32(desc.base);
+	writer.write_u16(desc.limit);
+	writer.write_u8(desc.access);
+}
+
+fn load_descriptor(reader: &mut SnapshotReader) -> Result<Descriptor, String>
+{
+	Ok(Descriptor
+	{
+		base: reader.read_u32()?,
+		limit: reader.read_u16()?,
+		access: reader.read_u8()?
+	})
+}
+
+impl Snapshot for ProtectedState
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"PROT");
+		for desc in &self.segments
+		{
+			save_descriptor(writer, desc);
+		}
+		for table in &[self.gdtr, self.idtr]
+		{
+			writer.write_u32(table.base);
+			writer.write_u16(table.limit);
+		}
+		writer.write_u16(self.ldtr);
+		save_descriptor(writer, &self.ldt);
+		writer.write_u16(self.tr);
+		save_descriptor(writer, &self.tss);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"PROT")?;
+		for i in 0 .. 4
+		{
+			self.segments[i] = load_descriptor(reader)?;
+		}
+		for table in &mut [&mut self.gdtr, &mut self.idtr]
+		{
+			table.base = reader.read_u32()?;
+			table.limit = reader.read_u16()?;
+		}
+		self.ldtr = reader.read_u16()?;
+		self.ldt = load_descriptor(reader)?;
+		self.tr = reader.read_u16()?;
+		self.tss = load_descriptor(reader)?;
+		Ok(())
+	}
+}
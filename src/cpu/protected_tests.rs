@@ -0,0 +1,248 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::instruction::*;
+	use super::super::protected::*;
+	use super::super::timing::CpuModel;
+	use super::super::super::mem::Memory;
+
+	const GDT: u32 = 0x1000;
+	const IDT: u32 = 0x3000;
+	const TSS1: u32 = 0x2000;
+	const TSS2: u32 = 0x2100;
+
+	/* Also gates: 'base' is then the selector and the word count */
+	fn descriptor(mem: &mut Memory, addr: u32, base: u32, limit: u16, access: u8)
+	{
+		mem.write_u16(addr, limit);
+		mem.write_u16(addr + 2, base as u16);
+		mem.write_u8(addr + 4, (base >> 16) as u8);
+		mem.write_u8(addr + 5, access);
+	}
+
+	/* Flat enough to test: a ring 0 code, data and stack segment, their
+	 * ring 3 counterparts, a call gate to ring 0 and two tasks */
+	fn protected_cpu() -> (Memory, CPU)
+	{
+		let mut mem = Memory::new(16 * 1024 * 1024);
+		let mut cpu = CPU::new(0x1000, 0x0000, CpuModel::I80286);
+		cpu.ds = 0x0000;
+		cpu.ss = 0x0000;
+		cpu.sp = 0x8000;
+
+		descriptor(&mut mem, GDT + 0x08, 0x10000, 0xffff, 0x9a);
+		descriptor(&mut mem, GDT + 0x10, 0x20000, 0x0fff, 0x92);
+		descriptor(&mut mem, GDT + 0x18, 0x30000, 0xffff, 0x92);
+		descriptor(&mut mem, GDT + 0x20, 0x140000, 0xffff, 0xf2);
+		descriptor(&mut mem, GDT + 0x28, 0x150000, 0xffff, 0xfa);
+		descriptor(&mut mem, GDT + 0x30, 0x160000, 0xffff, 0xf2);
+		descriptor(&mut mem, GDT + 0x38, 0x10008, 0x0100, 0xe4);
+		descriptor(&mut mem, GDT + 0x40, TSS1, 43, 0x81);
+		descriptor(&mut mem, GDT + 0x48, TSS2, 43, 0x81);
+		descriptor(&mut mem, GDT + 0x50, 0x70000, 0xffff, 0x12);
+		descriptor(&mut mem, IDT + 0x0d * 8, 0x0008, 0x0200, 0x86);
+		descriptor(&mut mem, IDT + 0x21 * 8, 0x0008, 0x0300, 0xe7);
+		/* Ring 0 stack of the first task */
+		mem.write_u16(TSS1 + 2, 0x0f00);
+		mem.write_u16(TSS1 + 4, 0x18);
+
+		mem.write_u16(0x500, 0x5f);
+		mem.write_u16(0x502, GDT as u16);
+		mem.write_u16(0x506, 0x17f);
+		mem.write_u16(0x508, IDT as u16);
+		cpu.run_system_ins(&mut mem, SystemOpCode::LGDT, WOperand::Direct(0x500));
+		cpu.run_system_ins(&mut mem, SystemOpCode::LIDT, WOperand::Direct(0x506));
+		cpu.ax = 0x0001;
+		cpu.run_system_ins(&mut mem, SystemOpCode::LMSW, WOperand::Reg(WReg::AX));
+
+		cpu.protected_far_transfer(&mut mem, 0x08, 0x0000, false);
+		cpu.load_segment(&mut mem, SegReg::SS, 0x18);
+		cpu.sp = 0x1000;
+		cpu.load_segment(&mut mem, SegReg::DS, 0x10);
+		cpu.load_segment(&mut mem, SegReg::ES, 0x10);
+		cpu.run_system_ins(&mut mem, SystemOpCode::LTR, WOperand::Immediate(0x40));
+		assert_eq!(cpu.fault.get(), None);
+		(mem, cpu)
+	}
+
+	fn take_fault(cpu: &CPU) -> Option<(u8, Option<u16>)>
+	{
+		cpu.fault.take().map(|fault| (fault.vector, fault.error_code))
+	}
+
+	#[test]
+	fn enter_protected_mode()
+	{
+		let (mut mem, mut cpu) = protected_cpu();
+		assert!(cpu.protected_mode());
+		assert_eq!((cpu.cs, cpu.code_address(0x10)), (0x08, 0x10010));
+		/* Loading a segment marks its descriptor accessed */
+		assert_eq!(mem.read_u8(GDT + 0x08 + 5), 0x9b);
+		assert_eq!(mem.read_u8(GDT + 0x40 + 5), 0x83);
+
+		cpu.store_memory_u16(&mut mem, SegReg::DS, 0x10, 0x1234);
+		assert_eq!(mem.read_u16(0x20010), 0x1234);
+
+		cpu.run_system_ins(&mut mem, SystemOpCode::SMSW, WOperand::Reg(WReg::BX));
+		assert_eq!(cpu.bx, 0xfff1);
+		/* PE cannot be cleared */
+		cpu.ax = 0x0000;
+		cpu.run_system_ins(&mut mem, SystemOpCode::LMSW, WOperand::Reg(WReg::AX));
+		assert!(cpu.protected_mode());
+
+		cpu.run_system_ins(&mut mem, SystemOpCode::SGDT, WOperand::Direct(0x20));
+		assert_eq!((mem.read_u16(0x20020), mem.read_u16(0x20022), mem.read_u16(0x20024)), (0x5f, GDT as u16, 0xff00));
+	}
+
+	#[test]
+	fn segment_faults()
+	{
+		let (mut mem, mut cpu) = protected_cpu();
+
+		cpu.load_segment(&mut mem, SegReg::DS, 0x50);
+		assert_eq!(take_fault(&cpu), Some((EXC_NOT_PRESENT, Some(0x50))));
+		/* Beyond the GDT */
+		cpu.load_segment(&mut mem, SegReg::ES, 0x60);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0x60))));
+		/* SS must be at the current privilege level */
+		cpu.load_segment(&mut mem, SegReg::SS, 0x23);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0x20))));
+		cpu.load_segment(&mut mem, SegReg::CS, 0x08);
+		assert_eq!(take_fault(&cpu), Some((EXC_INVALID_OPCODE, None)));
+
+		/* Past the limit */
+		cpu.load_memory_u16(&mem, SegReg::DS, 0x0fff);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0))));
+		/* Code segments cannot be written */
+		cpu.store_memory_u8(&mut mem, SegReg::CS, 0x0000, 0x90);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0))));
+		assert_eq!(mem.read_u8(0x10000), 0x00);
+
+		/* Null selectors can be loaded, but not used */
+		cpu.load_segment(&mut mem, SegReg::ES, 0x0000);
+		assert_eq!(take_fault(&cpu), None);
+		cpu.load_memory_u8(&mem, SegReg::ES, 0x0000);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0))));
+	}
+
+	#[test]
+	fn fault_delivery()
+	{
+		let (mut mem, mut cpu) = protected_cpu();
+		cpu.ip = 0x0042;
+		let checkpoint = cpu.checkpoint();
+
+		/* MOV ES, 0x60 */
+		cpu.ip = 0x0044;
+		cpu.load_segment(&mut mem, SegReg::ES, 0x60);
+		assert!(cpu.deliver_fault(&mut mem, &checkpoint));
+		assert_eq!((cpu.cs, cpu.ip, cpu.es), (0x08, 0x0200, 0x10));
+		/* Error code, then the faulting instruction */
+		let frame: Vec<u16> = (0 .. 3).map(|i| mem.read_u16(0x30ff8 + i * 2)).collect();
+		assert_eq!(frame, vec![0x60, 0x0042, 0x08]);
+		assert!(!cpu.deliver_fault(&mut mem, &checkpoint));
+	}
+
+	#[test]
+	fn software_interrupt()
+	{
+		let (mut mem, mut cpu) = protected_cpu();
+		cpu.ip = 0x0042;
+		cpu.flags |= FLAG_I;
+
+		cpu.software_interrupt(&mut mem, 0x21);
+		/* Trap gates leave interrupts enabled */
+		assert_eq!((cpu.cs, cpu.ip, cpu.sp, cpu.flags & FLAG_I), (0x08, 0x0300, 0x0ffa, FLAG_I));
+		cpu.protected_interrupt_return(&mut mem);
+		assert_eq!((cpu.cs, cpu.ip, cpu.sp), (0x08, 0x0042, 0x1000));
+
+		/* The gate of INT 0Dh is not accessible from ring 3 */
+		cpu.software_interrupt(&mut mem, 0x0d);
+		assert_eq!(cpu.ip, 0x0200);
+		cpu.ip = 0x0042;
+		cpu.flags &= !FLAG_IOPL;
+		to_ring3(&mut mem, &mut cpu);
+		cpu.software_interrupt(&mut mem, 0x0d);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0x0d * 8 + 2))));
+		/* Nor are CLI and port I/O, with IOPL 0 */
+		cpu.run_noop_ins(&mut mem, NoOperandOpCode::CLI);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0))));
+	}
+
+	/* RETF to ring 3 code, with the ring 3 stack */
+	fn to_ring3(mem: &mut Memory, cpu: &mut CPU)
+	{
+		for &value in &[0x33, 0x0800, 0x2b, 0x0040]
+		{
+			cpu.stack_push(mem, value);
+		}
+		cpu.protected_far_return(mem, 0);
+		assert_eq!(cpu.fault.get(), None);
+	}
+
+	#[test]
+	fn call_gate()
+	{
+		let (mut mem, mut cpu) = protected_cpu();
+		to_ring3(&mut mem, &mut cpu);
+		assert_eq!((cpu.cpl(), cpu.cs, cpu.ip, cpu.ss, cpu.sp), (3, 0x2b, 0x0040, 0x33, 0x0800));
+		/* Ring 0 data is not accessible anymore */
+		assert_eq!((cpu.ds, cpu.es), (0, 0));
+
+		/* One parameter */
+		cpu.stack_push(&mut mem, 0xabcd);
+		cpu.protected_far_transfer(&mut mem, 0x3b, 0x0000, true);
+		assert_eq!(cpu.fault.get(), None);
+		assert_eq!((cpu.cpl(), cpu.cs, cpu.ip, cpu.ss, cpu.sp), (0, 0x08, 0x0100, 0x18, 0x0ef6));
+		let frame: Vec<u16> = (0 .. 5).map(|i| mem.read_u16(0x30ef6 + i * 2)).collect();
+		assert_eq!(frame, vec![0x0040, 0x2b, 0xabcd, 0x07fe, 0x33]);
+
+		/* RETF 2 releases the parameter from both stacks */
+		cpu.protected_far_return(&mut mem, 2);
+		assert_eq!((cpu.cpl(), cpu.cs, cpu.ip, cpu.ss, cpu.sp), (3, 0x2b, 0x0040, 0x33, 0x0800));
+
+		/* Ring 3 cannot jump to ring 0 code directly */
+		cpu.protected_far_transfer(&mut mem, 0x08, 0x0000, false);
+		assert_eq!(take_fault(&cpu), Some((EXC_GENERAL_PROTECTION, Some(0x08))));
+	}
+
+	#[test]
+	fn task_switch()
+	{
+		let (mut mem, mut cpu) = protected_cpu();
+		cpu.ip = 0x0042;
+		cpu.ax = 0xaaaa;
+		mem.write_u16(TSS2 + 14, 0x0123);
+		mem.write_u16(TSS2 + 16, 0x0002);
+		mem.write_u16(TSS2 + 18, 0x1111);
+		for (i, &selector) in [0x10, 0x08, 0x18, 0x10].iter().enumerate()
+		{
+			mem.write_u16(TSS2 + 34 + i as u32 * 2, selector);
+		}
+
+		cpu.protected_far_transfer(&mut mem, 0x48, 0x0000, true);
+		assert_eq!(cpu.fault.get(), None);
+		assert_eq!((cpu.ip, cpu.ax, cpu.cs, cpu.ss, cpu.flags & FLAG_NT), (0x0123, 0x1111, 0x08, 0x18, FLAG_NT));
+		assert_eq!(cpu.protected.tr, 0x48);
+		assert_eq!((mem.read_u16(TSS2), mem.read_u16(TSS1 + 14), mem.read_u16(TSS1 + 18)), (0x40, 0x0042, 0xaaaa));
+		/* Both tasks are busy */
+		assert_eq!((mem.read_u8(GDT + 0x40 + 5), mem.read_u8(GDT + 0x48 + 5)), (0x83, 0x83));
+
+		/* Back to the calling task */
+		cpu.protected_interrupt_return(&mut mem);
+		assert_eq!(cpu.fault.get(), None);
+		assert_eq!((cpu.ip, cpu.ax, cpu.flags & FLAG_NT, cpu.protected.tr), (0x0042, 0xaaaa, 0, 0x40));
+		assert_eq!(mem.read_u8(GDT + 0x48 + 5), 0x81);
+	}
+
+	#[test]
+	fn real_mode_flags()
+	{
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I80286);
+		assert_eq!(cpu.flags, 0x0000);
+		/* Bits 12-15 stay clear in real mode */
+		cpu.load_flags(0xf202);
+		assert_eq!(cpu.flags, 0x0202);
+	}
+}
@@ -0,0 +1,598 @@
+use std::cell::Cell;
+
+use super::base::*;
+use super::instruction::*;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+/* Instruction timings of the 8086/8088, from the iAPX 86/88 User's Manual.
+ * The documented execution times assume that the instruction was already
+ * fetched by the bus interface unit and that word operands are aligned (8086)
+ * or byte-wide (8088); both effects are accounted for separately:
+ *  - each extra bus cycle needed for a word transfer costs 4 clocks
+ *  - instruction bytes are taken from a simple prefetch queue model, which
+ *    is refilled while the execution unit does not use the bus and flushed
+ *    by jumps */
+
+/* 14.31818 MHz / 3 */
+pub const CPU_FREQUENCY_HZ: u64 = 4772727;
+
+const BUS_CYCLE_CLOCKS: u32 = 4;
+pub const HW_INTERRUPT_CYCLES: u32 = 61;
+const PREFIX_CYCLES: u32 = 2;
+const REP_CYCLES: u32 = 9;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum CpuModel
+{
+	I8086,
+	I8088,
+	/* Timed as an 8086/8088, except for the instructions they introduced */
+	I80186,
+	I80188,
+	/* Timed as an 8086 too, except for the instructions it introduced */
+	I80286
+}
+
+impl CpuModel
+{
+	/* Bytes transferred per bus cycle */
+	fn bus_width(&self) -> u32
+	{
+		match *self
+		{
+			CpuModel::I8086 | CpuModel::I80186 | CpuModel::I80286 => 2,
+			CpuModel::I8088 | CpuModel::I80188 => 1
+		}
+	}
+
+	fn queue_size(&self) -> u32
+	{
+		match *self
+		{
+			CpuModel::I8086 | CpuModel::I80186 | CpuModel::I80286 => 6,
+			CpuModel::I8088 | CpuModel::I80188 => 4
+		}
+	}
+
+	/* The 80186 introduced the invalid opcode exception (INT 6), and faults
+	 * that return to the faulting instruction; the 8086 runs every opcode */
+	pub fn has_exceptions(&self) -> bool
+	{
+		match *self
+		{
+			CpuModel::I8086 | CpuModel::I8088 => false,
+			CpuModel::I80186 | CpuModel::I80188 | CpuModel::I80286 => true
+		}
+	}
+
+	/* PUSHA/POPA, PUSH imm, IMUL imm, INS/OUTS, ENTER/LEAVE, BOUND, shifts
+	 * by an immediate count, and shift counts masked to 5 bits */
+	pub fn has_80186_instructions(&self) -> bool
+	{
+		self.has_exceptions()
+	}
+
+	/* The 80286 protected mode, with its system instructions and a 24-bit
+	 * address bus */
+	pub fn has_protected_mode(&self) -> bool
+	{
+		*self == CpuModel::I80286
+	}
+
+	/* FLAGS bits 12-15: always set on the 8086 and 80186, while the 80286
+	 * clears them in real mode; CPU detection code relies on both */
+	pub fn fixed_flags(&self) -> u16
+	{
+		if self.has_protected_mode() { 0 } else { FLAG_SET_8086 }
+	}
+}
+
+pub struct Timing
+{
+	pub model: CpuModel,
+
+	/* Data bus usage of the current instruction. Memory accesses only borrow
+	 * the CPU, hence the cells */
+	bus_cycles: Cell<u32>,
+	extra_bus_cycles: Cell<u32>,
+
+	/* Bytes in the prefetch queue */
+	queue: u32,
+	/* A REP instruction is being repeated: its bytes are not fetched again */
+	repeating: bool
+}
+
+impl Timing
+{
+	pub fn new(model: CpuModel) -> Timing
+	{
+		Timing
+		{
+			model: model,
+			bus_cycles: Cell::new(0),
+			extra_bus_cycles: Cell::new(0),
+			queue: 0,
+			repeating: false
+		}
+	}
+
+	pub fn on_byte_access(&self)
+	{
+		self.bus_cycles.set(self.bus_cycles.get() + 1);
+	}
+
+	pub fn on_word_access(&self, phys_addr: u32)
+	{
+		/* The 8088 and 80188 always need two bus cycles, the 8086 and
+		 * 80186 only for odd addresses */
+		let cycles =
+			if self.model.bus_width() == 1 || phys_addr & 1 != 0 { 2 }
+			else { 1 };
+		self.bus_cycles.set(self.bus_cycles.get() + cycles);
+		self.extra_bus_cycles.set(self.extra_bus_cycles.get() + cycles - 1);
+	}
+
+	pub fn flush_queue(&mut self)
+	{
+		self.queue = 0;
+		self.repeating = false;
+	}
+
+	/* Clocks spent waiting for the bytes of an instruction of 'size' bytes
+	 * which executed in 'exec_cycles' clocks, extra bus cycles for word
+	 * transfers included */
+	fn fetch(&mut self, size: u32, exec_cycles: u32, repeat: bool, jumped: bool) -> u32
+	{
+		let width = self.model.bus_width();
+		let mut stall = 0;
+
+		if !self.repeating
+		{
+			if self.queue < size
+			{
+				let missing = size - self.queue;
+				stall = (missing + width - 1) / width * BUS_CYCLE_CLOCKS;
+				self.queue = 0;
+			}
+			else
+			{
+				self.queue -= size;
+			}
+		}
+
+		/* Refill the queue while the bus is idle */
+		let busy = self.bus_cycles.get() * BUS_CYCLE_CLOCKS;
+		let idle = exec_cycles.saturating_sub(busy);
+		self.queue = (self.queue + idle / BUS_CYCLE_CLOCKS * width).min(self.model.queue_size());
+
+		self.bus_cycles.set(0);
+		self.extra_bus_cycles.set(0);
+
+		if jumped
+		{
+			self.flush_queue();
+		}
+		self.repeating = repeat;
+
+		stall
+	}
+}
+
+/* The bus usage is reset before each instruction, so only the prefetch queue
+ * is part of the state */
+impl Snapshot for Timing
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"TIME");
+		writer.write_u8(match self.model
+			{
+				CpuModel::I8086 => 0,
+				CpuModel::I8088 => 1,
+				CpuModel::I80186 => 2,
+				CpuModel::I80188 => 3,
+				CpuModel::I80286 => 4
+			});
+		writer.write_u32(self.queue);
+		writer.write_bool(self.repeating);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"TIME")?;
+		self.model = match reader.read_u8()?
+		{
+			0 => CpuModel::I8086,
+			1 => CpuModel::I8088,
+			2 => CpuModel::I80186,
+			3 => CpuModel::I80188,
+			4 => CpuModel::I80286,
+			model => return Err(format!("invalid CPU model {}", model))
+		};
+		self.queue = reader.read_u32()?;
+		if self.queue > self.model.queue_size()
+		{
+			return Err(format!("invalid prefetch queue size {}", self.queue))
+		}
+		self.repeating = reader.read_bool()?;
+		Ok(())
+	}
+}
+
+/* Where an operand lives, as far as timings are concerned */
+pub enum OperandLocation
+{
+	Accumulator,
+	Register,
+	SegmentRegister,
+	Immediate,
+	/* Effective address calculation time, and whether the address is a
+	 * plain displacement */
+	Memory(u32, bool)
+}
+
+pub trait TimedOperand
+{
+	fn location(&self) -> OperandLocation;
+}
+
+fn ea_cycles(ir: &IndReg, has_displacement: bool) -> u32
+{
+	let base_index = match *ir
+	{
+		IndReg::BX | IndReg::BP | IndReg::SI | IndReg::DI => 5,
+		IndReg::BPDI | IndReg::BXSI => 7,
+		IndReg::BPSI | IndReg::BXDI => 8
+	};
+
+	if has_displacement { base_index + 4 } else { base_index }
+}
+
+const DIRECT_EA_CYCLES: u32 = 6;
+
+impl TimedOperand for BOperand
+{
+	fn location(&self) -> OperandLocation
+	{
+		match *self
+		{
+			BOperand::Reg(BReg::AL) => OperandLocation::Accumulator,
+			BOperand::Reg(_) => OperandLocation::Register,
+			BOperand::Immediate(_) => OperandLocation::Immediate,
+			BOperand::Direct(_) => OperandLocation::Memory(DIRECT_EA_CYCLES, true),
+			BOperand::Indirect(ref ir) => OperandLocation::Memory(ea_cycles(ir, false), false),
+			BOperand::Indirect8iDis(ref ir, _) | BOperand::Indirect16uDis(ref ir, _) =>
+				OperandLocation::Memory(ea_cycles(ir, true), false)
+		}
+	}
+}
+
+impl TimedOperand for WOperand
+{
+	fn location(&self) -> OperandLocation
+	{
+		match *self
+		{
+			WOperand::Reg(WReg::AX) => OperandLocation::Accumulator,
+			WOperand::Reg(_) => OperandLocation::Register,
+			WOperand::SegReg(_) => OperandLocation::SegmentRegister,
+			WOperand::Immediate(_) => OperandLocation::Immediate,
+			WOperand::Direct(_) => OperandLocation::Memory(DIRECT_EA_CYCLES, true),
+			WOperand::Indirect(ref ir) => OperandLocation::Memory(ea_cycles(ir, false), false),
+			WOperand::Indirect8iDis(ref ir, _) | WOperand::Indirect16uDis(ref ir, _) =>
+				OperandLocation::Memory(ea_cycles(ir, true), false)
+		}
+	}
+}
+
+impl TimedOperand for ImplicitBOperand
+{
+	fn location(&self) -> OperandLocation
+	{
+		/* String instruction timings include the addressing */
+		OperandLocation::Memory(0, false)
+	}
+}
+
+impl TimedOperand for ImplicitWOperand
+{
+	fn location(&self) -> OperandLocation
+	{
+		OperandLocation::Memory(0, false)
+	}
+}
+
+/* Two-operand instructions; 'from' is the source operand */
+pub fn two_operands_cycles<OperandType: TimedOperand>(op: &TwoOperandsOpCode, from: &OperandType, to: &OperandType) -> u32
+{
+	use self::OperandLocation::*;
+
+	match *op
+	{
+		TwoOperandsOpCode::MOV => match (to.location(), from.location())
+		{
+			/* MOV AL/AX, [addr] and MOV [addr], AL/AX have their own opcodes */
+			(Accumulator, Memory(_, true)) => 10,
+			(Memory(_, true), Accumulator) => 10,
+			(_, Memory(ea, _)) => 8 + ea,
+			(Memory(ea, _), Immediate) => 10 + ea,
+			(Memory(ea, _), _) => 9 + ea,
+			(_, Immediate) => 4,
+			_ => 2
+		},
+		TwoOperandsOpCode::CMP => match (to.location(), from.location())
+		{
+			(Memory(ea, _), Immediate) => 10 + ea,
+			(Memory(ea, _), _) | (_, Memory(ea, _)) => 9 + ea,
+			(_, Immediate) => 4,
+			_ => 3
+		},
+		TwoOperandsOpCode::TEST => match (to.location(), from.location())
+		{
+			(Memory(ea, _), Immediate) => 11 + ea,
+			(Memory(ea, _), _) | (_, Memory(ea, _)) => 9 + ea,
+			(Accumulator, Immediate) => 4,
+			(_, Immediate) => 5,
+			_ => 3
+		},
+		TwoOperandsOpCode::XCHG => match (to.location(), from.location())
+		{
+			(Memory(ea, _), _) | (_, Memory(ea, _)) => 17 + ea,
+			(Accumulator, _) | (_, Accumulator) => 3,
+			_ => 4
+		},
+		TwoOperandsOpCode::LEA => match from.location()
+		{
+			Memory(ea, _) => 2 + ea,
+			_ => 2
+		},
+		TwoOperandsOpCode::LDS | TwoOperandsOpCode::LES => match from.location()
+		{
+			Memory(ea, _) => 16 + ea,
+			_ => 16
+		},
+		/* 80186, bounds included */
+		TwoOperandsOpCode::BOUND => 34,
+		/* 80286, whose clocks include the effective address */
+		TwoOperandsOpCode::LAR | TwoOperandsOpCode::LSL => match from.location()
+		{
+			Memory(..) => 16,
+			_ => 14
+		},
+		TwoOperandsOpCode::ARPL => match to.location()
+		{
+			Memory(..) => 11,
+			_ => 10
+		},
+		/* ADD, ADC, SUB, SBB, AND, OR, XOR */
+		_ => match (to.location(), from.location())
+		{
+			(Memory(ea, _), Immediate) => 17 + ea,
+			(Memory(ea, _), _) => 16 + ea,
+			(_, Memory(ea, _)) => 9 + ea,
+			(_, Immediate) => 4,
+			_ => 3
+		}
+	}
+}
+
+/* Single operand instructions; multiplications and divisions vary with the
+ * operands: the middle of the documented range is used */
+pub fn single_operand_cycles<OperandType: TimedOperand>(op: &SingleOperandOpCode, operand: &OperandType, word: bool) -> u32
+{
+	let (reg_cycles, mem_cycles) = match *op
+	{
+		SingleOperandOpCode::PUSH => (11, 16),
+		SingleOperandOpCode::POP => (8, 17),
+		SingleOperandOpCode::INC | SingleOperandOpCode::DEC => (if word { 2 } else { 3 }, 15),
+		SingleOperandOpCode::NEG | SingleOperandOpCode::NOT => (3, 16),
+		SingleOperandOpCode::MUL => if word { (128, 134) } else { (73, 79) },
+		SingleOperandOpCode::IMUL => if word { (141, 147) } else { (89, 95) },
+		SingleOperandOpCode::DIV => if word { (153, 159) } else { (85, 91) },
+		SingleOperandOpCode::IDIV => if word { (174, 180) } else { (106, 112) },
+		SingleOperandOpCode::AAM => (83, 83),
+		SingleOperandOpCode::AAD => (60, 60)
+	};
+
+	match operand.location()
+	{
+		OperandLocation::Memory(ea, _) => mem_cycles + ea,
+		OperandLocation::SegmentRegister => if *op == SingleOperandOpCode::PUSH { 10 } else { 8 },
+		/* 80186 */
+		OperandLocation::Immediate if *op == SingleOperandOpCode::PUSH => 10,
+		_ => reg_cycles
+	}
+}
+
+pub fn shift_rotate_cycles<OperandType: TimedOperand>(by_one: bool, operand: &OperandType, cnt: u8) -> u32
+{
+	match (by_one, operand.location())
+	{
+		(true, OperandLocation::Memory(ea, _)) => 15 + ea,
+		(true, _) => 2,
+		(_, OperandLocation::Memory(ea, _)) => 20 + ea + 4 * cnt as u32,
+		_ => 8 + 4 * cnt as u32
+	}
+}
+
+pub fn no_operand_cycles(op: &NoOperandOpCode) -> u32
+{
+	match *op
+	{
+		NoOperandOpCode::CWD => 5,
+		NoOperandOpCode::PUSHF => 10,
+		NoOperandOpCode::POPF => 8,
+		NoOperandOpCode::IRET => 24,
+		NoOperandOpCode::XLAT => 11,
+		NoOperandOpCode::LAHF | NoOperandOpCode::SAHF => 4,
+		NoOperandOpCode::AAA | NoOperandOpCode::AAS => 4,
+		NoOperandOpCode::DAA | NoOperandOpCode::DAS => 4,
+		NoOperandOpCode::INTO => 4,
+		NoOperandOpCode::WAIT => 3,
+		/* 80186 */
+		NoOperandOpCode::PUSHA => 36,
+		NoOperandOpCode::POPA => 51,
+		NoOperandOpCode::LEAVE => 8,
+		/* Flag operations, CBW, HLT, ESC */
+		_ => 2
+	}
+}
+
+/* Per-iteration cost of string instructions: (single, repeated) */
+pub fn string_cycles(op: &ImplicitOperandOpCode) -> (u32, u32)
+{
+	match *op
+	{
+		ImplicitOperandOpCode::MOVS => (18, 17),
+		ImplicitOperandOpCode::CMPS => (22, 22),
+		ImplicitOperandOpCode::SCAS => (15, 15),
+		ImplicitOperandOpCode::LODS => (12, 13),
+		ImplicitOperandOpCode::STOS => (11, 10),
+		/* 80186 */
+		ImplicitOperandOpCode::INS | ImplicitOperandOpCode::OUTS => (14, 8)
+	}
+}
+
+/* 80186 IMUL with an immediate multiplier */
+pub fn three_operands_cycles(op: &ThreeOperandsOpCode, from: &WOperand) -> u32
+{
+	match (op, from.location())
+	{
+		(&ThreeOperandsOpCode::IMUL, OperandLocation::Memory(ea, _)) => 31 + ea,
+		(&ThreeOperandsOpCode::IMUL, _) => 24
+	}
+}
+
+/* 80186 ENTER: the nesting level tells how many frame pointers are copied */
+pub fn enter_cycles(level: u8) -> u32
+{
+	match level
+	{
+		0 => 15,
+		1 => 25,
+		_ => 22 + 16 * (level as u32 - 1)
+	}
+}
+
+/* 80286 system instructions, whose clocks include the effective address */
+pub fn system_cycles(op: &SystemOpCode, operand: &WOperand) -> u32
+{
+	let (reg_cycles, mem_cycles) = match *op
+	{
+		SystemOpCode::SLDT | SystemOpCode::STR | SystemOpCode::SMSW => (2, 3),
+		SystemOpCode::LLDT | SystemOpCode::LTR => (17, 19),
+		SystemOpCode::VERR | SystemOpCode::VERW => (14, 16),
+		SystemOpCode::SGDT | SystemOpCode::LGDT => (11, 11),
+		SystemOpCode::SIDT | SystemOpCode::LIDT => (12, 12),
+		SystemOpCode::LMSW => (3, 6)
+	};
+
+	match operand.location()
+	{
+		OperandLocation::Memory(..) => mem_cycles,
+		_ => reg_cycles
+	}
+}
+
+/* What ESC costs the CPU, which leaves the computation to the coprocessor */
+pub fn esc_cycles(operand: &FpuOperand) -> u32
+{
+	let memory = match *operand
+	{
+		FpuOperand::Memory(_, ref op) | FpuOperand::Word(ref op) | FpuOperand::Address(ref op) => Some(op),
+		_ => None
+	};
+	match memory.map(|op| op.location())
+	{
+		Some(OperandLocation::Memory(ea, _)) => 8 + ea,
+		_ => 2
+	}
+}
+
+/* Short jumps, loops, INT and I/O */
+pub fn short_imm_cycles(op: &SingleBImmOperandOpCode, taken: bool) -> u32
+{
+	match *op
+	{
+		SingleBImmOperandOpCode::JMPS => 15,
+		SingleBImmOperandOpCode::LOOP => if taken { 17 } else { 5 },
+		SingleBImmOperandOpCode::LOOPZ => if taken { 18 } else { 6 },
+		SingleBImmOperandOpCode::LOOPNZ => if taken { 19 } else { 5 },
+		SingleBImmOperandOpCode::JCXZ => if taken { 18 } else { 6 },
+		SingleBImmOperandOpCode::INT => 51,
+		SingleBImmOperandOpCode::INB | SingleBImmOperandOpCode::INW |
+		SingleBImmOperandOpCode::OUTB | SingleBImmOperandOpCode::OUTW => 10,
+		SingleBImmOperandOpCode::INVB | SingleBImmOperandOpCode::INVW |
+		SingleBImmOperandOpCode::OUTVB | SingleBImmOperandOpCode::OUTVW => 8,
+		/* Conditional jumps */
+		_ => if taken { 16 } else { 4 }
+	}
+}
+
+pub fn flow_control_cycles(op: &SingleOperandFCOpCode, to: &FlowControlOperand) -> u32
+{
+	let location = |operand: &WOperand| operand.location();
+
+	match (op, to)
+	{
+		(&SingleOperandFCOpCode::JMP, &FlowControlOperand::DirectSeg(_)) => 15,
+		(&SingleOperandFCOpCode::JMP, &FlowControlOperand::DirectInterSeg(_, _)) => 15,
+		(&SingleOperandFCOpCode::JMP, &FlowControlOperand::IndirectSeg(ref op)) => match location(op)
+		{
+			OperandLocation::Memory(ea, _) => 18 + ea,
+			_ => 11
+		},
+		(&SingleOperandFCOpCode::JMP, &FlowControlOperand::IndirectInterSeg(ref op)) => match location(op)
+		{
+			OperandLocation::Memory(ea, _) => 24 + ea,
+			_ => 24
+		},
+		(&SingleOperandFCOpCode::CALL, &FlowControlOperand::DirectSeg(_)) => 19,
+		(&SingleOperandFCOpCode::CALL, &FlowControlOperand::DirectInterSeg(_, _)) => 28,
+		(&SingleOperandFCOpCode::CALL, &FlowControlOperand::IndirectSeg(ref op)) => match location(op)
+		{
+			OperandLocation::Memory(ea, _) => 21 + ea,
+			_ => 16
+		},
+		(&SingleOperandFCOpCode::CALL, &FlowControlOperand::IndirectInterSeg(ref op)) => match location(op)
+		{
+			OperandLocation::Memory(ea, _) => 37 + ea,
+			_ => 37
+		}
+	}
+}
+
+pub fn return_cycles(inter_segment: bool, pops_imm: bool) -> u32
+{
+	match (inter_segment, pops_imm)
+	{
+		(false, false) => 8,
+		(false, true) => 12,
+		(true, false) => 18,
+		(true, true) => 17
+	}
+}
+
+/* REP-prefixed string instructions pay a setup cost once, accounted for
+ * with the last iteration */
+pub fn rep_string_cycles(per_iteration: u32, last_iteration: bool) -> u32
+{
+	if last_iteration { per_iteration + REP_CYCLES } else { per_iteration }
+}
+
+impl CPU
+{
+	/* Total duration of an instruction: prefix bytes, execution, extra bus
+	 * cycles for word transfers and prefetch stalls. 'repeat' tells whether
+	 * a string instruction will run again, 'jumped' whether the control flow
+	 * was transferred elsewhere */
+	pub fn instruction_cycles(&mut self, size: u16, prefixes: u32, exec_cycles: u32, repeat: bool, jumped: bool) -> u32
+	{
+		/* Prefixes of repeated instructions are only decoded once */
+		let prefix_cycles = if self.timing.repeating { 0 } else { prefixes * PREFIX_CYCLES };
+		let penalty = self.timing.extra_bus_cycles.get() * BUS_CYCLE_CLOCKS;
+		let cycles = prefix_cycles + exec_cycles + penalty;
+		let stall = self.timing.fetch(size as u32, cycles, repeat, jumped);
+		cycles + stall
+	}
+}
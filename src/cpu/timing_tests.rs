@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::instruction::*;
+	use super::super::timing::*;
+	use super::super::super::mem::Memory;
+
+	fn cpu(model: CpuModel) -> CPU
+	{
+		let mut cpu = CPU::new(0x0000, 0x1000, model);
+		cpu.ds = 0x0000;
+		cpu.ss = 0x0000;
+		cpu.sp = 0x8000;
+		cpu.bx = 0x2000;
+		cpu.si = 0x0010;
+		cpu
+	}
+
+	#[test]
+	fn effective_address()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I8086);
+
+		/* ADD reg, reg: 3 */
+		assert_eq!(cpu.run_twop_ins(&mut mem, TwoOperandsOpCode::ADD, WOperand::Reg(WReg::BX), WOperand::Reg(WReg::CX)), 3);
+		/* ADD [BX+SI+disp], reg: 16 + 7 + 4 */
+		assert_eq!(cpu.run_twop_ins(&mut mem, TwoOperandsOpCode::ADD, WOperand::Reg(WReg::CX), WOperand::Indirect8iDis(IndReg::BXSI, 2)), 27);
+		/* MOV AX, [addr] has its own opcode: 10 */
+		assert_eq!(cpu.run_twop_ins(&mut mem, TwoOperandsOpCode::MOV, WOperand::Direct(0x100), WOperand::Reg(WReg::AX)), 10);
+		/* MOV CX, [addr]: 8 + 6 */
+		assert_eq!(cpu.run_twop_ins(&mut mem, TwoOperandsOpCode::MOV, WOperand::Direct(0x100), WOperand::Reg(WReg::CX)), 14);
+	}
+
+	#[test]
+	fn flow_control()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = cpu(CpuModel::I8086);
+
+		assert_eq!(cpu.run_sfcop_ins(&mut mem, SingleOperandFCOpCode::CALL, FlowControlOperand::DirectSeg(0x10)), 19);
+		assert_eq!(cpu.run_sfcop_ins(&mut mem, SingleOperandFCOpCode::JMP, FlowControlOperand::IndirectSeg(WOperand::Reg(WReg::BX))), 11);
+		assert_eq!(cpu.run_fcnoop_seg_ins(&mem, NoOpFCOpCode::RET), 8);
+	}
+
+	#[test]
+	fn word_transfers()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+
+		/* Aligned word on the 8086: no penalty */
+		let mut cpu86 = cpu(CpuModel::I8086);
+		let exec = cpu86.run_twop_ins(&mut mem, TwoOperandsOpCode::ADD, WOperand::Reg(WReg::CX), WOperand::Indirect(IndReg::BX));
+		assert_eq!(exec, 21);
+		cpu86.timing.flush_queue();
+		assert_eq!(cpu86.instruction_cycles(2, 0, exec, false, false), 21 + 4);
+
+		/* Odd address on the 8086: one extra bus cycle per transfer */
+		cpu86.bx = 0x2001;
+		let exec = cpu86.run_twop_ins(&mut mem, TwoOperandsOpCode::ADD, WOperand::Reg(WReg::CX), WOperand::Indirect(IndReg::BX));
+		cpu86.timing.flush_queue();
+		assert_eq!(cpu86.instruction_cycles(2, 0, exec, false, false), 21 + 2 * 4 + 4);
+
+		/* The 8088 always needs two bus cycles per word, and one per
+		 * instruction byte */
+		let mut cpu88 = cpu(CpuModel::I8088);
+		let exec = cpu88.run_twop_ins(&mut mem, TwoOperandsOpCode::ADD, WOperand::Reg(WReg::CX), WOperand::Indirect(IndReg::BX));
+		cpu88.timing.flush_queue();
+		assert_eq!(cpu88.instruction_cycles(2, 0, exec, false, false), 21 + 2 * 4 + 2 * 4);
+	}
+
+	#[test]
+	fn prefetch_queue()
+	{
+		let mut cpu = cpu(CpuModel::I8088);
+		cpu.timing.flush_queue();
+
+		/* A long instruction refills the queue (4 bytes on the 8088)... */
+		assert_eq!(cpu.instruction_cycles(1, 0, 20, false, false), 20 + 4);
+		/* ...from which the next instructions are taken for free */
+		assert_eq!(cpu.instruction_cycles(2, 0, 2, false, false), 2);
+		assert_eq!(cpu.instruction_cycles(2, 0, 2, false, false), 2);
+		/* Until it runs dry */
+		assert_eq!(cpu.instruction_cycles(2, 0, 2, false, false), 2 + 2 * 4);
+	}
+}
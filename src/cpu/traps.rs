@@ -0,0 +1,88 @@
+use std::mem;
+use super::base::*;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrapKind
+{
+	/* Software interrupts, hardware interrupts and exceptions alike */
+	Interrupt,
+	PortIn,
+	PortOut,
+	/* In or out */
+	PortAccess
+}
+
+/* Interrupt number or I/O port range, bounds included */
+#[derive(Clone, Copy, Debug)]
+pub struct Trap
+{
+	pub id: i32,
+	pub kind: TrapKind,
+	pub first: u16,
+	pub last: u16
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrapEvent
+{
+	/* The return address is the one pushed on the stack */
+	Interrupt { number: u8, return_cs: u16, return_ip: u16 },
+	PortIn { port: u16, size: u32, value: u16 },
+	PortOut { port: u16, size: u32, value: u16 }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TrapHit
+{
+	pub id: i32,
+	pub event: TrapEvent
+}
+
+impl Trap
+{
+	fn matches(&self, event: &TrapEvent) -> bool
+	{
+		let (kind_matches, val) = match *event
+		{
+			TrapEvent::Interrupt { number, .. } => (self.kind == TrapKind::Interrupt, number as u16),
+			TrapEvent::PortIn { port, .. } => (self.kind == TrapKind::PortIn || self.kind == TrapKind::PortAccess, port),
+			TrapEvent::PortOut { port, .. } => (self.kind == TrapKind::PortOut || self.kind == TrapKind::PortAccess, port)
+		};
+		kind_matches && val >= self.first && val <= self.last
+	}
+}
+
+impl CPU
+{
+	pub fn add_trap(&mut self, trap: Trap)
+	{
+		self.traps.push(trap);
+	}
+
+	/* Returns false if there is no such trap */
+	pub fn remove_trap(&mut self, id: i32) -> bool
+	{
+		let count = self.traps.len();
+		self.traps.retain(|trap| trap.id != id);
+		self.traps.len() != count
+	}
+
+	pub fn traps(&self) -> &[Trap]
+	{
+		&self.traps
+	}
+
+	/* Hits since the last call, in dispatch order */
+	pub fn take_trap_hits(&mut self) -> Vec<TrapHit>
+	{
+		mem::replace(&mut self.trap_hits, Vec::new())
+	}
+
+	pub fn check_traps(&mut self, event: TrapEvent)
+	{
+		for trap in self.traps.iter().filter(|trap| trap.matches(&event))
+		{
+			self.trap_hits.push(TrapHit { id: trap.id, event: event });
+		}
+	}
+}
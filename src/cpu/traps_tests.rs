@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::base::*;
+	use super::super::instruction::*;
+	use super::super::protected::EXC_GENERAL_PROTECTION;
+	use super::super::timing::CpuModel;
+	use super::super::traps::*;
+	use super::super::super::mem::Memory;
+
+	#[test]
+	fn interrupts()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I8086);
+		cpu.ss = 0x0000;
+		cpu.sp = 0x8000;
+		mem.write_u16(0x21 * 4, 0x0100);
+		mem.write_u16(0x21 * 4 + 2, 0x0200);
+
+		cpu.add_trap(Trap { id: 3, kind: TrapKind::Interrupt, first: 0x20, last: 0x21 });
+		cpu.add_trap(Trap { id: 4, kind: TrapKind::Interrupt, first: 0x13, last: 0x13 });
+		cpu.request_interrupt(&mut mem, 0x21);
+		cpu.request_interrupt(&mut mem, 0x10);
+
+		assert_eq!(cpu.take_trap_hits(), vec![TrapHit
+			{
+				id: 3,
+				event: TrapEvent::Interrupt { number: 0x21, return_cs: 0x0000, return_ip: 0x1000 }
+			}]);
+		assert!(cpu.take_trap_hits().is_empty());
+
+		assert!(cpu.remove_trap(3));
+		assert!(!cpu.remove_trap(3));
+		cpu.request_interrupt(&mut mem, 0x21);
+		assert!(cpu.take_trap_hits().is_empty());
+		assert_eq!(cpu.traps().len(), 1);
+	}
+
+	#[test]
+	fn ports()
+	{
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I8086);
+		cpu.add_trap(Trap { id: 0, kind: TrapKind::PortOut, first: 0x3f8, last: 0x3ff });
+		cpu.add_trap(Trap { id: 1, kind: TrapKind::PortAccess, first: 0x3f8, last: 0x3f8 });
+
+		let input = TrapEvent::PortIn { port: 0x3f8, size: 1, value: 0x41 };
+		let output = TrapEvent::PortOut { port: 0x3fb, size: 2, value: 0x1234 };
+		cpu.check_traps(input);
+		cpu.check_traps(output);
+		cpu.check_traps(TrapEvent::PortOut { port: 0x400, size: 1, value: 0 });
+
+		assert_eq!(cpu.take_trap_hits(), vec![TrapHit { id: 1, event: input }, TrapHit { id: 0, event: output }]);
+	}
+
+	#[test]
+	fn protected_mode_faults()
+	{
+		let mut mem = Memory::new(16 * 1024 * 1024);
+		let mut cpu = CPU::new(0x0000, 0x1000, CpuModel::I80286);
+		cpu.ds = 0x0000;
+		/* A code and a stack segment, and gates for #GP and INT 21h */
+		let descriptors = [(0x1008, 0xffff, 0x0000, 0x01, 0x9a), (0x1010, 0xffff, 0x0000, 0x02, 0x92),
+			(0x2000 + 0x0d * 8, 0x0200, 0x0008, 0x00, 0x86), (0x2000 + 0x21 * 8, 0x0300, 0x0008, 0x00, 0x86)];
+		for &(addr, limit, base, base_high, access) in &descriptors
+		{
+			mem.write_u16(addr, limit);
+			mem.write_u16(addr + 2, base);
+			mem.write_u8(addr + 4, base_high);
+			mem.write_u8(addr + 5, access);
+		}
+		mem.write_u16(0x500, 0x17);
+		mem.write_u16(0x502, 0x1000);
+		mem.write_u16(0x506, 0x10f);
+		mem.write_u16(0x508, 0x2000);
+		cpu.run_system_ins(&mut mem, SystemOpCode::LGDT, WOperand::Direct(0x500));
+		cpu.run_system_ins(&mut mem, SystemOpCode::LIDT, WOperand::Direct(0x506));
+		cpu.ax = 0x0001;
+		cpu.run_system_ins(&mut mem, SystemOpCode::LMSW, WOperand::Reg(WReg::AX));
+		cpu.protected_far_transfer(&mut mem, 0x08, 0x0042, false);
+		cpu.load_segment(&mut mem, SegReg::SS, 0x10);
+		cpu.sp = 0x1000;
+		assert_eq!(cpu.fault.get(), None);
+
+		cpu.add_trap(Trap { id: 0, kind: TrapKind::Interrupt, first: EXC_GENERAL_PROTECTION as u16, last: 0x21 });
+		/* MOV ES, 0x60: past the end of the GDT */
+		let checkpoint = cpu.checkpoint();
+		cpu.load_segment(&mut mem, SegReg::ES, 0x60);
+		assert!(cpu.deliver_fault(&mut mem, &checkpoint));
+		cpu.request_interrupt(&mut mem, 0x21);
+
+		/* Once each */
+		assert_eq!(cpu.take_trap_hits(), vec![
+			TrapHit { id: 0, event: TrapEvent::Interrupt { number: EXC_GENERAL_PROTECTION, return_cs: 0x08, return_ip: 0x0042 } },
+			TrapHit { id: 0, event: TrapEvent::Interrupt { number: 0x21, return_cs: 0x08, return_ip: 0x0200 } }]);
+	}
+}
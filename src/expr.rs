@@ -0,0 +1,393 @@
+use cpu::*;
+use mem::Memory;
+
+/* Expressions over the machine state, used as breakpoint conditions:
+ *   - numbers: 42, 0x2a, 'C'
+ *   - registers: AX..DI, AL..DH, CS, DS, ES, SS, IP, FLAGS
+ *   - flags, worth 0 or 1: CF, PF, AF, ZF, SF, TF, IF, DF, OF
+ *   - memory: byte[SEG:OFF], word[SEG:OFF], or byte[ADDR] with a physical
+ *     address
+ *   - operators, with the C precedence: unary - ! ~, * / %, + -, << >>,
+ *     < <= > >=, == !=, &, ^, |, &&, ||
+ * Names are case insensitive. Values are unsigned 32-bit integers. */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token
+{
+	Number(u32),
+	Ident(usize, usize), // Start and end in the source
+	Op(&'static str),
+	End
+}
+
+const OPERATORS: [&'static str; 25] =
+[
+	/* Longest first */
+	"==", "!=", "<=", ">=", "<<", ">>", "&&", "||",
+	"<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "!", "~", "(", ")", "[", "]", ":"
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Register
+{
+	Word(WReg),
+	Byte(BReg),
+	Seg(SegReg),
+	Flags
+}
+
+#[derive(Clone, Debug)]
+enum Node
+{
+	Const(u32),
+	Reg(Register),
+	Flag(u16),
+	/* Size in bytes, optional segment, offset */
+	Mem(u32, Option<Box<Node>>, Box<Node>),
+	Unary(&'static str, Box<Node>),
+	Binary(&'static str, Box<Node>, Box<Node>)
+}
+
+#[derive(Clone)]
+pub struct Expression
+{
+	source: String,
+	root: Node
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String>
+{
+	let bytes = source.as_bytes();
+	let mut tokens = Vec::new();
+	let mut pos = 0;
+
+	while pos < bytes.len()
+	{
+		let c = bytes[pos];
+		if c.is_ascii_whitespace()
+		{
+			pos += 1;
+		}
+		else if c.is_ascii_digit()
+		{
+			let start = pos;
+			while pos < bytes.len() && bytes[pos].is_ascii_alphanumeric()
+			{
+				pos += 1;
+			}
+			let text = &source[start .. pos];
+			let parsed = if text.starts_with("0x") || text.starts_with("0X")
+			{
+				u32::from_str_radix(&text[2 ..], 16)
+			}
+			else
+			{
+				text.parse::<u32>()
+			};
+			match parsed
+			{
+				Ok(val) => tokens.push(Token::Number(val)),
+				Err(_) => return Err(format!("invalid number '{}'", text))
+			}
+		}
+		else if c == b'\''
+		{
+			if pos + 2 >= bytes.len() || bytes[pos + 2] != b'\''
+			{
+				return Err("invalid character literal".to_string())
+			}
+			tokens.push(Token::Number(bytes[pos + 1] as u32));
+			pos += 3;
+		}
+		else if c.is_ascii_alphabetic() || c == b'_'
+		{
+			let start = pos;
+			while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_')
+			{
+				pos += 1;
+			}
+			tokens.push(Token::Ident(start, pos));
+		}
+		else
+		{
+			match OPERATORS.iter().find(|op| source[pos ..].starts_with(*op))
+			{
+				Some(op) =>
+				{
+					tokens.push(Token::Op(op));
+					pos += op.len();
+				}
+				None => return Err(format!("unexpected character '{}'", &source[pos ..].chars().next().unwrap()))
+			}
+		}
+	}
+
+	tokens.push(Token::End);
+	Ok(tokens)
+}
+
+/* Also used by the debugger to name the registers to edit */
+pub fn register(name: &str) -> Option<Register>
+{
+	let reg = match &name.to_ascii_uppercase()[..]
+	{
+		"AX" => Register::Word(WReg::AX),
+		"BX" => Register::Word(WReg::BX),
+		"CX" => Register::Word(WReg::CX),
+		"DX" => Register::Word(WReg::DX),
+		"SI" => Register::Word(WReg::SI),
+		"DI" => Register::Word(WReg::DI),
+		"BP" => Register::Word(WReg::BP),
+		"SP" => Register::Word(WReg::SP),
+		"IP" => Register::Word(WReg::IP),
+		"AH" => Register::Byte(BReg::AH),
+		"AL" => Register::Byte(BReg::AL),
+		"BH" => Register::Byte(BReg::BH),
+		"BL" => Register::Byte(BReg::BL),
+		"CH" => Register::Byte(BReg::CH),
+		"CL" => Register::Byte(BReg::CL),
+		"DH" => Register::Byte(BReg::DH),
+		"DL" => Register::Byte(BReg::DL),
+		"CS" => Register::Seg(SegReg::CS),
+		"DS" => Register::Seg(SegReg::DS),
+		"ES" => Register::Seg(SegReg::ES),
+		"SS" => Register::Seg(SegReg::SS),
+		"FLAGS" => Register::Flags,
+		_ => return None
+	};
+	Some(reg)
+}
+
+/* The mask of a flag in FLAGS */
+pub fn flag(name: &str) -> Option<u16>
+{
+	let mask = match &name.to_ascii_uppercase()[..]
+	{
+		"CF" => FLAG_C,
+		"PF" => FLAG_P,
+		"AF" => FLAG_A,
+		"ZF" => FLAG_Z,
+		"SF" => FLAG_S,
+		"TF" => FLAG_T,
+		"IF" => FLAG_I,
+		"DF" => FLAG_D,
+		"OF" => FLAG_O,
+		_ => return None
+	};
+	Some(mask)
+}
+
+/* Binary operators, from the loosest to the tightest binding */
+const PRECEDENCE: [&'static [&'static str]; 10] =
+[
+	&["||"],
+	&["&&"],
+	&["|"],
+	&["^"],
+	&["&"],
+	&["==", "!="],
+	&["<", "<=", ">", ">="],
+	&["<<", ">>"],
+	&["+", "-"],
+	&["*", "/", "%"]
+];
+
+struct Parser<'a>
+{
+	source: &'a str,
+	tokens: Vec<Token>,
+	pos: usize
+}
+
+impl<'a> Parser<'a>
+{
+	fn peek(&self) -> Token
+	{
+		self.tokens[self.pos]
+	}
+
+	fn next(&mut self) -> Token
+	{
+		let token = self.tokens[self.pos];
+		if token != Token::End
+		{
+			self.pos += 1;
+		}
+		token
+	}
+
+	fn expect(&mut self, op: &str) -> Result<(), String>
+	{
+		match self.next()
+		{
+			Token::Op(found) if found == op => Ok(()),
+			_ => Err(format!("expected '{}'", op))
+		}
+	}
+
+	fn binary(&mut self, level: usize) -> Result<Node, String>
+	{
+		if level == PRECEDENCE.len()
+		{
+			return self.unary()
+		}
+
+		let mut node = self.binary(level + 1)?;
+		loop
+		{
+			match self.peek()
+			{
+				Token::Op(op) if PRECEDENCE[level].contains(&op) =>
+				{
+					self.next();
+					let rhs = self.binary(level + 1)?;
+					node = Node::Binary(op, Box::new(node), Box::new(rhs));
+				}
+				_ => return Ok(node)
+			}
+		}
+	}
+
+	fn unary(&mut self) -> Result<Node, String>
+	{
+		match self.next()
+		{
+			Token::Op(op @ "-") | Token::Op(op @ "!") | Token::Op(op @ "~") => Ok(Node::Unary(op, Box::new(self.unary()?))),
+			Token::Op("(") =>
+			{
+				let node = self.binary(0)?;
+				self.expect(")")?;
+				Ok(node)
+			}
+			Token::Number(val) => Ok(Node::Const(val)),
+			Token::Ident(start, end) =>
+			{
+				let name = &self.source[start .. end];
+				let size = match &name.to_ascii_lowercase()[..]
+				{
+					"byte" => Some(1),
+					"word" => Some(2),
+					_ => None
+				};
+
+				if let Some(size) = size
+				{
+					self.expect("[")?;
+					let first = self.binary(0)?;
+					let node = match self.peek()
+					{
+						Token::Op(":") =>
+						{
+							self.next();
+							let offset = self.binary(0)?;
+							Node::Mem(size, Some(Box::new(first)), Box::new(offset))
+						}
+						_ => Node::Mem(size, None, Box::new(first))
+					};
+					self.expect("]")?;
+					Ok(node)
+				}
+				else if let Some(reg) = register(name)
+				{
+					Ok(Node::Reg(reg))
+				}
+				else if let Some(mask) = flag(name)
+				{
+					Ok(Node::Flag(mask))
+				}
+				else
+				{
+					Err(format!("unknown name '{}'", name))
+				}
+			}
+			Token::Op(op) => Err(format!("unexpected '{}'", op)),
+			Token::End => Err("unexpected end of expression".to_string())
+		}
+	}
+}
+
+impl Expression
+{
+	pub fn parse(source: &str) -> Result<Expression, String>
+	{
+		let mut parser = Parser { source: source, tokens: tokenize(source)?, pos: 0 };
+		let root = parser.binary(0)?;
+		match parser.next()
+		{
+			Token::End => Ok(Expression { source: source.trim().to_string(), root: root }),
+			_ => Err("unexpected trailing characters".to_string())
+		}
+	}
+
+	pub fn source(&self) -> &str
+	{
+		&self.source
+	}
+
+	pub fn eval(&self, cpu: &CPU, mem: &Memory) -> Result<u32, String>
+	{
+		eval(&self.root, cpu, mem)
+	}
+}
+
+fn eval(node: &Node, cpu: &CPU, mem: &Memory) -> Result<u32, String>
+{
+	let val = match *node
+	{
+		Node::Const(val) => val,
+		Node::Reg(Register::Word(reg)) => cpu.get_reg(reg) as u32,
+		Node::Reg(Register::Byte(reg)) => cpu.get_reg(reg) as u32,
+		Node::Reg(Register::Seg(reg)) => cpu.get_reg(reg) as u32,
+		Node::Reg(Register::Flags) => cpu.flags as u32,
+		Node::Flag(mask) => (cpu.flags & mask != 0) as u32,
+		Node::Mem(size, ref seg, ref offset) =>
+		{
+			let offset = eval(offset, cpu, mem)?;
+			let addr = match *seg
+			{
+				Some(ref seg) => phys_addr(eval(seg, cpu, mem)? as u16, offset as u16),
+				None => mem.physical_address(offset)
+			};
+			if size == 1 { mem.read_u8(addr) as u32 } else { mem.read_u16(addr) as u32 }
+		}
+		Node::Unary(op, ref operand) =>
+		{
+			let val = eval(operand, cpu, mem)?;
+			match op
+			{
+				"-" => val.wrapping_neg(),
+				"!" => (val == 0) as u32,
+				_ => !val
+			}
+		}
+		/* Short-circuit evaluation */
+		Node::Binary("&&", ref lhs, ref rhs) => (eval(lhs, cpu, mem)? != 0 && eval(rhs, cpu, mem)? != 0) as u32,
+		Node::Binary("||", ref lhs, ref rhs) => (eval(lhs, cpu, mem)? != 0 || eval(rhs, cpu, mem)? != 0) as u32,
+		Node::Binary(op, ref lhs, ref rhs) =>
+		{
+			let (a, b) = (eval(lhs, cpu, mem)?, eval(rhs, cpu, mem)?);
+			match op
+			{
+				"*" => a.wrapping_mul(b),
+				"/" | "%" if b == 0 => return Err("division by zero".to_string()),
+				"/" => a / b,
+				"%" => a % b,
+				"+" => a.wrapping_add(b),
+				"-" => a.wrapping_sub(b),
+				"<<" => a.checked_shl(b).unwrap_or(0),
+				">>" => a.checked_shr(b).unwrap_or(0),
+				"<" => (a < b) as u32,
+				"<=" => (a <= b) as u32,
+				">" => (a > b) as u32,
+				">=" => (a >= b) as u32,
+				"==" => (a == b) as u32,
+				"!=" => (a != b) as u32,
+				"&" => a & b,
+				"^" => a ^ b,
+				"|" => a | b,
+				_ => unreachable!()
+			}
+		}
+	};
+	Ok(val)
+}
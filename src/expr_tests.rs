@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::expr::*;
+	use super::super::cpu::*;
+	use super::super::mem::Memory;
+
+	fn machine_state() -> (CPU, Memory)
+	{
+		let mut cpu = CPU::new(0x1234, 0x0100, CpuModel::I8086);
+		cpu.set_reg(WReg::AX, 0x3d02);
+		cpu.set_reg(WReg::DX, 0x0010);
+		cpu.set_reg(SegReg::DS, 0x2000);
+		cpu.flags |= FLAG_Z;
+		cpu.flags &= !FLAG_C;
+
+		let mut mem = Memory::new(0x100000);
+		mem.write_u8(0x20010, b'C');
+		mem.write_u16(0x00400, 0xbeef);
+		(cpu, mem)
+	}
+
+	fn eval(source: &str) -> Result<u32, String>
+	{
+		let (cpu, mem) = machine_state();
+		Expression::parse(source).and_then(|expr| expr.eval(&cpu, &mem))
+	}
+
+	#[test]
+	fn arithmetic()
+	{
+		assert_eq!(eval("1 + 2 * 3"), Ok(7));
+		assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+		assert_eq!(eval("0x10 >> 2 | 1"), Ok(5));
+		assert_eq!(eval("1 < 2 == 1"), Ok(1));
+		assert_eq!(eval("-1"), Ok(0xffffffff));
+		assert_eq!(eval("!0 + ~0"), Ok(0));
+		assert_eq!(eval("7 % 4 ^ 1"), Ok(2));
+		assert_eq!(eval("'A'"), Ok(0x41));
+		assert_eq!(eval("1 / 0"), Err("division by zero".to_string()));
+		/* The right-hand side is not evaluated */
+		assert_eq!(eval("0 && 1 / 0"), Ok(0));
+		assert_eq!(eval("1 || 1 / 0"), Ok(1));
+	}
+
+	#[test]
+	fn machine_state_operands()
+	{
+		assert_eq!(eval("AH == 0x3D && byte[DS:DX] == 'C'"), Ok(1));
+		assert_eq!(eval("al"), Ok(2));
+		assert!(eval("cs:ip").is_err());
+		assert_eq!(eval("IP + CS"), Ok(0x1334));
+		assert_eq!(eval("ZF && !CF"), Ok(1));
+		assert_eq!(eval("word[0x400]"), Ok(0xbeef));
+		assert_eq!(eval("byte[0x40:1]"), Ok(0xbe));
+	}
+
+	#[test]
+	fn extended_memory()
+	{
+		let cpu = CPU::new(0x1234, 0x0100, CpuModel::I80286);
+		let mut mem = Memory::new(16 * 1024 * 1024);
+		mem.set_a20(true);
+		mem.write_u8(0x00400, 0x12);
+		mem.write_u8(0x100400, 0x34);
+		let expr = Expression::parse("byte[0x100400]").unwrap();
+		assert_eq!(expr.eval(&cpu, &mem), Ok(0x34));
+		/* Wraps around with the A20 gate closed */
+		mem.set_a20(false);
+		assert_eq!(expr.eval(&cpu, &mem), Ok(0x12));
+	}
+
+	#[test]
+	fn parse_errors()
+	{
+		assert!(Expression::parse("").is_err());
+		assert!(Expression::parse("AX ==").is_err());
+		assert!(Expression::parse("(AX").is_err());
+		assert!(Expression::parse("AX BX").is_err());
+		assert!(Expression::parse("XY == 1").is_err());
+		assert!(Expression::parse("byte[DS:DX").is_err());
+		assert!(Expression::parse("0x1g").is_err());
+		assert!(Expression::parse("AX = 1").is_err());
+		assert_eq!(Expression::parse(" AX == 1 ").unwrap().source(), "AX == 1");
+	}
+}
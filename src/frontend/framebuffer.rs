@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+/* An RGB image, pixels being 0x00RRGGBB, row by row from the top */
+pub struct Framebuffer
+{
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<u32>
+}
+
+impl Framebuffer
+{
+	pub fn new(width: u32, height: u32) -> Framebuffer
+	{
+		Framebuffer
+		{
+			width: width,
+			height: height,
+			pixels: vec![0; (width * height) as usize]
+		}
+	}
+
+	pub fn pixel(&self, x: u32, y: u32) -> u32
+	{
+		self.pixels[(y * self.width + x) as usize]
+	}
+
+	pub fn set_pixel(&mut self, x: u32, y: u32, color: u32)
+	{
+		self.pixels[(y * self.width + x) as usize] = color;
+	}
+
+	pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: u32)
+	{
+		for row in y .. y + height
+		{
+			let start = (row * self.width + x) as usize;
+			for pixel in &mut self.pixels[start .. start + width as usize]
+			{
+				*pixel = color;
+			}
+		}
+	}
+
+	/* Uncompressed 24 or 32 bits per pixel BMP files only */
+	pub fn load_bmp(filename: &str) -> Result<Framebuffer, String>
+	{
+		let mut data = Vec::new();
+		File::open(filename)
+			.and_then(|mut file| file.read_to_end(&mut data))
+			.map_err(|e| format!("unable to read {}: {}", filename, e))?;
+
+		if data.len() < 54 || &data[0..2] != b"BM"
+		{
+			return Err(format!("{}: not a BMP file", filename))
+		}
+
+		let offset = le_u32(&data, 10) as usize;
+		let width = le_u32(&data, 18) as i32;
+		let height = le_u32(&data, 22) as i32;
+		let bpp = (data[28] as u32) | (data[29] as u32) << 8;
+		let compression = le_u32(&data, 30);
+		if width <= 0 || height == 0 || (bpp != 24 && bpp != 32) || compression != 0
+		{
+			return Err(format!("{}: unsupported BMP format", filename))
+		}
+
+		/* Rows are stored from the bottom unless the height is negative */
+		let (width, bottom_up) = (width as u32, height > 0);
+		let height = height.unsigned_abs();
+		let bytes_per_pixel = bpp / 8;
+		let stride = ((width * bytes_per_pixel + 3) & !3) as usize;
+		if data.len() < offset + stride * height as usize
+		{
+			return Err(format!("{}: truncated BMP file", filename))
+		}
+
+		let mut frame = Framebuffer::new(width, height);
+		for y in 0 .. height
+		{
+			let row = if bottom_up { height - 1 - y } else { y };
+			let row_start = offset + stride * row as usize;
+			for x in 0 .. width
+			{
+				let p = row_start + (x * bytes_per_pixel) as usize;
+				let color = (data[p] as u32) | (data[p + 1] as u32) << 8 | (data[p + 2] as u32) << 16;
+				frame.set_pixel(x, y, color);
+			}
+		}
+		Ok(frame)
+	}
+
+	/* Writes a 24 bits per pixel BMP file */
+	pub fn save_bmp(&self, filename: &str) -> Result<(), String>
+	{
+		let stride = ((self.width * 3 + 3) & !3) as usize;
+		let image_size = stride * self.height as usize;
+
+		let mut data = Vec::with_capacity(54 + image_size);
+		data.extend_from_slice(b"BM");
+		data.extend_from_slice(&u32_le(54 + image_size as u32));
+		data.extend_from_slice(&u32_le(0)); // Reserved
+		data.extend_from_slice(&u32_le(54)); // Pixel data offset
+		data.extend_from_slice(&u32_le(40)); // BITMAPINFOHEADER size
+		data.extend_from_slice(&u32_le(self.width));
+		data.extend_from_slice(&u32_le(self.height));
+		data.extend_from_slice(&[1, 0]); // Planes
+		data.extend_from_slice(&[24, 0]); // Bits per pixel
+		data.extend_from_slice(&u32_le(0)); // No compression
+		data.extend_from_slice(&u32_le(image_size as u32));
+		data.extend_from_slice(&u32_le(2835)); // 72 DPI
+		data.extend_from_slice(&u32_le(2835));
+		data.extend_from_slice(&u32_le(0)); // Palette size
+		data.extend_from_slice(&u32_le(0)); // Important colors
+
+		for y in (0 .. self.height).rev()
+		{
+			for x in 0 .. self.width
+			{
+				let color = self.pixel(x, y);
+				data.extend_from_slice(&[color as u8, (color >> 8) as u8, (color >> 16) as u8]);
+			}
+			let padded_len = data.len() + stride - self.width as usize * 3;
+			data.resize(padded_len, 0);
+		}
+
+		File::create(filename)
+			.and_then(|mut file| file.write_all(&data))
+			.map_err(|e| format!("unable to write {}: {}", filename, e))
+	}
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32
+{
+	(data[offset] as u32) | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16 | (data[offset + 3] as u32) << 24
+}
+
+fn u32_le(val: u32) -> [u8; 4]
+{
+	[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]
+}
@@ -0,0 +1,42 @@
+#[cfg(test)]
+mod tests
+{
+	use std::env;
+	use std::fs;
+	use super::super::framebuffer::*;
+
+	#[test]
+	fn bmp_roundtrip()
+	{
+		/* An odd width needs padded rows */
+		let mut frame = Framebuffer::new(3, 2);
+		frame.fill_rect(0, 0, 3, 1, 0x0000aa);
+		frame.set_pixel(2, 1, 0xff55ff);
+
+		let path = env::temp_dir().join("riapyx_bmp_roundtrip.bmp");
+		let filename = path.to_str().unwrap();
+		frame.save_bmp(filename).unwrap();
+		let loaded = Framebuffer::load_bmp(filename);
+		fs::remove_file(filename).unwrap();
+
+		let loaded = loaded.unwrap();
+		assert_eq!((loaded.width, loaded.height), (3, 2));
+		assert_eq!(loaded.pixels, vec![0x0000aa, 0x0000aa, 0x0000aa, 0, 0, 0xff55ff]);
+	}
+
+	#[test]
+	fn font()
+	{
+		let font = Framebuffer::load_bmp("cga_font.bmp").unwrap();
+		assert_eq!((font.width, font.height), (256, 64));
+		/* Top row of 'A' (0x41) is ...##... */
+		let row: Vec<bool> = (8 .. 16).map(|x| font.pixel(x, 16) != 0).collect();
+		assert_eq!(row, vec![false, false, false, true, true, false, false, false]);
+	}
+
+	#[test]
+	fn invalid_file()
+	{
+		assert!(Framebuffer::load_bmp("Cargo.toml").is_err());
+	}
+}
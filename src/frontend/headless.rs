@@ -0,0 +1,44 @@
+use super::{Frontend, Framebuffer, InputEvent};
+
+/* No window, no host input and no sound device: the screen is only drawn
+ * when a screenshot is taken, and the sound only goes to a WAV file if any */
+pub struct HeadlessFrontend;
+
+impl HeadlessFrontend
+{
+	pub fn new() -> HeadlessFrontend
+	{
+		HeadlessFrontend
+	}
+}
+
+impl Frontend for HeadlessFrontend
+{
+	fn poll_event(&mut self) -> Option<InputEvent>
+	{
+		None
+	}
+
+	fn wait_event(&mut self) -> InputEvent
+	{
+		panic!("Waiting for an input event while running headless")
+	}
+
+	fn has_input(&self) -> bool
+	{
+		false
+	}
+
+	fn shows_frames(&self) -> bool
+	{
+		false
+	}
+
+	fn present_frame(&mut self, _frame: &Framebuffer)
+	{
+	}
+
+	fn queue_audio(&mut self, _samples: &[i16])
+	{
+	}
+}
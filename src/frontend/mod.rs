@@ -0,0 +1,52 @@
+pub mod framebuffer;
+mod framebuffer_tests;
+pub mod headless;
+pub mod sdl;
+mod sdl_scancodes;
+
+pub use self::framebuffer::Framebuffer;
+
+/* Samples given to Frontend::queue_audio are 16-bit signed mono at this rate */
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+
+/* Host input, already translated into what the emulated devices understand */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent
+{
+	/* XT (set 1) make codes */
+	KeyDown(u8),
+	KeyUp(u8),
+	MouseMotion(i32, i32),
+	MouseButtonDown(MouseButton),
+	MouseButtonUp(MouseButton),
+	/* The user wants to leave the emulator */
+	Quit
+}
+
+/* The buttons of a Microsoft serial mouse */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MouseButton
+{
+	Left,
+	Right
+}
+
+/* The host side of the emulator: it supplies the input events and consumes
+ * the frames and the sound. Devices only talk to the host through it, so that
+ * another implementation (another library, a test harness...) can be plugged
+ * in without touching them. */
+pub trait Frontend
+{
+	/* Next pending input event, without blocking */
+	fn poll_event(&mut self) -> Option<InputEvent>;
+	/* Blocks until an input event is available; only called if has_input() */
+	fn wait_event(&mut self) -> InputEvent;
+	/* Whether any input event may ever come */
+	fn has_input(&self) -> bool;
+
+	/* Whether presented frames are shown at all, i.e. worth rendering */
+	fn shows_frames(&self) -> bool;
+	fn present_frame(&mut self, frame: &Framebuffer);
+
+	fn queue_audio(&mut self, samples: &[i16]);
+}
@@ -0,0 +1,183 @@
+extern crate sdl2;
+
+use self::sdl2::audio::{AudioQueue, AudioSpecDesired};
+use self::sdl2::event::Event;
+use self::sdl2::mouse::MouseWheelDirection;
+use self::sdl2::pixels::PixelFormatEnum;
+use self::sdl2::surface::Surface;
+use super::{Frontend, Framebuffer, InputEvent, MouseButton, AUDIO_SAMPLE_RATE};
+use super::sdl_scancodes::sdlk_to_scancode;
+
+/* Stop queuing samples if the emulation runs ahead of the audio device by
+ * more than ~0.2s: the latency would become noticeable */
+const MAX_QUEUED_BYTES: u32 = AUDIO_SAMPLE_RATE / 5 * 2;
+
+/* A window showing the frames, with the host keyboard and mouse as input and
+ * the default audio device as output */
+pub struct SdlFrontend
+{
+	sdl: sdl2::Sdl,
+	window: sdl2::video::Window,
+	event_pump: sdl2::EventPump,
+	/* Frames are copied there, then blitted to the window surface, whatever
+	 * its pixel format */
+	staging: Surface<'static>,
+	audio: Option<AudioQueue<i16>>
+}
+
+impl SdlFrontend
+{
+	pub fn new(width: u32, height: u32) -> SdlFrontend
+	{
+		let sdl = sdl2::init().unwrap();
+		let video = sdl.video().unwrap();
+		let window = sdl2::video::WindowBuilder::new(&video, "Riapyx", width, height).build().unwrap();
+		sdl.mouse().show_cursor(false);
+		let event_pump = sdl.event_pump().unwrap();
+		let audio = SdlFrontend::open_audio_queue(&sdl);
+
+		SdlFrontend
+		{
+			sdl: sdl,
+			window: window,
+			event_pump: event_pump,
+			staging: Surface::new(width, height, PixelFormatEnum::RGB888).unwrap(),
+			audio: audio
+		}
+	}
+
+	fn open_audio_queue(sdl: &sdl2::Sdl) -> Option<AudioQueue<i16>>
+	{
+		let spec = AudioSpecDesired
+		{
+			freq: Some(AUDIO_SAMPLE_RATE as i32),
+			channels: Some(1),
+			samples: Some(512)
+		};
+
+		match sdl.audio().and_then(|audio| audio.open_queue::<i16, _>(None, &spec))
+		{
+			Ok(queue) =>
+			{
+				queue.resume();
+				Some(queue)
+			}
+			Err(e) =>
+			{
+				println!("Warning: unable to open audio device, the PC speaker will be silent: {}", e);
+				None
+			}
+		}
+	}
+
+	/* None if the event is of no interest to the emulated machine */
+	fn translate(&self, event: Event) -> Option<InputEvent>
+	{
+		match event
+		{
+			Event::KeyDown { scancode: Some(scancode), .. } =>
+				sdlk_to_scancode(scancode).map(InputEvent::KeyDown),
+			Event::KeyUp { scancode: Some(scancode), .. } =>
+				sdlk_to_scancode(scancode).map(InputEvent::KeyUp),
+			Event::MouseMotion { xrel, yrel, .. } => Some(InputEvent::MouseMotion(xrel, yrel)),
+			Event::MouseButtonDown { mouse_btn, clicks: 1, .. } =>
+				translate_button(mouse_btn).map(InputEvent::MouseButtonDown),
+			Event::MouseButtonUp { mouse_btn, clicks: 1, .. } =>
+				translate_button(mouse_btn).map(InputEvent::MouseButtonUp),
+			/* Mouse wheel down captures the cursor, up releases it */
+			Event::MouseWheel { y, direction: MouseWheelDirection::Normal, .. } =>
+			{
+				if y > 0 { self.sdl.mouse().set_relative_mouse_mode(false); }
+				else if y < 0 { self.sdl.mouse().set_relative_mouse_mode(true); }
+				None
+			}
+			Event::Quit { .. } => Some(InputEvent::Quit),
+			_ => None
+		}
+	}
+}
+
+fn translate_button(button: sdl2::mouse::MouseButton) -> Option<MouseButton>
+{
+	match button
+	{
+		sdl2::mouse::MouseButton::Left => Some(MouseButton::Left),
+		sdl2::mouse::MouseButton::Right => Some(MouseButton::Right),
+		_ => None
+	}
+}
+
+impl Frontend for SdlFrontend
+{
+	fn poll_event(&mut self) -> Option<InputEvent>
+	{
+		while let Some(event) = self.event_pump.poll_event()
+		{
+			if let Some(event) = self.translate(event)
+			{
+				return Some(event)
+			}
+		}
+		None
+	}
+
+	fn wait_event(&mut self) -> InputEvent
+	{
+		loop
+		{
+			let event = self.event_pump.wait_event();
+			if let Some(event) = self.translate(event)
+			{
+				return event
+			}
+		}
+	}
+
+	fn has_input(&self) -> bool
+	{
+		true
+	}
+
+	fn shows_frames(&self) -> bool
+	{
+		true
+	}
+
+	fn present_frame(&mut self, frame: &Framebuffer)
+	{
+		let pitch = self.staging.pitch() as usize;
+		self.staging.with_lock_mut(|pixels|
+			{
+				for y in 0 .. frame.height as usize
+				{
+					let src = &frame.pixels[y * frame.width as usize .. (y + 1) * frame.width as usize];
+					let dst = &mut pixels[y * pitch .. y * pitch + src.len() * 4];
+					for (color, bytes) in src.iter().zip(dst.chunks_mut(4))
+					{
+						bytes[0] = *color as u8;
+						bytes[1] = (*color >> 8) as u8;
+						bytes[2] = (*color >> 16) as u8;
+						bytes[3] = 0;
+					}
+				}
+			});
+
+		let mut surface = self.window.surface(&self.event_pump).unwrap();
+		self.staging.blit(None, &mut surface, None).unwrap();
+		surface.update_window().unwrap();
+	}
+
+	fn queue_audio(&mut self, samples: &[i16])
+	{
+		if let Some(ref queue) = self.audio
+		{
+			if queue.size() < MAX_QUEUED_BYTES
+			{
+				if let Err(e) = queue.queue_audio(samples)
+				{
+					println!("Warning: unable to queue audio samples: {}", e);
+				}
+			}
+		}
+	}
+}
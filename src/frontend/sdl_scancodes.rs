@@ -0,0 +1,97 @@
+extern crate sdl2;
+
+use self::sdl2::keyboard::Scancode;
+
+/* Translates SDL scancodes to XT (set 1) make codes */
+pub fn sdlk_to_scancode(sdlk: Scancode) -> Option<u8>
+{
+	match sdlk
+	{
+		Scancode::Escape => Some(0x01),
+		Scancode::Num1 => Some(0x02),
+		Scancode::Num2 => Some(0x03),
+		Scancode::Num3 => Some(0x04),
+		Scancode::Num4 => Some(0x05),
+		Scancode::Num5 => Some(0x06),
+		Scancode::Num6 => Some(0x07),
+		Scancode::Num7 => Some(0x08),
+		Scancode::Num8 => Some(0x09),
+		Scancode::Num9 => Some(0x0a),
+		Scancode::Num0 => Some(0x0b),
+		Scancode::Minus => Some(0x0c),
+		Scancode::Equals => Some(0x0d),
+		Scancode::Backspace => Some(0x0e),
+		Scancode::Tab => Some(0x0f),
+		Scancode::Q => Some(0x10),
+		Scancode::W => Some(0x11),
+		Scancode::E => Some(0x12),
+		Scancode::R => Some(0x13),
+		Scancode::T => Some(0x14),
+		Scancode::Y => Some(0x15),
+		Scancode::U => Some(0x16),
+		Scancode::I => Some(0x17),
+		Scancode::O => Some(0x18),
+		Scancode::P => Some(0x19),
+		Scancode::LeftBracket => Some(0x1a),
+		Scancode::RightBracket => Some(0x1b),
+		Scancode::Return => Some(0x1c),
+		Scancode::LCtrl => Some(0x1d),
+		Scancode::A => Some(0x1e),
+		Scancode::S => Some(0x1f),
+		Scancode::D => Some(0x20),
+		Scancode::F => Some(0x21),
+		Scancode::G => Some(0x22),
+		Scancode::H => Some(0x23),
+		Scancode::J => Some(0x24),
+		Scancode::K => Some(0x25),
+		Scancode::L => Some(0x26),
+		Scancode::Semicolon => Some(0x27),
+		Scancode::Apostrophe => Some(0x28),
+		Scancode::Grave => Some(0x29),
+		Scancode::LShift => Some(0x2a),
+		Scancode::Backslash => Some(0x2b),
+		//on a 102-key keyboard
+		Scancode::Z => Some(0x2c),
+		Scancode::X => Some(0x2d),
+		Scancode::C => Some(0x2e),
+		Scancode::V => Some(0x2f),
+		Scancode::B => Some(0x30),
+		Scancode::N => Some(0x31),
+		Scancode::M => Some(0x32),
+		Scancode::Comma => Some(0x33),
+		Scancode::Period => Some(0x34),
+		Scancode::Slash => Some(0x35),
+		Scancode::RShift => Some(0x36),
+		//Scancode::*/PrtScn => Some(0x37 (Keypad-*) or on a 83/84-key keyboard),
+		Scancode::LAlt => Some(0x38),
+		Scancode::Space => Some(0x39),
+		Scancode::CapsLock => Some(0x3a),
+		Scancode::F1 => Some(0x3b),
+		Scancode::F2 => Some(0x3c),
+		Scancode::F3 => Some(0x3d),
+		Scancode::F4 => Some(0x3e),
+		Scancode::F5 => Some(0x3f),
+		Scancode::F6 => Some(0x40),
+		Scancode::F7 => Some(0x41),
+		Scancode::F8 => Some(0x42),
+		Scancode::F9 => Some(0x43),
+		Scancode::F10 => Some(0x44),
+		//Scancode::NumLock => Some(0x45),
+		Scancode::ScrollLock => Some(0x46),
+		Scancode::Home => Some(0x47),
+		Scancode::Up => Some(0x48),
+		Scancode::PageUp => Some(0x49),
+		//Scancode::=> Some(0x4a),
+		Scancode::Left => Some(0x4b),
+		//Scancode::=> Some(0x4c),
+		Scancode::Right => Some(0x4d),
+		//Scancode::=> Some(0x4e),
+		Scancode::End => Some(0x4f),
+		Scancode::Down => Some(0x50),
+		Scancode::PageDown => Some(0x51),
+		Scancode::Insert => Some(0x52),
+		Scancode::Delete => Some(0x53),
+		//Scancode::Alt-SysRq => Some(0x54 on a 84+ key keyboard),
+		_ => None
+	}
+}
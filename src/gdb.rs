@@ -0,0 +1,593 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str;
+use std::time::Duration;
+
+use cpu::CPU;
+use machine::Machine;
+use mem::{Watchpoint, WatchHit, WatchKind};
+
+/* GDB remote serial protocol stub, listening on localhost.
+ * GDB has no notion of segments, so addresses are linear ones: the register
+ * file is the i386 one, with eip holding CS*16+IP. Writing eip keeps CS if the
+ * new address is within the current code segment. Breakpoints, software or
+ * hardware, are handled by the emulator and never written into memory; so are
+ * watchpoints, on guest accesses only.
+ * Typical session:
+ *   (gdb) set architecture i8086
+ *   (gdb) target remote localhost:1234
+ *   (gdb) break *0x7c00 */
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/* i386 register file: eax, ecx, edx, ebx, esp, ebp, esi, edi, eip, eflags,
+ * cs, ss, ds, es, fs, gs; 32 bits each */
+const REGISTER_COUNT: usize = 16;
+const EIP: usize = 8;
+
+/* Instructions run between two checks for an interrupt request (Ctrl-C) */
+const INTERRUPT_CHECK_PERIOD: u32 = 10000;
+/* While waiting for a packet, host events are still pumped at this period */
+const IDLE_PERIOD_MS: u64 = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum Incoming
+{
+	Packet(String),
+	/* Bad checksum: GDB has to send the packet again */
+	Corrupted,
+	/* Ctrl-C */
+	Interrupt
+}
+
+/* What to do after a packet was handled */
+#[derive(Debug, PartialEq)]
+pub enum Action
+{
+	Reply(String),
+	/* The stop reply is sent once the machine stops */
+	Resume { step: bool },
+	Detach,
+	Kill
+}
+
+pub fn checksum(data: &[u8]) -> u8
+{
+	data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+pub fn frame(data: &str) -> Vec<u8>
+{
+	format!("${}#{:02x}", data, checksum(data.as_bytes())).into_bytes()
+}
+
+/* Extracts the next complete message from the received bytes; acks are
+ * dropped as the stub does not resend anything */
+pub fn parse_incoming(buffer: &mut Vec<u8>) -> Option<Incoming>
+{
+	loop
+	{
+		match buffer.first()
+		{
+			None => return None,
+			Some(&0x03) =>
+			{
+				buffer.remove(0);
+				return Some(Incoming::Interrupt)
+			}
+			Some(&b'$') => break,
+			Some(_) => { buffer.remove(0); }
+		}
+	}
+
+	let end = match buffer.iter().position(|&byte| byte == b'#')
+	{
+		Some(end) if buffer.len() >= end + 3 => end,
+		_ => return None
+	};
+	let message: Vec<u8> = buffer.drain(.. end + 3).collect();
+	let data = &message[1 .. end];
+	let expected = str::from_utf8(&message[end + 1 ..]).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+	if expected != Some(checksum(data))
+	{
+		return Some(Incoming::Corrupted)
+	}
+	Some(Incoming::Packet(String::from_utf8_lossy(data).into_owned()))
+}
+
+fn read_register(cpu: &CPU, idx: usize) -> u32
+{
+	match idx
+	{
+		0 => cpu.ax as u32,
+		1 => cpu.cx as u32,
+		2 => cpu.dx as u32,
+		3 => cpu.bx as u32,
+		4 => cpu.sp as u32,
+		5 => cpu.bp as u32,
+		6 => cpu.si as u32,
+		7 => cpu.di as u32,
+		EIP => cpu.code_address(cpu.ip),
+		9 => cpu.flags as u32,
+		10 => cpu.cs as u32,
+		11 => cpu.ss as u32,
+		12 => cpu.ds as u32,
+		13 => cpu.es as u32,
+		_ => 0 // fs, gs
+	}
+}
+
+fn write_register(cpu: &mut CPU, idx: usize, val: u32)
+{
+	match idx
+	{
+		0 => cpu.ax = val as u16,
+		1 => cpu.cx = val as u16,
+		2 => cpu.dx = val as u16,
+		3 => cpu.bx = val as u16,
+		4 => cpu.sp = val as u16,
+		5 => cpu.bp = val as u16,
+		6 => cpu.si = val as u16,
+		7 => cpu.di = val as u16,
+		EIP =>
+		{
+			/* Selectors cannot be made up in protected mode */
+			let base = cpu.code_address(0);
+			if val >= base && val - base <= 0xffff
+			{
+				cpu.ip = (val - base) as u16;
+			}
+			else if !cpu.protected_mode()
+			{
+				cpu.cs = (val >> 4) as u16;
+				cpu.ip = (val & 0xf) as u16;
+			}
+		}
+		9 => cpu.flags = val as u16 | cpu.timing.model.fixed_flags(),
+		10 => cpu.cs = val as u16,
+		11 => cpu.ss = val as u16,
+		12 => cpu.ds = val as u16,
+		13 => cpu.es = val as u16,
+		_ => {}
+	}
+}
+
+/* Registers are sent as little-endian hex strings */
+fn encode_u32(val: u32) -> String
+{
+	format!("{:08x}", val.swap_bytes())
+}
+
+fn decode_u32(hex: &str) -> Option<u32>
+{
+	if hex.len() != 8
+	{
+		return None
+	}
+	u32::from_str_radix(hex, 16).ok().map(|val| val.swap_bytes())
+}
+
+fn decode_hex_bytes(hex: &str) -> Option<Vec<u8>>
+{
+	if hex.len() % 2 != 0
+	{
+		return None
+	}
+	(0 .. hex.len() / 2).map(|idx| hex.get(idx * 2 .. idx * 2 + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+/* 'addr,length' */
+fn parse_range(args: &str) -> Option<(u32, u32)>
+{
+	let mut parts = args.splitn(2, ',');
+	match (parts.next().map(|addr| u32::from_str_radix(addr, 16)), parts.next().map(|len| u32::from_str_radix(len, 16)))
+	{
+		(Some(Ok(addr)), Some(Ok(len))) => Some((addr, len)),
+		_ => None
+	}
+}
+
+fn error(code: u8) -> Action
+{
+	Action::Reply(format!("E{:02x}", code))
+}
+
+fn ok() -> Action
+{
+	Action::Reply("OK".to_string())
+}
+
+fn watch_kind(kind: &str) -> Option<WatchKind>
+{
+	match kind
+	{
+		"2" => Some(WatchKind::Write),
+		"3" => Some(WatchKind::Read),
+		"4" => Some(WatchKind::Access),
+		_ => None
+	}
+}
+
+pub struct GdbStub
+{
+	breakpoints: HashSet<u32>,
+	/* Memory watchpoint ids, by kind, address and length */
+	watchpoints: HashMap<(WatchKind, u32, u32), i32>,
+	next_watchpoint_id: i32
+}
+
+impl GdbStub
+{
+	pub fn new() -> GdbStub
+	{
+		GdbStub
+		{
+			breakpoints: HashSet::new(),
+			watchpoints: HashMap::new(),
+			next_watchpoint_id: 0
+		}
+	}
+
+	fn add_watchpoint(&mut self, m: &mut Machine, key: (WatchKind, u32, u32))
+	{
+		if self.watchpoints.contains_key(&key)
+		{
+			return
+		}
+		let (kind, addr, len) = key;
+		let id = self.next_watchpoint_id;
+		self.next_watchpoint_id += 1;
+		m.memory_mut().add_watchpoint(Watchpoint { id: id, kind: kind, start: addr, end: addr + len - 1 });
+		self.watchpoints.insert(key, id);
+	}
+
+	/* Watchpoints do not outlive the GDB session */
+	pub fn remove_watchpoints(&mut self, m: &mut Machine)
+	{
+		for (_, id) in self.watchpoints.drain()
+		{
+			m.memory_mut().remove_watchpoint(id);
+		}
+	}
+
+	/* Stop reply for a watchpoint hit, e.g. 'T05watch:417;' */
+	fn watch_stop_reply(&self, hit: &WatchHit) -> String
+	{
+		let reason = match self.watchpoints.iter().find(|&(_, id)| *id == hit.id)
+		{
+			Some((&(WatchKind::Write, _, _), _)) => "watch",
+			Some((&(WatchKind::Read, _, _), _)) => "rwatch",
+			_ => "awatch"
+		};
+		format!("T{:02x}{}:{:x};", SIGTRAP, reason, hit.addr)
+	}
+
+	pub fn handle_packet(&mut self, m: &mut Machine, packet: &str) -> Action
+	{
+		let (command, args) = match packet.chars().next()
+		{
+			Some(command) => (command, &packet[command.len_utf8() ..]),
+			None => return Action::Reply(String::new())
+		};
+
+		match command
+		{
+			'?' => Action::Reply(format!("S{:02x}", SIGTRAP)),
+			'g' => Action::Reply((0 .. REGISTER_COUNT).map(|idx| encode_u32(read_register(m.cpu(), idx))).collect()),
+			'G' =>
+			{
+				let values: Option<Vec<u32>> = (0 .. REGISTER_COUNT).map(|idx| args.get(idx * 8 .. idx * 8 + 8).and_then(decode_u32)).collect();
+				match values
+				{
+					Some(values) =>
+					{
+						/* Segments first, as eip depends on CS */
+						for idx in (0 .. REGISTER_COUNT).rev()
+						{
+							write_register(m.cpu_mut(), idx, values[idx]);
+						}
+						ok()
+					}
+					None => error(1)
+				}
+			}
+			'p' => match usize::from_str_radix(args, 16)
+			{
+				Ok(idx) if idx < REGISTER_COUNT => Action::Reply(encode_u32(read_register(m.cpu(), idx))),
+				_ => error(1)
+			},
+			'P' =>
+			{
+				let mut parts = args.splitn(2, '=');
+				match (parts.next().map(|idx| usize::from_str_radix(idx, 16)), parts.next().and_then(decode_u32))
+				{
+					(Some(Ok(idx)), Some(val)) if idx < REGISTER_COUNT =>
+					{
+						write_register(m.cpu_mut(), idx, val);
+						ok()
+					}
+					_ => error(1)
+				}
+			}
+			'm' => match parse_range(args)
+			{
+				Some((addr, len)) => Action::Reply((0 .. len).map(|offset| format!("{:02x}", m.memory().read_u8(addr.wrapping_add(offset)))).collect()),
+				None => error(1)
+			},
+			'M' =>
+			{
+				let mut parts = args.splitn(2, ':');
+				match (parts.next().and_then(parse_range), parts.next().and_then(decode_hex_bytes))
+				{
+					(Some((addr, len)), Some(ref bytes)) if bytes.len() == len as usize =>
+					{
+						for (offset, byte) in bytes.iter().enumerate()
+						{
+							m.memory_mut().write_u8(addr.wrapping_add(offset as u32), *byte);
+						}
+						ok()
+					}
+					_ => error(1)
+				}
+			}
+			's' | 'c' =>
+			{
+				/* Optional resume address */
+				if let Ok(addr) = u32::from_str_radix(args, 16)
+				{
+					write_register(m.cpu_mut(), EIP, addr);
+				}
+				Action::Resume { step: command == 's' }
+			}
+			'Z' | 'z' =>
+			{
+				/* 'Z<type>,addr,kind': software (0) and hardware (1)
+				 * breakpoints, write (2), read (3) and access (4) watchpoints,
+				 * kind being the length of the watched range */
+				let mut parts = args.split(',');
+				let hex = |part: Option<&str>| part.and_then(|val| u32::from_str_radix(val, 16).ok());
+				match (parts.next(), hex(parts.next()), hex(parts.next()))
+				{
+					(Some("0"), Some(addr), _) | (Some("1"), Some(addr), _) =>
+					{
+						if command == 'Z'
+						{
+							self.breakpoints.insert(addr);
+						}
+						else
+						{
+							self.breakpoints.remove(&addr);
+						}
+						ok()
+					}
+					/* The range must end within the address space */
+					(Some(kind), Some(addr), Some(len)) if len > 0 && addr.checked_add(len - 1).is_some() && watch_kind(kind).is_some() =>
+					{
+						let key = (watch_kind(kind).unwrap(), addr, len);
+						if command == 'Z'
+						{
+							self.add_watchpoint(m, key);
+						}
+						else if let Some(id) = self.watchpoints.remove(&key)
+						{
+							m.memory_mut().remove_watchpoint(id);
+						}
+						ok()
+					}
+					(Some("0"), _, _) | (Some("1"), _, _) | (Some("2"), _, _) | (Some("3"), _, _) | (Some("4"), _, _) => error(1),
+					_ => Action::Reply(String::new())
+				}
+			}
+			'H' | 'T' => ok(),
+			'q' =>
+			{
+				if packet.starts_with("qSupported")
+				{
+					Action::Reply("PacketSize=1000".to_string())
+				}
+				else if packet == "qAttached"
+				{
+					Action::Reply("1".to_string())
+				}
+				else if packet == "qC"
+				{
+					Action::Reply("QC1".to_string())
+				}
+				else
+				{
+					Action::Reply(String::new())
+				}
+			}
+			'D' => Action::Detach,
+			'k' => Action::Kill,
+			_ => Action::Reply(String::new())
+		}
+	}
+
+	/* Runs until a breakpoint, a watchpoint, an interrupt request or a crash;
+	 * returns the stop reply */
+	fn run(&self, m: &mut Machine, conn: &mut Connection, step: bool) -> io::Result<String>
+	{
+		m.resume(false);
+		conn.set_running(true)?;
+
+		let mut count = 0;
+		let reply = loop
+		{
+			m.step();
+			if let Some(hit) = m.memory_mut().take_watch_hits().first()
+			{
+				break self.watch_stop_reply(hit)
+			}
+			if m.crashed()
+			{
+				break format!("S{:02x}", SIGILL)
+			}
+			if step || !m.is_running()
+			{
+				break format!("S{:02x}", SIGTRAP)
+			}
+			let ip = m.get_pc().1;
+			if self.breakpoints.contains(&m.cpu().code_address(ip))
+			{
+				break format!("S{:02x}", SIGTRAP)
+			}
+
+			count += 1;
+			if count % INTERRUPT_CHECK_PERIOD == 0 && conn.interrupt_requested()?
+			{
+				break format!("S{:02x}", SIGINT)
+			}
+		};
+
+		m.pause();
+		conn.set_running(false)?;
+		Ok(reply)
+	}
+
+	fn serve(&mut self, m: &mut Machine, conn: &mut Connection) -> io::Result<()>
+	{
+		loop
+		{
+			match conn.receive()?
+			{
+				/* Keeps the window alive */
+				None => m.step(),
+				Some(Incoming::Interrupt) => conn.send(&format!("S{:02x}", SIGINT))?,
+				Some(Incoming::Corrupted) => conn.send_raw(b"-")?,
+				Some(Incoming::Packet(packet)) =>
+				{
+					conn.send_raw(b"+")?;
+					match self.handle_packet(m, &packet)
+					{
+						Action::Reply(reply) => conn.send(&reply)?,
+						Action::Resume { step } =>
+						{
+							let reply = self.run(m, conn, step)?;
+							conn.send(&reply)?;
+						}
+						Action::Detach =>
+						{
+							conn.send("OK")?;
+							debug_print!("GDB detached.");
+							return Ok(())
+						}
+						Action::Kill =>
+						{
+							m.hw.close();
+							::std::process::exit(0)
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+struct Connection
+{
+	stream: TcpStream,
+	received: Vec<u8>
+}
+
+impl Connection
+{
+	fn new(stream: TcpStream) -> io::Result<Connection>
+	{
+		stream.set_nodelay(true)?;
+		let conn = Connection { stream: stream, received: Vec::new() };
+		conn.set_running(false)?;
+		Ok(conn)
+	}
+
+	/* Reads are non-blocking while the machine runs, and time out while
+	 * waiting for a packet */
+	fn set_running(&self, running: bool) -> io::Result<()>
+	{
+		self.stream.set_nonblocking(running)?;
+		self.stream.set_read_timeout(Some(Duration::from_millis(IDLE_PERIOD_MS)))
+	}
+
+	/* Returns false if nothing came */
+	fn fill(&mut self) -> io::Result<bool>
+	{
+		let mut buf = [0; 4096];
+		match self.stream.read(&mut buf)
+		{
+			Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+			Ok(len) =>
+			{
+				self.received.extend_from_slice(&buf[.. len]);
+				Ok(true)
+			}
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => Ok(false),
+			Err(e) => Err(e)
+		}
+	}
+
+	fn receive(&mut self) -> io::Result<Option<Incoming>>
+	{
+		if let Some(incoming) = parse_incoming(&mut self.received)
+		{
+			return Ok(Some(incoming))
+		}
+		self.fill()?;
+		Ok(parse_incoming(&mut self.received))
+	}
+
+	/* Anything else received meanwhile is kept for later */
+	fn interrupt_requested(&mut self) -> io::Result<bool>
+	{
+		self.fill()?;
+		match self.received.iter().position(|&byte| byte == 0x03)
+		{
+			Some(idx) =>
+			{
+				self.received.remove(idx);
+				Ok(true)
+			}
+			None => Ok(false)
+		}
+	}
+
+	fn send_raw(&mut self, data: &[u8]) -> io::Result<()>
+	{
+		self.stream.write_all(data)
+	}
+
+	fn send(&mut self, packet: &str) -> io::Result<()>
+	{
+		self.send_raw(&frame(packet))
+	}
+}
+
+/* Waits for GDB to connect, then serves it until it detaches or disconnects;
+ * the machine is paused meanwhile, except when GDB resumes it */
+pub fn serve(m: &mut Machine, port: u16)
+{
+	let listener = match TcpListener::bind(("127.0.0.1", port))
+	{
+		Ok(listener) => listener,
+		Err(e) => panic!("Unable to listen on port {}: {}", port, e)
+	};
+	debug_print!("Waiting for GDB on localhost:{}...", port);
+	let stream = match listener.accept()
+	{
+		Ok((stream, _)) => stream,
+		Err(e) => panic!("Unable to accept GDB connection: {}", e)
+	};
+	debug_print!("GDB connected.");
+
+	m.pause();
+	let mut stub = GdbStub::new();
+	if let Err(e) = Connection::new(stream).and_then(|mut conn| stub.serve(m, &mut conn))
+	{
+		debug_print!("GDB connection lost: {}", e);
+	}
+	stub.remove_watchpoints(m);
+}
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::gdb::*;
+	use super::super::bios::BootDrive;
+	use super::super::cpu::*;
+	use super::super::frontend::headless::HeadlessFrontend;
+	use super::super::hw::InputSource;
+	use super::super::machine::{Machine, Speed};
+
+	fn machine() -> Machine
+	{
+		Machine::new(BootDrive::Floppy, None, None, None, CpuModel::I8086, false, Speed::Unbounded,
+			InputSource::Host, Box::new(HeadlessFrontend::new()))
+	}
+
+	/* In protected mode at 08:0100, CS being based at 0x10000 */
+	fn protected_machine() -> Machine
+	{
+		let mut m = Machine::new(BootDrive::Floppy, None, None, None, CpuModel::I80286, false, Speed::Unbounded,
+			InputSource::Host, Box::new(HeadlessFrontend::new()));
+		{
+			let (cpu, mem) = m.cpu_and_memory_mut();
+			mem.write_u16(0x1008, 0xffff);
+			mem.write_u16(0x100a, 0x0000);
+			mem.write_u8(0x100c, 0x01);
+			mem.write_u8(0x100d, 0x9a);
+			mem.write_u16(0x500, 0x0f);
+			mem.write_u16(0x502, 0x1000);
+			cpu.ds = 0x0000;
+			cpu.run_system_ins(mem, SystemOpCode::LGDT, WOperand::Direct(0x500));
+			cpu.ax = 0x0001;
+			cpu.run_system_ins(mem, SystemOpCode::LMSW, WOperand::Reg(WReg::AX));
+			cpu.protected_far_transfer(mem, 0x08, 0x0100, false);
+		}
+		m
+	}
+
+	fn reply(stub: &mut GdbStub, m: &mut Machine, packet: &str) -> String
+	{
+		match stub.handle_packet(m, packet)
+		{
+			Action::Reply(reply) => reply,
+			action => panic!("Unexpected action {:?}", action)
+		}
+	}
+
+	#[test]
+	fn framing()
+	{
+		assert_eq!(frame("OK"), b"$OK#9a".to_vec());
+
+		let mut buffer = b"+$g#67\x03$m0,2#00$qC".to_vec();
+		assert_eq!(parse_incoming(&mut buffer), Some(Incoming::Packet("g".to_string())));
+		assert_eq!(parse_incoming(&mut buffer), Some(Incoming::Interrupt));
+		assert_eq!(parse_incoming(&mut buffer), Some(Incoming::Corrupted));
+		/* Incomplete */
+		assert_eq!(parse_incoming(&mut buffer), None);
+		buffer.extend_from_slice(b"#b4");
+		assert_eq!(parse_incoming(&mut buffer), Some(Incoming::Packet("qC".to_string())));
+		assert!(buffer.is_empty());
+	}
+
+	#[test]
+	fn registers()
+	{
+		let mut m = machine();
+		let mut stub = GdbStub::new();
+
+		/* Reset vector: eip is the linear address of F000:FFF0 */
+		let regs = reply(&mut stub, &mut m, "g");
+		assert_eq!(regs.len(), 16 * 8);
+		assert_eq!(&regs[8 * 8 .. 9 * 8], "f0ff0f00");
+		assert_eq!(&regs[10 * 8 .. 11 * 8], "00f00000");
+
+		assert_eq!(reply(&mut stub, &mut m, "P0=34120000"), "OK");
+		assert_eq!(m.cpu().ax, 0x1234);
+		/* Within the code segment, CS is kept */
+		assert_eq!(reply(&mut stub, &mut m, "P8=00f10f00"), "OK");
+		assert_eq!(m.get_pc(), (0xf000, 0xf100));
+		assert_eq!(reply(&mut stub, &mut m, "P8=007c0000"), "OK");
+		assert_eq!(m.get_pc(), (0x07c0, 0x0000));
+		assert_eq!(reply(&mut stub, &mut m, "p8"), "007c0000");
+		assert_eq!(reply(&mut stub, &mut m, "p10"), "E01");
+	}
+
+	#[test]
+	fn protected_mode_registers()
+	{
+		let mut m = protected_machine();
+		let mut stub = GdbStub::new();
+
+		/* eip is the linear address through the CS descriptor */
+		let regs = reply(&mut stub, &mut m, "g");
+		assert_eq!(&regs[8 * 8 .. 9 * 8], "00010100");
+		assert_eq!(&regs[10 * 8 .. 11 * 8], "08000000");
+		assert_eq!(reply(&mut stub, &mut m, "P8=00020100"), "OK");
+		assert_eq!(m.get_pc(), (0x08, 0x0200));
+		/* Outside of the segment */
+		assert_eq!(reply(&mut stub, &mut m, "P8=007c0000"), "OK");
+		assert_eq!(m.get_pc(), (0x08, 0x0200));
+	}
+
+	#[test]
+	fn memory()
+	{
+		let mut m = machine();
+		let mut stub = GdbStub::new();
+
+		assert_eq!(reply(&mut stub, &mut m, "M7c00,3:eb3c90"), "OK");
+		assert_eq!(reply(&mut stub, &mut m, "m7bff,5"), "00eb3c9000");
+		assert_eq!(m.memory().read_u16(0x7c00), 0x3ceb);
+		assert_eq!(reply(&mut stub, &mut m, "M7c00,2:eb"), "E01");
+	}
+
+	#[test]
+	fn control()
+	{
+		let mut m = machine();
+		let mut stub = GdbStub::new();
+
+		assert_eq!(reply(&mut stub, &mut m, "Z0,7c00,1"), "OK");
+		assert_eq!(reply(&mut stub, &mut m, "z1,7c00,1"), "OK");
+		assert_eq!(reply(&mut stub, &mut m, "Z9,7c00,1"), "");
+		assert_eq!(stub.handle_packet(&mut m, "s"), Action::Resume { step: true });
+		assert_eq!(stub.handle_packet(&mut m, "c7c00"), Action::Resume { step: false });
+		assert_eq!(m.get_pc(), (0x07c0, 0x0000));
+		assert_eq!(stub.handle_packet(&mut m, "D"), Action::Detach);
+		assert_eq!(reply(&mut stub, &mut m, "vMustReplyEmpty"), "");
+	}
+
+	#[test]
+	fn watchpoints()
+	{
+		let mut m = machine();
+		let mut stub = GdbStub::new();
+
+		assert_eq!(reply(&mut stub, &mut m, "Z2,400,100"), "OK");
+		assert_eq!(reply(&mut stub, &mut m, "Z4,7c00,2"), "OK");
+		assert_eq!(reply(&mut stub, &mut m, "Z3,7c00,0"), "E01");
+		assert_eq!(reply(&mut stub, &mut m, "Z2,ffffffff,4"), "E01");
+		assert_eq!(m.memory().watchpoints().len(), 2);
+		assert_eq!(reply(&mut stub, &mut m, "z2,400,100"), "OK");
+		assert_eq!(m.memory().watchpoints().len(), 1);
+		assert_eq!((m.memory().watchpoints()[0].start, m.memory().watchpoints()[0].end), (0x7c00, 0x7c01));
+
+		stub.remove_watchpoints(&mut m);
+		assert!(m.memory().watchpoints().is_empty());
+	}
+}
@@ -0,0 +1,102 @@
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+/* Emulates the battery-backed RAM of the PC/AT real-time clock (MC146818),
+ * selected through port 0x70 and accessed through port 0x71. The clock
+ * itself is not emulated: the BIOS answers the time services */
+
+const CMOS_SIZE: usize = 64;
+/* Bit 7 of the index disables the NMI */
+const INDEX_MASK: u8 = 0x3f;
+
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_D: u8 = 0x0d;
+/* Tells the BIOS why the CPU was reset; see bios.rs */
+pub const REG_SHUTDOWN: u8 = 0x0f;
+const REG_BASE_MEMORY: u8 = 0x15;
+const REG_EXTENDED_MEMORY: u8 = 0x17;
+/* As found by the POST */
+const REG_ACTUAL_EXTENDED_MEMORY: u8 = 0x30;
+
+/* 24-hour mode; battery good */
+const STATUS_B_24H: u8 = 0x02;
+const STATUS_D_VALID_RAM: u8 = 0x80;
+
+pub struct Cmos
+{
+	index: u8,
+	ram: [u8; CMOS_SIZE]
+}
+
+impl Cmos
+{
+	/* 'extended_kb' is the memory above 1 MB, in KB */
+	pub fn new(extended_kb: u16) -> Cmos
+	{
+		let mut cmos = Cmos
+		{
+			index: 0,
+			ram: [0; CMOS_SIZE]
+		};
+		cmos.ram[REG_STATUS_B as usize] = STATUS_B_24H;
+		cmos.ram[REG_STATUS_D as usize] = STATUS_D_VALID_RAM;
+		cmos.write_u16(REG_BASE_MEMORY, 640);
+		cmos.write_u16(REG_EXTENDED_MEMORY, extended_kb);
+		cmos.write_u16(REG_ACTUAL_EXTENDED_MEMORY, extended_kb);
+		cmos
+	}
+
+	fn write_u16(&mut self, reg: u8, value: u16)
+	{
+		self.ram[reg as usize] = value as u8;
+		self.ram[reg as usize + 1] = (value >> 8) as u8;
+	}
+
+	pub fn write_index(&mut self, index: u8)
+	{
+		self.index = index & INDEX_MASK;
+	}
+
+	pub fn read_data(&self) -> u8
+	{
+		self.read(self.index)
+	}
+
+	pub fn write_data(&mut self, value: u8)
+	{
+		let index = self.index;
+		self.write(index, value);
+	}
+
+	pub fn read(&self, reg: u8) -> u8
+	{
+		self.ram[(reg & INDEX_MASK) as usize]
+	}
+
+	pub fn write(&mut self, reg: u8, value: u8)
+	{
+		/* Status register D is read-only */
+		if reg != REG_STATUS_D
+		{
+			self.ram[(reg & INDEX_MASK) as usize] = value;
+		}
+	}
+}
+
+impl Snapshot for Cmos
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"CMOS");
+		writer.write_u8(self.index);
+		writer.write_bytes(&self.ram);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"CMOS")?;
+		self.index = reader.read_u8()?;
+		let ram = reader.read_bytes(CMOS_SIZE)?;
+		self.ram.copy_from_slice(&ram);
+		Ok(())
+	}
+}
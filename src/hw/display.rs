@@ -1,9 +1,8 @@
-extern crate sdl2;
-
-use std::path::Path;
 use super::super::mem::Memory;
 use super::super::bios::ByteBdaEntry;
 use super::super::bios::WordBdaEntry;
+use super::super::frontend::{Frontend, Framebuffer};
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 // 60 FPS
 const SYNC_PERIOD_NS: u64 = 16666667;
@@ -11,25 +10,28 @@ const SYNC_PERIOD_NS: u64 = 16666667;
 /* Emulates a CGA graphics adapter */
 const VRAM_ADDR: u32 = 0xb8000;
 
-use super::sdl2::pixels::Color;
-const CGA_PALETTE: [Color;16] =
+/* Text and graphics modes are both shown as 640x400 frames */
+pub const FRAME_WIDTH: u32 = 640;
+pub const FRAME_HEIGHT: u32 = 400;
+
+const CGA_PALETTE: [u32;16] =
 [
-	Color::RGB(0,0,0), // black
-	Color::RGB(0,0,0xaa), // blue
-	Color::RGB(0,0xaa,0), // green
-	Color::RGB(0,0xaa,0xaa), // cyan
-	Color::RGB(0xaa,0,0), // red
-	Color::RGB(0xaa,0,0xaa), // magenta
-	Color::RGB(0xaa,0x55,0), // brown
-	Color::RGB(0xaa,0xaa,0xaa), // grey
-	Color::RGB(0x55,0x55,0x55), // light grey
-	Color::RGB(0x55,0x55,0xff), // light blue
-	Color::RGB(0x55,0xff,0x55), // light green
-	Color::RGB(0x55,0xff,0xff), // light cyan
-	Color::RGB(0xff,0x55,0x55), // light red
-	Color::RGB(0xff,0x55,0xff), // light magenta
-	Color::RGB(0xff,0xff,0x55), // yellow
-	Color::RGB(0xff,0xff,0xff), // white
+	0x000000, // black
+	0x0000aa, // blue
+	0x00aa00, // green
+	0x00aaaa, // cyan
+	0xaa0000, // red
+	0xaa00aa, // magenta
+	0xaa5500, // brown
+	0xaaaaaa, // grey
+	0x555555, // light grey
+	0x5555ff, // light blue
+	0x55ff55, // light green
+	0x55ffff, // light cyan
+	0xff5555, // light red
+	0xff55ff, // light magenta
+	0xffff55, // yellow
+	0xffffff, // white
 ];
 
 #[derive(Clone,Copy)]
@@ -120,10 +122,10 @@ const VRAM_USED_INTERVAL: u32 = 1;
 pub struct Display
 {
 	mode: GraphicMode,
-	window: sdl2::video::Window,
+	frame: Framebuffer,
 
-	font: sdl2::surface::Surface<'static>,
-	horrible_bw_surface_table: sdl2::surface::Surface<'static>,
+	/* 8 bytes per character, one per row, most significant bit on the left */
+	font: Vec<u8>,
 	cursors: [Cursor; 8],
 	num_cols: WordBdaEntry,
 	cur_page: u8,
@@ -134,21 +136,15 @@ pub struct Display
 
 impl Display
 {
-	pub fn new(sdl: &sdl2::Sdl) -> Display
+	pub fn new() -> Display
 	{
-		let window = Display::create_window(&sdl.video().unwrap(), 640, 400);
-		let mut font = sdl2::surface::Surface::load_bmp(Path::new("cga_font.bmp")).unwrap();
-		let horrible_bw_surface_table = sdl2::surface::Surface::load_bmp(Path::new("horrible_bw_surface_table.bmp")).unwrap();
 		let cur = |x: u8| -> Cursor { Cursor::new(x) };
 
-		font.set_color_key(true, sdl2::pixels::Color::RGB(0,0,0)).unwrap();
-
 		Display
 		{
 			mode: GraphicMode::T8025,
-			window: window,
-			font: font,
-			horrible_bw_surface_table: horrible_bw_surface_table,
+			frame: Framebuffer::new(FRAME_WIDTH, FRAME_HEIGHT),
+			font: Display::load_font("cga_font.bmp"),
 			cursors: [cur(0x50), cur(0x52), cur(0x54), cur(0x56), cur(0x58), cur(0x5a), cur(0x5c), cur(0x5e)],
 			num_cols: WordBdaEntry::new(0x4a),
 			cur_page: 0, // TODO: should be BdaEntry
@@ -157,6 +153,32 @@ impl Display
 		}
 	}
 
+	/* The font image holds 32x8 characters of 8x8 pixels, white on black */
+	fn load_font(filename: &str) -> Vec<u8>
+	{
+		let image = match Framebuffer::load_bmp(filename)
+		{
+			Ok(image) => image,
+			Err(e) => panic!("Unable to load the font: {}", e)
+		};
+
+		let mut font = vec![0; 256 * 8];
+		for chr in 0 .. 256u32
+		{
+			for row in 0 .. 8
+			{
+				for col in 0 .. 8
+				{
+					if image.pixel((chr % 32) * 8 + col, (chr / 32) * 8 + row) != 0
+					{
+						font[(chr * 8 + row) as usize] |= 0x80 >> col;
+					}
+				}
+			}
+		}
+		font
+	}
+
 	fn page_addr(&self, page: u8) -> u32
 	{
 		VRAM_ADDR + self.mode.page_size() * (page as u32)
@@ -172,16 +194,14 @@ impl Display
 		self.chr_addr(page, self.cursors[page as usize].x.get(mem), self.cursors[page as usize].y.get(mem))
 	}
 
-	fn create_window(video: &sdl2::VideoSubsystem, width: u32, height: u32) -> sdl2::video::Window
-	{
-		let builder = sdl2::video::WindowBuilder::new(&video, "Riapyx", width, height);
-		builder.build().unwrap()
-	}
-
-	pub fn render(&mut self, event_pump: &sdl2::EventPump, mem: &mut Memory, clock: u32, time_ns: u64)
+	pub fn render(&mut self, frontend: &mut dyn Frontend, mem: &mut Memory, clock: u32, time_ns: u64)
 	{
 		// Only update once every 10k clock cycles to avoid artifacts when removing a character...
 		// TODO: have tty output set the dirty flag to avoid this
+		if !frontend.shows_frames()
+		{
+			return
+		}
 		if time_ns < self.last_sync_ns + SYNC_PERIOD_NS || (cfg!(feature="vram-dirty") && !(mem.is_vram_dirty() && clock % 10000 == 0))
 		{
 			return
@@ -190,57 +210,84 @@ impl Display
 		self.last_sync_ns = time_ns;
 		mem.clear_vram_dirty();
 		// display_print!("Sync");
+		frontend.present_frame(self.draw(mem));
+	}
+
+	/* Refreshes the screen at the next opportunity */
+	pub fn reset_sync(&mut self)
+	{
+		self.last_sync_ns = 0;
+	}
+
+	/* Saves the current screen content as a BMP file */
+	pub fn save_screenshot(&mut self, mem: &Memory, filename: &str)
+	{
+		if let Err(e) = self.draw(mem).save_bmp(filename)
+		{
+			panic!("Unable to save screenshot; error {}", e)
+		}
+	}
+
+	/* Draws the current page into the frame */
+	pub fn draw(&mut self, mem: &Memory) -> &Framebuffer
+	{
+		let page_addr = self.page_addr(self.cur_page);
+		let mode = self.mode;
 
-		match self.mode
+		match mode
 		{
 			GraphicMode::T8025 => 
 			{
-				/* Display screen content */
+				/* Display screen content; characters are drawn twice as high */
 				for y in 0 .. 25
 				{
 					for x in 0 .. 80
 					{
-						let addr = self.chr_addr(self.cur_page, x, y);
+						let addr = page_addr + (y * (mode.cols() as u32) + x) * 2;
 						let chr = mem.read_u8(addr);
 						let attr = mem.read_u8(addr + 1);
 
-						let src_rect = sdl2::rect::Rect::new(((chr % 32) as i32) * 8, ((chr / 32) as i32) * 8, 8, 8);
-						let dst_rect = sdl2::rect::Rect::new(x as i32 * 8, y as i32 * 16, 8, 16);
-
 						let text_color = CGA_PALETTE[(attr & 0x0f) as usize];
 						let back_color = CGA_PALETTE[((attr >> 4) & 0x7) as usize]; // Last bit is blink/not blink
 
-						self.window.surface(event_pump).unwrap().fill_rect(dst_rect, back_color).unwrap();
-						self.font.set_color_mod(text_color);
-
-						self.font.blit_scaled(src_rect, &mut self.window.surface(event_pump).unwrap(), dst_rect).unwrap();
+						for row in 0 .. 16
+						{
+							let bits = self.font[chr as usize * 8 + row / 2];
+							for col in 0 .. 8
+							{
+								let color = if bits & (0x80 >> col) != 0 { text_color } else { back_color };
+								self.frame.set_pixel(x * 8 + col, y * 16 + row as u32, color);
+							}
+						}
 					}
 				}
 
 				/* Display cursor */
 				let (x, y) = self.tty_coords(mem, self.cur_page);
-				let cur_rect = sdl2::rect::Rect::new(x as i32 * 8, y as i32 * 16 + 14, 8, 2);
-				self.window.surface(event_pump).unwrap().fill_rect(cur_rect, CGA_PALETTE[7]).unwrap();
+				self.frame.fill_rect(x as u32 * 8, y as u32 * 16 + 14, 8, 2, CGA_PALETTE[7]);
 			}
 			GraphicMode::G640200 =>
 			{
+				/* Even lines, then odd lines, 8 pixels per byte; lines are
+				 * drawn twice */
 				for y in 0 .. 200
 				{
 					for x in 0 .. (640 / 8)
 					{
-						let addr = self.page_addr(self.cur_page) + (y%2) * 8192 + (y / 2) * 80 + x;
+						let addr = page_addr + (y%2) * 8192 + (y / 2) * 80 + x;
 						let block = mem.read_u8(addr);
-						
-						let src_rect = sdl2::rect::Rect::new(0, block as i32, 8, 1);
-						let dst_rect = sdl2::rect::Rect::new(x as i32 * 8, (y as i32) * 2, 8, 2);
 
-						self.horrible_bw_surface_table.blit_scaled(src_rect, &mut self.window.surface(event_pump).unwrap(), dst_rect).unwrap();
+						for col in 0 .. 8
+						{
+							let color = if block & (0x80 >> col) != 0 { CGA_PALETTE[15] } else { CGA_PALETTE[0] };
+							self.frame.set_pixel(x * 8 + col, y * 2, color);
+							self.frame.set_pixel(x * 8 + col, y * 2 + 1, color);
+						}
 					}
 				}
 			}
 		}
-
-        self.window.surface(event_pump).unwrap().update_window();
+		&self.frame
 	}
 
 	pub fn set_mode(&mut self, mem: &mut Memory, mode: GraphicMode)
@@ -379,3 +426,34 @@ impl Display
 		status_reg
 	}
 }
+
+/* Cursors and the column count live in the BDA, hence in the memory snapshot */
+impl Snapshot for Display
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"CGA ");
+		writer.write_u8(self.mode.to_bios());
+		writer.write_u8(self.cur_page);
+		writer.write_u64(self.last_sync_ns);
+		writer.write_u32(self.count_until_vram_used);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"CGA ")?;
+		self.mode = match reader.read_u8()?
+		{
+			mode @ 0x3 | mode @ 0x6 => GraphicMode::from_bios(mode),
+			mode => return Err(format!("unsupported video mode {}", mode))
+		};
+		self.cur_page = reader.read_u8()?;
+		if self.cur_page >= self.mode.page_count()
+		{
+			return Err(format!("invalid video page {}", self.cur_page))
+		}
+		self.last_sync_ns = reader.read_u64()?;
+		self.count_until_vram_used = reader.read_u32()?;
+		Ok(())
+	}
+}
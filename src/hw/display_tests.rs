@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::display::*;
+	use super::super::super::mem::Memory;
+
+	#[test]
+	fn text_mode()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut display = Display::new();
+		mem.write_u16(0xb8000, 0x1f41); // White 'A' on blue
+
+		let frame = display.draw(&mem);
+		assert_eq!((frame.width, frame.height), (FRAME_WIDTH, FRAME_HEIGHT));
+		/* Top rows of 'A' are ...##..., doubled */
+		for y in 0 .. 2
+		{
+			assert_eq!(frame.pixel(2, y), 0x0000aa);
+			assert_eq!(frame.pixel(3, y), 0xffffff);
+		}
+		/* Cursor at 0,0 */
+		assert_eq!(frame.pixel(0, 15), 0xaaaaaa);
+		assert_eq!(frame.pixel(8, 0), 0x000000);
+	}
+
+	#[test]
+	fn graphics_mode()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		let mut display = Display::new();
+		display.set_mode(&mut mem, GraphicMode::G640200);
+		mem.write_u8(0xb8000, 0x80); // Line 0
+		mem.write_u8(0xba000, 0x01); // Line 1
+
+		let frame = display.draw(&mem);
+		assert_eq!(frame.pixel(0, 1), 0xffffff);
+		assert_eq!(frame.pixel(1, 1), 0x000000);
+		assert_eq!(frame.pixel(7, 2), 0xffffff);
+		assert_eq!(frame.pixel(7, 3), 0xffffff);
+		assert_eq!(frame.pixel(0, 2), 0x000000);
+	}
+}
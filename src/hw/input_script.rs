@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Read;
+
+use super::scancodes::scancode_from_name;
+use super::super::frontend::{InputEvent, MouseButton};
+
+/* Input events replayed in deterministic mode instead of the host ones.
+ * Each line of a script is '<cycle> <event> [arguments]', <cycle> being the
+ * amount of emulated CPU cycles since power on at which the event happens:
+ *   key-down <SDL scancode name>    e.g. '9545454 key-down Left Shift'
+ *   key-up <SDL scancode name>
+ *   mouse-move <dx> <dy>
+ *   mouse-down left|right
+ *   mouse-up left|right
+ * Events must be in chronological order. Empty lines and lines starting with
+ * '#' are ignored. */
+
+pub struct InputScript
+{
+	events: VecDeque<(u64, InputEvent)>
+}
+
+impl InputScript
+{
+	/* No input at all */
+	pub fn empty() -> InputScript
+	{
+		InputScript { events: VecDeque::new() }
+	}
+
+	pub fn load(filename: &str) -> InputScript
+	{
+		let mut script = String::new();
+		if let Err(e) = File::open(filename).and_then(|mut file| file.read_to_string(&mut script))
+		{
+			panic!("Unable to read input script '{}': {}", filename, e)
+		}
+		InputScript::parse(&script)
+	}
+
+	pub fn parse(script: &str) -> InputScript
+	{
+		let mut events = VecDeque::new();
+		let mut last_cycle = 0;
+
+		for (idx, line) in script.lines().enumerate()
+		{
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#')
+			{
+				continue
+			}
+
+			match parse_line(line)
+			{
+				Ok((cycle, event)) =>
+				{
+					if cycle < last_cycle
+					{
+						panic!("Input script, line {}: events are not in chronological order", idx + 1)
+					}
+					last_cycle = cycle;
+					events.push_back((cycle, event));
+				}
+				Err(e) => panic!("Input script, line {}: {}", idx + 1, e)
+			}
+		}
+
+		InputScript { events: events }
+	}
+
+	/* Drops the events which were already played after 'cycles' emulated CPU
+	 * cycles, e.g. when restoring a snapshot taken at that point */
+	pub fn skip_until(&mut self, cycles: u64)
+	{
+		while let Some(_) = self.next_due(cycles) {}
+	}
+
+	/* Pops the next event if it is due after 'cycles' emulated CPU cycles */
+	pub fn next_due(&mut self, cycles: u64) -> Option<InputEvent>
+	{
+		match self.events.front()
+		{
+			Some(&(at, _)) if at <= cycles => {}
+			_ => return None
+		}
+		self.events.pop_front().map(|(_, event)| event)
+	}
+}
+
+fn parse_line(line: &str) -> Result<(u64, InputEvent), String>
+{
+	let mut words = line.splitn(2, char::is_whitespace);
+	let cycle_str = words.next().unwrap();
+	let cycle = match cycle_str.parse::<u64>()
+	{
+		Ok(cycle) => cycle,
+		Err(_) => return Err(format!("invalid cycle count '{}'", cycle_str))
+	};
+
+	let mut words = words.next().unwrap_or("").trim_start().splitn(2, char::is_whitespace);
+	let name = words.next().unwrap();
+	let args = words.next().unwrap_or("").trim();
+
+	let event = match name
+	{
+		"key-down" => InputEvent::KeyDown(parse_scancode(args)?),
+		"key-up" => InputEvent::KeyUp(parse_scancode(args)?),
+		"mouse-move" =>
+		{
+			let mut coords = args.split_whitespace().map(|x| x.parse::<i32>());
+			match (coords.next(), coords.next(), coords.next())
+			{
+				(Some(Ok(dx)), Some(Ok(dy)), None) => InputEvent::MouseMotion(dx, dy),
+				_ => return Err(format!("invalid mouse motion '{}'", args))
+			}
+		}
+		"mouse-down" => InputEvent::MouseButtonDown(parse_button(args)?),
+		"mouse-up" => InputEvent::MouseButtonUp(parse_button(args)?),
+		_ => return Err(format!("unknown event '{}'", name))
+	};
+
+	Ok((cycle, event))
+}
+
+fn parse_scancode(name: &str) -> Result<u8, String>
+{
+	match scancode_from_name(name)
+	{
+		Some(scancode) => Ok(scancode),
+		None => Err(format!("unknown key '{}'", name))
+	}
+}
+
+fn parse_button(name: &str) -> Result<MouseButton, String>
+{
+	match name
+	{
+		"left" => Ok(MouseButton::Left),
+		"right" => Ok(MouseButton::Right),
+		_ => Err(format!("unknown mouse button '{}'", name))
+	}
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::input_script::*;
+	use super::super::super::frontend::{InputEvent, MouseButton};
+
+	#[test]
+	fn events_are_due_in_order()
+	{
+		let mut script = InputScript::parse(
+			"# Comment\n\
+			 \n\
+			 100 mouse-move 10 -5\n\
+			 100 mouse-down left\n\
+			 250 mouse-up  right\n");
+
+		assert_eq!(script.next_due(99), None);
+		assert_eq!(script.next_due(100), Some(InputEvent::MouseMotion(10, -5)));
+		assert_eq!(script.next_due(100), Some(InputEvent::MouseButtonDown(MouseButton::Left)));
+		assert_eq!(script.next_due(100), None);
+		assert_eq!(script.next_due(1000), Some(InputEvent::MouseButtonUp(MouseButton::Right)));
+		assert_eq!(script.next_due(1000), None);
+	}
+
+	#[test]
+	fn key_names()
+	{
+		let mut script = InputScript::parse("10 key-down Left Shift\n10 key-down a\n20 key-up F10\n");
+		assert_eq!(script.next_due(20), Some(InputEvent::KeyDown(0x2a)));
+		assert_eq!(script.next_due(20), Some(InputEvent::KeyDown(0x1e)));
+		assert_eq!(script.next_due(20), Some(InputEvent::KeyUp(0x44)));
+	}
+
+	#[test]
+	#[should_panic(expected = "line 1: unknown key 'Left Windows'")]
+	fn unknown_key()
+	{
+		InputScript::parse("100 key-down Left Windows\n");
+	}
+
+	#[test]
+	#[should_panic(expected = "line 2: events are not in chronological order")]
+	fn unordered_events()
+	{
+		InputScript::parse("200 mouse-down left\n100 mouse-up left\n");
+	}
+
+	#[test]
+	#[should_panic(expected = "line 1: invalid mouse motion '10'")]
+	fn invalid_event()
+	{
+		InputScript::parse("100 mouse-move 10\n");
+	}
+}
@@ -1,12 +1,12 @@
-extern crate sdl2;
 extern crate num;
 
 use std::collections::vec_deque::VecDeque;
 
 use super::scancodes::*;
-use super::super::cpu::CPU;
+use super::pic::Pic;
 use super::super::mem::Memory;
 use super::super::bios::ByteBdaEntry;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 #[derive(Clone,Copy)]
 pub struct Keystroke
@@ -36,9 +36,16 @@ pub struct Keyboard
 	io_queue: VecDeque<u8>,
 	ppi_a: u8,
 	enable_irq: bool, // Disabled for BIOS wait_for_keystroke
-	shift_flags: ByteBdaEntry
+	shift_flags: ByteBdaEntry,
+	/* PC/AT keyboard controller command waiting for its data byte */
+	controller_command: Option<u8>
 }
 
+/* Keyboard controller status: the system flag, set once the POST is done,
+ * and whether a scancode can be read */
+const STATUS_OUTPUT_FULL: u8 = 0x1;
+const STATUS_SYSTEM: u8 = 0x4;
+
 impl Keystroke
 {
 	pub fn new(scancode: u8, ascii: u8) -> Keystroke
@@ -57,34 +64,24 @@ impl Keyboard
 			io_queue: VecDeque::<u8>::new(),
 			ppi_a: 0,
 			enable_irq: true,
-			shift_flags: ByteBdaEntry::new(0x17)
+			shift_flags: ByteBdaEntry::new(0x17),
+			controller_command: None
 		}
 	}
 
-	pub fn on_keydown(&mut self, cpu: &mut CPU, sdlk_opt: Option<sdl2::keyboard::Scancode>)
+	/* 'scancode' is an XT (set 1) make code */
+	pub fn on_keydown(&mut self, pic: &mut Pic, scancode: u8)
 	{
-		if let Some(sdlk) = sdlk_opt
-		{
-			if let Some(scancode) = sdlk_to_scancode(sdlk)
-			{
-				keyboard_print!("Pressed: scancode={:x}", scancode);
-				self.io_queue.push_back(scancode);
-				cpu.queue_hw_interrupt(0x9);
-			}
-		}
+		keyboard_print!("Pressed: scancode={:x}", scancode);
+		self.io_queue.push_back(scancode);
+		pic.pulse_irq(1);
 	}
 
-	pub fn on_keyup(&mut self, cpu: &mut CPU, sdlk_opt: Option<sdl2::keyboard::Scancode>)
+	pub fn on_keyup(&mut self, pic: &mut Pic, scancode: u8)
 	{
-		if let Some(sdlk) = sdlk_opt
-		{
-			if let Some(scancode) = sdlk_to_scancode(sdlk)
-			{
-				keyboard_print!("Released: scancode={:x}", scancode);
-				self.io_queue.push_back(scancode | 0x80);
-				cpu.queue_hw_interrupt(0x9);
-			}
-		}
+		keyboard_print!("Released: scancode={:x}", scancode);
+		self.io_queue.push_back(scancode | 0x80);
+		pic.pulse_irq(1);
 	}
 
 	pub fn bios_pump_keystrokes(&mut self, mem: &mut Memory)
@@ -191,4 +188,64 @@ impl Keyboard
 	{
 		self.enable_irq = enabled;
 	}
+
+	pub fn get_controller_status(&self) -> u8
+	{
+		let output_full = if self.io_queue.is_empty() { 0 } else { STATUS_OUTPUT_FULL };
+		STATUS_SYSTEM | output_full
+	}
+
+	pub fn set_controller_command(&mut self, command: u8)
+	{
+		keyboard_print!("Controller command {:02x}", command);
+		self.controller_command = Some(command);
+	}
+
+	/* The command the byte written to port 0x60 is for, if any */
+	pub fn take_controller_command(&mut self) -> Option<u8>
+	{
+		self.controller_command.take()
+	}
+}
+
+/* The shift flags live in the BDA, hence in the memory snapshot */
+impl Snapshot for Keyboard
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"KBD ");
+		writer.write_u32(self.bios_queue.len() as u32);
+		for keystroke in self.bios_queue.iter()
+		{
+			writer.write_u8(keystroke.scancode);
+			writer.write_u8(keystroke.ascii);
+		}
+		let io_queue: Vec<u8> = self.io_queue.iter().cloned().collect();
+		writer.write_blob(&io_queue);
+		writer.write_u8(self.ppi_a);
+		writer.write_bool(self.enable_irq);
+		writer.write_u8(self.controller_command.unwrap_or(0));
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"KBD ")?;
+		let keystroke_count = reader.read_u32()?;
+		self.bios_queue.clear();
+		for _ in 0..keystroke_count
+		{
+			let scancode = reader.read_u8()?;
+			let ascii = reader.read_u8()?;
+			self.bios_queue.push_back(Keystroke::new(scancode, ascii));
+		}
+		self.io_queue = reader.read_blob()?.into_iter().collect();
+		self.ppi_a = reader.read_u8()?;
+		self.enable_irq = reader.read_bool()?;
+		self.controller_command = match reader.read_u8()?
+		{
+			0 => None,
+			command => Some(command)
+		};
+		Ok(())
+	}
 }
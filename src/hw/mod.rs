@@ -1,45 +1,67 @@
-extern crate sdl2;
 use super::mem::Memory;
-use super::cpu::CPU;
+use super::frontend::{Frontend, InputEvent};
+use super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 pub mod storage;
 pub mod keyboard;
 pub mod display;
+mod display_tests;
 pub mod serial;
 pub mod mouse;
+pub mod pic;
+mod pic_tests;
+pub mod pit;
+mod pit_tests;
+pub mod speaker;
+mod speaker_tests;
+pub mod cmos;
+pub mod input_script;
+mod input_script_tests;
 mod scancodes;
 
-// 55 ms
-const TIMER_PERIOD_NS: u64 = 55000000;
 // 1ms
 const EVENT_PUMP_PERIOD_NS: u64 = 1000000;
 
+/* Where keyboard and mouse events come from */
+pub enum InputSource
+{
+	/* Frontend events, as they happen on the host */
+	Host,
+	/* Events replayed at given emulated CPU cycles; host input is ignored so
+	 * that the emulation is deterministic */
+	Script(input_script::InputScript)
+}
+
 pub struct HW
 {
-	sdl: sdl2::Sdl,
-	pub event_pump: sdl2::EventPump,
+	frontend: Box<dyn Frontend>,
 
 	pub floppy: Option<storage::Storage>,
 	pub hdd: Option<storage::Storage>,
 	pub keyboard: keyboard::Keyboard,
 	pub display: display::Display,
 	pub com1: serial::SerialPort<mouse::Mouse>,
-
+	pub pic: pic::Pic,
+	pub pit: pit::Pit,
+	pub speaker: speaker::Speaker,
+	pub cmos: cmos::Cmos,
+	/* NMI mask: port 0xA0 on the PC, bit 7 of the CMOS index on the PC/AT */
+	pub nmi_enabled: bool,
+
+	input: InputSource,
+	/* CPU cycles since power on */
+	cycles: u64,
 	last_event_pump_ns: u64
 }
 
 impl HW
 {
-	pub fn new(floppy_filename: Option<String>, hdd_filename: Option<String>) -> HW
+	/* 'extended_kb' is the memory above 1 MB, in KB, that the CMOS reports */
+	pub fn new(floppy_filename: Option<String>, hdd_filename: Option<String>, wav_filename: Option<String>, extended_kb: u16, input: InputSource, frontend: Box<dyn Frontend>) -> HW
 	{
-		let sdl = sdl2::init().unwrap();
-		let display = display::Display::new(&sdl);
-		sdl.mouse().show_cursor(false);
-		let event_pump = sdl.event_pump().unwrap();
-
 		HW
 		{
-			sdl: sdl,
+			frontend: frontend,
 			floppy: floppy_filename.map(
 				|fname| -> storage::Storage
 				{ storage::Storage::new_floppy(&fname[..]) }),
@@ -48,87 +70,184 @@ impl HW
 				{ storage::Storage::new_hdd(&fname[..]) }),
 			keyboard: keyboard::Keyboard::new(),
 			com1: serial::SerialPort::<mouse::Mouse>::new(),
-			display: display,
-			event_pump: event_pump,
+			pic: pic::Pic::new(),
+			pit: pit::Pit::new(),
+			speaker: speaker::Speaker::new(wav_filename),
+			display: display::Display::new(),
+			cmos: cmos::Cmos::new(extended_kb),
+			nmi_enabled: false,
+			input: input,
+			cycles: 0,
 			last_event_pump_ns: 0
 		}
 	}
 
-	fn pump_events(sdl: &sdl2::Sdl, keyboard: &mut keyboard::Keyboard, mouse: &mut mouse::Mouse, cpu: &mut CPU, event_iterator: &mut Iterator<Item=sdl2::event::Event>, at_most_one: bool)
+	/* Host input is ignored in deterministic mode, but one can still quit */
+	fn handle_event(&mut self, event: InputEvent, accept_input: bool)
 	{
-		for event in event_iterator
+		match event
 		{
-			use self::sdl2::event::Event;
-			match event
+			InputEvent::Quit =>
 			{
-				Event::KeyDown {timestamp:_,window_id:_,keycode:_,scancode,keymod:_,repeat:_} => 
-					{ keyboard.on_keydown(cpu, scancode) }
-				Event::KeyUp {timestamp:_,window_id:_,keycode:_,scancode,keymod:_,repeat:_} => 
-					{ keyboard.on_keyup(cpu, scancode) }
-				Event::MouseMotion {timestamp:_,window_id:_,which:_,mousestate:_,x:_,y:_,xrel,yrel} =>
-					{ mouse.on_motion(xrel, yrel) }
-				Event::MouseButtonDown {timestamp:_,window_id:_,which:_,mouse_btn,x:_,y:_, clicks: 1} =>
-					{ mouse.on_button_down(mouse_btn) }
-				Event::MouseButtonUp {timestamp:_,window_id:_,which:_,mouse_btn,x:_,y:_, clicks: 1} =>
-					{ mouse.on_button_up(mouse_btn) },
-				Event::MouseWheel {timestamp:_, window_id:_, which:_, x:_, y, direction: sdl2::mouse::MouseWheelDirection::Normal } =>
-					{
-						if y > 0 { sdl.mouse().set_relative_mouse_mode(false); mouse.set_speed_divisor(1); }
-						else if y < 0 { sdl.mouse().set_relative_mouse_mode(true); mouse.set_speed_divisor(1); }
-					}
-				Event::Quit {timestamp:_} => super::std::process::exit(0),
-				_ => {}
-			};
-
-			if at_most_one { break }
+				self.close();
+				super::std::process::exit(0)
+			}
+			_ if !accept_input => {}
+			InputEvent::KeyDown(scancode) => self.keyboard.on_keydown(&mut self.pic, scancode),
+			InputEvent::KeyUp(scancode) => self.keyboard.on_keyup(&mut self.pic, scancode),
+			InputEvent::MouseMotion(dx, dy) => self.com1.device.on_motion(dx, dy),
+			InputEvent::MouseButtonDown(button) => self.com1.device.on_button_down(button),
+			InputEvent::MouseButtonUp(button) => self.com1.device.on_button_up(button)
 		}
 	}
 
-	pub fn wait_for_event(&mut self, cpu: &mut CPU)
+	/* Scripted input event due by now, if any */
+	fn next_script_event(&mut self) -> Option<InputEvent>
 	{
-		let mut event_it = self.event_pump.wait_iter();
-		/* At most one event as, else, we will iterate
-		 * indefinitely and the iterator will always wait */
-		HW::pump_events(&self.sdl, &mut self.keyboard, &mut self.com1.device, cpu, &mut event_it, true);
+		match self.input
+		{
+			InputSource::Host => None,
+			InputSource::Script(ref mut script) => script.next_due(self.cycles)
+		}
 	}
 
-    pub fn try_pump_event(&mut self, cpu: &mut CPU)
+	/* Finishes the output files; the process is about to exit */
+	pub fn close(&mut self)
 	{
-		let mut event_it = self.event_pump.poll_iter();
-		HW::pump_events(&self.sdl, &mut self.keyboard, &mut self.com1.device, cpu, &mut event_it, true);
+		self.speaker.close();
 	}
 
-	pub fn step(&mut self, cpu: &mut CPU, mem: &mut Memory, clock: u32, time_ns: u64)
+	/* In deterministic mode, all device timing derives from the emulated CPU
+	 * cycles and input only comes from a script */
+	pub fn is_deterministic(&self) -> bool
 	{
-		self.display.render(&self.event_pump, mem, clock, time_ns);
-		self.com1.step(cpu);
+		match self.input
+		{
+			InputSource::Host => false,
+			InputSource::Script(_) => true
+		}
+	}
 
-		if time_ns > self.last_event_pump_ns + EVENT_PUMP_PERIOD_NS
+	/* Whether waiting for a host event may bring some input */
+	pub fn has_host_input(&self) -> bool
+	{
+		!self.is_deterministic() && self.frontend.has_input()
+	}
+
+	pub fn wait_for_event(&mut self)
+	{
+		let host_input = !self.is_deterministic();
+		let event = self.frontend.wait_event();
+		self.handle_event(event, host_input);
+	}
+
+	pub fn try_pump_event(&mut self)
+	{
+		let host_input = !self.is_deterministic();
+		if let Some(event) = self.frontend.poll_event()
 		{
-			let mut event_it = self.event_pump.poll_iter();
-			HW::pump_events(&self.sdl, &mut self.keyboard, &mut self.com1.device, cpu, &mut event_it, false);
-			self.last_event_pump_ns = time_ns;
+			self.handle_event(event, host_input);
 		}
+	}
+
+	pub fn step(&mut self, mem: &mut Memory, clock: u32, time_ns: u64, cycles: u32)
+	{
+		self.display.render(&mut *self.frontend, mem, clock, time_ns);
+		self.com1.step(&mut self.pic, cycles);
+		self.pit.step(&mut self.pic, cycles);
+		self.speaker.step(&mut *self.frontend, self.pit.output(2), cycles);
+		self.cycles += cycles as u64;
 
-		/* TODO: move system time handling somewhere else */
-		let old_system_clock = mem.read_u16(0x46c) as u32 | ((mem.read_u16(0x46e) as u32) << 16);
-		let system_clock = ((time_ns / TIMER_PERIOD_NS) % 0x1800B0) as u32;
-		if old_system_clock != system_clock
+		while let Some(event) = self.next_script_event()
 		{
-			if (system_clock - old_system_clock) > 1
+			self.handle_event(event, true);
+		}
+
+		/* Host events are still pumped in deterministic mode, to keep the
+		 * window alive and to be able to quit */
+		if time_ns > self.last_event_pump_ns + EVENT_PUMP_PERIOD_NS
+		{
+			let host_input = !self.is_deterministic();
+			while let Some(event) = self.frontend.poll_event()
 			{
-				println!("Warning: system too slow for timer interrupt!");
+				self.handle_event(event, host_input);
 			}
-
-			cpu.queue_hw_interrupt(0x8);
+			self.last_event_pump_ns = time_ns;
 		}
+	}
+
+	pub fn save_screenshot(&mut self, mem: &Memory, filename: &str)
+	{
+		self.display.save_screenshot(mem, filename);
+	}
+}
+
+fn save_storage(writer: &mut SnapshotWriter, storage: &Option<storage::Storage>)
+{
+	writer.write_bool(storage.is_some());
+	if let Some(ref storage) = *storage
+	{
+		storage.save_state(writer);
+	}
+}
 
-		if system_clock < old_system_clock
+fn load_storage(reader: &mut SnapshotReader, storage: &mut Option<storage::Storage>, what: &str) -> Result<(), String>
+{
+	match (reader.read_bool()?, storage.as_mut())
+	{
+		(false, None) => Ok(()),
+		(true, Some(storage)) => storage.load_state(reader),
+		(true, None) => Err(format!("the snapshot was taken with a {} image, but none is attached", what)),
+		(false, Some(_)) => Err(format!("the snapshot was taken without any {} image", what))
+	}
+}
+
+/* The input source is not part of the snapshot: it belongs to the session */
+impl Snapshot for HW
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"HW  ");
+		writer.write_u64(self.cycles);
+		writer.write_u64(self.last_event_pump_ns);
+		save_storage(writer, &self.floppy);
+		save_storage(writer, &self.hdd);
+		self.keyboard.save_state(writer);
+		self.display.save_state(writer);
+		self.com1.save_state(writer);
+		self.pic.save_state(writer);
+		self.pit.save_state(writer);
+		self.speaker.save_state(writer);
+		self.cmos.save_state(writer);
+		writer.write_bool(self.nmi_enabled);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"HW  ")?;
+		self.cycles = reader.read_u64()?;
+		self.last_event_pump_ns = reader.read_u64()?;
+		load_storage(reader, &mut self.floppy, "floppy disk")?;
+		load_storage(reader, &mut self.hdd, "hard drive")?;
+		self.keyboard.load_state(reader)?;
+		self.display.load_state(reader)?;
+		self.com1.load_state(reader)?;
+		self.pic.load_state(reader)?;
+		self.pit.load_state(reader)?;
+		self.speaker.load_state(reader)?;
+		self.cmos.load_state(reader)?;
+		self.nmi_enabled = reader.read_bool()?;
+
+		match self.input
 		{
-			/* Midnight flag */
-			mem.write_u16(0x470, 0x1);
+			InputSource::Host =>
+			{
+				/* Host timestamps of another session are meaningless */
+				self.last_event_pump_ns = 0;
+				self.display.reset_sync();
+			}
+			InputSource::Script(ref mut script) => script.skip_until(self.cycles)
 		}
-		mem.write_u16(0x46c, (system_clock & 0xffff) as u16);
-		mem.write_u16(0x46e, (system_clock >> 16) as u16);
+		Ok(())
 	}
 }
@@ -1,6 +1,8 @@
 use std::collections::vec_deque::VecDeque;
 
 use super::serial::SerialDevice;
+use super::super::frontend::MouseButton;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 pub struct Mouse
 {
@@ -115,48 +117,87 @@ impl Mouse
 		}
 	}
 
-	pub fn on_button_down(&mut self, button: sdl2::mouse::MouseButton)
+	pub fn on_button_down(&mut self, button: MouseButton)
 	{
 		match button
 		{
-			sdl2::mouse::MouseButton::Left =>
+			MouseButton::Left =>
 			{
 				self.left_button_pressed = true;
 				mouse_print!("Left pressed")
 			}
-			sdl2::mouse::MouseButton::Right =>
+			MouseButton::Right =>
 			{
 				self.right_button_pressed = true;
 				mouse_print!("Right pressed")
 			}
-			_ => {}
 		}
 
 		self.push_event(0, 0);
 	}
 
-	pub fn on_button_up(&mut self, button: sdl2::mouse::MouseButton)
+	pub fn on_button_up(&mut self, button: MouseButton)
 	{
 		match button
 		{
-			sdl2::mouse::MouseButton::Left =>
+			MouseButton::Left =>
 			{
 				self.left_button_pressed = false;
 				mouse_print!("Left released")
 			}
-			sdl2::mouse::MouseButton::Right =>
+			MouseButton::Right =>
 			{
 				self.right_button_pressed = false;
 				mouse_print!("Right released")
 			}
-			_ => {}
 		}
 		
 		self.push_event(0, 0);
 	}
+}
+
+impl Snapshot for Mouse
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"MOUS");
+		writer.write_bool(self.hw_init);
+		writer.write_bool(self.initialized);
+		writer.write_u32(self.current_event_byte_count as u32);
+		writer.write_u32(self.events.len() as u32);
+		for event in self.events.iter()
+		{
+			writer.write_bytes(event);
+		}
+		writer.write_bool(self.left_button_pressed);
+		writer.write_bool(self.right_button_pressed);
+		writer.write_u32(self.speed_divisor as u32);
+	}
 
-	pub fn set_speed_divisor(&mut self, speed_divisor: i32)
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
 	{
-		self.speed_divisor = speed_divisor
+		reader.expect_section(b"MOUS")?;
+		self.hw_init = reader.read_bool()?;
+		self.initialized = reader.read_bool()?;
+		self.current_event_byte_count = reader.read_u32()? as usize;
+		if self.current_event_byte_count > 3
+		{
+			return Err(format!("invalid mouse event byte count {}", self.current_event_byte_count))
+		}
+		let event_count = reader.read_u32()?;
+		self.events.clear();
+		for _ in 0..event_count
+		{
+			let bytes = reader.read_bytes(3)?;
+			self.events.push_back([bytes[0], bytes[1], bytes[2]]);
+		}
+		self.left_button_pressed = reader.read_bool()?;
+		self.right_button_pressed = reader.read_bool()?;
+		self.speed_divisor = reader.read_u32()? as i32;
+		if self.speed_divisor <= 0
+		{
+			return Err(format!("invalid mouse speed divisor {}", self.speed_divisor))
+		}
+		Ok(())
 	}
 }
@@ -0,0 +1,431 @@
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+/* Emulates an Intel 8259A Programmable Interrupt Controller, as found on
+ * ports 0x20/0x21 of a PC/XT (single controller, no cascading) */
+
+const ICW1_IC4: u8 = 0x01;
+const ICW1_SNGL: u8 = 0x02;
+const ICW1_LTIM: u8 = 0x08;
+const ICW1_INIT: u8 = 0x10;
+
+const ICW4_AEOI: u8 = 0x02;
+
+const OCW3_SELECT: u8 = 0x08;
+const OCW3_RIS: u8 = 0x01;
+const OCW3_RR: u8 = 0x02;
+const OCW3_POLL: u8 = 0x04;
+const OCW3_SMM: u8 = 0x20;
+const OCW3_ESMM: u8 = 0x40;
+
+/* Lowest priority IRQ after initialization: IRQ0 has the highest priority */
+const DEFAULT_LOWEST_PRIORITY: u8 = 7;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum InitState
+{
+	Ready,
+	ExpectICW2,
+	ExpectICW3,
+	ExpectICW4
+}
+
+pub struct Pic
+{
+	/* Interrupt request, in service and mask registers */
+	irr: u8,
+	isr: u8,
+	imr: u8,
+
+	/* Current level of the IRQ lines, used for edge detection */
+	lines: u8,
+
+	init_state: InitState,
+	icw4_needed: bool,
+	single: bool,
+	level_triggered: bool,
+	vector_base: u8,
+	auto_eoi: bool,
+	rotate_on_auto_eoi: bool,
+
+	read_isr: bool,
+	poll: bool,
+	special_mask: bool,
+	lowest_priority: u8
+}
+
+impl Pic
+{
+	pub fn new() -> Pic
+	{
+		Pic
+		{
+			irr: 0,
+			isr: 0,
+			imr: 0,
+			lines: 0,
+			init_state: InitState::Ready,
+			icw4_needed: false,
+			single: true,
+			level_triggered: false,
+			vector_base: 0x08,
+			auto_eoi: false,
+			rotate_on_auto_eoi: false,
+			read_isr: false,
+			poll: false,
+			special_mask: false,
+			lowest_priority: DEFAULT_LOWEST_PRIORITY
+		}
+	}
+
+	/* Sets the level of an IRQ line; a rising edge (or a high level in
+	 * level-triggered mode) requests an interrupt */
+	pub fn set_irq_line(&mut self, irq: u8, level: bool)
+	{
+		let mask = 1 << irq;
+		let was_high = self.lines & mask != 0;
+
+		if level
+		{
+			self.lines |= mask;
+			if !was_high || self.level_triggered
+			{
+				pic_print!("IRQ{} raised", irq);
+				self.irr |= mask;
+			}
+		}
+		else
+		{
+			self.lines &= !mask;
+			if self.level_triggered
+			{
+				self.irr &= !mask;
+			}
+		}
+	}
+
+	/* Raises then lowers an IRQ line, which is how most of our devices
+	 * signal an event */
+	pub fn pulse_irq(&mut self, irq: u8)
+	{
+		self.set_irq_line(irq, true);
+		self.set_irq_line(irq, false);
+	}
+
+	/* IRQ numbers ordered from the highest to the lowest priority */
+	fn priority_order(&self) -> [u8; 8]
+	{
+		let mut order = [0; 8];
+		for i in 0..8
+		{
+			order[i] = (self.lowest_priority + 1 + i as u8) & 0x7;
+		}
+		order
+	}
+
+	fn highest_isr_irq(&self) -> Option<u8>
+	{
+		self.priority_order().iter().cloned().find(|irq| self.isr & (1 << irq) != 0)
+	}
+
+	/* Highest priority IRQ which is allowed to interrupt the CPU, if any */
+	fn next_irq(&self) -> Option<u8>
+	{
+		let requested = self.irr & !self.imr;
+		if requested == 0
+		{
+			return None
+		}
+
+		for irq in self.priority_order().iter().cloned()
+		{
+			let mask = 1 << irq;
+
+			/* An interrupt being serviced blocks its own level and all
+			 * lower-priority ones, unless the special mask mode is
+			 * enabled */
+			if self.isr & mask != 0
+			{
+				if self.special_mask
+				{
+					continue;
+				}
+				return None
+			}
+
+			if requested & mask != 0
+			{
+				return Some(irq)
+			}
+		}
+
+		None
+	}
+
+	/* Is the INT line to the CPU asserted? */
+	pub fn has_interrupt(&self) -> bool
+	{
+		self.init_state == InitState::Ready && self.next_irq() != None
+	}
+
+	/* INTA cycle: returns the interrupt vector the CPU should dispatch */
+	pub fn acknowledge(&mut self) -> u8
+	{
+		match self.next_irq()
+		{
+			Some(irq) =>
+			{
+				let mask = 1 << irq;
+				if !self.level_triggered
+				{
+					self.irr &= !mask;
+				}
+
+				if self.auto_eoi
+				{
+					if self.rotate_on_auto_eoi
+					{
+						self.lowest_priority = irq;
+					}
+				}
+				else
+				{
+					self.isr |= mask;
+				}
+
+				pic_print!("IRQ{} acknowledged; vector {:02x}", irq, self.vector_base + irq);
+				self.vector_base + irq
+			}
+			None =>
+			{
+				/* Spurious interrupt: the 8259A answers with IRQ7 */
+				pic_print!("Spurious interrupt");
+				self.vector_base + 7
+			}
+		}
+	}
+
+	fn poll_command(&mut self) -> u8
+	{
+		self.poll = false;
+		if self.next_irq() != None
+		{
+			let vector = self.acknowledge();
+			0x80 | (vector - self.vector_base)
+		}
+		else
+		{
+			0
+		}
+	}
+
+	fn write_ocw2(&mut self, val: u8)
+	{
+		let level = val & 0x7;
+
+		match val >> 5
+		{
+			0b001 | 0b101 =>
+			{
+				/* Non-specific EOI, with or without rotation */
+				if let Some(irq) = self.highest_isr_irq()
+				{
+					self.isr &= !(1 << irq);
+					if val & 0x80 != 0
+					{
+						self.lowest_priority = irq;
+					}
+				}
+			}
+			0b011 | 0b111 =>
+			{
+				/* Specific EOI, with or without rotation */
+				self.isr &= !(1 << level);
+				if val & 0x80 != 0
+				{
+					self.lowest_priority = level;
+				}
+			}
+			0b100 => self.rotate_on_auto_eoi = true,
+			0b000 => self.rotate_on_auto_eoi = false,
+			0b110 => self.lowest_priority = level,
+			_ => {} // No operation
+		}
+	}
+
+	fn write_ocw3(&mut self, val: u8)
+	{
+		if val & OCW3_ESMM != 0
+		{
+			self.special_mask = val & OCW3_SMM != 0;
+		}
+
+		if val & OCW3_RR != 0
+		{
+			self.read_isr = val & OCW3_RIS != 0;
+		}
+
+		self.poll = val & OCW3_POLL != 0;
+	}
+
+	/* Port 0x20 */
+	pub fn write_command(&mut self, val: u8)
+	{
+		pic_print!("Write command {:02x}", val);
+
+		if val & ICW1_INIT != 0
+		{
+			/* ICW1: restart the initialization sequence */
+			self.icw4_needed = val & ICW1_IC4 != 0;
+			self.single = val & ICW1_SNGL != 0;
+			self.level_triggered = val & ICW1_LTIM != 0;
+			self.init_state = InitState::ExpectICW2;
+
+			self.irr = 0;
+			self.isr = 0;
+			self.imr = 0;
+			self.auto_eoi = false;
+			self.rotate_on_auto_eoi = false;
+			self.read_isr = false;
+			self.poll = false;
+			self.special_mask = false;
+			self.lowest_priority = DEFAULT_LOWEST_PRIORITY;
+		}
+		else if val & OCW3_SELECT != 0
+		{
+			self.write_ocw3(val);
+		}
+		else
+		{
+			self.write_ocw2(val);
+		}
+	}
+
+	/* Port 0x21 */
+	pub fn write_data(&mut self, val: u8)
+	{
+		pic_print!("Write data {:02x}", val);
+
+		match self.init_state
+		{
+			InitState::ExpectICW2 =>
+			{
+				self.vector_base = val & 0xf8;
+				self.init_state =
+					if !self.single { InitState::ExpectICW3 }
+					else if self.icw4_needed { InitState::ExpectICW4 }
+					else { InitState::Ready };
+			}
+			InitState::ExpectICW3 =>
+			{
+				/* There is no slave controller on a PC/XT */
+				self.init_state =
+					if self.icw4_needed { InitState::ExpectICW4 }
+					else { InitState::Ready };
+			}
+			InitState::ExpectICW4 =>
+			{
+				self.auto_eoi = val & ICW4_AEOI != 0;
+				self.init_state = InitState::Ready;
+			}
+			InitState::Ready =>
+			{
+				/* OCW1 */
+				self.imr = val;
+			}
+		}
+	}
+
+	/* Port 0x20 */
+	pub fn read_command(&mut self) -> u8
+	{
+		let ret =
+			if self.poll { self.poll_command() }
+			else if self.read_isr { self.isr }
+			else { self.irr };
+
+		pic_print!("Read command = {:02x}", ret);
+		ret
+	}
+
+	/* Port 0x21 */
+	pub fn read_data(&mut self) -> u8
+	{
+		if self.poll
+		{
+			return self.poll_command()
+		}
+
+		pic_print!("Read IMR = {:02x}", self.imr);
+		self.imr
+	}
+}
+
+impl InitState
+{
+	fn to_u8(&self) -> u8
+	{
+		match *self
+		{
+			InitState::Ready => 0,
+			InitState::ExpectICW2 => 2,
+			InitState::ExpectICW3 => 3,
+			InitState::ExpectICW4 => 4
+		}
+	}
+
+	fn from_u8(val: u8) -> Result<InitState, String>
+	{
+		match val
+		{
+			0 => Ok(InitState::Ready),
+			2 => Ok(InitState::ExpectICW2),
+			3 => Ok(InitState::ExpectICW3),
+			4 => Ok(InitState::ExpectICW4),
+			_ => Err(format!("invalid PIC initialization state {}", val))
+		}
+	}
+}
+
+impl Snapshot for Pic
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"PIC ");
+		writer.write_u8(self.irr);
+		writer.write_u8(self.isr);
+		writer.write_u8(self.imr);
+		writer.write_u8(self.lines);
+		writer.write_u8(self.init_state.to_u8());
+		writer.write_bool(self.icw4_needed);
+		writer.write_bool(self.single);
+		writer.write_bool(self.level_triggered);
+		writer.write_u8(self.vector_base);
+		writer.write_bool(self.auto_eoi);
+		writer.write_bool(self.rotate_on_auto_eoi);
+		writer.write_bool(self.read_isr);
+		writer.write_bool(self.poll);
+		writer.write_bool(self.special_mask);
+		writer.write_u8(self.lowest_priority);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"PIC ")?;
+		self.irr = reader.read_u8()?;
+		self.isr = reader.read_u8()?;
+		self.imr = reader.read_u8()?;
+		self.lines = reader.read_u8()?;
+		self.init_state = InitState::from_u8(reader.read_u8()?)?;
+		self.icw4_needed = reader.read_bool()?;
+		self.single = reader.read_bool()?;
+		self.level_triggered = reader.read_bool()?;
+		self.vector_base = reader.read_u8()?;
+		self.auto_eoi = reader.read_bool()?;
+		self.rotate_on_auto_eoi = reader.read_bool()?;
+		self.read_isr = reader.read_bool()?;
+		self.poll = reader.read_bool()?;
+		self.special_mask = reader.read_bool()?;
+		self.lowest_priority = reader.read_u8()? & 0x7;
+		Ok(())
+	}
+}
@@ -0,0 +1,92 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::pic::*;
+
+	fn initialized_pic(vector_base: u8) -> Pic
+	{
+		let mut pic = Pic::new();
+		pic.write_command(0x13); // ICW1: edge, single, ICW4 needed
+		pic.write_data(vector_base); // ICW2
+		pic.write_data(0x01); // ICW4: 8086 mode
+		pic
+	}
+
+	#[test]
+	fn vector_base()
+	{
+		let mut pic = initialized_pic(0x70);
+		pic.pulse_irq(3);
+		assert!(pic.has_interrupt());
+		assert_eq!(pic.acknowledge(), 0x73);
+		assert!(!pic.has_interrupt());
+	}
+
+	#[test]
+	fn masking()
+	{
+		let mut pic = initialized_pic(0x08);
+		pic.write_data(0x02); // OCW1: mask IRQ1
+		pic.pulse_irq(1);
+		assert!(!pic.has_interrupt());
+		assert_eq!(pic.read_data(), 0x02);
+
+		pic.write_data(0x00);
+		assert!(pic.has_interrupt());
+		assert_eq!(pic.acknowledge(), 0x09);
+	}
+
+	#[test]
+	fn priority_and_eoi()
+	{
+		let mut pic = initialized_pic(0x08);
+		pic.pulse_irq(4);
+		assert_eq!(pic.acknowledge(), 0x0c);
+
+		/* Lower priority request is blocked while IRQ4 is in service */
+		pic.pulse_irq(6);
+		assert!(!pic.has_interrupt());
+
+		/* Higher priority request nests */
+		pic.pulse_irq(0);
+		assert_eq!(pic.acknowledge(), 0x08);
+
+		/* The same level does not, until its EOI */
+		pic.pulse_irq(0);
+		assert!(!pic.has_interrupt());
+
+		pic.write_command(0x0b); // OCW3: read ISR
+		assert_eq!(pic.read_command(), 0x11);
+
+		pic.write_command(0x20); // Non-specific EOI: clears IRQ0
+		assert_eq!(pic.acknowledge(), 0x08);
+		pic.write_command(0x20); // Clears IRQ0 again
+		assert!(!pic.has_interrupt());
+		pic.write_command(0x20); // Clears IRQ4
+		assert_eq!(pic.acknowledge(), 0x0e);
+	}
+
+	#[test]
+	fn specific_rotation()
+	{
+		let mut pic = initialized_pic(0x08);
+		pic.write_command(0xc4); // Set priority: IRQ4 lowest, IRQ5 highest
+		pic.pulse_irq(0);
+		pic.pulse_irq(5);
+		assert_eq!(pic.acknowledge(), 0x0d);
+		pic.write_command(0x65); // Specific EOI for IRQ5
+		assert_eq!(pic.acknowledge(), 0x08);
+	}
+
+	#[test]
+	fn poll()
+	{
+		let mut pic = initialized_pic(0x08);
+		pic.pulse_irq(2);
+		pic.write_command(0x0c); // OCW3: poll
+		assert_eq!(pic.read_command(), 0x82);
+		assert!(!pic.has_interrupt());
+		pic.write_command(0x0c);
+		assert_eq!(pic.read_command(), 0x00);
+	}
+}
@@ -0,0 +1,670 @@
+use super::pic::Pic;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+/* Emulates an Intel 8253/8254 Programmable Interval Timer on ports 0x40-0x43.
+ * Channel 0 drives IRQ0, channel 1 would refresh the DRAM and channel 2 is
+ * gated through port 0x61 (PC speaker). */
+
+/* The PIT input clock is 1.193182 MHz, i.e. the 4.77 MHz CPU clock / 4 */
+const CPU_CYCLES_PER_TICK: u32 = 4;
+
+const TIMER_IRQ: u8 = 0;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Access
+{
+	LoByte,
+	HiByte,
+	LoHiByte
+}
+
+impl Access
+{
+	fn from_control(control: u8) -> Access
+	{
+		match (control >> 4) & 0x3
+		{
+			1 => Access::LoByte,
+			2 => Access::HiByte,
+			3 => Access::LoHiByte,
+			_ => unreachable!()
+		}
+	}
+
+	fn from_u8(val: u8) -> Result<Access, String>
+	{
+		match val
+		{
+			1 ... 3 => Ok(Access::from_control(val << 4)),
+			_ => Err(format!("invalid PIT access mode {}", val))
+		}
+	}
+
+	fn to_control(&self) -> u8
+	{
+		match *self
+		{
+			Access::LoByte => 1,
+			Access::HiByte => 2,
+			Access::LoHiByte => 3
+		}
+	}
+}
+
+struct Channel
+{
+	mode: u8,
+	bcd: bool,
+	access: Access,
+
+	/* Count register: value written by the guest; 0 stands for 65536 */
+	reload: u16,
+	/* Counting element */
+	counter: u16,
+	/* Null count status bit: the count register was not transferred to the
+	 * counting element since the last write */
+	null_count: bool,
+	/* A complete count was written and is waiting to be loaded */
+	new_count: bool,
+	/* The counter only runs once a count was loaded (and, in modes 1 and 5,
+	 * once triggered by the gate) */
+	counting: bool,
+	/* Mode 1/5: a rising gate edge happened and the count must be (re)loaded */
+	triggered: bool,
+
+	gate: bool,
+	output: bool,
+
+	write_hi_next: bool,
+	pending_lo: u8,
+
+	read_hi_next: bool,
+	latched_count: Option<u16>,
+	latched_status: Option<u8>
+}
+
+impl Channel
+{
+	fn new() -> Channel
+	{
+		Channel
+		{
+			mode: 0,
+			bcd: false,
+			access: Access::LoHiByte,
+			reload: 0,
+			counter: 0,
+			null_count: true,
+			new_count: false,
+			counting: false,
+			triggered: false,
+			gate: true,
+			output: false,
+			write_hi_next: false,
+			pending_lo: 0,
+			read_hi_next: false,
+			latched_count: None,
+			latched_status: None
+		}
+	}
+
+	fn set_control(&mut self, control: u8)
+	{
+		self.access = Access::from_control(control);
+		self.mode = (control >> 1) & 0x7;
+		/* Modes 6 and 7 are aliases for modes 2 and 3 */
+		if self.mode > 5
+		{
+			self.mode -= 4;
+		}
+		self.bcd = control & 0x1 != 0;
+
+		self.output = self.mode != 0;
+		self.null_count = true;
+		self.new_count = false;
+		self.counting = false;
+		self.triggered = false;
+		self.write_hi_next = false;
+		self.read_hi_next = false;
+		self.latched_count = None;
+		self.latched_status = None;
+	}
+
+	fn control_bits(&self) -> u8
+	{
+		(self.access.to_control() << 4) | (self.mode << 1) | if self.bcd { 1 } else { 0 }
+	}
+
+	fn load_count(&mut self, count: u16)
+	{
+		self.reload = count;
+		self.null_count = true;
+
+		match self.mode
+		{
+			/* Writing a new count restarts modes 0 and 4 on the next clock */
+			0 | 4 =>
+			{
+				self.new_count = true;
+				self.counting = false;
+			}
+			/* Modes 2 and 3 only use the new count at the end of the current
+			 * period, unless the counter was not running yet */
+			2 | 3 => self.new_count = true,
+			/* Modes 1 and 5 wait for a gate trigger */
+			_ => {}
+		}
+	}
+
+	fn write(&mut self, val: u8)
+	{
+		match self.access
+		{
+			Access::LoByte => self.load_count(val as u16),
+			Access::HiByte => self.load_count((val as u16) << 8),
+			Access::LoHiByte =>
+			{
+				if self.write_hi_next
+				{
+					let count = (self.pending_lo as u16) | ((val as u16) << 8);
+					self.write_hi_next = false;
+					self.load_count(count);
+				}
+				else
+				{
+					self.pending_lo = val;
+					self.write_hi_next = true;
+					/* Mode 0 stops counting as soon as the first byte is written */
+					if self.mode == 0
+					{
+						self.output = false;
+						self.counting = false;
+					}
+				}
+			}
+		}
+	}
+
+	fn read(&mut self) -> u8
+	{
+		if let Some(status) = self.latched_status.take()
+		{
+			return status
+		}
+
+		let value = match self.latched_count
+		{
+			Some(latched) => latched,
+			None => self.counter
+		};
+
+		let (ret, done) = match self.access
+		{
+			Access::LoByte => (value as u8, true),
+			Access::HiByte => ((value >> 8) as u8, true),
+			Access::LoHiByte =>
+			{
+				if self.read_hi_next
+				{
+					self.read_hi_next = false;
+					((value >> 8) as u8, true)
+				}
+				else
+				{
+					self.read_hi_next = true;
+					(value as u8, false)
+				}
+			}
+		};
+
+		if done
+		{
+			self.latched_count = None;
+		}
+		ret
+	}
+
+	fn latch_count(&mut self)
+	{
+		/* Further latch commands are ignored until the latched value was read */
+		if self.latched_count == None
+		{
+			self.latched_count = Some(self.counter);
+		}
+	}
+
+	fn latch_status(&mut self)
+	{
+		if self.latched_status == None
+		{
+			let status =
+				if self.output { 0x80 } else { 0 } |
+				if self.null_count { 0x40 } else { 0 } |
+				self.control_bits();
+			self.latched_status = Some(status);
+		}
+	}
+
+	fn set_gate(&mut self, gate: bool)
+	{
+		let rising = gate && !self.gate;
+		self.gate = gate;
+
+		match self.mode
+		{
+			1 | 5 => if rising { self.triggered = true },
+			2 | 3 =>
+			{
+				if !gate
+				{
+					self.output = true;
+				}
+				else if rising && (self.counting || self.new_count)
+				{
+					/* Restart the period */
+					self.triggered = true;
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/* Decrements the counter by 'by', honouring BCD mode; 0 wraps to the
+	 * highest value */
+	fn decrement(&mut self, by: u16)
+	{
+		if self.bcd
+		{
+			let mut bin = bcd_to_bin(self.counter);
+			if bin < by as u32
+			{
+				bin += 10000;
+			}
+			self.counter = bin_to_bcd(bin - by as u32);
+		}
+		else
+		{
+			self.counter = self.counter.wrapping_sub(by);
+		}
+	}
+
+	/* Number of input clocks for a full count; 0 stands for the highest one */
+	fn reload_value(&self) -> u32
+	{
+		match (self.reload, self.bcd)
+		{
+			(0, false) => 0x10000,
+			(0, true) => 10000,
+			(x, false) => x as u32,
+			(x, true) => bcd_to_bin(x)
+		}
+	}
+
+	/* Transfers the count register to the counting element */
+	fn start(&mut self)
+	{
+		self.counter = self.reload;
+		self.new_count = false;
+		self.null_count = false;
+		self.counting = true;
+	}
+
+	/* One input clock */
+	fn tick(&mut self)
+	{
+		match self.mode
+		{
+			0 | 4 =>
+			{
+				if self.mode == 4 && !self.output
+				{
+					/* The strobe lasts for one clock */
+					self.output = true;
+				}
+
+				if self.new_count
+				{
+					self.start();
+					return
+				}
+
+				if !self.counting || !self.gate
+				{
+					return
+				}
+
+				self.decrement(1);
+				if self.counter == 0
+				{
+					if self.mode == 0
+					{
+						/* The counter keeps wrapping, the output stays high */
+						self.output = true;
+					}
+					else
+					{
+						/* Mode 4 only strobes once per written count */
+						self.output = false;
+						self.counting = false;
+					}
+				}
+			}
+			1 | 5 =>
+			{
+				if self.mode == 5 && !self.output
+				{
+					self.output = true;
+				}
+
+				if self.triggered
+				{
+					self.triggered = false;
+					self.start();
+					if self.mode == 1
+					{
+						self.output = false;
+					}
+					return
+				}
+
+				if !self.counting
+				{
+					return
+				}
+
+				self.decrement(1);
+				if self.counter == 0
+				{
+					/* Mode 1 ends its low pulse, mode 5 strobes for one clock */
+					self.output = self.mode == 1;
+					self.counting = false;
+				}
+			}
+			2 =>
+			{
+				if !self.gate
+				{
+					return
+				}
+
+				if self.triggered || (self.new_count && !self.counting)
+				{
+					self.triggered = false;
+					self.start();
+					self.output = true;
+					return
+				}
+
+				if !self.counting
+				{
+					return
+				}
+
+				if !self.output
+				{
+					/* End of the low pulse: reload */
+					self.start();
+					self.output = true;
+					return
+				}
+
+				self.decrement(1);
+				if self.counter == 1
+				{
+					self.output = false;
+				}
+			}
+			3 =>
+			{
+				if !self.gate
+				{
+					return
+				}
+
+				if self.triggered || (self.new_count && !self.counting)
+				{
+					self.triggered = false;
+					self.output = true;
+					self.start();
+					self.counter = self.half_period_start();
+					return
+				}
+
+				if !self.counting
+				{
+					return
+				}
+
+				self.decrement(2);
+				if self.counter == 0
+				{
+					self.output = !self.output;
+					self.start();
+					self.counter = self.half_period_start();
+				}
+			}
+			_ => unreachable!()
+		}
+	}
+
+	/* In mode 3, odd counts stay high for (N+1)/2 clocks and low for (N-1)/2
+	 * clocks; this is emulated by adjusting the value loaded for each half
+	 * period, the counter being decremented by 2 at each clock */
+	fn half_period_start(&self) -> u16
+	{
+		let reload = self.reload_value();
+		let start =
+			if reload & 1 == 0 { reload }
+			else if self.output { reload + 1 }
+			else { reload - 1 };
+
+		if self.bcd
+		{
+			bin_to_bcd(start % 10000)
+		}
+		else
+		{
+			start as u16
+		}
+	}
+}
+
+fn save_option_u16(writer: &mut SnapshotWriter, val: Option<u16>)
+{
+	writer.write_bool(val.is_some());
+	writer.write_u16(val.unwrap_or(0));
+}
+
+fn load_option_u16(reader: &mut SnapshotReader) -> Result<Option<u16>, String>
+{
+	let present = reader.read_bool()?;
+	let val = reader.read_u16()?;
+	Ok(if present { Some(val) } else { None })
+}
+
+impl Snapshot for Channel
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.write_u8(self.mode);
+		writer.write_bool(self.bcd);
+		writer.write_u8(self.access.to_control());
+		writer.write_u16(self.reload);
+		writer.write_u16(self.counter);
+		writer.write_bool(self.null_count);
+		writer.write_bool(self.new_count);
+		writer.write_bool(self.counting);
+		writer.write_bool(self.triggered);
+		writer.write_bool(self.gate);
+		writer.write_bool(self.output);
+		writer.write_bool(self.write_hi_next);
+		writer.write_u8(self.pending_lo);
+		writer.write_bool(self.read_hi_next);
+		save_option_u16(writer, self.latched_count);
+		save_option_u16(writer, self.latched_status.map(|status| status as u16));
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		self.mode = reader.read_u8()?;
+		if self.mode > 5
+		{
+			return Err(format!("invalid PIT mode {}", self.mode))
+		}
+		self.bcd = reader.read_bool()?;
+		self.access = Access::from_u8(reader.read_u8()?)?;
+		self.reload = reader.read_u16()?;
+		self.counter = reader.read_u16()?;
+		self.null_count = reader.read_bool()?;
+		self.new_count = reader.read_bool()?;
+		self.counting = reader.read_bool()?;
+		self.triggered = reader.read_bool()?;
+		self.gate = reader.read_bool()?;
+		self.output = reader.read_bool()?;
+		self.write_hi_next = reader.read_bool()?;
+		self.pending_lo = reader.read_u8()?;
+		self.read_hi_next = reader.read_bool()?;
+		self.latched_count = load_option_u16(reader)?;
+		self.latched_status = load_option_u16(reader)?.map(|status| status as u8);
+		Ok(())
+	}
+}
+
+fn bcd_to_bin(bcd: u16) -> u32
+{
+	((bcd >> 12) & 0xf) as u32 * 1000 +
+	((bcd >> 8) & 0xf) as u32 * 100 +
+	((bcd >> 4) & 0xf) as u32 * 10 +
+	(bcd & 0xf) as u32
+}
+
+fn bin_to_bcd(bin: u32) -> u16
+{
+	(((bin / 1000) % 10) << 12 |
+	((bin / 100) % 10) << 8 |
+	((bin / 10) % 10) << 4 |
+	(bin % 10)) as u16
+}
+
+pub struct Pit
+{
+	channels: [Channel; 3],
+	pending_cycles: u32
+}
+
+impl Pit
+{
+	pub fn new() -> Pit
+	{
+		let mut pit = Pit
+		{
+			channels: [Channel::new(), Channel::new(), Channel::new()],
+			pending_cycles: 0
+		};
+
+		/* Channel 2 is gated by port 0x61, which starts cleared */
+		pit.channels[2].gate = false;
+		pit
+	}
+
+	/* Port 0x43 */
+	pub fn write_control(&mut self, control: u8)
+	{
+		pit_print!("Write control word {:02x}", control);
+
+		let channel = (control >> 6) as usize;
+		if channel == 3
+		{
+			/* 8254 read-back command */
+			for i in 0..3
+			{
+				if control & (0x2 << i) != 0
+				{
+					if control & 0x20 == 0
+					{
+						self.channels[i].latch_count();
+					}
+					if control & 0x10 == 0
+					{
+						self.channels[i].latch_status();
+					}
+				}
+			}
+		}
+		else if control & 0x30 == 0
+		{
+			self.channels[channel].latch_count();
+		}
+		else
+		{
+			self.channels[channel].set_control(control);
+		}
+	}
+
+	/* Ports 0x40-0x42 */
+	pub fn write_counter(&mut self, channel: usize, val: u8)
+	{
+		pit_print!("Write {:02x} to channel {}", val, channel);
+		self.channels[channel].write(val);
+	}
+
+	/* Ports 0x40-0x42 */
+	pub fn read_counter(&mut self, channel: usize) -> u8
+	{
+		let ret = self.channels[channel].read();
+		pit_print!("Read {:02x} from channel {}", ret, channel);
+		ret
+	}
+
+	pub fn set_gate(&mut self, channel: usize, gate: bool)
+	{
+		self.channels[channel].set_gate(gate);
+	}
+
+	pub fn output(&self, channel: usize) -> bool
+	{
+		self.channels[channel].output
+	}
+
+	/* Advances the timer by the given amount of CPU cycles */
+	pub fn step(&mut self, pic: &mut Pic, cpu_cycles: u32)
+	{
+		self.pending_cycles += cpu_cycles;
+
+		while self.pending_cycles >= CPU_CYCLES_PER_TICK
+		{
+			self.pending_cycles -= CPU_CYCLES_PER_TICK;
+
+			for channel in self.channels.iter_mut()
+			{
+				channel.tick();
+			}
+
+			pic.set_irq_line(TIMER_IRQ, self.channels[0].output);
+		}
+	}
+}
+
+impl Snapshot for Pit
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"PIT ");
+		for channel in self.channels.iter()
+		{
+			channel.save_state(writer);
+		}
+		writer.write_u32(self.pending_cycles);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"PIT ")?;
+		for channel in self.channels.iter_mut()
+		{
+			channel.load_state(reader)?;
+		}
+		self.pending_cycles = reader.read_u32()?;
+		Ok(())
+	}
+}
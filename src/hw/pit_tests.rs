@@ -0,0 +1,114 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::pic::*;
+	use super::super::pit::*;
+
+	/* One PIT clock worth of CPU cycles */
+	const TICK: u32 = 4;
+
+	fn read_count(pit: &mut Pit, channel: usize) -> u16
+	{
+		let lo = pit.read_counter(channel) as u16;
+		let hi = pit.read_counter(channel) as u16;
+		lo | (hi << 8)
+	}
+
+	#[test]
+	fn interrupt_on_terminal_count()
+	{
+		let mut pic = Pic::new();
+		let mut pit = Pit::new();
+		pit.write_control(0x30); // Channel 0, lobyte/hibyte, mode 0
+		pit.write_counter(0, 10);
+		pit.write_counter(0, 0);
+
+		/* One clock to load the count, then 9 clocks down to 1 */
+		pit.step(&mut pic, 10 * TICK);
+		assert!(!pit.output(0));
+		assert!(!pic.has_interrupt());
+
+		pit.step(&mut pic, TICK);
+		assert!(pit.output(0));
+		assert!(pic.has_interrupt());
+		assert_eq!(pic.acknowledge(), 0x08);
+	}
+
+	#[test]
+	fn square_wave()
+	{
+		let mut pic = Pic::new();
+		let mut pit = Pit::new();
+		pit.write_control(0x16); // Channel 0, lobyte, mode 3
+		pit.write_counter(0, 5);
+
+		/* Odd count: high for 3 clocks, low for 2 */
+		pit.step(&mut pic, TICK);
+		let mut levels = Vec::new();
+		for _ in 0..10
+		{
+			levels.push(pit.output(0));
+			pit.step(&mut pic, TICK);
+		}
+		assert_eq!(levels, [true, true, true, false, false, true, true, true, false, false]);
+	}
+
+	#[test]
+	fn rate_generator()
+	{
+		let mut pic = Pic::new();
+		let mut pit = Pit::new();
+		pit.write_control(0x54); // Channel 1, lobyte, mode 2
+		pit.write_counter(1, 3);
+
+		pit.step(&mut pic, TICK);
+		let mut levels = Vec::new();
+		for _ in 0..6
+		{
+			levels.push(pit.output(1));
+			pit.step(&mut pic, TICK);
+		}
+		assert_eq!(levels, [true, true, false, true, true, false]);
+	}
+
+	#[test]
+	fn latch_and_read_back()
+	{
+		let mut pic = Pic::new();
+		let mut pit = Pit::new();
+		pit.write_control(0x34); // Channel 0, lobyte/hibyte, mode 2
+		pit.write_counter(0, 0x34);
+		pit.write_counter(0, 0x12);
+		pit.step(&mut pic, 3 * TICK);
+
+		pit.write_control(0x00); // Latch channel 0
+		pit.step(&mut pic, 5 * TICK);
+		assert_eq!(read_count(&mut pit, 0), 0x1232);
+		assert_eq!(read_count(&mut pit, 0), 0x122d);
+
+		pit.write_control(0xe2); // Read-back status of channel 0
+		assert_eq!(pit.read_counter(0), 0x80 | 0x34);
+	}
+
+	#[test]
+	fn gated_one_shot()
+	{
+		let mut pic = Pic::new();
+		let mut pit = Pit::new();
+		pit.write_control(0xb2); // Channel 2, lobyte/hibyte, mode 1
+		pit.write_counter(2, 4);
+		pit.write_counter(2, 0);
+
+		/* Nothing happens until the gate rises */
+		pit.step(&mut pic, 10 * TICK);
+		assert!(pit.output(2));
+
+		pit.set_gate(2, true);
+		pit.step(&mut pic, TICK);
+		assert!(!pit.output(2));
+		pit.step(&mut pic, 3 * TICK);
+		assert!(!pit.output(2));
+		pit.step(&mut pic, TICK);
+		assert!(pit.output(2));
+	}
+}
@@ -1,98 +1,90 @@
-extern crate sdl2;
+/* SDL scancode names of the emulated keys, used by input scripts */
+const KEY_NAMES: [(&'static str, u8); 78] =
+[
+	("Escape", 0x01),
+	("1", 0x02),
+	("2", 0x03),
+	("3", 0x04),
+	("4", 0x05),
+	("5", 0x06),
+	("6", 0x07),
+	("7", 0x08),
+	("8", 0x09),
+	("9", 0x0a),
+	("0", 0x0b),
+	("-", 0x0c),
+	("=", 0x0d),
+	("Backspace", 0x0e),
+	("Tab", 0x0f),
+	("Q", 0x10),
+	("W", 0x11),
+	("E", 0x12),
+	("R", 0x13),
+	("T", 0x14),
+	("Y", 0x15),
+	("U", 0x16),
+	("I", 0x17),
+	("O", 0x18),
+	("P", 0x19),
+	("[", 0x1a),
+	("]", 0x1b),
+	("Return", 0x1c),
+	("Left Ctrl", 0x1d),
+	("A", 0x1e),
+	("S", 0x1f),
+	("D", 0x20),
+	("F", 0x21),
+	("G", 0x22),
+	("H", 0x23),
+	("J", 0x24),
+	("K", 0x25),
+	("L", 0x26),
+	(";", 0x27),
+	("'", 0x28),
+	("`", 0x29),
+	("Left Shift", 0x2a),
+	("\\", 0x2b),
+	("Z", 0x2c),
+	("X", 0x2d),
+	("C", 0x2e),
+	("V", 0x2f),
+	("B", 0x30),
+	("N", 0x31),
+	("M", 0x32),
+	(",", 0x33),
+	(".", 0x34),
+	("/", 0x35),
+	("Right Shift", 0x36),
+	("Left Alt", 0x38),
+	("Space", 0x39),
+	("CapsLock", 0x3a),
+	("F1", 0x3b),
+	("F2", 0x3c),
+	("F3", 0x3d),
+	("F4", 0x3e),
+	("F5", 0x3f),
+	("F6", 0x40),
+	("F7", 0x41),
+	("F8", 0x42),
+	("F9", 0x43),
+	("F10", 0x44),
+	("ScrollLock", 0x46),
+	("Home", 0x47),
+	("Up", 0x48),
+	("PageUp", 0x49),
+	("Left", 0x4b),
+	("Right", 0x4d),
+	("End", 0x4f),
+	("Down", 0x50),
+	("PageDown", 0x51),
+	("Insert", 0x52),
+	("Delete", 0x53),
+];
 
-use super::sdl2::keyboard::Scancode;
-
-pub fn sdlk_to_scancode(sdlk: Scancode) -> Option<u8>
+/* XT make code of a key, from its name (case insensitive) */
+pub fn scancode_from_name(name: &str) -> Option<u8>
 {
-	match sdlk
-	{
-		Scancode::Escape => Some(0x01),
-		Scancode::Num1 => Some(0x02),
-		Scancode::Num2 => Some(0x03),
-		Scancode::Num3 => Some(0x04),
-		Scancode::Num4 => Some(0x05),
-		Scancode::Num5 => Some(0x06),
-		Scancode::Num6 => Some(0x07),
-		Scancode::Num7 => Some(0x08),
-		Scancode::Num8 => Some(0x09),
-		Scancode::Num9 => Some(0x0a),
-		Scancode::Num0 => Some(0x0b),
-		Scancode::Minus => Some(0x0c),
-		Scancode::Equals => Some(0x0d),
-		Scancode::Backspace => Some(0x0e),
-		Scancode::Tab => Some(0x0f),
-		Scancode::Q => Some(0x10),
-		Scancode::W => Some(0x11),
-		Scancode::E => Some(0x12),
-		Scancode::R => Some(0x13),
-		Scancode::T => Some(0x14),
-		Scancode::Y => Some(0x15),
-		Scancode::U => Some(0x16),
-		Scancode::I => Some(0x17),
-		Scancode::O => Some(0x18),
-		Scancode::P => Some(0x19),
-		Scancode::LeftBracket => Some(0x1a),
-		Scancode::RightBracket => Some(0x1b),
-		Scancode::Return => Some(0x1c),
-		Scancode::LCtrl => Some(0x1d),
-		Scancode::A => Some(0x1e),
-		Scancode::S => Some(0x1f),
-		Scancode::D => Some(0x20),
-		Scancode::F => Some(0x21),
-		Scancode::G => Some(0x22),
-		Scancode::H => Some(0x23),
-		Scancode::J => Some(0x24),
-		Scancode::K => Some(0x25),
-		Scancode::L => Some(0x26),
-		Scancode::Semicolon => Some(0x27),
-		Scancode::Apostrophe => Some(0x28),
-		Scancode::Grave => Some(0x29),
-		Scancode::LShift => Some(0x2a),
-		Scancode::Backslash => Some(0x2b),
-		//on a 102-key keyboard
-		Scancode::Z => Some(0x2c),
-		Scancode::X => Some(0x2d),
-		Scancode::C => Some(0x2e),
-		Scancode::V => Some(0x2f),
-		Scancode::B => Some(0x30),
-		Scancode::N => Some(0x31),
-		Scancode::M => Some(0x32),
-		Scancode::Comma => Some(0x33),
-		Scancode::Period => Some(0x34),
-		Scancode::Slash => Some(0x35),
-		Scancode::RShift => Some(0x36),
-		//Scancode::*/PrtScn => Some(0x37 (Keypad-*) or on a 83/84-key keyboard),
-		Scancode::LAlt => Some(0x38),
-		Scancode::Space => Some(0x39),
-		Scancode::CapsLock => Some(0x3a),
-		Scancode::F1 => Some(0x3b),
-		Scancode::F2 => Some(0x3c),
-		Scancode::F3 => Some(0x3d),
-		Scancode::F4 => Some(0x3e),
-		Scancode::F5 => Some(0x3f),
-		Scancode::F6 => Some(0x40),
-		Scancode::F7 => Some(0x41),
-		Scancode::F8 => Some(0x42),
-		Scancode::F9 => Some(0x43),
-		Scancode::F10 => Some(0x44),
-		//Scancode::NumLock => Some(0x45),
-		Scancode::ScrollLock => Some(0x46),
-		Scancode::Home => Some(0x47),
-		Scancode::Up => Some(0x48),
-		Scancode::PageUp => Some(0x49),
-		//Scancode::=> Some(0x4a),
-		Scancode::Left => Some(0x4b),
-		//Scancode::=> Some(0x4c),
-		Scancode::Right => Some(0x4d),
-		//Scancode::=> Some(0x4e),
-		Scancode::End => Some(0x4f),
-		Scancode::Down => Some(0x50),
-		Scancode::PageDown => Some(0x51),
-		Scancode::Insert => Some(0x52),
-		Scancode::Delete => Some(0x53),
-		//Scancode::Alt-SysRq => Some(0x54 on a 84+ key keyboard),
-		_ => None
-	}
+	KEY_NAMES.iter().find(|&&(key, _)| key.eq_ignore_ascii_case(name)).map(|&(_, scancode)| scancode)
 }
 
 pub fn scancode_to_ascii(scancode: u8, shifted: bool) -> Option<u8>
@@ -1,5 +1,6 @@
 use std::collections::vec_deque::VecDeque;
-use super::super::cpu::CPU;
+use super::pic::Pic;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 pub trait SerialDevice<DeviceType>
 {
@@ -16,9 +17,9 @@ enum SerialIrq
 	//StatusChange
 }
 
-/* Retain the RTD register value for some cycles before
+/* Retain the RTD register value for some CPU cycles before
  * sending a new byte. */
-const CYCLES_BEFORE_NEXT_READ: u32 = 1;
+const CYCLES_BEFORE_NEXT_READ: u32 = 10;
 
 impl SerialIrq
 {
@@ -315,12 +316,9 @@ impl<T: SerialDevice<T>> SerialPort<T>
 			self.interrupt_enable);
 	}
 
-	pub fn step(&mut self, cpu: &mut CPU)
+	pub fn step(&mut self, pic: &mut Pic, cycles: u32)
 	{
-		if self.cycles_before_next_read > 0
-		{
-			self.cycles_before_next_read -= 1;
-		}
+		self.cycles_before_next_read = self.cycles_before_next_read.saturating_sub(cycles);
 
 		if self.cycles_before_next_read == 0 && self.current_byte_read
 		{
@@ -331,10 +329,62 @@ impl<T: SerialDevice<T>> SerialPort<T>
 				self.current_byte_read = false;
 				if self.interrupt_enable && self.data_avail_irq
 				{
-					cpu.queue_hw_interrupt(0x0C);
+					pic.pulse_irq(4);
 					self.irq_queue.push_back(SerialIrq::DataAvail);
 				}
 			}
 		}
 	}
-}
\ No newline at end of file
+}
+
+impl<T: SerialDevice<T> + Snapshot> Snapshot for SerialPort<T>
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"UART");
+		writer.write_bool(self.data_avail_irq);
+		writer.write_bool(self.transmitter_empty_irq);
+		writer.write_bool(self.error_irq);
+		writer.write_bool(self.status_change_irq);
+		writer.write_u8(self.word_length);
+		writer.write_u8(self.stop_bits);
+		writer.write_bool(self.parity_enable);
+		writer.write_bool(self.baud_rate_divisor_latch);
+		writer.write_u16(self.baud_rate_divisor);
+		writer.write_bool(self.data_terminal_ready);
+		writer.write_bool(self.request_to_send);
+		writer.write_bool(self.interrupt_enable);
+		writer.write_u32(self.cycles_before_next_read);
+		writer.write_bool(self.current_byte_read);
+		writer.write_u8(self.current_read_byte);
+		let data_for_guest: Vec<u8> = self.data_for_guest.iter().cloned().collect();
+		writer.write_blob(&data_for_guest);
+		/* Only 'data available' interrupts exist */
+		writer.write_u32(self.irq_queue.len() as u32);
+		self.device.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"UART")?;
+		self.data_avail_irq = reader.read_bool()?;
+		self.transmitter_empty_irq = reader.read_bool()?;
+		self.error_irq = reader.read_bool()?;
+		self.status_change_irq = reader.read_bool()?;
+		self.word_length = reader.read_u8()?;
+		self.stop_bits = reader.read_u8()?;
+		self.parity_enable = reader.read_bool()?;
+		self.baud_rate_divisor_latch = reader.read_bool()?;
+		self.baud_rate_divisor = reader.read_u16()?;
+		self.data_terminal_ready = reader.read_bool()?;
+		self.request_to_send = reader.read_bool()?;
+		self.interrupt_enable = reader.read_bool()?;
+		self.cycles_before_next_read = reader.read_u32()?;
+		self.current_byte_read = reader.read_bool()?;
+		self.current_read_byte = reader.read_u8()?;
+		self.data_for_guest = reader.read_blob()?.into_iter().collect();
+		let irq_count = reader.read_u32()?;
+		self.irq_queue = (0..irq_count).map(|_| SerialIrq::DataAvail).collect();
+		self.device.load_state(reader)
+	}
+}
@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+use super::super::cpu::CPU_FREQUENCY_HZ;
+use super::super::frontend::{Frontend, AUDIO_SAMPLE_RATE};
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+/* Emulates the PC speaker: its input is the output of the PIT channel 2,
+ * ANDed with bit 1 of port 0x61 (bit 0 gates the PIT channel itself) */
+
+const SAMPLE_RATE: u32 = AUDIO_SAMPLE_RATE;
+const AMPLITUDE: i32 = 8192;
+/* Samples are handed to the sink by blocks */
+const BUFFER_SAMPLES: usize = 1024;
+
+const PPI_SPEAKER_DATA: u8 = 0x2;
+
+/* 16-bit mono PCM WAV file; the sizes in the header are only known, and
+ * patched, when the file is closed */
+struct WavWriter
+{
+	file: File,
+	data_size: u32
+}
+
+impl WavWriter
+{
+	fn new(filename: &str) -> WavWriter
+	{
+		let mut writer = WavWriter
+		{
+			file: File::create(filename).unwrap(),
+			data_size: 0
+		};
+		let header = writer.header();
+		writer.file.write_all(&header).unwrap();
+		writer
+	}
+
+	fn header(&self) -> Vec<u8>
+	{
+		let mut header = Vec::with_capacity(44);
+		header.extend_from_slice(b"RIFF");
+		header.extend_from_slice(&u32_le(36 + self.data_size));
+		header.extend_from_slice(b"WAVEfmt ");
+		header.extend_from_slice(&u32_le(16)); // fmt chunk size
+		header.extend_from_slice(&[1, 0]); // PCM
+		header.extend_from_slice(&[1, 0]); // Mono
+		header.extend_from_slice(&u32_le(SAMPLE_RATE));
+		header.extend_from_slice(&u32_le(SAMPLE_RATE * 2)); // Byte rate
+		header.extend_from_slice(&[2, 0]); // Block align
+		header.extend_from_slice(&[16, 0]); // Bits per sample
+		header.extend_from_slice(b"data");
+		header.extend_from_slice(&u32_le(self.data_size));
+		header
+	}
+
+	fn write_samples(&mut self, samples: &[i16])
+	{
+		let mut data = Vec::with_capacity(samples.len() * 2);
+		for sample in samples
+		{
+			data.push(*sample as u8);
+			data.push((*sample >> 8) as u8);
+		}
+
+		match self.file.write_all(&data)
+		{
+			Ok(()) => self.data_size += data.len() as u32,
+			Err(e) => panic!("Error while writing WAV file: {}", e)
+		}
+	}
+
+	/* Writes the final sizes in the header */
+	fn close(&mut self)
+	{
+		let header = self.header();
+		let result = self.file.seek(SeekFrom::Start(0))
+			.and_then(|_| self.file.write_all(&header))
+			.and_then(|_| self.file.seek(SeekFrom::End(0)))
+			.and_then(|_| self.file.flush());
+		if let Err(e) = result
+		{
+			println!("Warning: unable to finish the WAV file: {}", e);
+		}
+	}
+}
+
+fn u32_le(val: u32) -> [u8; 4]
+{
+	[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]
+}
+
+enum AudioSink
+{
+	Frontend,
+	Wav(WavWriter),
+	/* The WAV file was finished, later samples are dropped */
+	Closed
+}
+
+pub struct Speaker
+{
+	sink: AudioSink,
+
+	ppi_bits: u8,
+
+	/* Cycles elapsed in the current sample, multiplied by the sample rate */
+	sample_time: u64,
+	/* Cycles spent with the speaker cone pushed out in the current sample */
+	high_cycles: u64,
+	total_cycles: u64,
+
+	buffer: Vec<i16>
+}
+
+impl Speaker
+{
+	/* Plays through the frontend, or writes to a WAV file if a filename is
+	 * given */
+	pub fn new(wav_filename: Option<String>) -> Speaker
+	{
+		let sink = match wav_filename
+		{
+			Some(fname) => AudioSink::Wav(WavWriter::new(&fname[..])),
+			None => AudioSink::Frontend
+		};
+
+		Speaker
+		{
+			sink: sink,
+			ppi_bits: 0,
+			sample_time: 0,
+			high_cycles: 0,
+			total_cycles: 0,
+			buffer: Vec::with_capacity(BUFFER_SAMPLES)
+		}
+	}
+
+	/* Port 0x61 */
+	pub fn set_ppi_bits(&mut self, ppi_a: u8)
+	{
+		self.ppi_bits = ppi_a;
+	}
+
+	/* Accounts for 'cycles' CPU cycles spent with the given PIT channel 2
+	 * output */
+	pub fn step(&mut self, frontend: &mut dyn Frontend, timer2_out: bool, cycles: u32)
+	{
+		let high = timer2_out && (self.ppi_bits & PPI_SPEAKER_DATA != 0);
+		let mut cycles = cycles as u64;
+
+		while cycles > 0
+		{
+			/* Cycles left before the end of the current sample */
+			let left = (CPU_FREQUENCY_HZ - self.sample_time).div_ceil(SAMPLE_RATE as u64);
+			let spent = if cycles < left { cycles } else { left };
+
+			cycles -= spent;
+			self.total_cycles += spent;
+			if high
+			{
+				self.high_cycles += spent;
+			}
+
+			self.sample_time += spent * SAMPLE_RATE as u64;
+			if self.sample_time >= CPU_FREQUENCY_HZ
+			{
+				self.sample_time -= CPU_FREQUENCY_HZ;
+				self.push_sample(frontend);
+			}
+		}
+	}
+
+	fn push_sample(&mut self, frontend: &mut dyn Frontend)
+	{
+		/* Average the level over the sample period */
+		let sample = (self.high_cycles as i64 * AMPLITUDE as i64 / self.total_cycles as i64) as i16;
+		self.high_cycles = 0;
+		self.total_cycles = 0;
+
+		self.buffer.push(sample);
+		if self.buffer.len() >= BUFFER_SAMPLES
+		{
+			self.flush(frontend);
+		}
+	}
+
+	fn flush(&mut self, frontend: &mut dyn Frontend)
+	{
+		match self.sink
+		{
+			AudioSink::Frontend => frontend.queue_audio(&self.buffer),
+			AudioSink::Wav(ref mut writer) => writer.write_samples(&self.buffer),
+			AudioSink::Closed => {}
+		}
+		self.buffer.clear();
+	}
+
+	/* Finishes the WAV file, if any, with the samples not written yet; must
+	 * be called before the process exits, since that skips the destructors */
+	pub fn close(&mut self)
+	{
+		if let AudioSink::Wav(ref mut writer) = self.sink
+		{
+			writer.write_samples(&self.buffer);
+			writer.close();
+		}
+		else
+		{
+			return
+		}
+		self.buffer.clear();
+		self.sink = AudioSink::Closed;
+	}
+}
+
+impl Drop for Speaker
+{
+	fn drop(&mut self)
+	{
+		self.close();
+	}
+}
+
+impl Snapshot for Speaker
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"SPKR");
+		writer.write_u8(self.ppi_bits);
+		writer.write_u64(self.sample_time);
+		writer.write_u64(self.high_cycles);
+		writer.write_u64(self.total_cycles);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"SPKR")?;
+		self.ppi_bits = reader.read_u8()?;
+		self.sample_time = reader.read_u64()?;
+		self.high_cycles = reader.read_u64()?;
+		self.total_cycles = reader.read_u64()?;
+		if self.sample_time >= CPU_FREQUENCY_HZ || self.high_cycles > self.total_cycles
+		{
+			return Err("invalid PC speaker sample state".to_string())
+		}
+		/* Samples of the current session which were not played yet are dropped */
+		self.buffer.clear();
+		Ok(())
+	}
+}
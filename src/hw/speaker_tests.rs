@@ -0,0 +1,109 @@
+#[cfg(test)]
+mod tests
+{
+	use std::env;
+	use std::fs;
+	use super::super::pic::Pic;
+	use super::super::pit::Pit;
+	use super::super::speaker::*;
+	use super::super::super::frontend::{Frontend, Framebuffer, InputEvent};
+
+	/* Full level of a sample */
+	const HIGH: i16 = 8192;
+
+	/* Keeps the samples the speaker plays */
+	struct Recorder
+	{
+		samples: Vec<i16>
+	}
+
+	impl Frontend for Recorder
+	{
+		fn poll_event(&mut self) -> Option<InputEvent> { None }
+		fn wait_event(&mut self) -> InputEvent { panic!("No input") }
+		fn has_input(&self) -> bool { false }
+		fn shows_frames(&self) -> bool { false }
+		fn present_frame(&mut self, _frame: &Framebuffer) {}
+		fn queue_audio(&mut self, samples: &[i16]) { self.samples.extend_from_slice(samples) }
+	}
+
+	/* PIT channel 2 programmed as a square wave generator */
+	fn square_wave(count: u16) -> Pit
+	{
+		let mut pit = Pit::new();
+		pit.write_control(0xb6); // Channel 2, lobyte/hibyte, mode 3
+		pit.write_counter(2, count as u8);
+		pit.write_counter(2, (count >> 8) as u8);
+		pit
+	}
+
+	/* Writes 'port61' to port 0x61 then runs the PIT and the speaker, like
+	 * HW::step does, until the first block of samples is played */
+	fn record(mut pit: Pit, port61: u8) -> Vec<i16>
+	{
+		let mut pic = Pic::new();
+		let mut speaker = Speaker::new(None);
+		let mut recorder = Recorder { samples: Vec::new() };
+		pit.set_gate(2, port61 & 0x1 != 0);
+		speaker.set_ppi_bits(port61);
+
+		while recorder.samples.is_empty()
+		{
+			pit.step(&mut pic, 4);
+			speaker.step(&mut recorder, pit.output(2), 4);
+		}
+		recorder.samples
+	}
+
+	#[test]
+	fn tone()
+	{
+		/* 1193182 / 1193 = 1000 Hz, about 44 samples per period */
+		let samples = record(square_wave(1193), 0x03);
+		assert!(samples.iter().all(|sample| (0 ..= HIGH).contains(sample)));
+		assert!(samples.contains(&0) && samples.contains(&HIGH));
+
+		let rising_edges = samples.windows(2).filter(|pair| pair[0] < HIGH / 2 && pair[1] >= HIGH / 2).count();
+		assert!((22 ..= 24).contains(&rising_edges), "{} periods", rising_edges);
+	}
+
+	#[test]
+	fn port_61_bits()
+	{
+		/* Speaker data disabled: silence, whatever the PIT does */
+		assert!(record(square_wave(1193), 0x01).iter().all(|&sample| sample == 0));
+		assert!(record(square_wave(1193), 0x00).iter().all(|&sample| sample == 0));
+
+		/* PIT gate low: the output of mode 3 stays high, and so does the cone */
+		assert!(record(square_wave(1193), 0x02).iter().all(|&sample| sample == HIGH));
+	}
+
+	#[test]
+	fn wav_file()
+	{
+		let path = env::temp_dir().join("riapyx_speaker.wav");
+		let filename = path.to_str().unwrap();
+		{
+			let mut speaker = Speaker::new(Some(filename.to_string()));
+			let mut recorder = Recorder { samples: Vec::new() };
+			speaker.set_ppi_bits(0x02);
+			/* About 200 samples: less than a block, written when closing */
+			for _ in 0 .. 200
+			{
+				speaker.step(&mut recorder, true, 108);
+			}
+			assert!(recorder.samples.is_empty());
+		}
+		let wav = fs::read(filename).unwrap();
+		fs::remove_file(filename).unwrap();
+
+		let u32_at = |offset: usize| wav[offset] as u32 | (wav[offset + 1] as u32) << 8 | (wav[offset + 2] as u32) << 16 | (wav[offset + 3] as u32) << 24;
+		let data_size = wav.len() as u32 - 44;
+		assert!((2 * 198 ..= 2 * 200).contains(&data_size));
+		assert_eq!(&wav[0 .. 4], b"RIFF");
+		assert_eq!(u32_at(4), 36 + data_size);
+		assert_eq!(&wav[36 .. 40], b"data");
+		assert_eq!(u32_at(40), data_size);
+		assert!(wav[44 ..].chunks(2).all(|sample| sample == [0x00, 0x20]));
+	}
+}
@@ -4,6 +4,7 @@ use std::io::SeekFrom;
 use std::io::prelude::*;
 use std::fs::File;
 use std::fs::OpenOptions;
+use super::super::snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
 
 const DISKETTE_PARAMETERS_IRQ: u8 = 0x1E;
 const HDD_PARAMETERS_IRQ: u8 = 0x41;
@@ -41,6 +42,7 @@ pub enum DriveParameters
 pub struct Storage
 {
 	file: File,
+	filename: String,
 	pub parameters: DriveParameters
 }
 
@@ -244,6 +246,7 @@ impl Storage
 		Storage
 		{
 			file: OpenOptions::new().read(true).write(true).open(filename).unwrap(),
+			filename: filename.to_string(),
 			parameters: DriveParameters::Floppy(DisketteParameters::floppy_320kb())
 		}
 	}
@@ -259,6 +262,7 @@ impl Storage
 		Storage
 		{
 			file: hdd_file,
+			filename: filename.to_string(),
 			parameters: DriveParameters::HardDisk(HardDiskParameters::new(cylinders, heads, sectors_per_track))
 		}
 	}
@@ -386,6 +390,37 @@ impl Storage
 		}
 	}
 
+	fn image_size(&self) -> u64
+	{
+		self.file.metadata().unwrap().len()
+	}
+
+	/* FNV-1a hash of the whole image */
+	fn checksum(&self) -> u64
+	{
+		let mut file = &self.file;
+		file.seek(SeekFrom::Start(0)).unwrap();
+
+		let mut hash: u64 = 0xcbf29ce484222325;
+		let mut buf = vec![0; 64 * 1024];
+		loop
+		{
+			match file.read(&mut buf)
+			{
+				Ok(0) => break,
+				Ok(read) =>
+				{
+					for byte in &buf[..read]
+					{
+						hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+					}
+				}
+				Err(e) => panic!("Error while reading file: {}", e)
+			}
+		}
+		hash
+	}
+
 	/* -> (ivt_entry_to_overwrite, seg, addr) */
 	pub fn init(&self, mem: &mut Memory) -> (u8, u16, u16)
 	{
@@ -408,6 +443,94 @@ impl Storage
 	}
 }
 
+/* The image content is not part of the snapshot, only its identity: the
+ * same image must be attached when restoring it */
+impl Snapshot for Storage
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"DISK");
+		writer.write_str(&self.filename);
+		writer.write_u64(self.image_size());
+		writer.write_u64(self.checksum());
+
+		match self.parameters
+		{
+			DriveParameters::Floppy(ref params) =>
+			{
+				writer.write_u8(0);
+				writer.write_bytes(&[
+					params.step_rate_hi_head_unload_time_lo,
+					params.head_load_time_hi7b_non_dma_lo,
+					params.delay_until_motor_off,
+					params.bytes_per_sector,
+					params.sectors_per_track,
+					params.gap_len_between_sectors,
+					params.data_length,
+					params.gap_length,
+					params.format_filler,
+					params.head_settle_time,
+					params.motor_start_time]);
+			}
+			DriveParameters::HardDisk(ref params) =>
+			{
+				writer.write_u8(1);
+				writer.write_u16(params.cylinders);
+				writer.write_u8(params.heads);
+				writer.write_u8(params.control_byte);
+				writer.write_u16(params.landing_zone_cylinder);
+				writer.write_u8(params.sectors_per_track);
+			}
+		}
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"DISK")?;
+		let filename = reader.read_str()?;
+		let size = reader.read_u64()?;
+		let checksum = reader.read_u64()?;
+		if size != self.image_size()
+		{
+			return Err(format!("the snapshot was taken with image '{}' ({} bytes), but '{}' ({} bytes) is attached",
+				filename, size, self.filename, self.image_size()))
+		}
+		if checksum != self.checksum()
+		{
+			println!("Warning: the content of '{}' changed since the snapshot was taken with '{}'", self.filename, filename);
+		}
+
+		match (reader.read_u8()?, &mut self.parameters)
+		{
+			(0, &mut DriveParameters::Floppy(ref mut params)) =>
+			{
+				let bytes = reader.read_bytes(11)?;
+				params.step_rate_hi_head_unload_time_lo = bytes[0];
+				params.head_load_time_hi7b_non_dma_lo = bytes[1];
+				params.delay_until_motor_off = bytes[2];
+				params.bytes_per_sector = bytes[3];
+				params.sectors_per_track = bytes[4];
+				params.gap_len_between_sectors = bytes[5];
+				params.data_length = bytes[6];
+				params.gap_length = bytes[7];
+				params.format_filler = bytes[8];
+				params.head_settle_time = bytes[9];
+				params.motor_start_time = bytes[10];
+			}
+			(1, &mut DriveParameters::HardDisk(ref mut params)) =>
+			{
+				params.cylinders = reader.read_u16()?;
+				params.heads = reader.read_u8()?;
+				params.control_byte = reader.read_u8()?;
+				params.landing_zone_cylinder = reader.read_u16()?;
+				params.sectors_per_track = reader.read_u8()?;
+			}
+			_ => return Err(format!("drive type mismatch for image '{}'", filename))
+		}
+		Ok(())
+	}
+}
+
 impl Status
 {
 	pub fn get_bios_code(self) -> u8
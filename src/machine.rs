@@ -2,46 +2,89 @@ extern crate time;
 
 use std::fs::File;
 use std::io::Write;
+use std::thread;
+use std::time::Duration;
 
 use cpu::CPU;
-use cpu::phys_addr;
-use cpu::parse_instruction;
+use cpu::CpuModel;
+use cpu::Fpu;
+use cpu::CPU_FREQUENCY_HZ;
+use cpu::parse_model_instruction;
 use bios::BIOS;
 use hw::HW;
+use hw::InputSource;
+use frontend::Frontend;
 use cpu::CPUState;
 use bios::BIOSState;
 use bios::BootDrive;
 use mem::Memory;
+use snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+use symbols::SymbolTable;
 use cpu::instruction::*;
 use cpu::reg_access::*;
 
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Speed
+{
+	/* As fast as the original 4.77 MHz machine */
+	RealTime,
+	/* As fast as the host allows */
+	Unbounded
+}
+
+/* When running in real time, stop trying to catch up with the host clock
+ * once late by this amount (e.g. after a pause in the debugger) */
+const MAX_LAG_NS: u64 = 100000000;
+
 pub struct Machine
 {
 	cpu: CPU,
 	bios: BIOS,
 	memory: Memory,
 	pub hw: HW,
+	/* Debugger symbols, shown in disassemblies */
+	pub symbols: SymbolTable,
 
-	clock: u32,
-	last_time_ns: u64, // Only updated every 1k cycles
+	clock: u32, // Instructions
+	cycles: u64, // CPU clock cycles
+	speed: Speed,
+	last_time_ns: u64, // Only updated every 1k instructions
 	last_mcycle_ns: u64,
+	last_mcycle_cycles: u64,
+	/* Reference points of the emulated and host clocks */
+	sync_cycles: u64,
+	sync_time_ns: u64,
 
     trace: bool
 }
 
 impl Machine
 {
-	pub fn new(boot_drive: BootDrive, floppy_filename: Option<String>, hdd_filename: Option<String>) -> Machine
+	pub fn new(boot_drive: BootDrive, floppy_filename: Option<String>, hdd_filename: Option<String>, wav_filename: Option<String>, cpu_model: CpuModel, fpu: bool, speed: Speed, input: InputSource, frontend: Box<dyn Frontend>) -> Machine
 	{
+		/* The 80286 addresses 16 MB, as a fully populated PC/AT */
+		let memory_size = if cpu_model.has_protected_mode() { 16 * 1024 * 1024 } else { 1024 * 1024 };
+		let extended_kb = ((memory_size - 1024 * 1024) / 1024) as u16;
+		let mut cpu = CPU::new(0xf000, 0xfff0, cpu_model);
+		if fpu
+		{
+			cpu.fpu = Some(Fpu::new());
+		}
 		Machine
 		{
-			cpu: CPU::new(0xf000, 0xfff0),
+			cpu: cpu,
 			bios: BIOS::new(boot_drive),
-			memory: Memory::new(1024 * 1024),
-			hw: HW::new(floppy_filename, hdd_filename),
+			memory: Memory::new(memory_size),
+			hw: HW::new(floppy_filename, hdd_filename, wav_filename, extended_kb, input, frontend),
+			symbols: SymbolTable::new(),
 			clock: 0,
+			cycles: 0,
+			speed: speed,
 			last_time_ns: time::precise_time_ns(),
 			last_mcycle_ns: time::precise_time_ns(),
+			last_mcycle_cycles: 0,
+			sync_cycles: 0,
+			sync_time_ns: time::precise_time_ns(),
             trace: false
 		}
 	}
@@ -51,10 +94,37 @@ impl Machine
 		(self.cpu.get_reg(SegReg::CS), self.cpu.get_reg(WReg::IP))
 	}
 
+	/* For debuggers */
+	pub fn cpu(&self) -> &CPU
+	{
+		&self.cpu
+	}
+
+	pub fn cpu_mut(&mut self) -> &mut CPU
+	{
+		&mut self.cpu
+	}
+
+	/* For CPU methods that need the memory too */
+	pub fn cpu_and_memory_mut(&mut self) -> (&mut CPU, &mut Memory)
+	{
+		(&mut self.cpu, &mut self.memory)
+	}
+
+	pub fn memory(&self) -> &Memory
+	{
+		&self.memory
+	}
+
+	pub fn memory_mut(&mut self) -> &mut Memory
+	{
+		&mut self.memory
+	}
+
 	pub fn step(&mut self)
 	{
         if !self.is_running() {
-            self.hw.try_pump_event(&mut self.cpu);
+            self.hw.try_pump_event();
             return;
         }
 
@@ -63,10 +133,18 @@ impl Machine
 		if self.clock % 1000 == 0
 		{
 			self.last_time_ns = time::precise_time_ns();
+			if self.speed == Speed::RealTime
+			{
+				self.throttle();
+			}
 		}
 
-		self.cpu.step(&mut self.memory, &mut self.hw, &mut self.bios);
-		self.hw.step(&mut self.cpu, &mut self.memory, self.clock, self.last_time_ns);
+		self.memory.start_watching();
+		let cycles = self.cpu.step(&mut self.memory, &mut self.hw, &mut self.bios);
+		self.memory.stop_watching();
+		self.cycles += cycles as u64;
+		let time_ns = self.device_time_ns();
+		self.hw.step(&mut self.memory, self.clock, time_ns, cycles);
 
         if self.trace {
             self.dump_trace();
@@ -74,8 +152,47 @@ impl Machine
 
 		if self.clock % 10000000 == 0
 		{
-			machine_print!("Cycles/s: {}", 1e16 as u64 / (self.last_time_ns - self.last_mcycle_ns));
+			machine_print!("Instructions/s: {}, cycles/s: {}",
+				1e16 as u64 / (self.last_time_ns - self.last_mcycle_ns),
+				(self.cycles - self.last_mcycle_cycles) * 1000000000 / (self.last_time_ns - self.last_mcycle_ns));
 			self.last_mcycle_ns = self.last_time_ns;
+			self.last_mcycle_cycles = self.cycles;
+		}
+	}
+
+	/* Time seen by the devices: the host one, or the emulated one in
+	 * deterministic mode */
+	fn device_time_ns(&self) -> u64
+	{
+		if self.hw.is_deterministic()
+		{
+			self.cycles * 1000000000 / CPU_FREQUENCY_HZ
+		}
+		else
+		{
+			self.last_time_ns
+		}
+	}
+
+	pub fn cycles(&self) -> u64
+	{
+		self.cycles
+	}
+
+	/* Sleeps if the emulated clock is ahead of the host clock */
+	fn throttle(&mut self)
+	{
+		let emulated_ns = (self.cycles - self.sync_cycles) * 1000000000 / CPU_FREQUENCY_HZ;
+		let host_ns = self.last_time_ns - self.sync_time_ns;
+
+		if emulated_ns > host_ns
+		{
+			thread::sleep(Duration::from_nanos(emulated_ns - host_ns));
+		}
+		else if host_ns - emulated_ns > MAX_LAG_NS
+		{
+			self.sync_cycles = self.cycles;
+			self.sync_time_ns = self.last_time_ns;
 		}
 	}
 
@@ -147,18 +264,102 @@ impl Machine
 		}
 	}
 
+	pub fn save_snapshot(&self, fname: &str) -> Result<(), String>
+	{
+		let mut writer = SnapshotWriter::new();
+		self.save_state(&mut writer);
+		writer.save(fname)
+	}
+
+	/* On failure, the machine is left as it was */
+	pub fn load_snapshot(&mut self, fname: &str) -> Result<(), String>
+	{
+		let mut reader = SnapshotReader::open(fname)?;
+
+		let mut backup = SnapshotWriter::new();
+		self.save_state(&mut backup);
+
+		match self.load_state(&mut reader).and_then(|_| reader.finish())
+		{
+			Ok(()) =>
+			{
+				/* It cannot be undone across a snapshot */
+				self.cpu.history.clear();
+				Ok(())
+			},
+			Err(e) =>
+			{
+				let mut backup_reader = SnapshotReader::from_bytes(backup.into_bytes()).unwrap();
+				self.load_state(&mut backup_reader).unwrap();
+				Err(e)
+			}
+		}
+	}
+
+	pub fn save_screenshot(&mut self, fname: &str)
+	{
+		self.hw.save_screenshot(&self.memory, fname);
+	}
+
 	pub fn disas(&self, seg: u16, addr_start: u16, count: u32)
 	{
 		let mut addr = addr_start;
 		for _ins_count in 0..count
 		{
-			let bytecode = self.memory.slice_from(phys_addr(seg, addr));
-			let instruction = parse_instruction(bytecode);
-			disas_print!("{:04x}:{:04x}: {}", seg, addr, instruction);
+			let bytecode = self.memory.slice_from(self.cpu.debugger_address(&self.memory, seg, addr));
+			let instruction = parse_model_instruction(bytecode, self.cpu.timing.model);
+			if let Some(label) = self.symbols.label_at(seg, addr)
+			{
+				disas_print!("{}:", label);
+			}
+			match branch_target(seg, addr, &instruction).and_then(|(cs, ip)| self.symbols.name_at(cs, ip))
+			{
+				Some(target) => { disas_print!("{:04x}:{:04x}: {} <{}>", seg, addr, instruction, target); }
+				None => { disas_print!("{:04x}:{:04x}: {}", seg, addr, instruction); }
+			}
 			addr += instruction.size as u16;
 		}
 	}
 
+	/* The instruction at cs:ip, with its prefixes */
+	pub fn instruction_text(&self, cs: u16, ip: u16) -> String
+	{
+		let mut text = String::new();
+		let mut offset = 0;
+		loop
+		{
+			let addr = self.cpu.debugger_address(&self.memory, cs, ip.wrapping_add(offset));
+			let sized = parse_model_instruction(self.memory.slice_from(addr), self.cpu.timing.model);
+			text.push_str(&sized.instruction.to_string());
+			match sized.instruction
+			{
+				Instruction::Prefix(_) if offset < 15 => text.push(' '),
+				_ => return text
+			}
+			offset += sized.size;
+		}
+	}
+
+	/* Decodes the instruction at CS:IP, its prefixes included in the size;
+	 * also tells whether it has a REP or REPNE prefix */
+	pub fn next_instruction(&self) -> (SizedInstruction, bool)
+	{
+		let ip = self.get_pc().1;
+		let mut size = 0;
+		let mut rep = false;
+		loop
+		{
+			let sized = parse_model_instruction(self.memory.slice_from(self.cpu.code_address(ip.wrapping_add(size))), self.cpu.timing.model);
+			match sized.instruction
+			{
+				Instruction::Prefix(Prefix::REP) | Instruction::Prefix(Prefix::REPNE) => rep = true,
+				Instruction::Prefix(_) => {},
+				instruction => return (SizedInstruction { instruction: instruction, size: size + sized.size }, rep)
+			}
+			size += sized.size;
+		}
+	}
+
 	pub fn is_running(&self) -> bool
 	{
 		self.cpu.state == CPUState::Running && self.bios.state == BIOSState::Ok
@@ -180,3 +381,61 @@ impl Machine
         self.cpu.state = CPUState::Paused;
     }
 }
+
+/* The destination of direct jumps and calls */
+fn branch_target(seg: u16, addr: u16, sized: &SizedInstruction) -> Option<(u16, u16)>
+{
+	let next = addr.wrapping_add(sized.size);
+	match sized.instruction
+	{
+		Instruction::SingleFCOperand(SingleOperandFCOpCode::CALL, FlowControlOperand::DirectSeg(rel)) |
+		Instruction::SingleFCOperand(SingleOperandFCOpCode::JMP, FlowControlOperand::DirectSeg(rel)) =>
+			Some((seg, next.wrapping_add(rel))),
+		Instruction::SingleFCOperand(SingleOperandFCOpCode::CALL, FlowControlOperand::DirectInterSeg(cs, ip)) |
+		Instruction::SingleFCOperand(SingleOperandFCOpCode::JMP, FlowControlOperand::DirectInterSeg(cs, ip)) =>
+			Some((cs, ip)),
+		Instruction::SingleBImmOperand(ref op, rel) => match *op
+		{
+			SingleBImmOperandOpCode::INT | SingleBImmOperandOpCode::INB | SingleBImmOperandOpCode::INW |
+			SingleBImmOperandOpCode::OUTB | SingleBImmOperandOpCode::OUTW | SingleBImmOperandOpCode::INVB |
+			SingleBImmOperandOpCode::INVW | SingleBImmOperandOpCode::OUTVB | SingleBImmOperandOpCode::OUTVW => None,
+			/* Short jumps and loops */
+			_ => Some((seg, next.wrapping_add(rel as i8 as u16)))
+		},
+		_ => None
+	}
+}
+
+impl Snapshot for Machine
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"MACH");
+		writer.write_u32(self.clock);
+		writer.write_u64(self.cycles);
+		self.cpu.save_state(writer);
+		self.bios.save_state(writer);
+		self.memory.save_state(writer);
+		self.hw.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"MACH")?;
+		self.clock = reader.read_u32()?;
+		self.cycles = reader.read_u64()?;
+		self.cpu.load_state(reader)?;
+		self.bios.load_state(reader)?;
+		self.memory.load_state(reader)?;
+		self.hw.load_state(reader)?;
+
+		/* Start measuring and throttling again from here */
+		let now = time::precise_time_ns();
+		self.last_time_ns = now;
+		self.last_mcycle_ns = now;
+		self.last_mcycle_cycles = self.cycles;
+		self.sync_cycles = self.cycles;
+		self.sync_time_ns = now;
+		Ok(())
+	}
+}
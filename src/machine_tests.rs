@@ -0,0 +1,47 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::machine::*;
+	use super::super::bios::BootDrive;
+	use super::super::cpu::*;
+	use super::super::frontend::headless::HeadlessFrontend;
+	use super::super::hw::InputSource;
+
+	/* In protected mode at 08:0100, CS being based at 0x10000 */
+	fn protected_machine() -> Machine
+	{
+		let mut m = Machine::new(BootDrive::Floppy, None, None, None, CpuModel::I80286, false, Speed::Unbounded,
+			InputSource::Host, Box::new(HeadlessFrontend::new()));
+		{
+			let (cpu, mem) = m.cpu_and_memory_mut();
+			mem.write_u16(0x1008, 0xffff);
+			mem.write_u16(0x100a, 0x0000);
+			mem.write_u8(0x100c, 0x01);
+			mem.write_u8(0x100d, 0x9a);
+			mem.write_u16(0x500, 0x0f);
+			mem.write_u16(0x502, 0x1000);
+			cpu.ds = 0x0000;
+			cpu.run_system_ins(mem, SystemOpCode::LGDT, WOperand::Direct(0x500));
+			cpu.ax = 0x0001;
+			cpu.run_system_ins(mem, SystemOpCode::LMSW, WOperand::Reg(WReg::AX));
+			cpu.protected_far_transfer(mem, 0x08, 0x0100, false);
+		}
+		m
+	}
+
+	#[test]
+	fn protected_mode_instruction_text()
+	{
+		let mut m = protected_machine();
+		/* MOV AX, 1234h in the segment, NOP where 08:0100 is in real mode */
+		for (i, &byte) in [0xb8, 0x34, 0x12].iter().enumerate()
+		{
+			m.memory_mut().write_u8(0x10100 + i as u32, byte);
+		}
+		m.memory_mut().write_u8(0x180, 0x90);
+
+		let expected = parse_model_instruction(&[0xb8, 0x34, 0x12], CpuModel::I80286).instruction.to_string();
+		assert_eq!(m.instruction_text(0x08, 0x0100), expected);
+		assert_eq!(m.cpu().debugger_address(m.memory(), 0x08, 0x0100), 0x10100);
+	}
+}
@@ -4,20 +4,47 @@ mod color_print;
 
 mod cpu;
 mod bios;
+mod bios_tests;
 mod mem;
+mod mem_tests;
 mod machine;
+mod machine_tests;
 mod hw;
+mod frontend;
+mod snapshot;
+mod snapshot_tests;
+mod gdb;
+mod gdb_tests;
+mod expr;
+mod expr_tests;
+mod symbols;
+mod symbols_tests;
+mod backtrace;
+mod backtrace_tests;
+mod script;
+mod script_tests;
+mod conformance;
+mod conformance_tests;
 
 use std::io;
 use std::io::prelude::*;
+use std::fs::File;
 use std::string::String;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::iter::Peekable;
+use std::str::SplitWhitespace;
 use std::env::args;
 use std::{thread, time};
 use std::sync::mpsc;
-use std::sync::mpsc::{SyncSender, Receiver};
+use std::sync::mpsc::{SyncSender, Receiver, TryRecvError};
 use bios::BootDrive;
 use hw::storage;
+use mem::{Watchpoint, WatchKind};
+use cpu::{Trap, TrapEvent, TrapKind};
+use cpu::instruction::*;
+use expr::{Expression, Register};
+use backtrace::FrameKind;
+use cpu::{RegisterAccess, REGISTER_NAMES, phys_addr};
 
 extern crate rustc_serialize;
 extern crate docopt;
@@ -28,14 +55,31 @@ extern crate iced_x86;
 
 const USAGE: &'static str = 
 "Usage:
-	riapyx [--boot=<drive>] [--hd=<image>] [--fd=<image>]
+	riapyx [--boot=<drive>] [--hd=<image>] [--fd=<image>] [--wav=<file>] [--cpu=<model>] [--fpu] [--speed=<speed>] [--headless] [--deterministic] [--input=<script>] [--cycles=<n>] [--dump=<file>] [--screenshot=<file>] [--load-state=<file>] [--save-state=<file>] [--gdb=<port>] [--script=<file>] [--batch]
+	riapyx --cpu-tests=<file> [--cpu=<model>]
 	riapyx [--help]
 
 Options:
-	--help           Display this message
-	--hd=<img>       Hard drive image
-	--fd=<img>       Floppy disk image
-	--boot=<drive>   Boot from floppy disk (fd) or hard drive (hd) [default: hd]
+	--help               Display this message
+	--hd=<img>           Hard drive image
+	--fd=<img>           Floppy disk image
+	--boot=<drive>       Boot from floppy disk (fd) or hard drive (hd) [default: hd]
+	--wav=<file>         Write the PC speaker output to a WAV file instead of playing it
+	--cpu=<model>        Emulated CPU: 8086, 8088, 80186, 80188 or 80286 [default: 8086]
+	--fpu                Add a numeric coprocessor: 8087, or 80287 next to an 80286
+	--speed=<speed>      Run at the original 4.77 MHz (real) or as fast as possible (max) [default: real]
+	--headless           Run without window, host input nor sound device (see --wav and --screenshot)
+	--deterministic      Derive all device timing from the emulated CPU clock and ignore host input
+	--input=<script>     Replay the input events of a script (deterministic mode only)
+	--cycles=<n>         Run for n CPU cycles without the debugger, then exit
+	--dump=<file>        With --cycles: dump the RAM content into a file before exiting
+	--screenshot=<file>  With --cycles: save the screen as a BMP file before exiting
+	--load-state=<file>  Restore a machine snapshot before starting
+	--save-state=<file>  With --cycles: save a machine snapshot before exiting
+	--gdb=<port>         Wait for a GDB connection on localhost:<port> and debug through it
+	--script=<file>      Run the debugger commands of a file before reading commands from stdin
+	--batch              Run the script, or the commands from stdin, then exit; the exit status is 1 if a command failed
+	--cpu-tests=<file>   Run each instruction of a JSON or CSV test corpus on a fresh CPU, compare the results and the decoding with iced-x86, then exit
 ";
 
 #[derive(Debug, RustcDecodable)]
@@ -43,279 +87,1675 @@ struct Args
 {
 	flag_hd: Option<String>,
 	flag_fd: Option<String>,
+	flag_wav: Option<String>,
+	flag_cpu: String,
+	flag_fpu: bool,
+	flag_speed: String,
+	flag_headless: bool,
+	flag_deterministic: bool,
+	flag_input: Option<String>,
+	flag_cycles: Option<u64>,
+	flag_dump: Option<String>,
+	flag_screenshot: Option<String>,
+	flag_load_state: Option<String>,
+	flag_save_state: Option<String>,
+	flag_gdb: Option<u16>,
+	flag_script: Option<String>,
+	flag_batch: bool,
+	flag_cpu_tests: Option<String>,
 	flag_boot: String
 }
 
-const ZERO_U8: u8 = '0' as u8;
-const NINE_U8: u8 = '9' as u8;
-const A_U8: u8 = 'a' as u8;
-const F_U8: u8 = 'f' as u8;
-fn u32_from_hex_str(s: &str) -> u32
+/* None if 's' is not a hexadecimal number fitting in 32 bits */
+fn u32_from_hex_str(s: &str) -> Option<u32>
 {
-	let mut res: u32 = 0;
-
-	for c in s.as_bytes()
+	if s.is_empty()
 	{
-		res *= 0x10;
-		
-		let to_add = 
-			match *c
-			{
-				ZERO_U8 ... NINE_U8 => c - ZERO_U8,
-				A_U8 ... F_U8 => (c - A_U8) + 0xa,
-				_ => panic!("Invalid hex string: {}", s)
-			};
+		return None
+	}
 
-		res += to_add as u32;
+	let mut res: u32 = 0;
+	for c in s.chars()
+	{
+		let digit = c.to_digit(16)?;
+		res = res.checked_mul(0x10)?.checked_add(digit)?;
 	}
+	Some(res)
+}
+
+fn u16_from_hex_str(s: &str) -> Option<u16>
+{
+	u32_from_hex_str(s).and_then(|val| if val <= 0xffff { Some(val as u16) } else { None })
+}
+
+struct Breakpoint
+{
+    id: i32,
+    /* Only stop when this evaluates to non-zero */
+    condition: Option<Expression>,
+    /* Number of times the condition held */
+    hits: u32,
+    /* Number of hits to go through before stopping */
+    ignore: u32
+}
 
-	res
+/* Where the machine stops after a step-over, step-out or run-to command */
+#[derive(Clone, Copy)]
+enum Goal
+{
+    Address(u16, u16),
+    /* Back at CS:IP in the same frame or an outer one, i.e. at SS:SP or
+     * above: recursive calls returning there do not count */
+    Return(u16, u16, u16, u16),
+    /* A return instruction popping its address from SS:SP or above */
+    FrameExit(u16, u16)
 }
 
 struct BreakpointManager
 {
     next: i32,
-    addr_bkpt: HashMap<(u16, u16), i32>,
+    addr_bkpt: HashMap<(u16, u16), Breakpoint>,
+    goal: Option<Goal>,
 }
 
 impl BreakpointManager
 {
-    fn add_breakpoint(&mut self, seg: u16, addr: u16) -> i32
+    /* Breakpoints and watchpoints share their numbering */
+    fn next_id(&mut self) -> i32
     {
-        let bkpt = self.next;
-        self.addr_bkpt.insert((seg, addr), bkpt);
+        let id = self.next;
         self.next += 1;
-        bkpt
+        id
+    }
+
+    fn add_breakpoint(&mut self, seg: u16, addr: u16, condition: Option<Expression>) -> i32
+    {
+        let id = self.next_id();
+        self.addr_bkpt.insert((seg, addr), Breakpoint { id, condition, hits: 0, ignore: 0 });
+        id
+    }
+
+    fn find(&mut self, id: i32) -> Option<&mut Breakpoint>
+    {
+        self.addr_bkpt.values_mut().find(|bkpt| bkpt.id == id)
+    }
+
+    fn remove_breakpoint(&mut self, id: i32) -> bool
+    {
+        let len = self.addr_bkpt.len();
+        self.addr_bkpt.retain(|_, bkpt| bkpt.id != id);
+        self.addr_bkpt.len() != len
+    }
+
+    /* Called before each step, returns true if the next instruction leaves
+     * the frame a step-out command is waiting for */
+    fn exits_frame(&self, m: &machine::Machine) -> bool
+    {
+        match self.goal {
+            Some(Goal::FrameExit(ss, sp)) if m.cpu().ss == ss && m.cpu().sp >= sp => {
+                match m.next_instruction().0.instruction {
+                    Instruction::FCNoOperandSeg(NoOpFCOpCode::RET) |
+                    Instruction::FCNoOperandInterSeg(NoOpFCOpCode::RET) |
+                    Instruction::SingleWImmFCOperand(SingleWImmFCOpCode::RETANDADDTOSP, _) |
+                    Instruction::NoOperand(NoOperandOpCode::IRET) => true,
+                    _ => false
+                }
+            }
+            _ => false
+        }
+    }
+
+    /* Called after each step; 'exiting' comes from exits_frame and 'sp' is
+     * the stack pointer before the step */
+    fn goal_reached(&self, m: &machine::Machine, exiting: bool, sp: u16) -> bool
+    {
+        let cpu = m.cpu();
+        match self.goal {
+            Some(Goal::Address(cs, ip)) => (cpu.cs, cpu.ip) == (cs, ip),
+            Some(Goal::Return(cs, ip, frame_ss, frame_sp)) =>
+                (cpu.cs, cpu.ip) == (cs, ip) && cpu.ss == frame_ss && cpu.sp >= frame_sp,
+            /* A hardware interrupt may have been dispatched instead */
+            Some(Goal::FrameExit(..)) => exiting && cpu.sp > sp,
+            None => false
+        }
+    }
+
+    /* Called when the machine is about to run the instruction at seg:addr;
+     * returns true if it should pause there */
+    fn should_break(&mut self, m: &machine::Machine, seg: u16, addr: u16) -> bool
+    {
+        let bkpt = match self.addr_bkpt.get_mut(&(seg, addr)) {
+            Some(bkpt) => bkpt,
+            None => return false
+        };
+
+        if let Some(ref condition) = bkpt.condition {
+            match condition.eval(m.cpu(), m.memory()) {
+                Ok(0) => return false,
+                Ok(_) => { }
+                Err(e) => {
+                    debug_print!("Breakpoint #{} at {:04x}:{:04x}: unable to evaluate '{}': {}",
+                        bkpt.id, seg, addr, condition.source(), e);
+                    return true
+                }
+            }
+        }
+
+        bkpt.hits += 1;
+        if bkpt.ignore > 0 {
+            bkpt.ignore -= 1;
+            return false
+        }
+        debug_print!("Hit breakpoint #{} at {} ({} hits)", bkpt.id, m.symbols.describe(seg, addr), bkpt.hits);
+        true
+    }
+}
+
+/* Reports the guest memory accesses which hit a watchpoint during the last
+ * step, which ran the instruction at cs:ip; returns false if there was none */
+fn report_watch_hits(m: &mut machine::Machine, cs: u16, ip: u16) -> bool
+{
+    let hits = m.memory_mut().take_watch_hits();
+    for hit in &hits {
+        let size = if hit.size == 1 { "byte" } else { "word" };
+        if hit.write {
+            debug_print!("Watchpoint #{}: {} write to {:05x} by {:04x}:{:04x}, {:x} -> {:x}",
+                hit.id, size, hit.addr, cs, ip, hit.old, hit.new);
+        } else {
+            debug_print!("Watchpoint #{}: {} read from {:05x} by {:04x}:{:04x}, value {:x}",
+                hit.id, size, hit.addr, cs, ip, hit.new);
+        }
+    }
+    if !hits.is_empty() {
+        m.disas(cs, ip, 1);
+    }
+    !hits.is_empty()
+}
+
+/* Same as report_watch_hits, for interrupt and I/O port traps */
+fn report_trap_hits(m: &mut machine::Machine, cs: u16, ip: u16) -> bool
+{
+    let hits = m.cpu_mut().take_trap_hits();
+    for hit in &hits {
+        match hit.event {
+            TrapEvent::Interrupt { number, return_cs, return_ip } => {
+                let ax = m.cpu().ax;
+                debug_print!("Trap #{}: interrupt {:02x} with AX={:04x}, returning to {:04x}:{:04x}",
+                    hit.id, number, ax, return_cs, return_ip);
+            }
+            TrapEvent::PortIn { port, size, value } => {
+                debug_print!("Trap #{}: {} input from port {:04x} by {:04x}:{:04x}, value {:x}",
+                    hit.id, if size == 1 { "byte" } else { "word" }, port, cs, ip, value);
+            }
+            TrapEvent::PortOut { port, size, value } => {
+                debug_print!("Trap #{}: {} output to port {:04x} by {:04x}:{:04x}, value {:x}",
+                    hit.id, if size == 1 { "byte" } else { "word" }, port, cs, ip, value);
+            }
+        }
+    }
+    if !hits.is_empty() {
+        m.disas(cs, ip, 1);
+    }
+    !hits.is_empty()
+}
+
+/* Splits the first 'count' words of a command line from the rest, which may
+ * hold quoted strings */
+fn split_words(line: &str, count: usize) -> (Vec<&str>, &str)
+{
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while words.len() < count && !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        words.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    (words, rest)
+}
+
+/* Hex bytes, ?? for any byte, double-quoted strings and u"..." UTF-16
+ * strings, e.g. 'b4 ?? "done$"' */
+fn parse_pattern(s: &str) -> Result<Vec<Option<u8>>, String>
+{
+    let mut pattern = Vec::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let utf16 = rest.starts_with("u\"");
+        if utf16 || rest.starts_with('"') {
+            let text_start = if utf16 { 2 } else { 1 };
+            match rest[text_start..].find('"') {
+                Some(len) => {
+                    let text = &rest[text_start..text_start + len];
+                    if utf16 {
+                        for unit in text.encode_utf16() {
+                            pattern.push(Some(unit as u8));
+                            pattern.push(Some((unit >> 8) as u8));
+                        }
+                    } else {
+                        pattern.extend(text.bytes().map(Some));
+                    }
+                    rest = rest[text_start + len + 1..].trim_start();
+                }
+                None => return Err("unterminated string".to_string())
+            }
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            match (&rest[..end], u8::from_str_radix(&rest[..end], 16)) {
+                ("??", _) => pattern.push(None),
+                (_, Ok(byte)) => pattern.push(Some(byte)),
+                (word, Err(_)) => return Err(format!("invalid byte '{}'", word))
+            }
+            rest = rest[end..].trim_start();
+        }
+    }
+    Ok(pattern)
+}
+
+/* Same as parse_pattern, without wildcards */
+fn parse_bytes(s: &str) -> Result<Vec<u8>, String>
+{
+    parse_pattern(s)?.into_iter()
+        .map(|byte| byte.ok_or_else(|| "wildcards are only allowed in search patterns".to_string()))
+        .collect()
+}
+
+fn parse_words(s: &str) -> Result<Vec<u16>, String>
+{
+    s.split_whitespace()
+        .map(|word| u16::from_str_radix(word, 16).map_err(|_| format!("invalid word '{}'", word)))
+        .collect()
+}
+
+/* An address given to a debugger command; symbols are resolved when the
+ * command runs, as the symbol table belongs to the machine */
+enum Location
+{
+    Address(u16, u16),
+    Symbol(String)
+}
+
+impl Location
+{
+    fn resolve(&self, m: &machine::Machine) -> Option<(u16, u16)>
+    {
+        match *self {
+            Location::Address(seg, addr) => Some((seg, addr)),
+            Location::Symbol(ref name) => {
+                let location = m.symbols.lookup(name);
+                if location.is_none() {
+                    debug_print!("Unknown symbol: {}", name);
+                }
+                location
+            }
+        }
+    }
+}
+
+/* 'SEG ADDR', 'SEG:ADDR' or a symbol name; a breakpoint condition may follow */
+fn parse_location(words: &mut Peekable<SplitWhitespace>) -> Option<Location>
+{
+    let first = words.next()?;
+    if first.contains(':') {
+        let mut parts = first.splitn(2, ':');
+        let seg = u16_from_hex_str(parts.next().unwrap())?;
+        let addr = u16_from_hex_str(parts.next().unwrap())?;
+        return Some(Location::Address(seg, addr))
+    }
+    match words.peek().cloned() {
+        Some(second) if second != "if" => {
+            words.next();
+            Some(Location::Address(u16_from_hex_str(first)?, u16_from_hex_str(second)?))
+        }
+        _ => Some(Location::Symbol(first.to_string()))
+    }
+}
+
+trait Command
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager);
+}
+
+struct QuitCommand
+{
+    status: i32
+}
+
+impl Command for QuitCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        m.hw.close();
+        std::process::exit(self.status);
+    }
+}
+
+struct DumpCommand
+{
+    location: Location
+}
+
+impl Command for DumpCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if let Some((seg, addr)) = self.location.resolve(m) {
+            m.print_memory(seg, addr, 16);
+        }
+    }
+}
+
+struct ContinueCommand
+{
+    trace: bool
+}
+
+impl Command for ContinueCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        m.resume(self.trace);
+    }
+}
+
+fn single_step(m: &mut machine::Machine)
+{
+    let (cs, ip) = m.get_pc();
+    m.resume(true);
+    m.step();
+    report_watch_hits(m, cs, ip);
+    report_trap_hits(m, cs, ip);
+    m.dump();
+    m.pause();
+}
+
+struct StepCommand { }
+
+impl Command for StepCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if !m.is_running() {
+            single_step(m);
+        }
+    }
+}
+
+struct StepOverCommand { }
+
+impl Command for StepOverCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if m.is_running() {
+            return
+        }
+
+        let (sized, rep) = m.next_instruction();
+        let over = match sized.instruction {
+            Instruction::SingleFCOperand(SingleOperandFCOpCode::CALL, _) |
+            Instruction::SingleBImmOperand(SingleBImmOperandOpCode::INT, _) |
+            Instruction::NoOperand(NoOperandOpCode::INTO) => true,
+            Instruction::ImplicitBOperand(_) | Instruction::ImplicitWOperand(_) => rep,
+            _ => false
+        };
+
+        if over {
+            let (cs, ip) = m.get_pc();
+            let (ss, sp) = (m.cpu().ss, m.cpu().sp);
+            bpm.goal = Some(Goal::Return(cs, ip.wrapping_add(sized.size), ss, sp));
+            m.resume(false);
+        } else {
+            single_step(m);
+        }
+    }
+}
+
+struct StepOutCommand { }
+
+impl Command for StepOutCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if !m.is_running() {
+            bpm.goal = Some(Goal::FrameExit(m.cpu().ss, m.cpu().sp));
+            m.resume(false);
+        }
+    }
+}
+
+/* Execution history kept for 'hist' and 'back' after 'hist on' */
+const HISTORY_SIZE: usize = 4096;
+/* Instructions printed when the machine crashes */
+const CRASH_HISTORY: usize = 16;
+
+/* '#-N CS:IP <symbol> INSTRUCTION', then the registers it changed, with their
+ * new values, and the memory it wrote to as [ADDR]:LENGTH */
+fn print_history(m: &machine::Machine, count: usize)
+{
+    let history = &m.cpu().history;
+    let shown = history.len().min(count);
+    for (i, entry) in history.last(count).enumerate() {
+        let mut line = format!("#-{} ", shown - i);
+        if let Some(irq) = entry.irq {
+            line.push_str(&format!("(interrupt {:02x}) ", irq));
+        }
+        line.push_str(&format!("{} {}", m.symbols.describe(entry.cs, entry.ip), m.instruction_text(entry.cs, entry.ip)));
+
+        for &(reg, _, new) in &entry.registers {
+            line.push_str(&format!("  {}={:04x}", REGISTER_NAMES[reg], new));
+        }
+        let mut writes: Vec<u32> = entry.writes.iter().map(|&(addr, _)| addr).collect();
+        writes.sort();
+        writes.dedup();
+        let mut start = 0;
+        while start < writes.len() {
+            let mut end = start + 1;
+            while end < writes.len() && writes[end] == writes[end - 1] + 1 {
+                end += 1;
+            }
+            line.push_str(&format!("  [{:05x}]:{}", writes[start], end - start));
+            start = end;
+        }
+        println!("{}", line);
+    }
+}
+
+struct HistoryCommand
+{
+    count: usize
+}
+
+impl Command for HistoryCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if !m.cpu().history.is_enabled() {
+            debug_print!("The history is not recorded; 'hist on' starts recording it");
+        } else if m.cpu().history.len() == 0 {
+            debug_print!("The history is empty");
+        }
+        print_history(m, self.count);
+    }
+}
+
+/* Recording slows every instruction down, so it is off until asked for */
+struct HistoryRecordingCommand
+{
+    capacity: usize
+}
+
+impl Command for HistoryRecordingCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        m.cpu_mut().history.set_capacity(self.capacity);
+        if self.capacity == 0 {
+            debug_print!("History recording off");
+        } else {
+            debug_print!("Recording the last {} instructions", self.capacity);
+        }
+    }
+}
+
+struct StepBackCommand
+{
+    count: usize
+}
+
+impl Command for StepBackCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if m.is_running() {
+            return
+        }
+        if !m.cpu().history.is_enabled() {
+            debug_print!("The history is not recorded; 'hist on' starts recording it");
+            return
+        }
+        for i in 0..self.count {
+            let (cpu, memory) = m.cpu_and_memory_mut();
+            if cpu.step_back(memory).is_none() {
+                debug_print!("Went back {} instructions: the history is empty", i);
+                break
+            }
+        }
+        m.dump();
+    }
+}
+
+struct RunToCommand
+{
+    location: Location
+}
+
+impl Command for RunToCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if let Some((seg, addr)) = self.location.resolve(m) {
+            bpm.goal = Some(Goal::Address(seg, addr));
+            m.resume(false);
+        }
+    }
+}
+
+struct InsertBreakpointCommand
+{
+    location: Location,
+    condition: Option<Expression>
+}
+
+impl Command for InsertBreakpointCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if let Some((seg, addr)) = self.location.resolve(m) {
+            let id = bpm.add_breakpoint(seg, addr, self.condition.clone());
+            debug_print!("Breakpoint #{} at {}", id, m.symbols.describe(seg, addr));
+        }
+    }
+}
+
+struct DeleteBreakpointCommand
+{
+    id: i32
+}
+
+impl Command for DeleteBreakpointCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if !bpm.remove_breakpoint(self.id) && !m.cpu_mut().remove_trap(self.id) {
+            debug_print!("No breakpoint #{}", self.id);
+        }
+    }
+}
+
+struct ConditionCommand
+{
+    id: i32,
+    condition: Option<Expression>
+}
+
+impl Command for ConditionCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        match bpm.find(self.id) {
+            Some(bkpt) => {
+                bkpt.condition = self.condition.clone();
+            }
+            None => { debug_print!("No breakpoint #{}", self.id); }
+        }
     }
+}
+
+struct IgnoreCommand
+{
+    id: i32,
+    count: u32
+}
+
+impl Command for IgnoreCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        match bpm.find(self.id) {
+            Some(bkpt) => { bkpt.ignore = self.count; }
+            None => { debug_print!("No breakpoint #{}", self.id); }
+        }
+    }
+}
+
+struct ListBreakpointsCommand { }
+
+impl Command for ListBreakpointsCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        let mut bkpts: Vec<_> = bpm.addr_bkpt.iter().collect();
+        bkpts.sort_by_key(|&(_, bkpt)| bkpt.id);
+        for (&(seg, addr), bkpt) in bkpts {
+            let mut line = format!("#{}: {}, {} hits", bkpt.id, m.symbols.describe(seg, addr), bkpt.hits);
+            if bkpt.ignore > 0 {
+                line += &format!(", ignoring the next {}", bkpt.ignore);
+            }
+            if let Some(ref condition) = bkpt.condition {
+                line += &format!(", if {}", condition.source());
+            }
+            debug_print!("{}", line);
+        }
+        for trap in m.cpu().traps() {
+            let what = match trap.kind {
+                TrapKind::Interrupt => "interrupt",
+                TrapKind::PortIn => "port input",
+                TrapKind::PortOut => "port output",
+                TrapKind::PortAccess => "port access"
+            };
+            debug_print!("#{}: {} {:x}-{:x}", trap.id, what, trap.first, trap.last);
+        }
+    }
+}
+
+struct TrapCommand
+{
+    kind: TrapKind,
+    first: u16,
+    last: u16
+}
+
+impl Command for TrapCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        let id = bpm.next_id();
+        m.cpu_mut().add_trap(Trap {
+            id,
+            kind: self.kind,
+            first: self.first,
+            last: self.last
+        });
+        debug_print!("Trap #{} on {:?} {:x}-{:x}", id, self.kind, self.first, self.last);
+    }
+}
+
+struct WatchCommand
+{
+    kind: WatchKind,
+    start: u32,
+    len: u32
+}
+
+impl Command for WatchCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if self.start + self.len > m.memory().size() {
+            debug_print!("Invalid address range");
+            return
+        }
+        let id = bpm.next_id();
+        let watchpoint = Watchpoint {
+            id,
+            kind: self.kind,
+            start: self.start,
+            end: self.start + self.len - 1
+        };
+        m.memory_mut().add_watchpoint(watchpoint);
+        debug_print!("Watchpoint #{} on {:05x}-{:05x}", id, watchpoint.start, watchpoint.end);
+    }
+}
+
+struct UnwatchCommand
+{
+    id: i32
+}
+
+impl Command for UnwatchCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if !m.memory_mut().remove_watchpoint(self.id) {
+            debug_print!("No watchpoint #{}", self.id);
+        }
+    }
+}
+
+struct ListWatchpointsCommand { }
+
+impl Command for ListWatchpointsCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        for watchpoint in m.memory().watchpoints() {
+            debug_print!("#{}: {:?} {:05x}-{:05x}", watchpoint.id, watchpoint.kind, watchpoint.start, watchpoint.end);
+        }
+    }
+}
+
+struct DisassembleCommand
+{
+    location: Location
+}
+
+impl Command for DisassembleCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        if let Some((seg, addr)) = self.location.resolve(m) {
+            m.disas(seg, addr, 5);
+        }
+    }
+}
+
+struct WriteMemoryCommand
+{
+    filename: String,    
+}
+
+impl Command for WriteMemoryCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        m.dump_memory_to_file(&self.filename);
+    }
+}
+
+struct ChangeFloppyCommand
+{
+    filename: String,
+}
+
+impl Command for ChangeFloppyCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        m.hw.floppy = Some(storage::Storage::new_floppy(&self.filename));
+        //println!("Not yet implemented.");
+    }
+}
+
+struct SaveStateCommand
+{
+    filename: String,
+}
+
+impl Command for SaveStateCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        match m.save_snapshot(&self.filename) {
+            Ok(()) => { debug_print!("Snapshot saved to {}", self.filename); },
+            Err(e) => { debug_print!("Unable to save snapshot: {}", e); }
+        }
+    }
+}
+
+struct LoadStateCommand
+{
+    filename: String,
+}
+
+impl Command for LoadStateCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        match m.load_snapshot(&self.filename) {
+            Ok(()) => {
+                debug_print!("Snapshot loaded from {}", self.filename);
+                m.dump();
+            }
+            Err(e) => { debug_print!("Unable to load snapshot: {}", e); }
+        }
+    }
+}
+
+enum RegisterEdit
+{
+    Register(Register),
+    /* A flag mask */
+    Flag(u16)
+}
+
+struct SetRegisterCommand
+{
+    reg: RegisterEdit,
+    value: u16
+}
+
+impl Command for SetRegisterCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        let cpu = m.cpu_mut();
+        match self.reg {
+            RegisterEdit::Register(Register::Word(reg)) => cpu.set_reg(reg, self.value),
+            RegisterEdit::Register(Register::Byte(reg)) => cpu.set_reg(reg, self.value as u8),
+            RegisterEdit::Register(Register::Seg(reg)) => cpu.set_reg(reg, self.value),
+            RegisterEdit::Register(Register::Flags) => cpu.flags = self.value | cpu.timing.model.fixed_flags(),
+            RegisterEdit::Flag(mask) if self.value != 0 => cpu.flags |= mask,
+            RegisterEdit::Flag(mask) => cpu.flags &= !mask
+        }
+        cpu.dump();
+    }
+}
+
+struct DumpRegistersCommand { }
+
+impl Command for DumpRegistersCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        m.dump();
+    }
+}
+
+/* Writes go to consecutive physical addresses, even across a segment end */
+fn write_bytes(m: &mut machine::Machine, addr: u32, bytes: &[u8])
+{
+    for (i, byte) in bytes.iter().enumerate() {
+        let addr = m.memory().physical_address(addr.wrapping_add(i as u32));
+        m.memory_mut().write_u8(addr, *byte);
+    }
+}
+
+struct WriteBytesCommand
+{
+    seg: u16,
+    addr: u16,
+    bytes: Vec<u8>
+}
+
+impl Command for WriteBytesCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
+    {
+        write_bytes(m, phys_addr(self.seg, self.addr), &self.bytes);
+    }
+}
+
+struct FillCommand
+{
+    seg: u16,
+    addr: u16,
+    len: u32,
+    pattern: Vec<u8>
+}
 
-    fn get_breakpoint(&self, seg: u16, addr: u16) -> Option<&i32>
+impl Command for FillCommand
+{
+    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        self.addr_bkpt.get(&(seg, addr))
+        if self.len > m.memory().size() {
+            debug_print!("Invalid length");
+            return
+        }
+        let bytes: Vec<u8> = self.pattern.iter().cloned().cycle().take(self.len as usize).collect();
+        write_bytes(m, phys_addr(self.seg, self.addr), &bytes);
     }
 }
 
-trait Command
+struct CopyCommand
 {
-    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager);
+    src_seg: u16,
+    src_addr: u16,
+    len: u32,
+    dst_seg: u16,
+    dst_addr: u16
 }
 
-struct QuitCommand { }
-
-impl Command for QuitCommand
+impl Command for CopyCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        std::process::exit(0);
+        if self.len > m.memory().size() {
+            debug_print!("Invalid length");
+            return
+        }
+        /* Read everything first so that overlapping ranges are copied as a whole */
+        let src = phys_addr(self.src_seg, self.src_addr);
+        let mem = m.memory();
+        let bytes: Vec<u8> = (0..self.len).map(|i| mem.read_u8(mem.physical_address(src.wrapping_add(i)))).collect();
+        write_bytes(m, phys_addr(self.dst_seg, self.dst_addr), &bytes);
     }
 }
 
-struct DumpCommand
+struct LoadFileCommand
 {
+    filename: String,
     seg: u16,
     addr: u16
 }
 
-impl Command for DumpCommand
+impl Command for LoadFileCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        m.print_memory(self.seg, self.addr, 16);
+        let mut data = Vec::new();
+        if let Err(e) = File::open(&self.filename).and_then(|mut file| file.read_to_end(&mut data)) {
+            debug_print!("Unable to read {}: {}", self.filename, e);
+            return
+        }
+
+        let addr = phys_addr(self.seg, self.addr);
+        if addr as usize + data.len() > m.memory().size() as usize {
+            debug_print!("{} does not fit in memory at {:04x}:{:04x} ({} bytes)", self.filename, self.seg, self.addr, data.len());
+            return
+        }
+        write_bytes(m, addr, &data);
+        debug_print!("Loaded {} bytes at {:05x}-{:05x}", data.len(), addr, addr as usize + data.len().max(1) - 1);
     }
 }
 
-struct ContinueCommand
+/* Lists at most that many matches */
+const MAX_SEARCH_RESULTS: usize = 256;
+
+struct SearchCommand
 {
-    trace: bool
+    /* All of the memory if None */
+    range: Option<(u16, u16, u32)>,
+    pattern: Vec<Option<u8>>
 }
 
-impl Command for ContinueCommand
+impl Command for SearchCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        m.resume(self.trace);
+        let (start, end) = match self.range {
+            Some((seg, addr, len)) => (phys_addr(seg, addr), phys_addr(seg, addr).saturating_add(len)),
+            None => (0, m.memory().size())
+        };
+        let matches = m.memory().search(start, end, &self.pattern);
+
+        for addr in matches.iter().take(MAX_SEARCH_RESULTS) {
+            match self.range {
+                /* Offsets within the segment searched */
+                Some((seg, _, _)) => println!("{:04x}:{:04x}", seg, addr - phys_addr(seg, 0)),
+                None if addr >> 4 <= 0xffff => println!("{:04x}:{:04x}", addr >> 4, addr & 0xf),
+                /* Beyond what a segment reaches in real mode */
+                None => println!("{:06x}", addr)
+            }
+        }
+        if matches.len() > MAX_SEARCH_RESULTS {
+            debug_print!("... {} more matches", matches.len() - MAX_SEARCH_RESULTS);
+        }
+        debug_print!("{} matches", matches.len());
     }
 }
 
-struct StepCommand { }
+struct BacktraceCommand { }
 
-impl Command for StepCommand
+impl Command for BacktraceCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        if !m.is_running() {
-            m.resume(true);
-            m.step();
-            m.dump();
-            m.pause();
+        for (i, frame) in backtrace::backtrace(m.cpu(), m.memory()).iter().enumerate() {
+            let how = match frame.kind {
+                FrameKind::Current => String::new(),
+                FrameKind::Near => format!(", near call, return address at SS:{:04x}", frame.stack),
+                FrameKind::Far => format!(", far call, return address at SS:{:04x}", frame.stack),
+                FrameKind::Interrupt => format!(", interrupt, return address at SS:{:04x}", frame.stack)
+            };
+            println!("#{} {}{}", i, m.symbols.describe(frame.cs, frame.ip), how);
         }
     }
 }
 
-struct InsertBreakpointCommand
+struct LoadSymbolsCommand
 {
+    filename: String,
     seg: u16,
-    addr: u16
+    off: u16
 }
 
-impl Command for InsertBreakpointCommand
+impl Command for LoadSymbolsCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        bpm.add_breakpoint(self.seg, self.addr);
+        match m.symbols.load(&self.filename, self.seg, self.off) {
+            Ok(count) => { debug_print!("Loaded {} symbols from {}", count, self.filename); }
+            Err(e) => { debug_print!("Unable to load symbols: {}", e); }
+        }
     }
 }
 
-struct DisassembleCommand
+struct ListSymbolsCommand
 {
-    seg: u16,
-    addr: u16
+    /* Lowercase */
+    filter: Option<String>
 }
 
-impl Command for DisassembleCommand
+impl Command for ListSymbolsCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        m.disas(self.seg, self.addr, 5);
+        let mut shown = 0;
+        for &(seg, off, ref name) in m.symbols.iter() {
+            if self.filter.as_ref().map_or(true, |filter| name.to_ascii_lowercase().contains(&filter[..])) {
+                println!("{:04x}:{:04x} {}", seg, off, name);
+                shown += 1;
+            }
+        }
+        debug_print!("{} of {} symbols", shown, m.symbols.len());
     }
 }
 
-struct WriteMemoryCommand
-{
-    filename: String,    
-}
+struct ClearSymbolsCommand { }
 
-impl Command for WriteMemoryCommand
+impl Command for ClearSymbolsCommand
 {
     fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
     {
-        m.dump_memory_to_file(&self.filename);
+        m.symbols.clear();
     }
 }
 
-struct ChangeFloppyCommand
+struct CommandResult { }
+
+/* Runs the commands of the script file first, then those typed on stdin, unless
+ * in batch mode. Commands wait for the machine to pause (on a breakpoint, at
+ * the end of a step...) before the next one runs if more are pending, so that
+ * scripts and macros can continue the machine and inspect it afterwards */
+fn console_thread(tx: SyncSender<(Box<dyn Command + Send>, bool)>, rx: Receiver<CommandResult>, script: Option<String>, batch: bool)
 {
-    filename: String,
+    let mut macros = script::Macros::new();
+    // pending command lines, with the macro expansion depth they come from
+    let mut pending: VecDeque<(String, usize)> = VecDeque::new();
+    // in batch mode, stdin is only read without a script
+    let script_only = batch && script.is_some();
+    if let Some(fname) = script {
+        pending.push_back((format!("source {}", fname), 0));
+    }
+
+    loop {
+        let line = match pending.pop_front() {
+            Some(line) => Some(line),
+            None if script_only => None,
+            None => {
+                print!("{}", if macros.is_defining() { "> " } else { "debug> " });
+                io::stdout().flush().unwrap();
+                let mut cmd_str = String::new();
+                match io::stdin().read_line(&mut cmd_str).unwrap() {
+                    0 => None,
+                    _ => Some((cmd_str, 0))
+                }
+            }
+        };
+
+        let cmd = match line {
+            // end of the script in batch mode, or of the input
+            None => Some(Box::new(QuitCommand{ status: 0 }) as Box<dyn Command + Send>),
+            Some((cmd_str, depth)) => {
+                let mut words = cmd_str.split_whitespace();
+                let result = match (words.next(), words.next(), words.next()) {
+                    (Some("define"), None, None) if !macros.is_defining() => {
+                        for (name, lines) in macros.list() {
+                            println!("{}:", name);
+                            for line in lines {
+                                println!("    {}", line);
+                            }
+                        }
+                        Ok(Vec::new())
+                    }
+                    (Some("source"), Some(fname), None) if !macros.is_defining() => {
+                        script_lines(fname)
+                    }
+                    (Some("source"), _, _) if !macros.is_defining() => {
+                        Err("Usage: source FILENAME".to_string())
+                    }
+                    _ => macros.expand(&cmd_str)
+                };
+
+                match result {
+                    // a plain command: parse it
+                    Ok(ref lines) if lines.len() == 1 && lines[0] == cmd_str.trim() => parse_command(&lines[0]),
+                    Ok(ref lines) if depth >= script::MAX_DEPTH && !lines.is_empty() => {
+                        debug_print!("Macros or scripts nested too deeply");
+                        None
+                    }
+                    Ok(lines) => {
+                        for line in lines.into_iter().rev() {
+                            pending.push_front((line, depth + 1));
+                        }
+                        continue
+                    }
+                    Err(e) => {
+                        debug_print!("{}", e);
+                        None
+                    }
+                }
+            }
+        };
+
+        let cmd = match cmd {
+            Some(cmd) => cmd,
+            None if batch => {
+                debug_print!("Script aborted.");
+                Box::new(QuitCommand{ status: 1 })
+            }
+            None => {
+                if !pending.is_empty() {
+                    debug_print!("Script aborted.");
+                    pending.clear();
+                }
+                continue
+            }
+        };
+
+        let wait = batch || !pending.is_empty();
+        // the emulator loop only stops by exiting the process
+        if tx.send((cmd, wait)).is_err() || rx.recv().is_err() {
+            return
+        }
+    }
 }
 
-impl Command for ChangeFloppyCommand
+/* The console thread stopped, most likely on a panic: a batch run can no longer
+ * complete, so it fails, while an interactive session keeps running */
+fn console_lost(m: &mut machine::Machine, batch: bool)
 {
-    fn execute(&self, m: &mut machine::Machine, bpm: &mut BreakpointManager)
-    {
-        m.hw.floppy = Some(storage::Storage::new_floppy(&self.filename));
-        //println!("Not yet implemented.");
+    if batch {
+        debug_print!("Console stopped unexpectedly.");
+        m.hw.close();
+        std::process::exit(1);
     }
 }
 
-struct CommandResult { }
+/* The lines of a script file, without the empty ones: they would step */
+fn script_lines(fname: &str) -> Result<Vec<String>, String>
+{
+    let mut text = String::new();
+    File::open(fname)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+    Ok(text.lines().filter(|line| !line.trim().is_empty()).map(|line| line.to_string()).collect())
+}
 
-fn console_thread(tx: SyncSender<Box<dyn Command + Send>>, rx: Receiver<CommandResult>)
+/* None, after reporting the problem, if the line is not a valid command */
+fn parse_command(cmd_str: &str) -> Option<Box<dyn Command + Send>>
 {
-    loop {
-        print!("debug> ");
-        io::stdout().flush().unwrap();
-        let mut cmd_str = String::new();
-        io::stdin().read_line(&mut cmd_str).unwrap();
+	let mut words = cmd_str.split_whitespace().peekable();
 
-		let mut words = cmd_str.split_whitespace();
+    let cmd: Box<dyn Command + Send>;
+	let word0 = words.next();
+	match word0
+	{
+        Some("q") => { 
+            cmd = Box::new(QuitCommand{ status: 0 });
+        }
+        Some("b") | Some("d") | Some("u") | Some("until") => {
+            let location = match parse_location(&mut words) {
+                Some(location) => location,
+                None => {
+                    debug_print!("Usage: <CMD> [segment] [address] or <CMD> [symbol]");
+                    return None
+                }
+            };
 
-        let cmd: Box<dyn Command + Send>;
-		let word0 = words.next();
-		match word0
-		{
-            Some("q") => { 
-                cmd = Box::new(QuitCommand{ });
-            }
-            Some("b") | Some("d") | Some("u") => {
-				let args = (words.next(), words.next());
-				match args
-				{
-					(Some(seg_str), Some(addr_str)) =>
-					{
-						let seg = u32_from_hex_str(seg_str) as u16;
-						let addr = u32_from_hex_str(addr_str) as u16;
-
-                        match word0 {
-                            Some("b") => {
-                                cmd = Box::new(InsertBreakpointCommand{
-                                    seg,
-                                    addr
-                                });
+            match word0 {
+                Some("b") => {
+                    let rest: Vec<&str> = words.collect();
+                    let condition = match rest.split_first() {
+                        None => None,
+                        Some((&"if", cond)) if !cond.is_empty() => {
+                            match Expression::parse(&cond.join(" ")) {
+                                Ok(condition) => Some(condition),
+                                Err(e) => {
+                                    debug_print!("Invalid condition: {}", e);
+                                    return None
+                                }
                             }
-                            Some("d") => {
-                                cmd = Box::new(DumpCommand{
-                                    seg,
-                                    addr
-                                });
-                            }
-                            Some("u") => {
-                                cmd = Box::new(DisassembleCommand{
-                                    seg,
-                                    addr
-                                });
-                            }
-                            _ => panic!("Impossible command!"),
                         }
-					},
-					_ => {
-                        debug_print!("Usage: <CMD> [segment] [address]");
-                        continue
+                        _ => {
+                            debug_print!("Usage: b SEG ADDR [if CONDITION]");
+                            return None
+                        }
+                    };
+                    cmd = Box::new(InsertBreakpointCommand{
+                        location,
+                        condition
+                    });
+                }
+                Some("d") => {
+                    cmd = Box::new(DumpCommand{
+                        location
+                    });
+                }
+                Some("u") => {
+                    cmd = Box::new(DisassembleCommand{
+                        location
+                    });
+                }
+                Some("until") => {
+                    cmd = Box::new(RunToCommand{
+                        location
+                    });
+                }
+                _ => panic!("Impossible command!"),
+            }
+        }
+        Some("sym") => {
+            match (words.next(), words.next(), words.next(), words.next()) {
+                (Some("load"), Some(fname), seg_str, off_str) => {
+                    match (seg_str.map_or(Some(0), u16_from_hex_str), off_str.map_or(Some(0), u16_from_hex_str)) {
+                        (Some(seg), Some(off)) => {
+                            cmd = Box::new(LoadSymbolsCommand{
+                                filename: fname.to_string(),
+                                seg,
+                                off
+                            });
+                        }
+                        _ => {
+                            debug_print!("Usage: sym load FILENAME [SEG [OFF]]");
+                            return None
+                        }
                     }
                 }
-			}
-            Some("c") | Some("t") => {
-                let trace = Some("t") == word0;
-                cmd = Box::new(ContinueCommand{
-                    trace
-                });
+                (Some("list"), filter, None, None) => {
+                    cmd = Box::new(ListSymbolsCommand{
+                        filter: filter.map(|filter| filter.to_ascii_lowercase())
+                    });
+                }
+                (Some("clear"), None, None, None) => {
+                    cmd = Box::new(ClearSymbolsCommand{ });
+                }
+                _ => {
+                    debug_print!("Usage: sym load FILENAME [SEG [OFF]], sym list [TEXT] or sym clear");
+                    return None
+                }
+            }
+        }
+        Some("bint") | Some("bport") => {
+            let kind = if word0 == Some("bint") {
+                Some(TrapKind::Interrupt)
+            } else {
+                match words.next() {
+                    Some("in") => Some(TrapKind::PortIn),
+                    Some("out") => Some(TrapKind::PortOut),
+                    Some("rw") => Some(TrapKind::PortAccess),
+                    _ => None
+                }
+            };
+            let first = words.next().and_then(u32_from_hex_str);
+            let last = words.next().map_or(first, u32_from_hex_str);
+            let max = if word0 == Some("bint") { 0xff } else { 0xffff };
+            match (kind, first, last) {
+                (Some(kind), Some(first), Some(last)) if first <= last && last <= max => {
+                    cmd = Box::new(TrapCommand{
+                        kind,
+                        first: first as u16,
+                        last: last as u16
+                    });
+                }
+                _ => {
+                    debug_print!("Usage: bint NUM [LAST] or bport in|out|rw PORT [LAST]");
+                    return None
+                }
+            }
+        }
+        Some("bl") => {
+            cmd = Box::new(ListBreakpointsCommand{ });
+        }
+        Some("bd") => {
+            match words.next().map(|id| id.parse::<i32>()) {
+                Some(Ok(id)) => {
+                    cmd = Box::new(DeleteBreakpointCommand{
+                        id
+                    });
+                }
+                _ => {
+                    debug_print!("Usage: bd ID");
+                    return None
+                }
+            }
+        }
+        Some("cond") => {
+            let id = words.next().map(|id| id.parse::<i32>());
+            let rest: Vec<&str> = words.collect();
+            let condition = if rest.is_empty() { Ok(None) } else { Expression::parse(&rest.join(" ")).map(Some) };
+            match (id, condition) {
+                (Some(Ok(id)), Ok(condition)) => {
+                    cmd = Box::new(ConditionCommand{
+                        id,
+                        condition
+                    });
+                }
+                (Some(Ok(_)), Err(e)) => {
+                    debug_print!("Invalid condition: {}", e);
+                    return None
+                }
+                _ => {
+                    debug_print!("Usage: cond ID [CONDITION]");
+                    return None
+                }
+            }
+        }
+        Some("ignore") => {
+            match (words.next().map(|id| id.parse::<i32>()), words.next().map(|n| n.parse::<u32>())) {
+                (Some(Ok(id)), Some(Ok(count))) => {
+                    cmd = Box::new(IgnoreCommand{
+                        id,
+                        count
+                    });
+                }
+                _ => {
+                    debug_print!("Usage: ignore ID COUNT");
+                    return None
+                }
             }
-			Some("w") =>
+        }
+        Some("watch") => {
+            let args = (words.next(), words.next(), words.next());
+            let kind = match args.0 {
+                Some("r") => Some(WatchKind::Read),
+                Some("w") => Some(WatchKind::Write),
+                Some("rw") => Some(WatchKind::Access),
+                _ => None
+            };
+            match (kind, args) {
+                (_, (None, _, _)) => {
+                    cmd = Box::new(ListWatchpointsCommand{ });
+                }
+                (Some(kind), (_, Some(addr_str), len_str)) => {
+                    let (start, len) = match (u32_from_hex_str(addr_str), len_str.map_or(Some(1), u32_from_hex_str)) {
+                        (Some(start), Some(len)) => (start, len),
+                        _ => {
+                            debug_print!("Usage: watch [r|w|rw ADDR [LEN]]");
+                            return None
+                        }
+                    };
+                    if len == 0 || start.checked_add(len).is_none() {
+                        debug_print!("Invalid address range");
+                        return None
+                    }
+                    cmd = Box::new(WatchCommand{
+                        kind,
+                        start,
+                        len
+                    });
+                }
+                _ => {
+                    debug_print!("Usage: watch [r|w|rw ADDR [LEN]]");
+                    return None
+                }
+            }
+        }
+        Some("unwatch") => {
+            match words.next().map(|id| id.parse::<i32>()) {
+                Some(Ok(id)) => {
+                    cmd = Box::new(UnwatchCommand{
+                        id
+                    });
+                }
+                _ => {
+                    debug_print!("Usage: unwatch ID");
+                    return None
+                }
+            }
+        }
+        Some("c") | Some("t") => {
+            let trace = Some("t") == word0;
+            cmd = Box::new(ContinueCommand{
+                trace
+            });
+        }
+		Some("w") =>
+		{
+			let arg = words.next();
+			match arg
 			{
-				let arg = words.next();
-				match arg
-				{
-					Some(fname) => {
-                        cmd = Box::new(WriteMemoryCommand{
+				Some(fname) => {
+                    cmd = Box::new(WriteMemoryCommand{
+                        filename: fname.to_string()
+                    });
+				},
+				_ => {
+                    debug_print!("Usage: w [filename]");
+                    return None
+                }				}
+		}
+        Some("change-floppy") => {
+			let arg = words.next();
+			match arg {
+				Some(fname) => {
+                    cmd = Box::new(ChangeFloppyCommand{
+                        filename: fname.to_string()
+                    });
+				},
+				_ => {
+                    debug_print!("Usage: change-floppy FILENAME");
+                    return None
+                }
+            }
+        }
+        Some("save-state") | Some("load-state") => {
+			let arg = words.next();
+			match arg {
+				Some(fname) => {
+                    if word0 == Some("save-state") {
+                        cmd = Box::new(SaveStateCommand{
                             filename: fname.to_string()
                         });
-					},
-					_ => {
-                        debug_print!("Usage: w [filename]");
-                        continue
-                    }				}
-			}
-            Some("change-floppy") => {
-				let arg = words.next();
-				match arg {
-					Some(fname) => {
-                        cmd = Box::new(ChangeFloppyCommand{
+                    } else {
+                        cmd = Box::new(LoadStateCommand{
                             filename: fname.to_string()
                         });
-					},
-					_ => {
-                        debug_print!("Usage: change-floppy FILENAME");
-                        continue
                     }
+				},
+				_ => {
+                    debug_print!("Usage: save-state|load-state FILENAME");
+                    return None
                 }
             }
-            None => {
-                cmd = Box::new(StepCommand{ });
-            }
-            _ => {
-                println!("Bad command.");
-                continue
+        }
+        Some("r") => {
+            let args = (words.next(), words.next(), words.next());
+            let reg = args.0.and_then(|name| {
+                expr::register(name).map(RegisterEdit::Register)
+                    .or_else(|| expr::flag(name).map(RegisterEdit::Flag))
+            });
+            let value = args.1.map(|value| u16::from_str_radix(value, 16));
+            match (args, reg, value) {
+                ((None, _, _), _, _) => {
+                    cmd = Box::new(DumpRegistersCommand{ });
+                }
+                ((_, _, None), Some(reg), Some(Ok(value))) => {
+                    cmd = Box::new(SetRegisterCommand{
+                        reg,
+                        value
+                    });
+                }
+                _ => {
+                    debug_print!("Usage: r [REG VALUE]");
+                    return None
+                }
             }
         }
+        Some("e") | Some("ew") | Some("fill") => {
+            let arg_count = if word0 == Some("fill") { 4 } else { 3 };
+            let (args, data) = split_words(&cmd_str, arg_count);
+            let bytes = if word0 == Some("ew") {
+                parse_words(data).map(|words| words.iter().flat_map(|w| vec![*w as u8, (*w >> 8) as u8]).collect())
+            } else {
+                parse_bytes(data)
+            };
+            let bytes = match bytes {
+                Ok(bytes) if args.len() == arg_count && !bytes.is_empty() => bytes,
+                Err(e) => {
+                    debug_print!("{}", e);
+                    return None
+                }
+                _ => {
+                    debug_print!("Usage: e SEG ADDR DATA, ew SEG ADDR WORDS or fill SEG ADDR LEN DATA");
+                    return None
+                }
+            };
 
-        tx.send(cmd);
-
-        rx.recv();
+            let (seg, addr) = match (u16_from_hex_str(args[1]), u16_from_hex_str(args[2])) {
+                (Some(seg), Some(addr)) => (seg, addr),
+                _ => {
+                    debug_print!("Invalid address");
+                    return None
+                }
+            };
+            if word0 == Some("fill") {
+                let len = match u32_from_hex_str(args[3]) {
+                    Some(len) => len,
+                    _ => {
+                        debug_print!("Invalid length");
+                        return None
+                    }
+                };
+                cmd = Box::new(FillCommand{
+                    seg,
+                    addr,
+                    len,
+                    pattern: bytes
+                });
+            } else {
+                cmd = Box::new(WriteBytesCommand{
+                    seg,
+                    addr,
+                    bytes
+                });
+            }
+        }
+        Some("s") => {
+            /* A first argument with a colon is a SEG:ADDR range start */
+            let ranged = words.next().map_or(false, |word| word.contains(':'));
+            let (args, pattern) = split_words(&cmd_str, if ranged { 3 } else { 1 });
+            let range = if ranged {
+                let mut start = args[1].splitn(2, ':');
+                let seg = u16_from_hex_str(start.next().unwrap());
+                let addr = u16_from_hex_str(start.next().unwrap());
+                match (seg, addr, args.get(2).and_then(|len| u32_from_hex_str(len))) {
+                    (Some(seg), Some(addr), Some(len)) => Some((seg, addr, len)),
+                    _ => {
+                        debug_print!("Usage: s [SEG:ADDR LEN] PATTERN");
+                        return None
+                    }
+                }
+            } else {
+                None
+            };
+            match parse_pattern(pattern) {
+                Ok(ref pattern) if pattern.is_empty() => {
+                    debug_print!("Usage: s [SEG:ADDR LEN] PATTERN");
+                    return None
+                }
+                Ok(pattern) => {
+                    cmd = Box::new(SearchCommand{
+                        range,
+                        pattern
+                    });
+                }
+                Err(e) => {
+                    debug_print!("{}", e);
+                    return None
+                }
+            }
+        }
+        Some("copy") => {
+            let args: Vec<&str> = words.collect();
+            let copy = if args.len() == 5 {
+                match (u16_from_hex_str(args[0]), u16_from_hex_str(args[1]), u32_from_hex_str(args[2]), u16_from_hex_str(args[3]), u16_from_hex_str(args[4])) {
+                    (Some(src_seg), Some(src_addr), Some(len), Some(dst_seg), Some(dst_addr)) => {
+                        Some(CopyCommand{
+                            src_seg,
+                            src_addr,
+                            len,
+                            dst_seg,
+                            dst_addr
+                        })
+                    }
+                    _ => None
+                }
+            } else {
+                None
+            };
+            match copy {
+                Some(copy) => {
+                    cmd = Box::new(copy);
+                }
+                None => {
+                    debug_print!("Usage: copy SEG ADDR LEN SEG ADDR");
+                    return None
+                }
+            }
+        }
+        Some("load") => {
+            match (words.next(), words.next(), words.next()) {
+                (Some(fname), Some(seg_str), Some(addr_str)) => {
+                    match (u16_from_hex_str(seg_str), u16_from_hex_str(addr_str)) {
+                        (Some(seg), Some(addr)) => {
+                            cmd = Box::new(LoadFileCommand{
+                                filename: fname.to_string(),
+                                seg,
+                                addr
+                            });
+                        }
+                        _ => {
+                            debug_print!("Usage: load FILENAME SEG ADDR");
+                            return None
+                        }
+                    }
+                }
+                _ => {
+                    debug_print!("Usage: load FILENAME SEG ADDR");
+                    return None
+                }
+            }
+        }
+        Some("hist") if words.peek() == Some(&"on") || words.peek() == Some(&"off") => {
+            let capacity = match (words.next(), words.next().map(|size| size.parse::<usize>())) {
+                (Some("off"), None) => 0,
+                (Some("on"), None) => HISTORY_SIZE,
+                (Some("on"), Some(Ok(size))) if size > 0 => size,
+                _ => {
+                    debug_print!("Usage: hist on [SIZE] or hist off");
+                    return None
+                }
+            };
+            cmd = Box::new(HistoryRecordingCommand{ capacity });
+        }
+        Some("hist") | Some("back") => {
+            let count = match words.next().map(|count| count.parse::<usize>()) {
+                None if word0 == Some("hist") => 16,
+                None => 1,
+                Some(Ok(count)) if count > 0 => count,
+                _ => {
+                    debug_print!("Usage: hist [COUNT] or back [COUNT]");
+                    return None
+                }
+            };
+            if word0 == Some("hist") {
+                cmd = Box::new(HistoryCommand{ count });
+            } else {
+                cmd = Box::new(StepBackCommand{ count });
+            }
+        }
+        Some("bt") => {
+            cmd = Box::new(BacktraceCommand{ });
+        }
+        Some("n") => {
+            cmd = Box::new(StepOverCommand{ });
+        }
+        Some("finish") => {
+            cmd = Box::new(StepOutCommand{ });
+        }
+        None => {
+            cmd = Box::new(StepCommand{ });
+        }
+        _ => {
+            println!("Bad command.");
+            return None
+        }
     }
+
+    Some(cmd)
 }
 
 fn main()
 {
 	let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
 
+	let cpu_model =
+		match &args.flag_cpu[..]
+		{
+			"8086" => cpu::CpuModel::I8086,
+			"8088" => cpu::CpuModel::I8088,
+			"80186" => cpu::CpuModel::I80186,
+			"80188" => cpu::CpuModel::I80188,
+			"80286" => cpu::CpuModel::I80286,
+			_ => panic!("Unrecognized CPU model: '{}'. use '8086', '8088', '80186', '80188' or '80286'", args.flag_cpu)
+		};
+
+	if let Some(ref corpus) = args.flag_cpu_tests
+	{
+		std::process::exit(conformance::run_corpus(corpus, cpu_model));
+	}
+
 	let boot_drive = 
 		match &args.flag_boot[..]
 		{
@@ -338,51 +1778,174 @@ fn main()
 		}
 	}
 
+	let speed =
+		match &args.flag_speed[..]
+		{
+			"real" => machine::Speed::RealTime,
+			"max" => machine::Speed::Unbounded,
+			_ => panic!("Unrecognized speed: '{}'. use 'real' or 'max'", args.flag_speed)
+		};
+
+	let input =
+		match (args.flag_deterministic, args.flag_input)
+		{
+			(false, None) => hw::InputSource::Host,
+			(false, Some(_)) => panic!("Input scripts are only supported in deterministic mode; use --deterministic"),
+			(true, None) => hw::InputSource::Script(hw::input_script::InputScript::empty()),
+			(true, Some(fname)) => hw::InputSource::Script(hw::input_script::InputScript::load(&fname[..]))
+		};
+
+	let frontend: Box<dyn frontend::Frontend> =
+		if args.flag_headless
+		{
+			Box::new(frontend::headless::HeadlessFrontend::new())
+		}
+		else
+		{
+			Box::new(frontend::sdl::SdlFrontend::new(hw::display::FRAME_WIDTH, hw::display::FRAME_HEIGHT))
+		};
+
 	let mut m = machine::Machine::new(
 				boot_drive,
 				args.flag_fd,
-				args.flag_hd);
+				args.flag_hd,
+				args.flag_wav,
+				cpu_model,
+				args.flag_fpu,
+				speed,
+				input,
+				frontend);
+
+	if let Some(ref fname) = args.flag_load_state
+	{
+		if let Err(e) = m.load_snapshot(fname)
+		{
+			panic!("Unable to load snapshot {}: {}", fname, e);
+		}
+	}
 	m.dump();
 
+	if let Some(cycles) = args.flag_cycles
+	{
+		if args.flag_gdb.is_some()
+		{
+			panic!("--gdb cannot be used with --cycles");
+		}
+
+		/* Non-interactive run, e.g. for regression tests */
+		m.resume(false);
+		while m.is_running() && m.cycles() < cycles
+		{
+			m.step();
+		}
+		if !m.is_running()
+		{
+			debug_print!("Machine halted after {} cycles.", m.cycles());
+		}
+
+		if let Some(fname) = args.flag_dump
+		{
+			m.dump_memory_to_file(&fname);
+		}
+		if let Some(fname) = args.flag_screenshot
+		{
+			m.save_screenshot(&fname);
+		}
+		if let Some(fname) = args.flag_save_state
+		{
+			if let Err(e) = m.save_snapshot(&fname)
+			{
+				panic!("Unable to save snapshot {}: {}", fname, e);
+			}
+		}
+		return
+	}
+
+	if let Some(port) = args.flag_gdb
+	{
+		/* Once GDB detaches, the machine runs under the console debugger */
+		gdb::serve(&mut m, port);
+		m.resume(false);
+	}
+
     // channel to communicate console commands to the emulator loop
-    let (tx, rx): (SyncSender<Box<dyn Command + Send>>, Receiver<Box<dyn Command + Send>>) = mpsc::sync_channel(1);
+    // with a flag telling whether to wait until the machine pauses before reporting the result
+    let (tx, rx): (SyncSender<(Box<dyn Command + Send>, bool)>, Receiver<(Box<dyn Command + Send>, bool)>) = mpsc::sync_channel(1);
     let (tx_finished, rx_finished): (SyncSender<CommandResult>, Receiver<CommandResult>) = mpsc::sync_channel(1);
 
     // console thread
+    let (script, batch) = (args.flag_script, args.flag_batch);
     let console_thread_handle = thread::spawn(move || {
-        console_thread(tx, rx_finished);
+        console_thread(tx, rx_finished, script, batch);
     });
 
     let mut bpm = BreakpointManager {
         next: 0,
-        addr_bkpt: HashMap::new()
+        addr_bkpt: HashMap::new(),
+        goal: None
     };
 
+    // a command waiting for the machine to pause
+    let mut waiting = false;
+
     // main emulator loop
     loop {
+        if waiting && !m.is_running() {
+            waiting = false;
+            if tx_finished.send(CommandResult{ }).is_err() {
+                console_lost(&mut m, batch);
+            }
+        }
+
         match rx.try_recv() {
-            Ok(cmd) => {
+            Ok((cmd, wait)) => {
                 (*cmd).execute(&mut m, &mut bpm);
-                tx_finished.send(CommandResult{ });
+                if wait && m.is_running() {
+                    waiting = true;
+                } else if tx_finished.send(CommandResult{ }).is_err() {
+                    console_lost(&mut m, batch);
+                }
             }
-            Err(_) => {
+            Err(e) => {
+                if e == TryRecvError::Disconnected {
+                    console_lost(&mut m, batch);
+                }
+                let (cs, ip) = m.get_pc();
+                let sp = m.cpu().sp;
+                let exiting = bpm.exits_frame(&m);
                 m.step();
 
+                if report_watch_hits(&mut m, cs, ip) {
+                    m.pause();
+                }
+
+                if report_trap_hits(&mut m, cs, ip) {
+                    m.pause();
+                }
+
                 if m.crashed() {
-                    debug_print!("Machine halted.");
+                    if m.cpu().history.is_enabled() {
+                        debug_print!("Machine halted. Last instructions:");
+                        print_history(&m, CRASH_HISTORY);
+                    } else {
+                        debug_print!("Machine halted.");
+                    }
                     m.pause();
                 }
 
                 if m.is_running() {
                     let (cs, ip) = m.get_pc();
-                    match bpm.get_breakpoint(cs, ip) {
-                        Some(bkpt) => {
-                            debug_print!("Hit breakpoint #{} at {:04x}:{:04x}", bkpt, cs, ip);
-                            m.pause();
-                        }
-                        _ => { }
+                    if bpm.should_break(&m, cs, ip) {
+                        m.pause();
+                    } else if bpm.goal_reached(&m, exiting, sp) {
+                        m.dump();
+                        m.pause();
                     }
                 }
+
+                if !m.is_running() {
+                    bpm.goal = None;
+                }
             }
         }
     }
@@ -1,9 +1,86 @@
 use std::boxed::Box;
+use std::cell::RefCell;
+use std::mem;
+use snapshot::{Snapshot, SnapshotReader, SnapshotWriter};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WatchKind
+{
+	Read,
+	Write,
+	/* Read or write */
+	Access
+}
+
+/* Physical address range, bounds included */
+#[derive(Clone, Copy, Debug)]
+pub struct Watchpoint
+{
+	pub id: i32,
+	pub kind: WatchKind,
+	pub start: u32,
+	pub end: u32
+}
+
+impl Watchpoint
+{
+	fn matches(&self, addr: u32, size: u32, write: bool) -> bool
+	{
+		let kind_matches = match self.kind
+		{
+			WatchKind::Read => !write,
+			WatchKind::Write => write,
+			WatchKind::Access => true
+		};
+		kind_matches && addr <= self.end && addr.saturating_add(size - 1) >= self.start
+	}
+}
+
+/* A guest access to a watched range; 'old' and 'new' are the same for reads */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WatchHit
+{
+	pub id: i32,
+	pub addr: u32,
+	pub size: u32,
+	pub write: bool,
+	pub old: u16,
+	pub new: u16
+}
+
+/* What the CPU decode cache has to drop */
+#[derive(Debug, Eq, PartialEq)]
+pub enum CodeWrites
+{
+	/* The instructions these bytes are part of */
+	Bytes(Vec<u32>),
+	/* Everything: the whole memory got replaced */
+	All
+}
 
 pub struct Memory
 {
 	ram: Box<[u8]>,
-	dirty: bool
+	dirty: bool,
+	/* Address lines 20 and up are masked off while the A20 gate is closed,
+	 * so that addresses wrap at 1 MB as on the 8086 */
+	a20: bool,
+	addr_mask: u32,
+
+	watchpoints: Vec<Watchpoint>,
+	/* Only accesses made while the CPU (or the BIOS) runs are guest ones, not
+	 * the debugger or display ones */
+	watching: bool,
+	watch_hits: RefCell<Vec<WatchHit>>,
+
+	/* Old values of the bytes written, for the execution history */
+	journal: Option<Vec<(u32, u8)>>,
+
+	/* Bytes that instructions in the CPU decode cache were decoded from, one
+	 * bit each; writes to them are queued for the cache */
+	code: Box<[u8]>,
+	code_writes: Vec<u32>,
+	code_replaced: bool
 }
 
 fn bound_checks(what: &str, addr: u32)
@@ -34,33 +111,76 @@ fn bound_checks(what: &str, addr: u32)
 fn read_bound_checks(addr: u32) {bound_checks("Reading from",addr)}
 fn write_bound_checks(addr: u32) {bound_checks("Writing to",addr)}
 
-const ADDR_MAX: u32 = 0x00100000;
+const A20_LINE: u32 = 0x00100000;
 const VRAM_MASK: u32 = 0xf8000;
 const VRAM_VAL: u32 = 0xb8000;
 
 impl Memory
 {
 	// TODO: provide ROM and prevent write access to it
+	/* 'size' is a power of two, 1 MB or more; addresses wrap around it */
 	pub fn new(size: u32) -> Memory
 	{
 		Memory
 		{
 			ram: vec![0; size as usize].into_boxed_slice(),
-			dirty: true
+			dirty: true,
+			/* Closed at power-on, as on the PC/AT */
+			a20: false,
+			addr_mask: (size - 1) & !A20_LINE,
+			watchpoints: Vec::new(),
+			watching: false,
+			watch_hits: RefCell::new(Vec::new()),
+			journal: None,
+			code: vec![0; size as usize / 8].into_boxed_slice(),
+			code_writes: Vec::new(),
+			code_replaced: false
 			//rom: rom_vec.into_boxed_slice()
 		}
 	}
 
+	pub fn size(&self) -> u32
+	{
+		self.ram.len() as u32
+	}
+
+	pub fn a20(&self) -> bool
+	{
+		self.a20
+	}
+
+	pub fn set_a20(&mut self, enabled: bool)
+	{
+		let size = self.ram.len() as u32;
+		self.a20 = enabled;
+		self.addr_mask = if enabled { size - 1 } else { (size - 1) & !A20_LINE };
+	}
+
+	fn index(&self, addr: u32) -> usize
+	{
+		(addr & self.addr_mask) as usize
+	}
+
 	pub fn read_u8(&self, addr: u32) -> u8
 	{
 		read_bound_checks(addr);
-		self.ram[(addr % ADDR_MAX) as usize]
+		let val = self.ram[self.index(addr)];
+		if self.watching
+		{
+			self.check_watchpoints(addr, 1, false, val as u16, val as u16);
+		}
+		val
 	}
 
 	pub fn read_u16(&self, addr: u32) -> u16
 	{
 		read_bound_checks(addr);
-		(self.ram[(addr % ADDR_MAX) as usize] as u16) + ((self.ram[((addr + 1) % ADDR_MAX) as usize] as u16) << 8)
+		let val = (self.ram[self.index(addr)] as u16) + ((self.ram[self.index(addr + 1)] as u16) << 8);
+		if self.watching
+		{
+			self.check_watchpoints(addr, 2, false, val, val);
+		}
+		val
 	}
 
 	pub fn write_u8(&mut self, addr: u32, data: u8)
@@ -70,7 +190,14 @@ impl Memory
 		{
 			self.dirty = true;
 		}
-		self.ram[(addr % ADDR_MAX) as usize] = data
+		if self.watching
+		{
+			let old = self.ram[self.index(addr)];
+			self.check_watchpoints(addr, 1, true, old as u16, data as u16);
+		}
+		self.record_write(addr, 1);
+		self.check_code(addr);
+		self.ram[self.index(addr)] = data
 	}
 
 	pub fn write_u16(&mut self, addr: u32, data: u16)
@@ -80,14 +207,142 @@ impl Memory
 		{
 			self.dirty = true;
 		}
-		self.ram[(addr % ADDR_MAX) as usize] = (data & 0xFF) as u8;
-		self.ram[((addr + 1) % ADDR_MAX) as usize] = (data>>8) as u8
+		if self.watching
+		{
+			let old = (self.ram[self.index(addr)] as u16) + ((self.ram[self.index(addr + 1)] as u16) << 8);
+			self.check_watchpoints(addr, 2, true, old, data);
+		}
+		self.record_write(addr, 2);
+		self.check_code(addr);
+		self.check_code(addr + 1);
+		self.ram[self.index(addr)] = (data & 0xFF) as u8;
+		self.ram[self.index(addr + 1)] = (data>>8) as u8
+	}
+
+	fn check_watchpoints(&self, addr: u32, size: u32, write: bool, old: u16, new: u16)
+	{
+		for watchpoint in self.watchpoints.iter().filter(|watchpoint| watchpoint.matches(addr, size, write))
+		{
+			self.watch_hits.borrow_mut().push(WatchHit
+				{
+					id: watchpoint.id,
+					addr: addr,
+					size: size,
+					write: write,
+					old: old,
+					new: new
+				});
+		}
+	}
+
+	pub fn add_watchpoint(&mut self, watchpoint: Watchpoint)
+	{
+		self.watchpoints.push(watchpoint);
+	}
+
+	/* Returns false if there is no such watchpoint */
+	pub fn remove_watchpoint(&mut self, id: i32) -> bool
+	{
+		let count = self.watchpoints.len();
+		self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+		self.watchpoints.len() != count
+	}
+
+	pub fn watchpoints(&self) -> &[Watchpoint]
+	{
+		&self.watchpoints
+	}
+
+	/* Accesses are only checked against watchpoints in between */
+	pub fn start_watching(&mut self)
+	{
+		self.watching = !self.watchpoints.is_empty();
+	}
+
+	pub fn stop_watching(&mut self)
+	{
+		self.watching = false;
+	}
+
+	fn record_write(&mut self, addr: u32, size: u32)
+	{
+		if let Some(ref mut journal) = self.journal
+		{
+			for i in 0 .. size
+			{
+				let addr = (addr + i) & self.addr_mask;
+				journal.push((addr, self.ram[addr as usize]));
+			}
+		}
+	}
+
+	/* Physical address, once the A20 gate and the memory size applied */
+	pub fn physical_address(&self, addr: u32) -> u32
+	{
+		addr & self.addr_mask
+	}
+
+	/* Marks the bytes of an instruction the CPU decode cache holds */
+	pub fn mark_code(&mut self, addr: u32, size: u32)
+	{
+		for i in 0 .. size
+		{
+			let index = self.index(addr + i);
+			self.code[index >> 3] |= 1 << (index & 7);
+		}
+	}
+
+	fn check_code(&mut self, addr: u32)
+	{
+		let index = self.index(addr);
+		let bit = 1 << (index & 7);
+		if self.code[index >> 3] & bit != 0
+		{
+			self.code[index >> 3] &= !bit;
+			self.code_writes.push(index as u32);
+		}
+	}
+
+	/* Marked bytes written since the last call, if any */
+	pub fn take_code_writes(&mut self) -> Option<CodeWrites>
+	{
+		if self.code_replaced
+		{
+			self.code_replaced = false;
+			self.code_writes.clear();
+			Some(CodeWrites::All)
+		}
+		else if self.code_writes.is_empty()
+		{
+			None
+		}
+		else
+		{
+			Some(CodeWrites::Bytes(mem::take(&mut self.code_writes)))
+		}
+	}
+
+	/* Writes are recorded in between, with the value they overwrite */
+	pub fn start_journal(&mut self)
+	{
+		self.journal = Some(Vec::new());
+	}
+
+	pub fn take_journal(&mut self) -> Vec<(u32, u8)>
+	{
+		self.journal.take().unwrap_or_default()
+	}
+
+	/* Hits since the last call, in access order */
+	pub fn take_watch_hits(&mut self) -> Vec<WatchHit>
+	{
+		mem::replace(self.watch_hits.get_mut(), Vec::new())
 	}
 
 	pub fn slice_from(&self, addr: u32) -> &[u8]
 	{
 		let len = self.ram.len();
-		&self.ram[(addr as usize) .. len]
+		&self.ram[self.index(addr) .. len]
 	}
 
 	pub fn slice(&self, addr: u32, len: u32) -> &[u8]
@@ -95,6 +350,24 @@ impl Memory
 		&self.ram[(addr as usize) .. ((addr + len) as usize)]
 	}
 
+	/* Addresses in [start, end[ where the pattern matches as a whole; None
+	 * matches any byte */
+	pub fn search(&self, start: u32, end: u32, pattern: &[Option<u8>]) -> Vec<u32>
+	{
+		let end = end.min(self.ram.len() as u32) as usize;
+		let start = start as usize;
+		if pattern.is_empty() || start + pattern.len() > end
+		{
+			return Vec::new()
+		}
+
+		self.ram[start .. end].windows(pattern.len())
+			.enumerate()
+			.filter(|&(_, window)| window.iter().zip(pattern).all(|(byte, expected)| expected.map_or(true, |e| e == *byte)))
+			.map(|(offset, _)| (start + offset) as u32)
+			.collect()
+	}
+
 	pub fn clear_vram_dirty(&mut self)
 	{
 		self.dirty = false;
@@ -112,3 +385,34 @@ impl Memory
         }
     }
 }
+
+impl Snapshot for Memory
+{
+	fn save_state(&self, writer: &mut SnapshotWriter)
+	{
+		writer.begin_section(b"RAM ");
+		writer.write_blob(&self.ram);
+		writer.write_bool(self.a20);
+	}
+
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>
+	{
+		reader.expect_section(b"RAM ")?;
+		let ram = reader.read_blob()?;
+		if ram.len() != self.ram.len()
+		{
+			return Err(format!("the snapshot holds {} bytes of memory instead of {}", ram.len(), self.ram.len()))
+		}
+		self.ram.copy_from_slice(&ram);
+		for byte in self.code.iter_mut()
+		{
+			*byte = 0;
+		}
+		self.code_replaced = true;
+		let a20 = reader.read_bool()?;
+		self.set_a20(a20);
+		/* The screen content changed */
+		self.dirty = true;
+		Ok(())
+	}
+}
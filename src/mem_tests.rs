@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::mem::*;
+
+	fn watchpoint(id: i32, kind: WatchKind, start: u32, end: u32) -> Watchpoint
+	{
+		Watchpoint { id: id, kind: kind, start: start, end: end }
+	}
+
+	#[test]
+	fn watchpoints()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		mem.write_u16(0x417, 0x1234);
+		mem.add_watchpoint(watchpoint(0, WatchKind::Write, 0x418, 0x418));
+		mem.add_watchpoint(watchpoint(1, WatchKind::Read, 0x400, 0x4ff));
+		mem.add_watchpoint(watchpoint(2, WatchKind::Access, 0x7c00, 0x7dff));
+
+		/* Not watching: accesses by the debugger or the display */
+		mem.write_u8(0x418, 0);
+		mem.read_u8(0x7c00);
+		assert!(mem.take_watch_hits().is_empty());
+
+		mem.start_watching();
+		mem.write_u16(0x417, 0xabcd); // Overlaps the write watchpoint
+		mem.write_u8(0x419, 1);
+		mem.read_u16(0x4ff);
+		mem.read_u8(0x7dff);
+		mem.write_u8(0x7e00, 1);
+		mem.stop_watching();
+		mem.write_u8(0x418, 0);
+
+		assert_eq!(mem.take_watch_hits(), vec![
+			WatchHit { id: 0, addr: 0x417, size: 2, write: true, old: 0x0034, new: 0xabcd },
+			WatchHit { id: 1, addr: 0x4ff, size: 2, write: false, old: 0, new: 0 },
+			WatchHit { id: 2, addr: 0x7dff, size: 1, write: false, old: 0, new: 0 }
+		]);
+		assert!(mem.take_watch_hits().is_empty());
+
+		assert!(mem.remove_watchpoint(1));
+		assert!(!mem.remove_watchpoint(1));
+		assert_eq!(mem.watchpoints().len(), 2);
+	}
+
+	#[test]
+	fn a20()
+	{
+		let mut mem = Memory::new(16 * 1024 * 1024);
+		mem.write_u8(0x000010, 0x12);
+		mem.write_u8(0x100010, 0x34);
+		mem.write_u8(0x300010, 0x56);
+
+		/* FFFF:0020 wraps around to 0:0010 on an 8086 */
+		assert!(!mem.a20());
+		assert_eq!(mem.read_u8(0x100010), 0x34);
+		assert_eq!(mem.read_u8(0x000010), 0x34);
+		assert_eq!(mem.read_u8(0x200010), 0x56);
+
+		mem.set_a20(true);
+		mem.write_u8(0x100010, 0x78);
+		assert_eq!((mem.read_u8(0x000010), mem.read_u8(0x100010)), (0x34, 0x78));
+		/* Beyond the 24-bit address space */
+		assert_eq!(mem.read_u8(0x1000010), 0x34);
+	}
+
+	#[test]
+	fn search()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		for (i, byte) in b"ABCABD".iter().enumerate()
+		{
+			mem.write_u8(0x500 + i as u32, *byte);
+		}
+		mem.write_u8(0xffffe, b'A');
+		mem.write_u8(0xfffff, b'B');
+
+		let ab = [Some(b'A'), Some(b'B')];
+		assert_eq!(mem.search(0, 0x100000, &ab), vec![0x500, 0x503, 0xffffe]);
+		/* Matches must lie within the range */
+		assert_eq!(mem.search(0x501, 0x504, &ab), vec![]);
+		assert_eq!(mem.search(0x501, 0x505, &ab), vec![0x503]);
+		assert_eq!(mem.search(0, 0x100000, &[Some(b'A'), Some(b'B'), None]), vec![0x500, 0x503]);
+		assert_eq!(mem.search(0x500, 0x506, &[None, Some(b'D')]), vec![0x504]);
+		assert_eq!(mem.search(0, 0x100000, &[]), vec![]);
+	}
+
+	#[test]
+	fn code_writes()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		mem.write_u8(0x500, 0x90);
+		assert_eq!(mem.take_code_writes(), None);
+
+		mem.mark_code(0x500, 3);
+		mem.write_u8(0x503, 0x90);
+		mem.write_u16(0x4ff, 0x9090);
+		/* Once per byte, until marked again */
+		mem.write_u8(0x500, 0x90);
+		/* 0x100502 is 0x502 with the A20 gate closed */
+		mem.write_u8(0x100502, 0x90);
+		assert_eq!(mem.take_code_writes(), Some(CodeWrites::Bytes(vec![0x500, 0x502])));
+		assert_eq!(mem.take_code_writes(), None);
+		assert_eq!(mem.physical_address(0x100502), 0x502);
+	}
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+/* Macros expanding to other macros deeper than that are assumed to recurse */
+pub const MAX_DEPTH: usize = 16;
+
+/* Debugger command lines, before they are parsed into commands:
+ *   - '# comment' lines are ignored
+ *   - 'repeat COUNT [COMMAND]' runs COMMAND (a single step by default) COUNT
+ *     times
+ *   - 'define NAME', followed by command lines and 'end', defines a macro;
+ *     'NAME ARGS...' then runs these lines, with $1 to $9 replaced by ARGS */
+pub struct Macros
+{
+	macros: HashMap<String, Vec<String>>,
+	/* The macro being defined, and its lines so far */
+	defining: Option<(String, Vec<String>)>
+}
+
+impl Macros
+{
+	pub fn new() -> Macros
+	{
+		Macros
+		{
+			macros: HashMap::new(),
+			defining: None
+		}
+	}
+
+	pub fn is_defining(&self) -> bool
+	{
+		self.defining.is_some()
+	}
+
+	/* All macros, by name */
+	pub fn list(&self) -> Vec<(&str, &[String])>
+	{
+		let mut macros: Vec<(&str, &[String])> = self.macros.iter()
+			.map(|(name, lines)| (&name[..], &lines[..]))
+			.collect();
+		macros.sort();
+		macros
+	}
+
+	/* The command lines to run for 'line'; they may need expanding too */
+	pub fn expand(&mut self, line: &str) -> Result<Vec<String>, String>
+	{
+		let line = line.trim();
+		let mut words = line.split_whitespace();
+		let word0 = words.next();
+
+		if let Some((name, mut lines)) = self.defining.take()
+		{
+			match word0
+			{
+				Some("end") => { self.macros.insert(name, lines); },
+				Some("define") =>
+				{
+					self.defining = Some((name, lines));
+					return Err("macros cannot be defined inside macros".to_string())
+				},
+				Some(word) if !word.starts_with('#') =>
+				{
+					lines.push(line.to_string());
+					self.defining = Some((name, lines));
+				},
+				_ => self.defining = Some((name, lines))
+			}
+			return Ok(Vec::new())
+		}
+
+		match word0
+		{
+			/* Empty lines step */
+			None => Ok(vec![String::new()]),
+			Some(word) if word.starts_with('#') => Ok(Vec::new()),
+			Some("define") => match (words.next(), words.next())
+			{
+				(Some(name), None) =>
+				{
+					self.defining = Some((name.to_string(), Vec::new()));
+					Ok(Vec::new())
+				},
+				_ => Err("Usage: define NAME, then command lines and 'end'".to_string())
+			},
+			Some("end") => Err("'end' without 'define'".to_string()),
+			Some("repeat") =>
+			{
+				let count = match words.next().map(|count| count.parse::<usize>())
+				{
+					Some(Ok(count)) => count,
+					_ => return Err("Usage: repeat COUNT [COMMAND]".to_string())
+				};
+				let command = words.collect::<Vec<&str>>().join(" ");
+				Ok(vec![command; count])
+			},
+			Some(name) => match self.macros.get(name)
+			{
+				Some(lines) =>
+				{
+					let args: Vec<&str> = words.collect();
+					Ok(lines.iter().map(|line| substitute(line, &args)).collect())
+				},
+				None => Ok(vec![line.to_string()])
+			}
+		}
+	}
+}
+
+/* $1 to $9 are the arguments of a macro; missing ones are empty */
+fn substitute(line: &str, args: &[&str]) -> String
+{
+	let mut result = String::new();
+	let mut chars = line.chars().peekable();
+	while let Some(c) = chars.next()
+	{
+		match (c, chars.peek().and_then(|next| next.to_digit(10)))
+		{
+			('$', Some(n)) if n > 0 =>
+			{
+				chars.next();
+				result.push_str(args.get(n as usize - 1).cloned().unwrap_or(""));
+			},
+			_ => result.push(c)
+		}
+	}
+	result
+}
@@ -0,0 +1,47 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::script::*;
+
+	fn lines(lines: &[&str]) -> Vec<String>
+	{
+		lines.iter().map(|line| line.to_string()).collect()
+	}
+
+	#[test]
+	fn plain_lines()
+	{
+		let mut macros = Macros::new();
+		assert_eq!(macros.expand("  b 0 7c00 \n"), Ok(lines(&["b 0 7c00"])));
+		assert_eq!(macros.expand("# set up"), Ok(lines(&[])));
+		assert_eq!(macros.expand(""), Ok(lines(&[""])));
+		assert_eq!(macros.expand("repeat 3 n"), Ok(lines(&["n", "n", "n"])));
+		assert_eq!(macros.expand("repeat 2"), Ok(lines(&["", ""])));
+		assert!(macros.expand("repeat n").is_err());
+		assert!(macros.expand("end").is_err());
+	}
+
+	#[test]
+	fn macros()
+	{
+		let mut macros = Macros::new();
+		assert_eq!(macros.expand("define show"), Ok(lines(&[])));
+		assert!(macros.is_defining());
+		assert_eq!(macros.expand("d $1 $2"), Ok(lines(&[])));
+		assert_eq!(macros.expand("# not part of it"), Ok(lines(&[])));
+		assert!(macros.expand("define nested").is_err());
+		assert_eq!(macros.expand("u $1 $2"), Ok(lines(&[])));
+		assert_eq!(macros.expand("end"), Ok(lines(&[])));
+		assert!(!macros.is_defining());
+
+		assert_eq!(macros.expand("show 40 17"), Ok(lines(&["d 40 17", "u 40 17"])));
+		assert_eq!(macros.expand("show main"), Ok(lines(&["d main ", "u main "])));
+		assert_eq!(macros.list(), vec![("show", &lines(&["d $1 $2", "u $1 $2"])[..])]);
+
+		/* Redefinition */
+		macros.expand("define show").unwrap();
+		macros.expand("echo $10 costs $$").unwrap();
+		macros.expand("end").unwrap();
+		assert_eq!(macros.expand("show a"), Ok(lines(&["echo a0 costs $$"])));
+	}
+}
@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+/* Machine snapshots: the state of every emulated component is serialized, in
+ * a fixed order, into a versioned binary file. Each component writes its own
+ * section, starting with a 4-character tag, so that a layout mismatch is
+ * reported instead of silently loading garbage. Values are little-endian. */
+
+const MAGIC: &'static [u8; 8] = b"RIAPYXSS";
+/* To be bumped whenever the content of a section changes */
+const VERSION: u32 = 3;
+
+pub trait Snapshot
+{
+	fn save_state(&self, writer: &mut SnapshotWriter);
+	fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), String>;
+}
+
+pub struct SnapshotWriter
+{
+	data: Vec<u8>
+}
+
+impl SnapshotWriter
+{
+	pub fn new() -> SnapshotWriter
+	{
+		let mut writer = SnapshotWriter { data: Vec::new() };
+		writer.write_bytes(MAGIC);
+		writer.write_u32(VERSION);
+		writer
+	}
+
+	pub fn begin_section(&mut self, tag: &[u8; 4])
+	{
+		self.write_bytes(tag);
+	}
+
+	pub fn write_bool(&mut self, val: bool)
+	{
+		self.write_u8(val as u8);
+	}
+
+	pub fn write_u8(&mut self, val: u8)
+	{
+		self.data.push(val);
+	}
+
+	pub fn write_u16(&mut self, val: u16)
+	{
+		self.write_bytes(&[val as u8, (val >> 8) as u8]);
+	}
+
+	pub fn write_u32(&mut self, val: u32)
+	{
+		self.write_u16(val as u16);
+		self.write_u16((val >> 16) as u16);
+	}
+
+	pub fn write_u64(&mut self, val: u64)
+	{
+		self.write_u32(val as u32);
+		self.write_u32((val >> 32) as u32);
+	}
+
+	pub fn write_bytes(&mut self, bytes: &[u8])
+	{
+		self.data.extend_from_slice(bytes);
+	}
+
+	/* Length-prefixed bytes */
+	pub fn write_blob(&mut self, bytes: &[u8])
+	{
+		self.write_u32(bytes.len() as u32);
+		self.write_bytes(bytes);
+	}
+
+	pub fn write_str(&mut self, s: &str)
+	{
+		self.write_blob(s.as_bytes());
+	}
+
+	pub fn into_bytes(self) -> Vec<u8>
+	{
+		self.data
+	}
+
+	pub fn save(&self, filename: &str) -> Result<(), String>
+	{
+		File::create(filename)
+			.and_then(|mut file| file.write_all(&self.data))
+			.map_err(|e| format!("unable to write {}: {}", filename, e))
+	}
+}
+
+pub struct SnapshotReader
+{
+	data: Vec<u8>,
+	pos: usize
+}
+
+impl SnapshotReader
+{
+	pub fn from_bytes(data: Vec<u8>) -> Result<SnapshotReader, String>
+	{
+		let mut reader = SnapshotReader { data: data, pos: 0 };
+		match reader.read_bytes(MAGIC.len())
+		{
+			Ok(ref magic) if &magic[..] == &MAGIC[..] => {}
+			_ => return Err("not a snapshot file".to_string())
+		}
+
+		let version = reader.read_u32()?;
+		if version != VERSION
+		{
+			return Err(format!("unsupported snapshot version {} (expected {})", version, VERSION))
+		}
+		Ok(reader)
+	}
+
+	pub fn open(filename: &str) -> Result<SnapshotReader, String>
+	{
+		let mut data = Vec::new();
+		File::open(filename)
+			.and_then(|mut file| file.read_to_end(&mut data))
+			.map_err(|e| format!("unable to read {}: {}", filename, e))?;
+		SnapshotReader::from_bytes(data)
+	}
+
+	pub fn expect_section(&mut self, tag: &[u8; 4]) -> Result<(), String>
+	{
+		let found = self.read_bytes(4)?;
+		if &found[..] != &tag[..]
+		{
+			return Err(format!("expected section '{}', found '{}'",
+				String::from_utf8_lossy(tag), String::from_utf8_lossy(&found)))
+		}
+		Ok(())
+	}
+
+	pub fn read_bool(&mut self) -> Result<bool, String>
+	{
+		match self.read_u8()?
+		{
+			0 => Ok(false),
+			1 => Ok(true),
+			x => Err(format!("invalid boolean value {}", x))
+		}
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, String>
+	{
+		Ok(self.read_bytes(1)?[0])
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16, String>
+	{
+		let bytes = self.read_bytes(2)?;
+		Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32, String>
+	{
+		let lo = self.read_u16()? as u32;
+		let hi = self.read_u16()? as u32;
+		Ok(lo | hi << 16)
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64, String>
+	{
+		let lo = self.read_u32()? as u64;
+		let hi = self.read_u32()? as u64;
+		Ok(lo | hi << 32)
+	}
+
+	pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, String>
+	{
+		if self.data.len() - self.pos < len
+		{
+			return Err("unexpected end of file".to_string())
+		}
+		let bytes = self.data[self.pos..self.pos + len].to_vec();
+		self.pos += len;
+		Ok(bytes)
+	}
+
+	pub fn read_blob(&mut self) -> Result<Vec<u8>, String>
+	{
+		let len = self.read_u32()? as usize;
+		self.read_bytes(len)
+	}
+
+	pub fn read_str(&mut self) -> Result<String, String>
+	{
+		String::from_utf8(self.read_blob()?).map_err(|_| "invalid string".to_string())
+	}
+
+	/* Checks that the whole snapshot was consumed */
+	pub fn finish(&self) -> Result<(), String>
+	{
+		if self.pos != self.data.len()
+		{
+			return Err(format!("{} trailing bytes", self.data.len() - self.pos))
+		}
+		Ok(())
+	}
+}
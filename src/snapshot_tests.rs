@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::snapshot::*;
+	use super::super::hw::pic::Pic;
+	use super::super::hw::pit::Pit;
+	use super::super::mem::{CodeWrites, Memory};
+
+	fn reader_for(writer: SnapshotWriter) -> SnapshotReader
+	{
+		SnapshotReader::from_bytes(writer.into_bytes()).unwrap()
+	}
+
+	#[test]
+	fn values()
+	{
+		let mut writer = SnapshotWriter::new();
+		writer.begin_section(b"TEST");
+		writer.write_bool(true);
+		writer.write_u8(0x12);
+		writer.write_u16(0x3456);
+		writer.write_u32(0x789abcde);
+		writer.write_u64(0x0123456789abcdef);
+		writer.write_str("C:\\WINDOWS");
+
+		let mut reader = reader_for(writer);
+		reader.expect_section(b"TEST").unwrap();
+		assert_eq!(reader.read_bool(), Ok(true));
+		assert_eq!(reader.read_u8(), Ok(0x12));
+		assert_eq!(reader.read_u16(), Ok(0x3456));
+		assert_eq!(reader.read_u32(), Ok(0x789abcde));
+		assert_eq!(reader.read_u64(), Ok(0x0123456789abcdef));
+		assert_eq!(reader.read_str(), Ok("C:\\WINDOWS".to_string()));
+		assert_eq!(reader.finish(), Ok(()));
+		assert!(reader.read_u8().is_err());
+	}
+
+	#[test]
+	fn invalid_files()
+	{
+		assert!(SnapshotReader::from_bytes(b"RIAPYX".to_vec()).is_err());
+
+		let mut bytes = SnapshotWriter::new().into_bytes();
+		bytes[8] += 1; // Version
+		assert!(SnapshotReader::from_bytes(bytes).is_err());
+
+		let mut writer = SnapshotWriter::new();
+		writer.begin_section(b"PIT ");
+		let mut reader = reader_for(writer);
+		assert!(reader.expect_section(b"PIC ").is_err());
+	}
+
+	#[test]
+	fn devices()
+	{
+		let mut pic = Pic::new();
+		let mut pit = Pit::new();
+		pit.write_control(0x36); // Channel 0, lobyte/hibyte, mode 3
+		pit.write_counter(0, 100);
+		pit.write_counter(0, 0);
+		pit.step(&mut pic, 1000);
+		pic.set_irq_line(1, true);
+
+		let mut writer = SnapshotWriter::new();
+		pic.save_state(&mut writer);
+		pit.save_state(&mut writer);
+
+		let mut restored_pic = Pic::new();
+		let mut restored_pit = Pit::new();
+		let mut reader = reader_for(writer);
+		restored_pic.load_state(&mut reader).unwrap();
+		restored_pit.load_state(&mut reader).unwrap();
+		reader.finish().unwrap();
+
+		/* Both machines must now behave the same */
+		for _ in 0..100
+		{
+			pit.step(&mut pic, 7);
+			restored_pit.step(&mut restored_pic, 7);
+			assert_eq!(pit.output(0), restored_pit.output(0));
+			assert_eq!(pic.has_interrupt(), restored_pic.has_interrupt());
+			if pic.has_interrupt()
+			{
+				assert_eq!(pic.acknowledge(), restored_pic.acknowledge());
+			}
+		}
+	}
+
+	#[test]
+	fn memory()
+	{
+		let mut mem = Memory::new(1024 * 1024);
+		mem.write_u16(0xb8000, 0x0741);
+		mem.write_u8(0xfffff, 0xea);
+
+		let mut writer = SnapshotWriter::new();
+		mem.save_state(&mut writer);
+
+		let mut restored = Memory::new(1024 * 1024);
+		restored.load_state(&mut reader_for(writer)).unwrap();
+		assert_eq!(restored.read_u16(0xb8000), 0x0741);
+		assert_eq!(restored.read_u8(0xfffff), 0xea);
+		/* Instructions decoded from the old content are stale */
+		assert_eq!(restored.take_code_writes(), Some(CodeWrites::All));
+
+		let mut writer = SnapshotWriter::new();
+		Memory::new(64 * 1024).save_state(&mut writer);
+		assert!(restored.load_state(&mut reader_for(writer)).is_err());
+	}
+}
@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use cpu::phys_addr;
+
+/* Addresses farther than that from the closest symbol below are not named
+ * after it */
+const MAX_SYMBOL_DISTANCE: u32 = 0x10000;
+
+/* Names for guest addresses, loaded from:
+ *   - MS LINK .MAP files, and any text file with 'SEG:OFF name' lines
+ *   - assembler listings (NASM, as86): 'label:' lines, with the offset of the
+ *     code they precede
+ * Names are case insensitive. */
+pub struct SymbolTable
+{
+	by_addr: BTreeMap<u32, (u16, u16, String)>,
+	by_name: HashMap<String, (u16, u16)>
+}
+
+fn parse_hex(s: &str) -> Option<u16>
+{
+	if s.is_empty() || s.len() > 4
+	{
+		return None
+	}
+	u16::from_str_radix(s, 16).ok()
+}
+
+fn is_name(s: &str) -> bool
+{
+	let valid_char = |c: char| c.is_ascii_alphanumeric() || "_.$@?~#".contains(c);
+	s.chars().next().map_or(false, |c| !c.is_ascii_digit()) && s.chars().all(valid_char)
+}
+
+/* 'SEG:OFF [Abs|Imp|Res] name', as in the publics of a .MAP file; absolute
+ * symbols are constants, not addresses */
+fn parse_map_line(line: &str) -> Option<(u16, u16, &str)>
+{
+	let mut words = line.split_whitespace();
+	let mut addr = words.next()?.splitn(2, ':');
+	let (seg, off) = (parse_hex(addr.next()?)?, parse_hex(addr.next()?)?);
+	let name = match words.next()?
+	{
+		"Abs" => return None,
+		"Imp" | "Res" => words.next()?,
+		name => name
+	};
+	if is_name(name) { Some((seg, off, name)) } else { None }
+}
+
+/* The bytes column of a listing: 'B409', 'BA[0B00]', 'E8(0000)', '<res 10>',
+ * '<1>' for macro expansions, '-' for continued lines */
+fn is_listing_data(word: &str) -> bool
+{
+	word == "<res" || word.chars().all(|c| c.is_ascii_hexdigit() || "[]()<>-".contains(c))
+}
+
+/* Returns the offset, if any, and the label, if any, defined by a listing
+ * line: 'LINE [OFFSET [BYTES...]] source' */
+fn parse_listing_line(line: &str) -> (Option<u16>, Option<&str>)
+{
+	let mut words = line.split_whitespace().peekable();
+	match words.next()
+	{
+		Some(number) if number.chars().all(|c| c.is_ascii_digit()) => {},
+		_ => return (None, None)
+	}
+
+	/* 4 digits for as86, 8 for NASM */
+	let offset = match words.peek()
+	{
+		Some(word) if (word.len() == 4 || word.len() == 8) && word.chars().all(|c| c.is_ascii_hexdigit()) =>
+			u32::from_str_radix(word, 16).ok().map(|offset| offset as u16),
+		_ => None
+	};
+	if offset.is_some()
+	{
+		words.next();
+	}
+
+	let source = words.find(|word| !is_listing_data(word));
+	let label = source.and_then(|word|
+		{
+			if word.ends_with(':') && is_name(&word[.. word.len() - 1]) { Some(&word[.. word.len() - 1]) } else { None }
+		});
+	(offset, label)
+}
+
+impl SymbolTable
+{
+	pub fn new() -> SymbolTable
+	{
+		SymbolTable
+		{
+			by_addr: BTreeMap::new(),
+			by_name: HashMap::new()
+		}
+	}
+
+	pub fn add(&mut self, seg: u16, off: u16, name: &str)
+	{
+		self.by_addr.insert(phys_addr(seg, off), (seg, off, name.to_string()));
+		self.by_name.insert(name.to_ascii_lowercase(), (seg, off));
+	}
+
+	pub fn clear(&mut self)
+	{
+		self.by_addr.clear();
+		self.by_name.clear();
+	}
+
+	pub fn len(&self) -> usize
+	{
+		self.by_name.len()
+	}
+
+	/* All symbols, by address */
+	pub fn iter(&self) -> impl Iterator<Item = &(u16, u16, String)>
+	{
+		self.by_addr.values()
+	}
+
+	/* Segments are relative to 'seg' and offsets to 'off', e.g. the load
+	 * segment of a program and 0x100 for an assembled .COM file; returns
+	 * the number of symbols loaded */
+	pub fn load(&mut self, filename: &str, seg: u16, off: u16) -> Result<usize, String>
+	{
+		let mut text = String::new();
+		File::open(filename)
+			.and_then(|mut file| file.read_to_string(&mut text))
+			.map_err(|e| format!("unable to read {}: {}", filename, e))?;
+		Ok(self.load_str(&text, seg, off))
+	}
+
+	pub fn load_str(&mut self, text: &str, seg: u16, off: u16) -> usize
+	{
+		let symbols: Vec<(u16, u16, String)> = text.lines()
+			.filter_map(parse_map_line)
+			.map(|(sym_seg, sym_off, name)| (sym_seg, sym_off, name.to_string()))
+			.collect();
+		let symbols = if symbols.is_empty() { parse_listing(text) } else { symbols };
+
+		for &(sym_seg, sym_off, ref name) in &symbols
+		{
+			self.add(sym_seg.wrapping_add(seg), sym_off.wrapping_add(off), name);
+		}
+		symbols.len()
+	}
+
+	pub fn lookup(&self, name: &str) -> Option<(u16, u16)>
+	{
+		self.by_name.get(&name.to_ascii_lowercase()).cloned()
+	}
+
+	/* 'name' or 'name+0x12' for the closest symbol at or below seg:off */
+	pub fn name_at(&self, seg: u16, off: u16) -> Option<String>
+	{
+		let addr = phys_addr(seg, off);
+		self.by_addr.range(.. addr + 1).next_back().and_then(|(sym_addr, &(_, _, ref name))|
+			{
+				match addr - sym_addr
+				{
+					0 => Some(name.clone()),
+					delta if delta < MAX_SYMBOL_DISTANCE => Some(format!("{}+0x{:x}", name, delta)),
+					_ => None
+				}
+			})
+	}
+
+	/* The exact symbol at seg:off */
+	pub fn label_at(&self, seg: u16, off: u16) -> Option<&str>
+	{
+		self.by_addr.get(&phys_addr(seg, off)).map(|&(_, _, ref name)| &name[..])
+	}
+
+	/* 'SEG:OFF', followed by ' <name+0x12>' if there is a symbol close enough */
+	pub fn describe(&self, seg: u16, off: u16) -> String
+	{
+		match self.name_at(seg, off)
+		{
+			Some(name) => format!("{:04x}:{:04x} <{}>", seg, off, name),
+			None => format!("{:04x}:{:04x}", seg, off)
+		}
+	}
+}
+
+/* Labels get the offset of the first line with one from theirs; local labels
+ * (.name) are prefixed with the previous global one, as NASM does */
+fn parse_listing(text: &str) -> Vec<(u16, u16, String)>
+{
+	let mut symbols = Vec::new();
+	let mut pending = Vec::new();
+	let mut global = String::new();
+
+	for line in text.lines()
+	{
+		let (offset, label) = parse_listing_line(line);
+		if let Some(label) = label
+		{
+			if label.starts_with('.')
+			{
+				pending.push(format!("{}{}", global, label));
+			}
+			else
+			{
+				global = label.to_string();
+				pending.push(global.clone());
+			}
+		}
+		if let Some(offset) = offset
+		{
+			symbols.extend(pending.drain(..).map(|name| (0, offset, name)));
+		}
+	}
+	symbols
+}
@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests
+{
+	use super::super::symbols::*;
+
+	const LINK_MAP: &'static str = "
+ Start  Stop   Length Name                   Class
+ 00000H 0002FH 00030H _TEXT                  CODE
+ 00030H 0004FH 00020H _DATA                  DATA
+
+  Address         Publics by Name
+
+ 0000:0010       _main
+ 0003:0000       _message
+ 0000:0000  Abs  __acrtused
+ 0000:0020       _Exit
+
+Program entry point at 0000:0000
+";
+
+	const NASM_LISTING: &'static str = "
+     1                                  org 100h
+     2                                  start:
+     3 00000000 B409                    	mov ah, 9
+     4 00000002 BA[0B00]                	mov dx, msg
+     5                                  .loop:
+     6 00000005 CD21                    	int 21h
+     7 00000007 E8(0000)                done: call exit
+     8 0000000A C3                      	ret
+     9 0000000B 48656C6C6F2C20776F-     msg: db \"Hello, world!$\"
+    10 00000014 726C642124
+    11                                  end:
+";
+
+	#[test]
+	fn link_map()
+	{
+		let mut symbols = SymbolTable::new();
+		assert_eq!(symbols.load_str(LINK_MAP, 0x1000, 0), 3);
+		assert_eq!(symbols.lookup("_main"), Some((0x1000, 0x0010)));
+		assert_eq!(symbols.lookup("_MESSAGE"), Some((0x1003, 0x0000)));
+		assert_eq!(symbols.lookup("__acrtused"), None);
+		assert_eq!(symbols.lookup("_TEXT"), None);
+	}
+
+	#[test]
+	fn listing()
+	{
+		let mut symbols = SymbolTable::new();
+		assert_eq!(symbols.load_str(NASM_LISTING, 0x2000, 0x100), 4);
+		assert_eq!(symbols.lookup("start"), Some((0x2000, 0x100)));
+		assert_eq!(symbols.lookup("start.loop"), Some((0x2000, 0x105)));
+		assert_eq!(symbols.lookup("done"), Some((0x2000, 0x107)));
+		assert_eq!(symbols.lookup("msg"), Some((0x2000, 0x10b)));
+		/* No code follows */
+		assert_eq!(symbols.lookup("end"), None);
+
+		let as86 = "00001                       start:\n00002  0000  B4 09          mov ah,#9\n";
+		assert_eq!(symbols.load_str(as86, 0x3000, 0), 1);
+		assert_eq!(symbols.lookup("start"), Some((0x3000, 0)));
+	}
+
+	#[test]
+	fn names()
+	{
+		let mut symbols = SymbolTable::new();
+		symbols.load_str("f000:e05b bios_entry\n0040:0000 bda\n", 0, 0);
+		assert_eq!(symbols.len(), 2);
+		assert_eq!(symbols.label_at(0xf000, 0xe05b), Some("bios_entry"));
+		assert_eq!(symbols.label_at(0xf000, 0xe05c), None);
+		/* Any segment:offset pair for the same physical address */
+		assert_eq!(symbols.name_at(0xfe05, 0x000b), Some("bios_entry".to_string()));
+		assert_eq!(symbols.name_at(0x0040, 0x0017), Some("bda+0x17".to_string()));
+		assert_eq!(symbols.name_at(0x0000, 0x0010), None);
+		assert_eq!(symbols.describe(0x0040, 0x0010), "0040:0010 <bda+0x10>");
+		assert_eq!(symbols.describe(0x1000, 0x0000), "1000:0000 <bda+0xfc00>");
+		assert_eq!(symbols.describe(0x2000, 0x0000), "2000:0000");
+
+		symbols.clear();
+		assert_eq!(symbols.len(), 0);
+		assert_eq!(symbols.lookup("bda"), None);
+	}
+}